@@ -4,19 +4,13 @@
 use ariel_os::{
     config,
     debug::{ExitCode, exit, log::*},
-    net,
+    net::{self, tls},
     reexports::embassy_net,
 };
-use embassy_net::{
-    dns::DnsSocket,
-    tcp::client::{TcpClient, TcpClientState},
-};
-use reqwless::{
-    client::{HttpClient, TlsConfig, TlsVerify},
-    request::Method,
-};
+use embassy_net::{dns::DnsQueryType, tcp::TcpSocket};
+use reqwless::{Url, UrlScheme, client::HttpConnection, request::Request};
 
-// RFC8449: TLS 1.3 encrypted records are limited to 16 KiB + 256 bytes.
+// RFC8449: TLS 1.3 encrypted records are limited to 16 KiB + 256 bytes.
 const MAX_ENCRYPTED_TLS_13_RECORD_SIZE: usize = 16640;
 // Required by `embedded_tls::TlsConnection::new()`.
 const TLS_READ_BUFFER_SIZE: usize = MAX_ENCRYPTED_TLS_13_RECORD_SIZE;
@@ -27,38 +21,34 @@ const TLS_WRITE_BUFFER_SIZE: usize = 4096;
 const TCP_BUFFER_SIZE: usize = 1024;
 const HTTP_BUFFER_SIZE: usize = 1024;
 
-const MAX_CONCURRENT_CONNECTIONS: usize = 2;
+net::declare_sockets!("http-client", 1);
 
 const ENDPOINT_URL: &str = config::str_from_env_or!(
     "ENDPOINT_URL",
-    "https://crab.ariel-os.org",
+    "https://10.42.0.1:4433/",
     "endpoint to send the GET request to",
 );
 
+// The server is authenticated by pinning the key of its certificate, see the README.
+const SERVER_CERTIFICATE: &[u8] = include_bytes!("../server.der");
+
+#[derive(Debug)]
+#[allow(dead_code, reason = "the errors are only logged")]
+enum Error {
+    Url,
+    Dns,
+    Connect,
+    Tls(tls::Error),
+    Http(reqwless::Error),
+}
+
 #[ariel_os::task(autostart)]
 async fn main() {
     let stack = net::network_stack().await.unwrap();
 
-    let tcp_client_state =
-        TcpClientState::<MAX_CONCURRENT_CONNECTIONS, TCP_BUFFER_SIZE, TCP_BUFFER_SIZE>::new();
-    let tcp_client = TcpClient::new(stack, &tcp_client_state);
-    let dns_client = DnsSocket::new(stack);
-
-    let tls_seed: u64 = rand_core::RngCore::next_u64(&mut ariel_os::random::crypto_rng());
-
-    let mut tls_rx_buffer = [0; TLS_READ_BUFFER_SIZE];
-    let mut tls_tx_buffer = [0; TLS_WRITE_BUFFER_SIZE];
-
-    // We do not authenticate the server in this example, as that would require setting up a PSK
-    // with the server.
-    let tls_verify = TlsVerify::None;
-    let tls_config = TlsConfig::new(tls_seed, &mut tls_rx_buffer, &mut tls_tx_buffer, tls_verify);
-
-    let mut client = HttpClient::new_with_tls(&tcp_client, &dns_client, tls_config);
-
     stack.wait_config_up().await;
 
-    if let Err(err) = send_http_get_request(&mut client, ENDPOINT_URL).await {
+    if let Err(err) = send_https_get_request(stack, ENDPOINT_URL).await {
         error!(
             "Error while sending an HTTP request: {:?}",
             defmt::Debug2Format(&err)
@@ -68,14 +58,50 @@ async fn main() {
     exit(ExitCode::SUCCESS);
 }
 
-async fn send_http_get_request(
-    client: &mut HttpClient<'_, TcpClient<'_, MAX_CONCURRENT_CONNECTIONS>, DnsSocket<'_>>,
-    url: &str,
-) -> Result<(), reqwless::Error> {
-    let mut http_rx_buf = [0; HTTP_BUFFER_SIZE];
+async fn send_https_get_request(stack: net::NetworkStack, url: &str) -> Result<(), Error> {
+    let url = Url::parse(url).map_err(|_| Error::Url)?;
+    if url.scheme() != UrlScheme::HTTPS {
+        return Err(Error::Url);
+    }
+
+    let address = stack
+        .dns_query(url.host(), DnsQueryType::A)
+        .await
+        .map_err(|_| Error::Dns)?
+        .first()
+        .copied()
+        .ok_or(Error::Dns)?;
+
+    let mut tcp_rx_buffer = [0; TCP_BUFFER_SIZE];
+    let mut tcp_tx_buffer = [0; TCP_BUFFER_SIZE];
+    let mut socket = TcpSocket::new(stack, &mut tcp_rx_buffer, &mut tcp_tx_buffer);
+    socket
+        .connect((address, url.port_or_default()))
+        .await
+        .map_err(|_| Error::Connect)?;
 
-    let mut handle = client.request(Method::GET, url).await?;
-    let response = handle.send(&mut http_rx_buf).await?;
+    let mut tls_rx_buffer = [0; TLS_READ_BUFFER_SIZE];
+    let mut tls_tx_buffer = [0; TLS_WRITE_BUFFER_SIZE];
+    let connection = tls::connect(
+        socket,
+        url.host(),
+        tls::Authentication::PinnedKeys {
+            certificates: &[SERVER_CERTIFICATE],
+            client: None,
+        },
+        &mut tls_rx_buffer,
+        &mut tls_tx_buffer,
+    )
+    .await
+    .map_err(Error::Tls)?;
+    let mut connection = HttpConnection::Tls(connection);
+
+    let mut http_rx_buf = [0; HTTP_BUFFER_SIZE];
+    let request = Request::get(url.path()).host(url.host()).build();
+    let response = connection
+        .send(request, &mut http_rx_buf)
+        .await
+        .map_err(Error::Http)?;
 
     info!("Response status: {}", response.status.0);
 
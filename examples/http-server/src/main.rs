@@ -20,6 +20,9 @@ const SERVER_CONFIG: picoserve::Config<Duration> = picoserve::Config::new(picose
 
 static APP: StaticCell<picoserve::Router<routes::AppRouter>> = StaticCell::new();
 
+net::declare_sockets!("http-server", WEB_TASK_POOL_SIZE);
+net::mdns::declare_service!("_http._tcp", HTTP_PORT);
+
 #[cfg(feature = "button-reading")]
 static BUTTON_INPUT: OnceLock<ariel_os::gpio::Input> = OnceLock::new();
 
@@ -13,6 +13,8 @@ const RX_BUFFER_SIZE: usize = 128;
 const TX_BUFFER_SIZE: usize = 8;
 const RW_BUFFER_SIZE: usize = 8;
 
+net::declare_sockets!("tcp-echo", 1);
+
 #[ariel_os::task(autostart)]
 async fn tcp_echo() {
     let stack = net::network_stack().await.unwrap();
@@ -8,6 +8,8 @@ use embassy_net::udp::{PacketMetadata, UdpSocket};
 // and sending, so the size of the three buffers needs to be the same in this echo example.
 const BUFFER_SIZE: usize = 128;
 
+net::declare_sockets!("udp-echo", 1);
+
 #[ariel_os::task(autostart)]
 async fn udp_echo() {
     let stack = net::network_stack().await.unwrap();
@@ -10,13 +10,50 @@
 // In the latter case, `cfg(test)` is set.
 // So we *only* set up the global stuff if *not* testing in order to avoid clashes.
 #[cfg(not(test))]
-pub use alloc::init;
+pub use alloc::{HeapStats, init, stats};
 
 #[cfg(not(test))]
 mod alloc {
     const CONFIG_HEAPSIZE: usize =
         ariel_os_utils::usize_from_env_or!("CONFIG_HEAPSIZE", 2048, "heap size (in bytes)");
 
+    #[cfg(context = "cortex-m")]
+    #[global_allocator]
+    static HEAP: counting::Counting<embedded_alloc::TlsfHeap> =
+        const { counting::Counting::new(embedded_alloc::TlsfHeap::empty()) };
+
+    /// Usage statistics of the heap.
+    #[derive(Debug, Clone, Copy)]
+    pub struct HeapStats {
+        /// Size of the heap in bytes.
+        pub size: usize,
+        /// Number of bytes currently allocated, if known.
+        pub used: Option<usize>,
+    }
+
+    /// Returns usage statistics of the heap.
+    #[must_use]
+    pub fn stats() -> HeapStats {
+        cfg_if::cfg_if! {
+            if #[cfg(context = "cortex-m")] {
+                HeapStats {
+                    size: heap_bounds().1,
+                    used: Some(HEAP.used()),
+                }
+            } else if #[cfg(context = "esp")] {
+                HeapStats {
+                    size: CONFIG_HEAPSIZE,
+                    used: Some(esp_alloc::HEAP.used()),
+                }
+            } else {
+                HeapStats {
+                    size: CONFIG_HEAPSIZE,
+                    used: None,
+                }
+            }
+        }
+    }
+
     /// Initializes the heap.
     ///
     /// This is called by `ariel-os-rt` early during system initialization.
@@ -44,11 +81,23 @@ mod alloc {
     unsafe fn init_embedded_alloc() {
         use ariel_os_debug::log::debug;
 
-        use embedded_alloc::TlsfHeap as Heap;
+        let (start, size) = heap_bounds();
+
+        // No `const { assert!(..) }` here unfortunately due to the use of linker
+        // values.
+        assert!(size >= CONFIG_HEAPSIZE);
+
+        debug!(
+            "ariel-os-alloc: initializing heap with {} bytes at 0x{:x}",
+            size, start
+        );
 
-        #[global_allocator]
-        static HEAP: Heap = const { Heap::empty() };
+        unsafe { HEAP.inner().init(start, size) }
+    }
 
+    /// Returns the start address and size of the heap, as placed by the linker.
+    #[cfg(context = "cortex-m")]
+    fn heap_bounds() -> (usize, usize) {
         unsafe extern "C" {
             static __sheap: u32;
             static __eheap: u32;
@@ -56,17 +105,59 @@ mod alloc {
 
         let start = &raw const __sheap as usize;
         let size = &raw const __eheap as usize - start;
+        (start, size)
+    }
 
-        // No `const { assert!(..) }` here unfortunately due to the use of linker
-        // values.
-        assert!(size >= CONFIG_HEAPSIZE);
+    #[cfg(context = "cortex-m")]
+    mod counting {
+        use core::alloc::{GlobalAlloc, Layout};
+        use core::cell::Cell;
 
-        debug!(
-            "ariel-os-alloc: initializing heap with {} bytes at 0x{:x}",
-            size, start
-        );
+        use critical_section::Mutex;
+
+        /// Wrapper around an allocator that keeps track of the number of allocated bytes.
+        pub(super) struct Counting<A> {
+            inner: A,
+            used: Mutex<Cell<usize>>,
+        }
 
-        unsafe { HEAP.init(start, size) }
+        impl<A> Counting<A> {
+            pub(super) const fn new(inner: A) -> Self {
+                Self {
+                    inner,
+                    used: Mutex::new(Cell::new(0)),
+                }
+            }
+
+            pub(super) fn inner(&self) -> &A {
+                &self.inner
+            }
+
+            pub(super) fn used(&self) -> usize {
+                critical_section::with(|cs| self.used.borrow(cs).get())
+            }
+        }
+
+        unsafe impl<A: GlobalAlloc> GlobalAlloc for Counting<A> {
+            unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+                let ptr = unsafe { self.inner.alloc(layout) };
+                if !ptr.is_null() {
+                    critical_section::with(|cs| {
+                        let used = self.used.borrow(cs);
+                        used.set(used.get() + layout.size());
+                    });
+                }
+                ptr
+            }
+
+            unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+                unsafe { self.inner.dealloc(ptr, layout) };
+                critical_section::with(|cs| {
+                    let used = self.used.borrow(cs);
+                    used.set(used.get() - layout.size());
+                });
+            }
+        }
     }
 
     /// Initializes an `esp_alloc` heap.
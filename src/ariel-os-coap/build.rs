@@ -8,7 +8,31 @@ use std::fmt::Write as _;
 struct Peer {
     kccs: Option<String>,
     from: Option<KnownSource>,
-    scope: Scope,
+    #[serde(rename = "as")]
+    authorization_server: Option<AuthorizationServer>,
+    scope: Option<Scope>,
+}
+
+/// An ACE Authorization Server (AS) whose tokens are accepted.
+///
+/// Keys are given in CBOR Diagnostic Notation (EDN), like the `kccs`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AuthorizationServer {
+    /// Key shared with the AS for tokens encrypted with AES-CCM-16-128-256 (COSE algorithm 31).
+    aesccm256: Option<String>,
+    /// Key ID by which tokens indicate the use of `aesccm256`.
+    kid: Option<String>,
+    /// Public key of the AS for tokens signed with ES256 (COSE algorithm -7).
+    es256: Option<Es256Key>,
+    /// Audience value by which signed tokens need to address this device.
+    audience: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Es256Key {
+    x: String,
+    y: String,
 }
 
 #[derive(Debug, Deserialize)]
@@ -48,6 +72,25 @@ impl Permission {
     }
 }
 
+/// Parses a byte string given in CBOR Diagnostic Notation (EDN), e.g. `h'0102'`.
+fn edn_bytes(edn: &str) -> Vec<u8> {
+    let cbor = cbor_edn::StandaloneItem::parse(edn)
+        .expect("key is not valid CBOR Diagnostic Notation (EDN)")
+        .to_cbor()
+        .expect("CBOR Diagnostic Notation (EDN) is not expressible in CBOR");
+    minicbor::Decoder::new(&cbor)
+        .bytes()
+        .expect("key is not a byte string")
+        .to_vec()
+}
+
+/// Like [`edn_bytes`], but for fixed length keys.
+fn edn_key(edn: &str) -> [u8; 32] {
+    edn_bytes(edn)
+        .try_into()
+        .expect("keys need to be 32 bytes long")
+}
+
 impl SinglePermission {
     /// The `Tperm` unsigned integer representation of the REST-specific AIF model described in
     /// RFC9237.
@@ -74,29 +117,77 @@ fn main() {
     }
 
     build_rs::output::rerun_if_env_changed("PEERS_YML");
-    let peers_yml = std::path::PathBuf::from(std::env::var("PEERS_YML").unwrap());
-
-    build_rs::output::rerun_if_changed(&peers_yml);
-    let peers_file = std::fs::File::open(&peers_yml)
-        .map_err(|e| {
-            format!(
-                "{} while opening {} inside {}",
-                e,
-                peers_yml.display(),
-                std::env::current_dir().unwrap().display()
-            )
-        })
-        .expect("no peers.yml usable in specified location");
-
-    let peers: Vec<Peer> = serde_yaml::from_reader(peers_file).expect("failed to parse peers.yml");
+    let peers: Vec<Peer> = match std::env::var("PEERS_YML") {
+        Ok(peers_yml) => read_peers(&std::path::PathBuf::from(peers_yml)),
+        // Host tests are run through `cargo test`, without laze providing a peers file.
+        Err(_) if build_rs::input::cargo_feature("_test") => Vec::new(),
+        Err(e) => panic!("PEERS_YML: {e}"),
+    };
 
     let mut unauthenticated_scope = None;
     let mut chain_once_per_kccs = String::new();
+    let mut chain_once_per_symmetric_as = String::new();
+    let mut chain_once_per_asymmetric_as = String::new();
     for peer in peers {
+        if let Some(authorization_server) = peer.authorization_server {
+            assert!(
+                peer.kccs.is_none() && peer.from.is_none() && peer.scope.is_none(),
+                "An `as: ...` record can not have other keys; scopes are granted by the AS's tokens."
+            );
+
+            if let Some(key) = authorization_server.aesccm256 {
+                let key = edn_key(&key);
+                let kid = match authorization_server.kid {
+                    Some(kid) => format!("Some(&{:?} as &[u8])", edn_bytes(&kid)),
+                    None => "None".to_string(),
+                };
+                write!(
+                    chain_once_per_symmetric_as,
+                    ".chain(core::iter::once(({kid},
+                            coapcore::seccfg::ConfigBuilder::new().with_aif_symmetric_as_aesccm256({key:?}),
+                            )))"
+                )
+                .expect("writing to String is infallible");
+            } else {
+                assert!(
+                    authorization_server.kid.is_none(),
+                    "`kid` only applies to `aesccm256` keys."
+                );
+            }
+
+            if let Some(Es256Key { x, y }) = authorization_server.es256 {
+                let (x, y) = (edn_key(&x), edn_key(&y));
+                let audience = authorization_server
+                    .audience
+                    .expect("Signed tokens require an `audience` to be set.");
+                assert!(
+                    audience.len() <= 8,
+                    "Audience values are limited to 8 bytes."
+                );
+                write!(
+                    chain_once_per_asymmetric_as,
+                    ".chain(core::iter::once(
+                            coapcore::seccfg::ConfigBuilder::new().with_aif_asymmetric_es256({x:?}, {y:?}, heapless::String::try_from({audience:?}).unwrap()),
+                            ))"
+                )
+                .expect("writing to String is infallible");
+            } else {
+                assert!(
+                    authorization_server.audience.is_none(),
+                    "`audience` only applies to `es256` keys."
+                );
+            }
+
+            continue;
+        }
+
         // FIXME: Should we pre-parse the KCCS and have the parsed credentials as const in flash? Or
         // just parsed enough that there is no CBOR parsing but credential and material point to
         // overlapping slices?
-        let scope = match peer.scope {
+        let scope = match peer
+            .scope
+            .expect("Every `kccs: ...` or `from: ...` record needs a `scope: ...`.")
+        {
             Scope::KnownScope(KnownScope::AllowAll) => {
                 "coapcore::scope::UnionScope::AllowAll".to_string()
             }
@@ -139,13 +230,15 @@ fn main() {
             }
             _ => {
                 panic!(
-                    "Every peer record needs to have either a `kccs: ...` or a `from: unauthenticated` key."
+                    "Every peer record needs to have either a `kccs: ...`, a `from: unauthenticated` or an `as: ...` key."
                 )
             }
         }
     }
 
     let unauthenticated_scope = unauthenticated_scope.unwrap_or("None".to_string());
+    let parses_tokens =
+        !chain_once_per_symmetric_as.is_empty() || !chain_once_per_asymmetric_as.is_empty();
 
     let peers_data = format!(
         "
@@ -157,8 +250,36 @@ fn main() {
         pub(super) fn unauthenticated_scope() -> Option<coapcore::scope::UnionScope> {{
             {unauthenticated_scope}
         }}
+
+        pub(super) const PARSES_TOKENS: bool = {parses_tokens};
+
+        pub(super) fn symmetric_issuers() -> impl Iterator<Item=(Option<&'static [u8]>, coapcore::seccfg::ConfigBuilder)> {{
+            core::iter::empty()
+                {chain_once_per_symmetric_as}
+        }}
+
+        pub(super) fn asymmetric_issuers() -> impl Iterator<Item=coapcore::seccfg::ConfigBuilder> {{
+            core::iter::empty()
+                {chain_once_per_asymmetric_as}
+        }}
     ");
 
     let peers_file = build_rs::input::out_dir().join("peers.rs");
     std::fs::write(peers_file, peers_data).unwrap();
 }
+
+fn read_peers(peers_yml: &std::path::Path) -> Vec<Peer> {
+    build_rs::output::rerun_if_changed(peers_yml);
+    let peers_file = std::fs::File::open(peers_yml)
+        .map_err(|e| {
+            format!(
+                "{} while opening {} inside {}",
+                e,
+                peers_yml.display(),
+                std::env::current_dir().unwrap().display()
+            )
+        })
+        .expect("no peers.yml usable in specified location");
+
+    serde_yaml::from_reader(peers_file).expect("failed to parse peers.yml")
+}
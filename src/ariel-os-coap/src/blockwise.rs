@@ -0,0 +1,894 @@
+//! Block-wise transfer ([RFC7959](https://www.rfc-editor.org/rfc/rfc7959.html)) of
+//! representations that do not fit into a single message.
+//!
+//! On the server side, [`BlockwiseGet`] serves a large [`Representation`] in Block2 blocks, and
+//! [`BlockwiseUpload`] passes Block1 blocks of a request payload to a [`Sink`]. On the client side,
+//! [`get()`] and [`upload()`] perform block-wise transfers through any [`coap_request::Stack`],
+//! including the one returned by [`coap_client()`][crate::coap_client]`.to(..)`.
+//!
+//! Block options are end-to-end options: Under OSCORE, they are protected along with the payload.
+//! The server side handlers thus work unmodified behind [`coapcore`], and the client side functions
+//! work through a [`coapcore::client::ProtectedStack`] as well.
+//!
+//! Blocks are at most [`MAX_BLOCK_SIZE`] long, which leaves room for options and for the OSCORE
+//! overhead in the 1152-byte messages used throughout the stack.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! static FIRMWARE_INFO: &[u8] = include_bytes!("large.txt");
+//!
+//! let handler = new_dispatcher().at(&["info"], BlockwiseGet::new(FIRMWARE_INFO));
+//! ```
+
+use coap_message::{
+    Code as _, MessageOption as _, MinimalWritableMessage, MutableWritableMessage,
+    OptionNumber as _, ReadableMessage,
+};
+use coap_message_utils::{Error as CoAPError, OptionsExt as _};
+use coap_numbers::{code, option};
+use embassy_time::{Duration, Instant};
+
+/// Size exponent of the largest blocks that are sent, and that are requested.
+const MAX_SZX: u8 = 5;
+
+/// Length of the largest blocks that are sent, and that are requested.
+pub const MAX_BLOCK_SIZE: usize = 16 << MAX_SZX;
+
+/// Value of a Block1 or Block2 option.
+#[doc(hidden)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockValue {
+    num: u32,
+    more: bool,
+    szx: u8,
+}
+
+impl BlockValue {
+    /// Largest block number that can be expressed in an option.
+    const MAX_NUM: u32 = (1 << 20) - 1;
+
+    /// Parses an option value, failing on values that are too long or use the reserved size.
+    fn parse(value: &[u8]) -> Option<Self> {
+        if value.len() > 3 {
+            return None;
+        }
+        let raw = value
+            .iter()
+            .fold(0u32, |raw, byte| (raw << 8) | u32::from(*byte));
+        let szx = value.last().map_or(0, |last| last & 0x7);
+        if szx == 7 {
+            return None;
+        }
+        Some(Self {
+            num: raw >> 4,
+            more: raw & 0x8 != 0,
+            szx,
+        })
+    }
+
+    /// The block of size `szx` that starts at `offset`, if `offset` is aligned to the block size.
+    fn at(offset: usize, szx: u8, more: bool) -> Option<Self> {
+        let size = 16 << szx;
+        if offset % size != 0 {
+            return None;
+        }
+        let num = u32::try_from(offset / size)
+            .ok()
+            .filter(|num| *num <= Self::MAX_NUM)?;
+        Some(Self { num, more, szx })
+    }
+
+    fn size(self) -> usize {
+        16 << self.szx
+    }
+
+    fn offset(self) -> usize {
+        self.num as usize * self.size()
+    }
+
+    /// The same position expressed in blocks of at most [`MAX_BLOCK_SIZE`].
+    fn limited(self) -> Self {
+        let shift = self.szx.saturating_sub(MAX_SZX);
+        Self {
+            num: self.num << shift,
+            szx: self.szx - shift,
+            ..self
+        }
+    }
+
+    fn encode(self) -> u32 {
+        (self.num << 4) | (u32::from(self.more) << 3) | u32::from(self.szx)
+    }
+}
+
+/// Extracts the Block option `number` from `request`, and checks that all other critical options
+/// besides Uri-Path were processed.
+fn extract_block<M: ReadableMessage>(
+    request: &M,
+    number: u16,
+) -> Result<Option<BlockValue>, CoAPError> {
+    let mut block = None;
+    request
+        .options()
+        .filter(|o| {
+            if o.number() == number {
+                block = Some(BlockValue::parse(o.value()));
+            }
+            o.number() != number && o.number() != option::URI_PATH
+        })
+        .ignore_elective_others()?;
+    block
+        .map(|parsed| parsed.ok_or_else(|| CoAPError::bad_option(number)))
+        .transpose()
+}
+
+/// A representation that is served in blocks by a [`BlockwiseGet`].
+///
+/// The representation should not change while clients are fetching it.
+pub trait Representation {
+    /// Total length of the representation in bytes.
+    fn total_len(&mut self) -> usize;
+
+    /// Fills `buffer` with the bytes of the representation starting at `offset`.
+    ///
+    /// The requested range is always within the [total length][Self::total_len].
+    fn read_at(&mut self, offset: usize, buffer: &mut [u8]);
+
+    /// Content-Format of the representation, if any is indicated.
+    fn content_format(&self) -> Option<u16> {
+        None
+    }
+}
+
+impl Representation for &[u8] {
+    fn total_len(&mut self) -> usize {
+        self.len()
+    }
+
+    fn read_at(&mut self, offset: usize, buffer: &mut [u8]) {
+        #[allow(
+            clippy::indexing_slicing,
+            reason = "the range is guaranteed by the caller"
+        )]
+        buffer.copy_from_slice(&self[offset..offset + buffer.len()]);
+    }
+}
+
+/// A handler that serves a [`Representation`] on GET, split into Block2 blocks when it exceeds
+/// [`MAX_BLOCK_SIZE`] or the block size requested by the client.
+pub struct BlockwiseGet<R> {
+    representation: R,
+}
+
+impl<R: Representation> BlockwiseGet<R> {
+    /// Creates a handler serving `representation`.
+    pub fn new(representation: R) -> Self {
+        Self { representation }
+    }
+}
+
+impl<R: Representation> coap_handler::Handler for BlockwiseGet<R> {
+    type RequestData = BlockValue;
+    type ExtractRequestError = CoAPError;
+    type BuildResponseError<M: MinimalWritableMessage> = M::UnionError;
+
+    fn extract_request_data<M: ReadableMessage>(
+        &mut self,
+        request: &M,
+    ) -> Result<Self::RequestData, Self::ExtractRequestError> {
+        let method: u8 = request.code().into();
+        if method != code::GET {
+            return Err(CoAPError::method_not_allowed());
+        }
+
+        let block = extract_block(request, option::BLOCK2)?.map_or(
+            BlockValue {
+                num: 0,
+                more: false,
+                szx: MAX_SZX,
+            },
+            BlockValue::limited,
+        );
+
+        let total_len = self.representation.total_len();
+        if block.num != 0 && block.offset() >= total_len {
+            return Err(CoAPError::bad_option(option::BLOCK2));
+        }
+
+        Ok(BlockValue {
+            more: block.offset() + block.size() < total_len,
+            ..block
+        })
+    }
+
+    fn estimate_length(&mut self, request: &Self::RequestData) -> usize {
+        // Content-Format, Block2 and Size2 options take up to 4 bytes each.
+        request.size() + 12
+    }
+
+    fn build_response<M: MutableWritableMessage>(
+        &mut self,
+        response: &mut M,
+        block: Self::RequestData,
+    ) -> Result<(), Self::BuildResponseError<M>> {
+        response.set_code(M::Code::new(code::CONTENT)?);
+        if let Some(content_format) = self.representation.content_format() {
+            response.add_option_uint(
+                M::OptionNumber::new(option::CONTENT_FORMAT)?,
+                content_format,
+            )?;
+        }
+
+        let total_len = self.representation.total_len();
+        if block.num != 0 || block.more {
+            response.add_option_uint(M::OptionNumber::new(option::BLOCK2)?, block.encode())?;
+        }
+        if block.num == 0 && block.more {
+            response.add_option_uint(
+                M::OptionNumber::new(option::SIZE2)?,
+                u32::try_from(total_len).unwrap_or(u32::MAX),
+            )?;
+        }
+
+        let len = total_len.saturating_sub(block.offset()).min(block.size());
+        let payload = response.payload_mut_with_len(len)?;
+        self.representation.read_at(block.offset(), payload);
+        Ok(())
+    }
+}
+
+/// Destination of uploads through a [`BlockwiseUpload`].
+pub trait Sink {
+    /// Stores `data` at `offset` of the upload.
+    ///
+    /// Blocks are passed in sequence; a block at offset 0 starts a new upload, discarding any
+    /// upload that was not finished.
+    ///
+    /// # Errors
+    ///
+    /// The error is sent as a response to the request, and the upload is aborted.
+    fn write_at(&mut self, offset: usize, data: &[u8]) -> Result<(), CoAPError>;
+
+    /// Completes an upload of `total_len` bytes.
+    ///
+    /// # Errors
+    ///
+    /// The error is sent as a response to the request carrying the last block.
+    fn finish(&mut self, total_len: usize) -> Result<(), CoAPError>;
+}
+
+/// Time after which an upload whose next block did not arrive is abandoned, so that other clients
+/// can upload (`MAX_TRANSMIT_WAIT` of RFC7252).
+const UPLOAD_TIMEOUT: Duration = Duration::from_secs(93);
+
+/// Option number of the Request-Tag option ([RFC9175](https://www.rfc-editor.org/rfc/rfc9175.html)).
+const REQUEST_TAG: u16 = 292;
+
+type RequestTag = heapless::Vec<u8, 8>;
+
+/// An upload in progress.
+struct Upload {
+    /// Offset of the block that continues the upload.
+    expected: usize,
+    /// Request-Tag of the blocks of the upload (empty if they carry none).
+    request_tag: RequestTag,
+    /// Time at which the latest block was received.
+    last_block: Instant,
+}
+
+/// A handler that accepts PUT and POST payloads of any size, passing them to a [`Sink`] block by
+/// block.
+///
+/// Only one upload is processed at a time. Blocks continue the upload in progress if they carry
+/// the same Request-Tag option ([RFC9175](https://www.rfc-editor.org/rfc/rfc9175.html)) as its
+/// first block; other uploads are rejected with 5.03 Service Unavailable until it is finished, or
+/// until its next block did not arrive for 93 seconds. Clients that do not send a Request-Tag can
+/// not be told apart, and a block at offset 0 from any of them restarts the upload in progress.
+pub struct BlockwiseUpload<S> {
+    sink: S,
+    current: Option<Upload>,
+}
+
+impl<S: Sink> BlockwiseUpload<S> {
+    /// Creates a handler passing uploaded data into `sink`.
+    pub fn new(sink: S) -> Self {
+        Self {
+            sink,
+            current: None,
+        }
+    }
+
+    /// Processes a request received at `now`.
+    fn process<M: ReadableMessage>(
+        &mut self,
+        request: &M,
+        now: Instant,
+    ) -> Result<UploadOutcome, CoAPError> {
+        let method: u8 = request.code().into();
+        if method != code::PUT && method != code::POST {
+            return Err(CoAPError::method_not_allowed());
+        }
+
+        let payload = request.payload();
+        let block = extract_block(request, option::BLOCK1)?;
+        let request_tag = request
+            .options()
+            .find(|o| o.number() == REQUEST_TAG)
+            .map_or(Ok(RequestTag::new()), |o| RequestTag::from_slice(o.value()))
+            .map_err(|()| CoAPError::bad_option(REQUEST_TAG))?;
+
+        if let Some(current) = &self.current {
+            let idle = now.saturating_duration_since(current.last_block);
+            if idle >= UPLOAD_TIMEOUT {
+                self.current = None;
+            } else if current.request_tag != request_tag {
+                let remaining = (UPLOAD_TIMEOUT - idle).as_millis().div_ceil(1000);
+                return Ok(UploadOutcome::Busy(
+                    u32::try_from(remaining).unwrap_or(u32::MAX),
+                ));
+            }
+        }
+
+        let Some(block) = block else {
+            self.current = None;
+            self.sink.write_at(0, payload)?;
+            self.sink.finish(payload.len())?;
+            return Ok(UploadOutcome::Changed(None));
+        };
+
+        let offset = block.offset();
+        if offset != 0
+            && self
+                .current
+                .as_ref()
+                .is_none_or(|current| current.expected != offset)
+        {
+            return Ok(UploadOutcome::Incomplete);
+        }
+        if block.more && payload.len() != block.size() {
+            return Err(CoAPError::bad_request());
+        }
+
+        self.current = None;
+        self.sink.write_at(offset, payload)?;
+        if block.more {
+            self.current = Some(Upload {
+                expected: offset + payload.len(),
+                request_tag,
+                last_block: now,
+            });
+            Ok(UploadOutcome::Continue(block))
+        } else {
+            self.sink.finish(offset + payload.len())?;
+            Ok(UploadOutcome::Changed(Some(block)))
+        }
+    }
+}
+
+/// Outcome of a request to a [`BlockwiseUpload`].
+#[doc(hidden)]
+#[derive(Debug, PartialEq, Eq)]
+pub enum UploadOutcome {
+    /// A block was stored; the client continues with the next one.
+    Continue(BlockValue),
+    /// The upload is complete, possibly after its last block.
+    Changed(Option<BlockValue>),
+    /// The block does not continue the upload in progress.
+    Incomplete,
+    /// Another upload is in progress, which is abandoned after the given number of seconds at the
+    /// latest.
+    Busy(u32),
+}
+
+impl<S: Sink> coap_handler::Handler for BlockwiseUpload<S> {
+    type RequestData = UploadOutcome;
+    type ExtractRequestError = CoAPError;
+    type BuildResponseError<M: MinimalWritableMessage> = M::UnionError;
+
+    fn extract_request_data<M: ReadableMessage>(
+        &mut self,
+        request: &M,
+    ) -> Result<Self::RequestData, Self::ExtractRequestError> {
+        self.process(request, Instant::now())
+    }
+
+    fn estimate_length(&mut self, _request: &Self::RequestData) -> usize {
+        5
+    }
+
+    fn build_response<M: MutableWritableMessage>(
+        &mut self,
+        response: &mut M,
+        request: Self::RequestData,
+    ) -> Result<(), Self::BuildResponseError<M>> {
+        let (response_code, block) = match request {
+            UploadOutcome::Continue(block) => (code::CONTINUE, Some(block)),
+            UploadOutcome::Changed(block) => (code::CHANGED, block),
+            UploadOutcome::Incomplete => (code::REQUEST_ENTITY_INCOMPLETE, None),
+            UploadOutcome::Busy(max_age) => {
+                response.set_code(M::Code::new(code::SERVICE_UNAVAILABLE)?);
+                response.add_option_uint(M::OptionNumber::new(option::MAX_AGE)?, max_age)?;
+                return Ok(());
+            }
+        };
+        response.set_code(M::Code::new(response_code)?);
+        if let Some(block) = block {
+            response.add_option_uint(M::OptionNumber::new(option::BLOCK1)?, block.encode())?;
+        }
+        Ok(())
+    }
+}
+
+/// Errors that can occur in a block-wise transfer from the client side.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum BlockwiseError<T> {
+    /// A request could not be sent, or no response was received.
+    Transport(T),
+    /// The server responded with a block that does not continue the transfer.
+    Mismatch,
+}
+
+/// Fetches the representation of the resource at `path` through `stack` with GET, using Block2 if
+/// the server splits it up.
+///
+/// The payload is passed to `sink` block by block along with its offset, as long as the server
+/// responds successfully; the final response code is returned (e.g., `0x45` for 2.05 Content).
+///
+/// # Errors
+///
+/// This errors if any request fails on the transport level, or if the server responds with blocks
+/// out of sequence.
+pub async fn get<S: coap_request::Stack>(
+    stack: &mut S,
+    path: &str,
+    mut sink: impl FnMut(usize, &[u8]),
+) -> Result<u8, BlockwiseError<S::TransportError>> {
+    let mut block = BlockValue {
+        num: 0,
+        more: false,
+        szx: MAX_SZX,
+    };
+    loop {
+        let step = stack
+            .request(BlockwiseRequest {
+                code: code::GET,
+                path,
+                block: Some((option::BLOCK2, block)),
+                payload: &[],
+                sink: &mut sink,
+            })
+            .await
+            .map_err(BlockwiseError::Transport)?;
+
+        match step.block {
+            Some(received) if step.code >> 5 == 2 && received.more => {
+                if received.offset() != block.offset() {
+                    return Err(BlockwiseError::Mismatch);
+                }
+                // The server may pick smaller blocks than requested; we follow its choice.
+                block = BlockValue::at(received.offset() + received.size(), received.szx, false)
+                    .ok_or(BlockwiseError::Mismatch)?;
+            }
+            _ => return Ok(step.code),
+        }
+    }
+}
+
+/// Sends `payload` to the resource at `path` through `stack` with the request method `code` (e.g.,
+/// `3` for PUT), using Block1 if it exceeds [`MAX_BLOCK_SIZE`].
+///
+/// The response code to the last block is returned, or the first response code that is not 2.31
+/// Continue.
+///
+/// # Errors
+///
+/// This errors if any request fails on the transport level, or if the payload is too large to be
+/// expressed in blocks.
+pub async fn upload<S: coap_request::Stack>(
+    stack: &mut S,
+    code: u8,
+    path: &str,
+    payload: &[u8],
+) -> Result<u8, BlockwiseError<S::TransportError>> {
+    let mut offset = 0;
+    let mut szx = MAX_SZX;
+    loop {
+        let size = 16 << szx;
+        let more = payload.len() - offset > size;
+        let block = BlockValue::at(offset, szx, more).ok_or(BlockwiseError::Mismatch)?;
+        let end = if more { offset + size } else { payload.len() };
+        #[allow(
+            clippy::indexing_slicing,
+            reason = "offset and end are within the payload"
+        )]
+        let chunk = &payload[offset..end];
+
+        let step = stack
+            .request(BlockwiseRequest {
+                code,
+                path,
+                // Small payloads are sent without a Block1 option.
+                block: (offset != 0 || more).then_some((option::BLOCK1, block)),
+                payload: chunk,
+                sink: &mut discard,
+            })
+            .await
+            .map_err(BlockwiseError::Transport)?;
+
+        if !more || step.code != code::CONTINUE {
+            return Ok(step.code);
+        }
+        // The server may ask for smaller blocks, which are then used for the remaining payload.
+        if let Some(received) = step.block {
+            szx = szx.min(received.szx);
+        }
+        offset = end;
+    }
+}
+
+/// Sink for the response payloads of uploads, which are not processed.
+fn discard(_offset: usize, _data: &[u8]) {}
+
+/// Result of a single request of a block-wise transfer.
+struct Step {
+    code: u8,
+    /// The block option in the response that corresponds to the one sent.
+    block: Option<BlockValue>,
+}
+
+/// A single request of a block-wise transfer.
+struct BlockwiseRequest<'a, F: FnMut(usize, &[u8])> {
+    code: u8,
+    path: &'a str,
+    /// Option number and value of the Block option to send.
+    block: Option<(u16, BlockValue)>,
+    payload: &'a [u8],
+    /// Receiver of successful response payloads.
+    sink: &'a mut F,
+}
+
+impl<S: coap_request::Stack, F: FnMut(usize, &[u8])> coap_request::Request<S>
+    for BlockwiseRequest<'_, F>
+{
+    type Output = Step;
+    type Carry = ();
+
+    async fn build_request(
+        &mut self,
+        request: &mut S::RequestMessage<'_>,
+    ) -> Result<(), S::RequestUnionError> {
+        build(self, request)
+    }
+
+    async fn process_response(&mut self, response: &S::ResponseMessage<'_>, _carry: ()) -> Step {
+        let response_code: u8 = response.code().into();
+        let number = self.block.map_or(option::BLOCK2, |(number, _)| number);
+        let block = response
+            .options()
+            .find(|o| o.number() == number)
+            .and_then(|o| BlockValue::parse(o.value()));
+
+        if response_code >> 5 == 2 && number == option::BLOCK2 {
+            (self.sink)(block.map_or(0, BlockValue::offset), response.payload());
+        }
+
+        Step {
+            code: response_code,
+            block,
+        }
+    }
+}
+
+fn build<F: FnMut(usize, &[u8]), M: MinimalWritableMessage>(
+    request: &BlockwiseRequest<'_, F>,
+    message: &mut M,
+) -> Result<(), M::UnionError> {
+    message.set_code(M::Code::new(request.code)?);
+    let uri_path = M::OptionNumber::new(option::URI_PATH)?;
+    let path = request.path.strip_prefix('/').unwrap_or(request.path);
+    if !path.is_empty() {
+        for segment in path.split('/') {
+            message.add_option(uri_path, segment.as_bytes())?;
+        }
+    }
+    if let Some((number, block)) = request.block {
+        message.add_option_uint(M::OptionNumber::new(number)?, block.encode())?;
+        // Announce the total size along with the first block of an upload.
+        if number == option::BLOCK1 && block.num == 0 {
+            message.add_option_uint(
+                M::OptionNumber::new(option::SIZE1)?,
+                u32::try_from(request.payload.len()).unwrap_or(u32::MAX),
+            )?;
+        }
+    }
+    message.set_payload(request.payload)?;
+    Ok(())
+}
+
+#[cfg(test)]
+#[allow(clippy::indexing_slicing, reason = "panicking is fine in tests")]
+mod test {
+    use super::*;
+
+    use coap_handler::Handler as _;
+    use coap_message_implementations::{inmemory, inmemory_write};
+
+    /// An encoded request.
+    struct Request {
+        code: u8,
+        buffer: [u8; 128],
+        len: usize,
+    }
+
+    impl Request {
+        fn new(
+            code: u8,
+            block: Option<(u16, BlockValue)>,
+            request_tag: Option<&[u8]>,
+            payload: &[u8],
+        ) -> Self {
+            let mut buffer = [0; 128];
+            let mut written_code = 0;
+            let mut message = inmemory_write::Message::new(&mut written_code, &mut buffer);
+            message.set_code(code);
+            if let Some((number, block)) = block {
+                message.add_option_uint(number, block.encode()).unwrap();
+            }
+            if let Some(request_tag) = request_tag {
+                message.add_option(REQUEST_TAG, request_tag).unwrap();
+            }
+            message.set_payload(payload).unwrap();
+            let len = message.finish();
+            Self { code, buffer, len }
+        }
+
+        fn message(&self) -> inmemory::Message<'_> {
+            inmemory::Message::new(self.code, &self.buffer[..self.len])
+        }
+    }
+
+    fn block(num: u32, more: bool, szx: u8) -> BlockValue {
+        BlockValue { num, more, szx }
+    }
+
+    #[derive(Default)]
+    struct Recorder {
+        data: heapless::Vec<u8, 256>,
+        finished: Option<usize>,
+    }
+
+    impl Sink for Recorder {
+        fn write_at(&mut self, offset: usize, data: &[u8]) -> Result<(), CoAPError> {
+            self.data.truncate(offset);
+            assert_eq!(self.data.len(), offset, "blocks are written in sequence");
+            self.data.extend_from_slice(data).unwrap();
+            self.finished = None;
+            Ok(())
+        }
+
+        fn finish(&mut self, total_len: usize) -> Result<(), CoAPError> {
+            self.finished = Some(total_len);
+            Ok(())
+        }
+    }
+
+    /// Sends block `num` of 16-byte blocks of `data` to `upload` at `now` seconds.
+    fn put(
+        upload: &mut BlockwiseUpload<Recorder>,
+        data: &[u8],
+        num: u32,
+        request_tag: Option<&[u8]>,
+        now: u64,
+    ) -> Result<UploadOutcome, CoAPError> {
+        let start = num as usize * 16;
+        let end = data.len().min(start + 16);
+        let more = end < data.len();
+        let request = Request::new(
+            code::PUT,
+            Some((option::BLOCK1, block(num, more, 0))),
+            request_tag,
+            &data[start..end],
+        );
+        upload.process(&request.message(), Instant::from_secs(now))
+    }
+
+    const DATA: &[u8] = b"0123456789abcdef0123456789ABCDEF01234567";
+
+    #[test]
+    fn upload_in_order() {
+        let mut upload = BlockwiseUpload::new(Recorder::default());
+
+        assert_eq!(
+            put(&mut upload, DATA, 0, None, 0).unwrap(),
+            UploadOutcome::Continue(block(0, true, 0))
+        );
+        assert_eq!(
+            put(&mut upload, DATA, 1, None, 1).unwrap(),
+            UploadOutcome::Continue(block(1, true, 0))
+        );
+        assert_eq!(upload.sink.finished, None);
+        assert_eq!(
+            put(&mut upload, DATA, 2, None, 2).unwrap(),
+            UploadOutcome::Changed(Some(block(2, false, 0)))
+        );
+        assert_eq!(upload.sink.data, DATA);
+        assert_eq!(upload.sink.finished, Some(DATA.len()));
+
+        // Small payloads are sent without a Block1 option.
+        let request = Request::new(code::POST, None, None, b"small");
+        assert_eq!(
+            upload
+                .process(&request.message(), Instant::from_secs(3))
+                .unwrap(),
+            UploadOutcome::Changed(None)
+        );
+        assert_eq!(upload.sink.data, b"small");
+        assert_eq!(upload.sink.finished, Some(5));
+    }
+
+    #[test]
+    fn upload_out_of_order() {
+        let mut upload = BlockwiseUpload::new(Recorder::default());
+
+        // No upload is in progress to continue.
+        assert_eq!(
+            put(&mut upload, DATA, 1, None, 0).unwrap(),
+            UploadOutcome::Incomplete
+        );
+        assert!(upload.sink.data.is_empty());
+
+        put(&mut upload, DATA, 0, None, 0).unwrap();
+        // A block is skipped.
+        assert_eq!(
+            put(&mut upload, DATA, 2, None, 1).unwrap(),
+            UploadOutcome::Incomplete
+        );
+        // A block is repeated after the next one was received.
+        put(&mut upload, DATA, 1, None, 1).unwrap();
+        assert_eq!(
+            put(&mut upload, DATA, 1, None, 2).unwrap(),
+            UploadOutcome::Incomplete
+        );
+        assert_eq!(upload.sink.data, &DATA[..32]);
+        assert_eq!(upload.sink.finished, None);
+
+        // A block that is not the last needs to fill the block size.
+        let request = Request::new(
+            code::PUT,
+            Some((option::BLOCK1, block(2, true, 0))),
+            None,
+            b"short",
+        );
+        assert!(
+            upload
+                .process(&request.message(), Instant::from_secs(3))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn upload_other_client() {
+        let mut upload = BlockwiseUpload::new(Recorder::default());
+        let other = b"another upload that is in the way";
+
+        put(&mut upload, DATA, 0, Some(b"a"), 0).unwrap();
+
+        // Other uploads, with or without Block1, have to wait.
+        assert_eq!(
+            put(&mut upload, other, 0, Some(b"b"), 10).unwrap(),
+            UploadOutcome::Busy(83)
+        );
+        assert_eq!(
+            put(&mut upload, other, 0, None, 10).unwrap(),
+            UploadOutcome::Busy(83)
+        );
+        let request = Request::new(code::PUT, None, None, b"small");
+        assert_eq!(
+            upload
+                .process(&request.message(), Instant::from_secs(10))
+                .unwrap(),
+            UploadOutcome::Busy(83)
+        );
+        assert_eq!(upload.sink.data, &DATA[..16]);
+
+        // The upload in progress is not disturbed.
+        assert_eq!(
+            put(&mut upload, DATA, 1, Some(b"a"), 20).unwrap(),
+            UploadOutcome::Continue(block(1, true, 0))
+        );
+
+        // Once it is abandoned, others can upload.
+        assert_eq!(
+            put(&mut upload, other, 0, Some(b"b"), 20 + 93).unwrap(),
+            UploadOutcome::Continue(block(0, true, 0))
+        );
+        assert_eq!(
+            put(&mut upload, DATA, 2, Some(b"a"), 20 + 93).unwrap(),
+            UploadOutcome::Busy(93)
+        );
+        assert_eq!(upload.sink.data, &other[..16]);
+    }
+
+    /// Requests a block of `representation`, returning the response's options and payload.
+    fn get(
+        representation: &[u8],
+        block: Option<BlockValue>,
+    ) -> Result<
+        (
+            heapless::Vec<(u16, heapless::Vec<u8, 4>), 4>,
+            heapless::Vec<u8, 512>,
+        ),
+        CoAPError,
+    > {
+        let mut handler = BlockwiseGet::new(representation);
+        let request = Request::new(
+            code::GET,
+            block.map(|block| (option::BLOCK2, block)),
+            None,
+            &[],
+        );
+        let block = handler.extract_request_data(&request.message())?;
+
+        let mut buffer = [0u8; 600];
+        let mut response_code = 0;
+        let mut response = inmemory_write::Message::new(&mut response_code, &mut buffer);
+        handler.build_response(&mut response, block).unwrap();
+        let len = response.finish();
+        let response = inmemory::Message::new(response_code, &buffer[..len]);
+        assert_eq!(response_code, code::CONTENT);
+
+        let options = response
+            .options()
+            .map(|o| (o.number(), heapless::Vec::from_slice(o.value()).unwrap()))
+            .collect();
+        Ok((
+            options,
+            heapless::Vec::from_slice(response.payload()).unwrap(),
+        ))
+    }
+
+    #[test]
+    fn block2_slicing() {
+        let mut data = [0u8; 2000];
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = i.to_le_bytes()[0];
+        }
+
+        // Representations that fit a block are sent without Block2.
+        let (options, payload) = get(&data[..100], None).unwrap();
+        assert!(options.is_empty());
+        assert_eq!(payload, &data[..100]);
+
+        // The first block announces the total size.
+        let (options, payload) = get(&data[..100], Some(block(0, false, 1))).unwrap();
+        assert_eq!(
+            options,
+            [
+                (option::BLOCK2, heapless::Vec::from_slice(&[0x09]).unwrap()),
+                (option::SIZE2, heapless::Vec::from_slice(&[100]).unwrap()),
+            ]
+        );
+        assert_eq!(payload, &data[..32]);
+
+        // The last block is shorter.
+        let (options, payload) = get(&data[..100], Some(block(3, false, 1))).unwrap();
+        assert_eq!(
+            options,
+            [(option::BLOCK2, heapless::Vec::from_slice(&[0x31]).unwrap())]
+        );
+        assert_eq!(payload, &data[96..100]);
+
+        // Blocks beyond the end are rejected.
+        assert!(get(&data[..100], Some(block(4, false, 1))).is_err());
+
+        // Larger blocks than MAX_BLOCK_SIZE are split up.
+        let (options, payload) = get(&data, Some(block(1, false, 6))).unwrap();
+        assert_eq!(
+            options,
+            [(option::BLOCK2, heapless::Vec::from_slice(&[0x2d]).unwrap())]
+        );
+        assert_eq!(payload, &data[1024..1536]);
+    }
+}
@@ -0,0 +1,55 @@
+//! Security events reported by the CoAP server.
+//!
+//! The server's [`coapcore::OscoreEdhocHandler`] reports [`SecurityEvent`]s (failed EDHOC
+//! handshakes, unknown credentials, rejected tokens and requests, denied access …), which
+//! applications can receive through [`security_events()`], eg. to keep audit records or to
+//! throttle misbehaving peers.
+//!
+//! Events are delivered to every subscriber; when a subscriber falls behind by more than
+//! `CONFIG_COAP_SECURITY_EVENT_QUEUE` events, it misses the oldest ones (and is told so by
+//! [`embassy_sync::pubsub::WaitResult::Lagged`]).
+
+use embassy_sync::{
+    blocking_mutex::raw::CriticalSectionRawMutex,
+    pubsub::{PubSubChannel, Subscriber},
+};
+
+pub use coapcore::events::SecurityEvent;
+
+/// Number of events that are retained until all subscribers received them.
+const QUEUE_LEN: usize = ariel_os_utils::usize_from_env_or!(
+    "CONFIG_COAP_SECURITY_EVENT_QUEUE",
+    8,
+    "number of CoAP security events queued for their subscribers"
+);
+
+/// Maximum number of concurrent subscribers.
+const SUBSCRIBERS: usize = ariel_os_utils::usize_from_env_or!(
+    "CONFIG_COAP_SECURITY_EVENT_SUBSCRIBERS",
+    2,
+    "maximum number of concurrent subscribers to CoAP security events"
+);
+
+static EVENTS: PubSubChannel<CriticalSectionRawMutex, SecurityEvent, QUEUE_LEN, SUBSCRIBERS, 0> =
+    PubSubChannel::new();
+
+/// A subscription to the [`SecurityEvent`]s of the CoAP server, obtained through
+/// [`security_events()`].
+pub type SecurityEventSubscriber =
+    Subscriber<'static, CriticalSectionRawMutex, SecurityEvent, QUEUE_LEN, SUBSCRIBERS, 0>;
+
+/// Subscribes to the [`SecurityEvent`]s of the CoAP server.
+///
+/// Only events reported after subscribing are received.
+///
+/// Returns `None` if there are already `CONFIG_COAP_SECURITY_EVENT_SUBSCRIBERS` subscribers.
+pub fn security_events() -> Option<SecurityEventSubscriber> {
+    EVENTS.subscriber().ok()
+}
+
+/// Reports an event to all subscribers.
+///
+/// This does not block: if the queue is full, the oldest event is dropped.
+pub(crate) fn report(event: SecurityEvent) {
+    EVENTS.immediate_publisher().publish_immediate(event);
+}
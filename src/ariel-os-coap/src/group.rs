@@ -0,0 +1,242 @@
+//! Membership in an OSCORE group, so that requests sent to a group of devices (eg. all lights on
+//! a floor) are processed.
+//!
+//! The group's keying material is provisioned into storage through [`provision()`], and used by
+//! the CoAP server from its next start on. The server then joins the multicast groups of All CoAP
+//! Nodes (`ff02::fd`, `ff05::fd` and `224.0.1.187`) as well as the address configured in
+//! `CONFIG_COAP_GROUP_ADDRESS`, and processes requests protected with Group OSCORE as described
+//! in [`coapcore::group`].
+//!
+//! The material is stored as an encrypted blob (see [`ariel_os_storage::blob`]), under a key
+//! derived from the device identity and the `CONFIG_STORAGE_ENCRYPTION_SECRET` build-time secret.
+//! Material that fails authentication (eg. after that secret changed) is ignored, and needs to be
+//! provisioned again.
+//!
+//! The material is encoded as a CBOR array:
+//!
+//! ```cddl
+//! group-material = [
+//!     master_secret: bstr,
+//!     master_salt: bstr,
+//!     group_id: bstr,
+//!     sender_id: bstr,
+//!     sender_credential: bstr, ; CCS containing this device's EC2 P-256 key
+//!     private_key: bstr .size 32,
+//!     scope: AIF-REST, ; requests the group's members may perform on this device
+//!     members: [* [id: bstr, credential: bstr]],
+//! ]
+//! ```
+//!
+//! Sequence numbers for protecting responses are reserved in storage at startup in blocks of
+//! `CONFIG_COAP_GROUP_SEQUENCE_NUMBERS`, so that none is ever used twice; they continue across
+//! provisioning of new material.
+//!
+//! ## Caveats
+//!
+//! * The replay windows of the members are not stored. After every start, each member's first
+//!   request is answered with a 4.01 Unauthorized response carrying an Echo option, and only
+//!   processed once the member repeats it with that option (see [`coapcore::group`]).
+//! * Once the reserved sequence numbers are used up, responses to group requests fail until the
+//!   next start.
+
+use ariel_os_debug::log::{info, warn};
+use ariel_os_storage::blob;
+use coapcore::group::{GroupContext, GroupMaterial};
+use embedded_io_async::{Read as _, Write as _};
+
+/// Key of the encrypted blob in which the group material is stored.
+const MATERIAL_KEY: &str = "ariel-os-coap.group";
+
+/// Key under which the first sequence number that was not reserved yet is stored.
+const SEQUENCE_NUMBER_KEY: &str = "ariel-os-coap.group-seqno";
+
+/// Upper bound of the group material's length.
+const MAX_MATERIAL_LEN: usize = 256
+    + coapcore::group::MAX_MEMBERS
+        * (4 + coapcore::group::MAX_ID_LEN + coapcore::group::MAX_CREDENTIAL_LEN);
+
+/// Number of sequence numbers that are reserved at startup.
+///
+/// Once they are used up, responses to group requests fail until the next startup.
+const SEQUENCE_NUMBER_RESERVATION: u32 = ariel_os_utils::u32_from_env_or!(
+    "CONFIG_COAP_GROUP_SEQUENCE_NUMBERS",
+    1024,
+    "number of Group OSCORE sequence numbers reserved at each startup"
+);
+
+/// Additional multicast address on which group requests are received.
+const GROUP_ADDRESS: &str = ariel_os_utils::str_from_env_or!(
+    "CONFIG_COAP_GROUP_ADDRESS",
+    "",
+    "multicast address of the OSCORE group, in addition to the All CoAP Nodes addresses"
+);
+
+/// Errors that can occur when provisioning group material.
+#[derive(Debug)]
+pub enum ProvisioningError {
+    /// The material can not be decoded, or is not usable (see [`GroupContext::new()`]).
+    InvalidMaterial,
+    /// The material could not be written to storage.
+    Storage(blob::Error),
+}
+
+impl From<blob::Error> for ProvisioningError {
+    fn from(e: blob::Error) -> Self {
+        Self::Storage(e)
+    }
+}
+
+/// Stores the keying material of an OSCORE group, in the format described in the [module level
+/// documentation][self].
+///
+/// This replaces any previously provisioned material; it is used from the next start of the CoAP
+/// server on.
+///
+/// # Errors
+///
+/// This produces an error if the material is invalid, in which case storage is left unchanged,
+/// or if writing to storage failed.
+pub async fn provision(material: &[u8]) -> Result<(), ProvisioningError> {
+    if material.len() > MAX_MATERIAL_LEN || build_context(material, 0..0).is_none() {
+        return Err(ProvisioningError::InvalidMaterial);
+    }
+
+    let mut writer = blob::encrypted_writer(MATERIAL_KEY).await?;
+    writer.write_all(material).await?;
+    writer.finish().await?;
+    Ok(())
+}
+
+/// Removes the keying material of the OSCORE group from storage.
+///
+/// # Errors
+///
+/// This produces an error if accessing storage failed.
+pub async fn remove() -> Result<(), blob::Error> {
+    blob::encrypted_remove(MATERIAL_KEY).await
+}
+
+/// Loads the group's security context from storage, reserving sequence numbers for it.
+///
+/// # Panics
+///
+/// Panics if the storage is not accessible.
+pub(crate) async fn load() -> Option<GroupContext> {
+    let mut reader = match blob::encrypted_reader(MATERIAL_KEY).await {
+        Ok(reader) => reader?,
+        Err(blob::Error::Tampered) => {
+            warn!("Stored OSCORE group material fails authentication, ignoring it.");
+            return None;
+        }
+        Err(_) => panic!("flash error prevents startup"),
+    };
+
+    let mut buffer = [0; MAX_MATERIAL_LEN];
+    let Some(material) = usize::try_from(reader.len())
+        .ok()
+        .and_then(|len| buffer.get_mut(..len))
+    else {
+        warn!("Stored OSCORE group material exceeds the supported size, ignoring it.");
+        return None;
+    };
+    match reader.read_exact(material).await {
+        Ok(()) => (),
+        Err(embedded_io_async::ReadExactError::Other(blob::Error::Tampered)) => {
+            warn!("Stored OSCORE group material fails authentication, ignoring it.");
+            return None;
+        }
+        Err(_) => panic!("flash error prevents startup"),
+    }
+
+    let start: u64 = ariel_os_storage::get(SEQUENCE_NUMBER_KEY)
+        .await
+        .expect("flash error prevents startup")
+        .unwrap_or(0);
+    let end = start.saturating_add(u64::from(SEQUENCE_NUMBER_RESERVATION));
+    // The reservation is persisted before any of the sequence numbers is used, so that they are
+    // not used again after a reboot (cf. RFC8613 Appendix B.1.1).
+    ariel_os_storage::insert(SEQUENCE_NUMBER_KEY, end)
+        .await
+        .expect("flash error prevents startup");
+
+    let context = build_context(material, start..end);
+    if context.is_some() {
+        info!("Joined OSCORE group from storage.");
+    } else {
+        warn!("Stored OSCORE group material is unusable, ignoring it.");
+    }
+    context
+}
+
+/// Joins the multicast groups on which group requests are received.
+pub(crate) fn join_multicast_groups(stack: embassy_net::Stack<'static>) {
+    use embassy_net::IpAddress;
+
+    let configured = match GROUP_ADDRESS {
+        "" => None,
+        address => {
+            let parsed = address.parse::<core::net::IpAddr>().ok();
+            if parsed.is_none() {
+                warn!("CONFIG_COAP_GROUP_ADDRESS is not an IP address, ignoring it.");
+            }
+            parsed.map(IpAddress::from)
+        }
+    };
+
+    for address in [
+        IpAddress::v6(0xff02, 0, 0, 0, 0, 0, 0, 0xfd),
+        IpAddress::v6(0xff05, 0, 0, 0, 0, 0, 0, 0xfd),
+        IpAddress::v4(224, 0, 1, 187),
+    ]
+    .into_iter()
+    .chain(configured)
+    {
+        if stack.join_multicast_group(address).is_err() {
+            warn!("Joining multicast group {} failed.", address);
+        }
+    }
+}
+
+/// Decodes group material and sets up a security context from it.
+fn build_context(material: &[u8], sequence_numbers: core::ops::Range<u64>) -> Option<GroupContext> {
+    let mut decoder = minicbor::Decoder::new(material);
+    if decoder.array().ok()? != Some(8) {
+        return None;
+    }
+    let master_secret = decoder.bytes().ok()?;
+    let master_salt = decoder.bytes().ok()?;
+    let group_id = decoder.bytes().ok()?;
+    let sender_id = decoder.bytes().ok()?;
+    let sender_credential = decoder.bytes().ok()?;
+    let private_key = decoder.bytes().ok()?.try_into().ok()?;
+    let scope_start = decoder.position();
+    decoder.skip().ok()?;
+    let scope = coapcore::scope::AifValue::parse(material.get(scope_start..decoder.position())?)
+        .ok()?
+        .into();
+
+    let mut context = GroupContext::new(
+        &GroupMaterial {
+            master_secret,
+            master_salt,
+            group_id,
+            sender_id,
+            sender_credential,
+            private_key,
+            scope,
+        },
+        sequence_numbers,
+    )
+    .ok()?;
+
+    for _ in 0..decoder.array().ok()?? {
+        if decoder.array().ok()? != Some(2) {
+            return None;
+        }
+        let id = decoder.bytes().ok()?;
+        let credential = decoder.bytes().ok()?;
+        context.add_member(id, credential).ok()?;
+    }
+
+    (decoder.position() == material.len()).then_some(context)
+}
@@ -2,18 +2,40 @@
 //!
 //! This crate mainly provides easy-to-use wrappers around the [`coapcore`] crate, with presets
 //! tailored towards Ariel OS: It utilizes [`embassy_net`] to open a network accessible CoAP socket
-//! and selects [`embedded_nal_coap`] for CoAP over UDP, it selects [`ariel_os_random`] as a source
+//! and selects [`embedded_nal_coap`] for CoAP over UDP (with an optional CoAP over TCP transport
+//! serving the same resources), it selects [`ariel_os_random`] as a source
 //! of randomness, and [`lakers_crypto_rustcrypto`] for the cryptographic algorithm
 //! implementations.
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 #![deny(missing_docs)]
 
 // Moving work from https://github.com/embassy-rs/embassy/pull/2519 in here for the time being
 mod udp_nal;
 
+pub mod blockwise;
+pub mod events;
+#[cfg(feature = "group")]
+pub mod group;
+pub mod observe;
+#[cfg(feature = "persistence")]
+mod persistence;
+#[cfg(feature = "rd")]
+mod rd;
+mod shared_client;
+mod shared_handler;
+pub mod system;
+#[cfg(feature = "tcp")]
+mod tcp;
+pub use events::{SecurityEvent, SecurityEventSubscriber, security_events};
+pub use shared_client::{MAX_PAYLOAD_LEN, RequestError, Response, SharedClient};
+pub use system::with_system_resources;
+
 #[cfg(feature = "coap-server-config-storage")]
 mod stored;
 
+#[cfg(feature = "coap-server-config-storage")]
+pub use stored::peers::PeerManagement;
+
 use core::net::{Ipv6Addr, SocketAddr};
 
 use ariel_os_debug::log::info;
@@ -25,6 +47,11 @@ use static_cell::StaticCell;
 
 const CONCURRENT_REQUESTS: usize = 3;
 
+ariel_os_embassy::net::declare_sockets!("coap", 1);
+
+#[cfg(feature = "coap-server")]
+ariel_os_embassy::net::mdns::declare_service!("_coap._udp", 5683);
+
 static CLIENT_READY: Watch<
     embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
     SameExecutorCell<&'static embedded_nal_coap::CoAPRuntimeClient<'static, CONCURRENT_REQUESTS>>,
@@ -127,6 +154,9 @@ async fn coap_run_impl(handler: impl coap_handler::Handler + coap_handler::Repor
     // request, because we shouldn't hand out a client early).
     stack.wait_config_up().await;
 
+    #[cfg(feature = "group")]
+    group::join_multicast_groups(stack);
+
     // FIXME trim to CoAP requirements (those values are just a likely good starting point for "we
     // process any message immediately anyway")
     let mut rx_meta = [PacketMetadata::EMPTY; 2];
@@ -145,7 +175,7 @@ async fn coap_run_impl(handler: impl coap_handler::Handler + coap_handler::Repor
     info!("Starting up CoAP server");
 
     let local_any = SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 5683);
-    let mut unconnected = udp_nal::UnconnectedUdp::bind_multiple(socket, local_any)
+    let unconnected = udp_nal::UnconnectedUdp::bind_multiple(socket, local_any)
         .await
         .unwrap();
 
@@ -169,13 +199,40 @@ async fn coap_run_impl(handler: impl coap_handler::Handler + coap_handler::Repor
     // FIXME: Should we allow users to override that? After all, this is just convenience and may
     // be limiting in special applications.
     let handler = handler.with_wkc();
-    let mut handler = coapcore::OscoreEdhocHandler::new(
+    // The links are rendered before the security policy applies to them.
+    #[cfg(feature = "rd")]
+    let (handler, links) = {
+        let mut handler = handler;
+        let links = rd::render_links(&mut handler);
+        (handler, links)
+    };
+    let handler = coapcore::OscoreEdhocHandler::new(
         handler,
         security_config,
         || lakers_crypto_rustcrypto::Crypto::new(ariel_os_random::crypto_rng()),
         ariel_os_random::crypto_rng(),
-        coapcore::time::TimeUnknown,
-    );
+        WallClock,
+    )
+    .with_security_events(events::report);
+    #[cfg(feature = "group")]
+    let handler = match group::load().await {
+        Some(group) => handler.with_group(group),
+        None => handler,
+    };
+    #[cfg(feature = "persistence")]
+    let (handler, persisted) = {
+        let mut handler = handler.with_persistence(
+            persistence::notify,
+            u64::from(persistence::SEQUENCE_NUMBER_RESERVATION),
+        );
+        let persisted = persistence::restore(&mut handler).await;
+        (handler, persisted)
+    };
+
+    // The transports and the notifications take turns in using the handler.
+    let shared_handler = core::cell::RefCell::new(handler);
+    let mut handler = shared_handler::SharedHandler(&shared_handler);
+    let mut unconnected = observe::ObservingUdp::new(unconnected, &shared_handler);
 
     info!("Server is ready.");
 
@@ -188,21 +245,103 @@ async fn coap_run_impl(handler: impl coap_handler::Handler + coap_handler::Repor
     static CLIENT: StaticCell<embedded_nal_coap::CoAPRuntimeClient<'static, CONCURRENT_REQUESTS>> =
         StaticCell::new();
 
+    let client = &*CLIENT.init(client);
     CLIENT_READY
         .sender()
-        .send(SameExecutorCell::new_async(&*CLIENT.init(client)).await);
-
-    server
-        .run(
-            &mut unconnected,
-            &mut handler,
-            &mut ariel_os_random::fast_rng(),
-        )
-        .await
-        .expect("UDP error");
+        .send(SameExecutorCell::new_async(client).await);
+
+    let server = server.run(
+        &mut unconnected,
+        &mut handler,
+        &mut ariel_os_random::fast_rng(),
+    );
+
+    #[cfg(feature = "tcp")]
+    let server = async {
+        use embassy_futures::select::{Either, select};
+
+        match select(server, tcp::run(stack, &shared_handler)).await {
+            Either::First(result) => result,
+            Either::Second(never) => match never {},
+        }
+    };
+
+    // Requests from the shared client are sent from this task, as that is where the client lives.
+    let server = async {
+        use embassy_futures::select::{Either, select};
+
+        match select(server, shared_client::serve(client)).await {
+            Either::First(result) => result,
+            Either::Second(never) => match never {},
+        }
+    };
+
+    // The registration with a resource directory is sent through the client from this task.
+    #[cfg(feature = "rd")]
+    let server = async {
+        use embassy_futures::select::{Either, select};
+
+        match select(server, rd::run(stack, client, &links)).await {
+            Either::First(result) => result,
+            Either::Second(never) => match never {},
+        }
+    };
+
+    // Actions requested through the system resources run from this task, as the handler can not
+    // perform them.
+    let server = async {
+        use embassy_futures::select::{Either, select};
+
+        match select(server, system::run()).await {
+            Either::First(result) => result,
+            Either::Second(never) => match never {},
+        }
+    };
+
+    // Changes to the runtime peers are persisted alongside the server, as the handler can not
+    // access storage.
+    #[cfg(feature = "coap-server-config-storage")]
+    let server = async {
+        use embassy_futures::select::{Either, select};
+
+        match select(server, stored::peers::persist()).await {
+            Either::First(result) => result,
+            Either::Second(never) => match never {},
+        }
+    };
+
+    // Security contexts are persisted alongside the server, as the handler can not access storage.
+    #[cfg(feature = "persistence")]
+    let server = async {
+        use embassy_futures::select::{Either, select};
+
+        match select(server, persistence::persist(&shared_handler, persisted)).await {
+            Either::First(result) => result,
+            Either::Second(never) => match never {},
+        }
+    };
+
+    server.await.expect("UDP error");
     unreachable!("embassy-net's sockets do not get closed (but embedded-nal-coap can't know that)");
 }
 
+/// Time provider of the CoAP server backed by the system's [wall
+/// clock][ariel_os_embassy::wallclock].
+///
+/// Time stamps of trusted tokens are reported back to the wall clock as having passed.
+struct WallClock;
+
+impl coapcore::time::TimeProvider for WallClock {
+    fn now(&mut self) -> (u64, Option<u64>) {
+        let bounds = ariel_os_embassy::wallclock::now();
+        (bounds.earliest, bounds.latest)
+    }
+
+    fn past_trusted(&mut self, timestamp: u64) {
+        ariel_os_embassy::wallclock::report_past(timestamp);
+    }
+}
+
 /// Returns a CoAP client requester.
 ///
 /// This asynchronously blocks until [`coap_run()`] has been called (which happens at startup
@@ -210,9 +349,8 @@ async fn coap_run_impl(handler: impl coap_handler::Handler + coap_handler::Repor
 ///
 /// # Panics
 ///
-/// This is currently only available from the thread that hosts the network stack, and panics
-/// otherwise. This restriction will be lifted in the future (by generalization in
-/// [`embedded_nal_coap`] to allow different mutexes).
+/// This is only available from the thread that hosts the network stack, and panics otherwise. Use
+/// [`shared_client()`] from other executors, threads or cores.
 pub async fn coap_client()
 -> &'static embedded_nal_coap::CoAPRuntimeClient<'static, CONCURRENT_REQUESTS> {
     let mut receiver = CLIENT_READY
@@ -226,17 +364,27 @@ pub async fn coap_client()
         .expect("CoAP client can currently only be used from the thread the network is bound to")
 }
 
+/// Returns a CoAP client handle that can be used from any executor, thread or core.
+///
+/// Requests sent through it are forwarded to the task running the CoAP stack; they wait until
+/// [`coap_run()`] has been called and the CoAP stack is operational. Compared to
+/// [`coap_client()`], this only supports requests and responses whose payload is copied in full,
+/// and processes one request at a time.
+#[must_use]
+pub fn shared_client() -> &'static SharedClient {
+    &shared_client::SHARED_CLIENT
+}
+
 /// Auto-started CoAP server that serves two purposes:
 ///
 /// * It provides the backend for the CoAP client operation (which leaves message sending to that
 ///   task).
-/// * It runs any CoAP server components provided by the OS (none yet).
+/// * It runs the CoAP server components provided by the OS (see [`with_system_resources()`]).
 #[cfg(not(feature = "coap-server"))]
 #[ariel_os_macros::task(autostart)]
 async fn coap_run() {
     use coap_handler_implementations::new_dispatcher;
 
-    // FIXME: Provide an "all system components" constructor in this crate.
-    let handler = new_dispatcher();
+    let handler = with_system_resources(new_dispatcher());
     coap_run_impl(handler).await;
 }
@@ -0,0 +1,741 @@
+//! Observation of resources ([RFC7641](https://www.rfc-editor.org/rfc/rfc7641.html)) on the CoAP
+//! server.
+//!
+//! A resource becomes observable by wrapping its handler in an [`Observable`] that refers to a
+//! static [`Resource`]. Whenever the resource's state changes, any task (on any executor, thread or
+//! core) calls [`Resource::notify()`], and the CoAP task sends notifications to all observers of
+//! that resource.
+//!
+//! The CoAP task does not need to know which resource an observer registered with: the Observe
+//! option that the [`Observable`] adds to the responses carries the resource's state. A
+//! registration is only retained if its response has that option, and whenever any resource
+//! changed, the observed requests are processed again, and a notification is only sent if the
+//! value changed.
+//!
+//! Observations are accepted over UDP, both on unprotected and OSCORE protected requests; in the
+//! latter case, notifications are protected by [`coapcore`]. Notifications are sent as
+//! non-confirmable messages, except that a notification is sent confirmable if an observer has not
+//! acknowledged one for 24 hours; observers that do not acknowledge it are removed ([RFC7641
+//! Section 4.5](https://www.rfc-editor.org/rfc/rfc7641.html#section-4.5)).
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! static LED_STATE: ariel_os::coap::observe::Resource = ariel_os::coap::observe::Resource::new();
+//!
+//! let handler = new_dispatcher().at(&["led"], Observable::new(&LED_STATE, led_handler));
+//!
+//! // Later, from any task:
+//! LED_STATE.notify();
+//! ```
+
+use core::cell::{Cell, RefCell};
+use core::net::SocketAddr;
+
+use ariel_os_debug::log::debug;
+use coap_message::{
+    Code as _, MessageOption as _, MinimalWritableMessage, OptionNumber as _, ReadableMessage,
+    error::RenderableOnMinimal,
+};
+use coap_message_implementations::inmemory_write;
+use coapcore::observe::{NotificationError, NotificationOutcome, Observation};
+use embassy_futures::select::{Either3, select3};
+use embassy_sync::{
+    blocking_mutex::{Mutex, raw::CriticalSectionRawMutex},
+    signal::Signal,
+};
+use embassy_time::{Duration, Instant, Timer};
+use embedded_nal_async::{self as nal, UnconnectedUdp as _};
+use rand_core::RngCore as _;
+
+use crate::udp_nal;
+
+/// Maximum number of observers across all resources.
+const MAX_OBSERVERS: usize = ariel_os_utils::usize_from_env_or!(
+    "CONFIG_COAP_MAX_OBSERVERS",
+    4,
+    "maximum number of CoAP observers across all resources"
+);
+
+/// Size of the buffer in which notifications are built.
+const NOTIFICATION_BUFFER_SIZE: usize = 1152;
+
+/// Longest time for which notifications are sent non-confirmable to an observer, after which
+/// one is sent confirmable to learn whether the observer is still interested (RFC7641 Section
+/// 4.5).
+const CONFIRMABLE_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Time to wait for the acknowledgement of a confirmable notification before it is first
+/// retransmitted (`ACK_TIMEOUT` of RFC7252 Section 4.8).
+const ACK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Number of retransmissions of a confirmable notification, after which the observer is removed
+/// (`MAX_RETRANSMIT` of RFC7252 Section 4.8).
+const MAX_RETRANSMIT: u8 = 4;
+
+/// Signaled whenever any resource has changed.
+static NOTIFY: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// State of an observable resource.
+///
+/// This is typically placed in a `static`, and referenced by the [`Observable`] that serves it.
+pub struct Resource {
+    /// Value of the Observe option sent in the next response.
+    sequence: Mutex<CriticalSectionRawMutex, Cell<u32>>,
+}
+
+impl Resource {
+    /// Creates a new observable resource.
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            sequence: Mutex::new(Cell::new(0)),
+        }
+    }
+
+    /// Indicates that the resource's state has changed, and notifications should be sent to its
+    /// observers.
+    ///
+    /// Notifications are built by the CoAP task some time after this was called; repeated calls
+    /// before that result in a single notification.
+    pub fn notify(&self) {
+        self.sequence.lock(|sequence| {
+            // The Observe option carries 24 bits.
+            sequence.set(sequence.get().wrapping_add(1) & 0x00ff_ffff);
+        });
+        NOTIFY.signal(());
+    }
+
+    fn sequence(&self) -> u32 {
+        self.sequence.lock(Cell::get)
+    }
+}
+
+impl Default for Resource {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A handler wrapper that makes a resource observable.
+///
+/// On registration requests (GET with Observe set to 0), this adds an Observe option to the
+/// response before the inner handler builds it; the inner handler thus can not add options with
+/// lower numbers (such as `ETag`). The option's value reflects the resource's state, and tells the
+/// CoAP task that the registration was accepted, and later, whether a notification is due.
+pub struct Observable<H> {
+    resource: &'static Resource,
+    inner: H,
+}
+
+impl<H> Observable<H> {
+    /// Wraps the handler of a resource, whose changes are indicated through `resource`.
+    pub fn new(resource: &'static Resource, inner: H) -> Self {
+        Self { resource, inner }
+    }
+}
+
+/// Error type of an [`Observable`].
+#[derive(Debug)]
+pub enum ObservableError<O, I> {
+    /// The Observe option could not be added.
+    Own(O),
+    /// The inner handler failed.
+    Inner(I),
+}
+
+impl<O: RenderableOnMinimal, I: RenderableOnMinimal> RenderableOnMinimal for ObservableError<O, I> {
+    type Error<IE>
+        = ObservableError<O::Error<IE>, I::Error<IE>>
+    where
+        IE: RenderableOnMinimal,
+        IE: core::fmt::Debug;
+    fn render<M: MinimalWritableMessage>(
+        self,
+        msg: &mut M,
+    ) -> Result<(), Self::Error<M::UnionError>> {
+        match self {
+            Self::Own(own) => own.render(msg).map_err(ObservableError::Own),
+            Self::Inner(inner) => inner.render(msg).map_err(ObservableError::Inner),
+        }
+    }
+}
+
+impl<H: coap_handler::Handler> coap_handler::Handler for Observable<H> {
+    type RequestData = (bool, H::RequestData);
+    type ExtractRequestError = H::ExtractRequestError;
+    type BuildResponseError<M: MinimalWritableMessage> =
+        ObservableError<M::UnionError, H::BuildResponseError<M>>;
+
+    fn extract_request_data<M: ReadableMessage>(
+        &mut self,
+        request: &M,
+    ) -> Result<Self::RequestData, Self::ExtractRequestError> {
+        let code: u8 = request.code().into();
+        let registration = code == coap_numbers::code::GET
+            && request.options().any(|o| {
+                o.number() == coap_numbers::option::OBSERVE && matches!(o.value(), [] | [0])
+            });
+        let extracted = self.inner.extract_request_data(request)?;
+        Ok((registration, extracted))
+    }
+
+    fn estimate_length(&mut self, request: &Self::RequestData) -> usize {
+        // Observe option with up to 3 bytes of value.
+        self.inner.estimate_length(&request.1) + 4
+    }
+
+    fn build_response<M: coap_message::MutableWritableMessage>(
+        &mut self,
+        response: &mut M,
+        (registration, request): Self::RequestData,
+    ) -> Result<(), Self::BuildResponseError<M>> {
+        if registration {
+            add_observe(response, self.resource.sequence()).map_err(ObservableError::Own)?;
+        }
+        self.inner
+            .build_response(response, request)
+            .map_err(ObservableError::Inner)
+    }
+}
+
+fn add_observe<M: MinimalWritableMessage>(
+    response: &mut M,
+    sequence: u32,
+) -> Result<(), M::UnionError> {
+    let bytes = sequence.to_be_bytes();
+    let leading_zeros = bytes.iter().take_while(|b| **b == 0).count();
+    #[allow(clippy::indexing_slicing, reason = "count is at most the length")]
+    response.add_option(
+        M::OptionNumber::new(coap_numbers::option::OBSERVE)?,
+        &bytes[leading_zeros..],
+    )?;
+    Ok(())
+}
+
+/// Access to the observation API of the [`coapcore::OscoreEdhocHandler`].
+///
+/// This abstracts over the handler's many type parameters.
+pub(crate) trait NotificationSource {
+    fn take_observation(&mut self) -> Option<Observation>;
+
+    fn observe_value(
+        &mut self,
+        observation: &Observation,
+        buffer: &mut [u8],
+    ) -> Result<Option<u32>, NotificationError>;
+
+    fn build_notification(
+        &mut self,
+        observation: &mut Observation,
+        response: &mut inmemory_write::Message<'_>,
+    ) -> Result<NotificationOutcome, NotificationError>;
+}
+
+impl<
+    H: coap_handler::Handler,
+    Crypto: lakers::Crypto,
+    CryptoFactory: Fn() -> Crypto,
+    SSC: coapcore::seccfg::ServerSecurityConfig,
+    RNG: rand_core::RngCore + rand_core::CryptoRng,
+    TP: coapcore::time::TimeProvider,
+> NotificationSource for coapcore::OscoreEdhocHandler<H, Crypto, CryptoFactory, SSC, RNG, TP>
+{
+    fn take_observation(&mut self) -> Option<Observation> {
+        self.take_observation()
+    }
+
+    fn observe_value(
+        &mut self,
+        observation: &Observation,
+        buffer: &mut [u8],
+    ) -> Result<Option<u32>, NotificationError> {
+        self.observe_value(observation, buffer)
+    }
+
+    fn build_notification(
+        &mut self,
+        observation: &mut Observation,
+        response: &mut inmemory_write::Message<'_>,
+    ) -> Result<NotificationOutcome, NotificationError> {
+        self.build_notification(observation, response)
+    }
+}
+
+type Token = heapless::Vec<u8, 8>;
+
+/// A request that is currently being processed.
+struct Exchange {
+    remote: SocketAddr,
+    local: SocketAddr,
+    token: Token,
+}
+
+struct Observer {
+    remote: SocketAddr,
+    local: SocketAddr,
+    token: Token,
+    observation: Observation,
+    /// Message ID of the latest notification, by which a Reset message or an acknowledgement is
+    /// recognized.
+    message_id: u16,
+    /// Value of the Observe option in the latest response, by which changes are recognized.
+    observe: u32,
+    /// Time at which the observer last showed its interest, by registering or by acknowledging a
+    /// notification.
+    confirmed: Instant,
+}
+
+/// A confirmable notification that has not been acknowledged yet.
+struct Unacknowledged {
+    remote: SocketAddr,
+    local: SocketAddr,
+    message_id: u16,
+    /// Length of the message in the retransmission buffer.
+    len: usize,
+    retransmissions: u8,
+    /// Time to wait for an acknowledgement after the latest transmission.
+    timeout: Duration,
+    deadline: Instant,
+}
+
+impl Unacknowledged {
+    fn is_for(&self, observer: &Observer) -> bool {
+        self.remote == observer.remote && self.message_id == observer.message_id
+    }
+}
+
+/// Returns the time to wait for the acknowledgement of a confirmable notification before its first
+/// retransmission, which is randomized between [`ACK_TIMEOUT`] and 1.5 times that (RFC7252 Section
+/// 4.8).
+fn initial_ack_timeout(random: u32) -> Duration {
+    ACK_TIMEOUT + Duration::from_millis(u64::from(random) % (ACK_TIMEOUT.as_millis() / 2))
+}
+
+/// Header fields of a CoAP message over UDP.
+struct Header<'a> {
+    message_type: u8,
+    code: u8,
+    message_id: u16,
+    token: &'a [u8],
+}
+
+impl<'a> Header<'a> {
+    fn parse(message: &'a [u8]) -> Option<Self> {
+        let [first, code, id_high, id_low, rest @ ..] = message else {
+            return None;
+        };
+        if first >> 6 != 1 {
+            return None;
+        }
+        Some(Self {
+            message_type: (first >> 4) & 0x03,
+            code: *code,
+            message_id: u16::from_be_bytes([*id_high, *id_low]),
+            token: rest.get(..usize::from(first & 0x0f))?,
+        })
+    }
+}
+
+const TYPE_CON: u8 = 0;
+const TYPE_NON: u8 = 1;
+const TYPE_ACK: u8 = 2;
+const TYPE_RST: u8 = 3;
+
+/// A UDP socket that tracks observation registrations in the requests and responses that pass
+/// through it, and sends notifications while it is waiting for requests.
+pub(crate) struct ObservingUdp<'a, N> {
+    inner: udp_nal::UnconnectedUdp<'a>,
+    handler: &'a RefCell<N>,
+    observers: heapless::Vec<Observer, MAX_OBSERVERS>,
+    current: Option<Exchange>,
+    /// Message ID of the latest notification.
+    ///
+    /// The CoAP client sends from the same socket, and picks its message IDs independently;
+    /// starting at a random value makes it unlikely for the two to collide within the exchange
+    /// lifetime.
+    message_id: u16,
+    /// The confirmable notification that is awaiting acknowledgement, if any.
+    ///
+    /// Only one is outstanding at a time; notifications to its observer, and other confirmable
+    /// notifications, are held back until it was acknowledged or its observer was removed.
+    unacknowledged: Option<Unacknowledged>,
+    /// The message of [`Self::unacknowledged`], which is sent again unchanged.
+    retransmission: [u8; NOTIFICATION_BUFFER_SIZE],
+}
+
+impl<'a, N: NotificationSource> ObservingUdp<'a, N> {
+    pub(crate) fn new(inner: udp_nal::UnconnectedUdp<'a>, handler: &'a RefCell<N>) -> Self {
+        let mut message_id = [0u8; 2];
+        ariel_os_random::fast_rng().fill_bytes(&mut message_id);
+        Self {
+            inner,
+            handler,
+            observers: heapless::Vec::new(),
+            current: None,
+            message_id: u16::from_be_bytes(message_id),
+            unacknowledged: None,
+            retransmission: [0; NOTIFICATION_BUFFER_SIZE],
+        }
+    }
+
+    fn track_request(&mut self, local: SocketAddr, remote: SocketAddr, message: &[u8]) {
+        let Some(header) = Header::parse(message) else {
+            return;
+        };
+        let outstanding = self.unacknowledged.take_if(|unacknowledged| {
+            unacknowledged.remote == remote && unacknowledged.message_id == header.message_id
+        });
+        match header.message_type {
+            TYPE_RST => {
+                self.observers
+                    .retain(|o| !(o.remote == remote && o.message_id == header.message_id));
+            }
+            TYPE_ACK => {
+                if let Some(observer) = outstanding.as_ref().and_then(|outstanding| {
+                    self.observers.iter_mut().find(|o| outstanding.is_for(o))
+                }) {
+                    debug!("Observer acknowledged notification");
+                    observer.confirmed = Instant::now();
+                }
+            }
+            _ => (),
+        }
+        if outstanding.is_some() {
+            // Notifications that were held back can be sent now.
+            NOTIFY.signal(());
+        }
+
+        // Requests are in the 0.xx class, excluding 0.00 Empty.
+        if header.code == 0 || header.code >> 5 != 0 {
+            return;
+        }
+        let Ok(token) = Token::from_slice(header.token) else {
+            return;
+        };
+        self.current = Some(Exchange {
+            remote,
+            local,
+            token,
+        });
+    }
+
+    fn track_response(&mut self, remote: SocketAddr, message: &[u8]) {
+        let Some(header) = Header::parse(message) else {
+            return;
+        };
+        let Some(exchange) = self.current.take_if(|exchange| {
+            exchange.remote == remote && exchange.token == header.token && header.code >> 5 >= 2
+        }) else {
+            return;
+        };
+
+        let observation = self.handler.borrow_mut().take_observation();
+
+        // Any request on the same token ends a previous observation (RFC7641 Section 3.3.1); a
+        // registration replaces it.
+        self.observers
+            .retain(|o| !(o.remote == exchange.remote && o.token == exchange.token));
+
+        let Some(observation) = observation else {
+            return;
+        };
+        // Only observable resources add an Observe option, and thereby accept the registration.
+        let mut buffer = [0u8; NOTIFICATION_BUFFER_SIZE];
+        let Ok(Some(observe)) = self
+            .handler
+            .borrow_mut()
+            .observe_value(&observation, &mut buffer)
+        else {
+            return;
+        };
+
+        if self.observers.is_full() {
+            debug!("Too many observers, dropping the oldest");
+            self.observers.remove(0);
+        }
+        debug!("Registered observer");
+        // Infallible: space was made above.
+        let _ = self.observers.push(Observer {
+            remote: exchange.remote,
+            local: exchange.local,
+            token: exchange.token,
+            observation,
+            message_id: 0,
+            observe,
+            confirmed: Instant::now(),
+        });
+    }
+
+    async fn send_notifications(&mut self) {
+        let mut index = 0;
+        while let Some(observer) = self.observers.get_mut(index) {
+            let confirmable = Instant::now().saturating_duration_since(observer.confirmed)
+                >= CONFIRMABLE_INTERVAL;
+            if self
+                .unacknowledged
+                .as_ref()
+                .is_some_and(|unacknowledged| confirmable || unacknowledged.is_for(observer))
+            {
+                index += 1;
+                continue;
+            }
+
+            let mut buffer = [0u8; NOTIFICATION_BUFFER_SIZE];
+            let observe = match self
+                .handler
+                .borrow_mut()
+                .observe_value(&observer.observation, &mut buffer)
+            {
+                Ok(Some(observe)) if observe == observer.observe => {
+                    index += 1;
+                    continue;
+                }
+                Ok(observe) => observe,
+                Err(e) => {
+                    debug!("Observation ended: {:?}", e);
+                    self.observers.remove(index);
+                    continue;
+                }
+            };
+
+            self.message_id = self.message_id.wrapping_add(1);
+            observer.message_id = self.message_id;
+            if let Some(observe) = observe {
+                observer.observe = observe;
+            }
+
+            let message_type = if confirmable { TYPE_CON } else { TYPE_NON };
+            let built = build_notification(
+                observer,
+                message_type,
+                &mut *self.handler.borrow_mut(),
+                &mut buffer,
+            );
+
+            let keep = match built {
+                Ok((len, outcome)) => {
+                    #[allow(clippy::indexing_slicing, reason = "length is populated above")]
+                    let message = &buffer[..len];
+                    if self
+                        .inner
+                        .send(observer.local, observer.remote, message)
+                        .await
+                        .is_err()
+                    {
+                        debug!("Sending notification failed");
+                    }
+                    let keep = outcome == NotificationOutcome::Continues;
+                    if confirmable && keep {
+                        #[allow(clippy::indexing_slicing, reason = "buffers are equally long")]
+                        self.retransmission[..len].copy_from_slice(message);
+                        let timeout = initial_ack_timeout(ariel_os_random::fast_rng().next_u32());
+                        self.unacknowledged = Some(Unacknowledged {
+                            remote: observer.remote,
+                            local: observer.local,
+                            message_id: observer.message_id,
+                            len,
+                            retransmissions: 0,
+                            timeout,
+                            deadline: Instant::now() + timeout,
+                        });
+                    }
+                    keep
+                }
+                Err(e) => {
+                    debug!("Observation ended: {:?}", e);
+                    false
+                }
+            };
+
+            if keep {
+                index += 1;
+            } else {
+                self.observers.remove(index);
+            }
+        }
+    }
+
+    /// Sends the unacknowledged notification again, or removes its observer once all
+    /// retransmissions went unacknowledged.
+    async fn retransmit(&mut self) {
+        let Some(unacknowledged) = &mut self.unacknowledged else {
+            return;
+        };
+
+        if unacknowledged.retransmissions == MAX_RETRANSMIT {
+            debug!("Observer did not acknowledge notification, removing it");
+            self.observers.retain(|o| !unacknowledged.is_for(o));
+            self.unacknowledged = None;
+            // Notifications that were held back can be sent now.
+            NOTIFY.signal(());
+            return;
+        }
+
+        unacknowledged.retransmissions += 1;
+        unacknowledged.timeout = unacknowledged.timeout * 2;
+        unacknowledged.deadline = Instant::now() + unacknowledged.timeout;
+        #[allow(clippy::indexing_slicing, reason = "length is populated on sending")]
+        let message = &self.retransmission[..unacknowledged.len];
+        if self
+            .inner
+            .send(unacknowledged.local, unacknowledged.remote, message)
+            .await
+            .is_err()
+        {
+            debug!("Retransmitting notification failed");
+        }
+    }
+}
+
+/// Builds a notification for `observer` into `buffer` as a message of the given type, returning its
+/// length.
+fn build_notification(
+    observer: &mut Observer,
+    message_type: u8,
+    handler: &mut impl NotificationSource,
+    buffer: &mut [u8],
+) -> Result<(usize, NotificationOutcome), NotificationError> {
+    let token_len = observer.token.len();
+    let (header, tail) = buffer.split_at_mut(4 + token_len);
+
+    let mut code = 0;
+    let mut message = inmemory_write::Message::new(&mut code, tail);
+    let outcome = handler.build_notification(&mut observer.observation, &mut message)?;
+    let len = message.finish();
+
+    let [first, code_byte, id_high, id_low, token @ ..] = header else {
+        unreachable!("header is at least 4 bytes long");
+    };
+    #[allow(
+        clippy::cast_possible_truncation,
+        reason = "tokens are at most 8 bytes long"
+    )]
+    {
+        *first = (1 << 6) | (message_type << 4) | token_len as u8;
+    }
+    *code_byte = code;
+    [*id_high, *id_low] = observer.message_id.to_be_bytes();
+    token.copy_from_slice(&observer.token);
+
+    Ok((4 + token_len + len, outcome))
+}
+
+impl<N: NotificationSource> nal::UnconnectedUdp for ObservingUdp<'_, N> {
+    type Error = udp_nal::Error;
+
+    async fn send(
+        &mut self,
+        local: SocketAddr,
+        remote: SocketAddr,
+        buf: &[u8],
+    ) -> Result<(), Self::Error> {
+        // Error responses to requests sent to a multicast address are suppressed (RFC7252 Section
+        // 8.2.1): they would come from every device that is not a suitable recipient.
+        if local.ip().is_multicast() && buf.get(1).is_some_and(|code| code >> 5 >= 4) {
+            return Ok(());
+        }
+
+        // This runs before the first await point, and thus before any other request can be
+        // processed.
+        self.track_response(remote, buf);
+        self.inner.send(local, remote, buf).await
+    }
+
+    async fn receive_into(
+        &mut self,
+        buf: &mut [u8],
+    ) -> Result<(usize, SocketAddr, SocketAddr), Self::Error> {
+        loop {
+            let deadline = self
+                .unacknowledged
+                .as_ref()
+                .map(|unacknowledged| unacknowledged.deadline);
+            let retransmission = async move {
+                match deadline {
+                    Some(deadline) => Timer::at(deadline).await,
+                    None => core::future::pending().await,
+                }
+            };
+
+            // Receiving is cancellation safe, so notifications can be sent in between.
+            match select3(self.inner.receive_into(buf), NOTIFY.wait(), retransmission).await {
+                Either3::First(received) => {
+                    let (len, local, remote) = received?;
+                    if let Some(message) = buf.get(..len) {
+                        self.track_request(local, remote, message);
+                    }
+                    return Ok((len, local, remote));
+                }
+                Either3::Second(()) => self.send_notifications().await,
+                Either3::Third(()) => self.retransmit().await,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::indexing_slicing, reason = "panicking is fine in tests")]
+mod test {
+    use super::*;
+
+    #[test]
+    fn header() {
+        // NON 2.05 Content, message ID 0x1234, token 0xab 0xcd, and a payload.
+        let message = [0x52, 0x45, 0x12, 0x34, 0xab, 0xcd, 0xff, b'x'];
+        let header = Header::parse(&message).unwrap();
+        assert_eq!(header.message_type, TYPE_NON);
+        assert_eq!(header.code, coap_numbers::code::CONTENT);
+        assert_eq!(header.message_id, 0x1234);
+        assert_eq!(header.token, [0xab, 0xcd]);
+
+        // An empty RST.
+        let header = Header::parse(&[0x70, 0x00, 0x00, 0x01]).unwrap();
+        assert_eq!(header.message_type, TYPE_RST);
+
+        // An empty ACK.
+        let header = Header::parse(&[0x60, 0x00, 0x12, 0x35]).unwrap();
+        assert_eq!(header.message_type, TYPE_ACK);
+        assert_eq!(header.code, 0);
+        assert_eq!(header.message_id, 0x1235);
+
+        // Wrong version, and a token longer than the message.
+        assert!(Header::parse(&[0x90, 0x45, 0x00, 0x01]).is_none());
+        assert!(Header::parse(&[0x52, 0x45, 0x00, 0x01, 0xab]).is_none());
+        assert!(Header::parse(&[0x50, 0x45, 0x00]).is_none());
+    }
+
+    #[test]
+    fn ack_timeout() {
+        for random in [0, 1, 999, 1000, u32::MAX] {
+            let timeout = initial_ack_timeout(random);
+            assert!(timeout >= ACK_TIMEOUT);
+            assert!(timeout < ACK_TIMEOUT + ACK_TIMEOUT / 2);
+        }
+        assert_eq!(initial_ack_timeout(0), ACK_TIMEOUT);
+        assert_eq!(initial_ack_timeout(999), Duration::from_millis(2999));
+    }
+
+    #[test]
+    fn observe_option() {
+        for (sequence, value) in [
+            (0, &[][..]),
+            (5, &[5][..]),
+            (0x1234, &[0x12, 0x34][..]),
+            (0x00ff_ffff, &[0xff, 0xff, 0xff][..]),
+        ] {
+            let mut code = 0;
+            let mut buffer = [0u8; 8];
+            let mut message = inmemory_write::Message::new(&mut code, &mut buffer);
+            add_observe(&mut message, sequence).unwrap();
+            let len = message.finish();
+
+            let message = coap_message_implementations::inmemory::Message::new(0, &buffer[..len]);
+            let mut options = message.options();
+            let option = options.next().unwrap();
+            assert_eq!(option.number(), coap_numbers::option::OBSERVE);
+            assert_eq!(option.value(), value);
+            assert!(options.next().is_none());
+        }
+    }
+}
@@ -0,0 +1,196 @@
+//! Persistence of OSCORE security contexts, so that peers do not need to run EDHOC again after a
+//! restart.
+//!
+//! Security contexts established through EDHOC are kept in storage as described in
+//! [`coapcore::persistence`]: the `CONFIG_COAP_PERSISTED_CONTEXTS` most recently stored contexts
+//! are restored when the CoAP server starts, and own sequence numbers are reserved in storage in
+//! blocks of `CONFIG_COAP_OSCORE_SEQUENCE_NUMBERS`.
+//!
+//! As the contexts contain key material, they are stored as an encrypted blob (see
+//! [`ariel_os_storage::blob`]), like the material of an OSCORE group. If they fail authentication
+//! (eg. because the `CONFIG_STORAGE_ENCRYPTION_SECRET` build-time secret changed), none is
+//! restored, and peers run EDHOC again.
+
+use core::cell::RefCell;
+
+use ariel_os_debug::log::{error, info, warn};
+use ariel_os_storage::blob;
+use coapcore::persistence::{MAX_ENCODED_LEN, PersistedContext, RestoreError};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use embedded_io_async::{Read as _, Write as _};
+
+/// Key of the encrypted blob in which the contexts are stored, as a CBOR sequence of encoded
+/// contexts.
+const CONTEXTS_KEY: &str = "ariel-os-coap.oscore-contexts";
+
+/// Maximum number of contexts that are kept in storage.
+const MAX_CONTEXTS: usize = ariel_os_utils::usize_from_env_or!(
+    "CONFIG_COAP_PERSISTED_CONTEXTS",
+    4,
+    "maximum number of OSCORE security contexts kept in storage"
+);
+
+/// Number of own sequence numbers reserved in storage at a time.
+pub(crate) const SEQUENCE_NUMBER_RESERVATION: u32 = ariel_os_utils::u32_from_env_or!(
+    "CONFIG_COAP_OSCORE_SEQUENCE_NUMBERS",
+    256,
+    "number of OSCORE sequence numbers reserved in storage at a time for each persisted context"
+);
+
+/// Signaled whenever the handler has contexts to persist.
+static PENDING: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+type Contexts = heapless::Vec<PersistedContext, MAX_CONTEXTS>;
+
+/// Access to the persistence API of the [`coapcore::OscoreEdhocHandler`].
+///
+/// This abstracts over the handler's many type parameters.
+pub(crate) trait PersistenceSource {
+    fn restore_context(&mut self, persisted: &PersistedContext) -> Result<(), RestoreError>;
+
+    fn pending_persistence(&mut self) -> Option<PersistedContext>;
+
+    fn confirm_persisted(&mut self, persisted: &PersistedContext);
+}
+
+impl<
+    H: coap_handler::Handler,
+    Crypto: lakers::Crypto,
+    CryptoFactory: Fn() -> Crypto,
+    SSC: coapcore::seccfg::ServerSecurityConfig,
+    RNG: rand_core::RngCore + rand_core::CryptoRng,
+    TP: coapcore::time::TimeProvider,
+> PersistenceSource for coapcore::OscoreEdhocHandler<H, Crypto, CryptoFactory, SSC, RNG, TP>
+{
+    fn restore_context(&mut self, persisted: &PersistedContext) -> Result<(), RestoreError> {
+        self.restore_context(persisted)
+    }
+
+    fn pending_persistence(&mut self) -> Option<PersistedContext> {
+        self.pending_persistence()
+    }
+
+    fn confirm_persisted(&mut self, persisted: &PersistedContext) {
+        self.confirm_persisted(persisted);
+    }
+}
+
+/// Called by the handler when it has contexts to persist.
+pub(crate) fn notify() {
+    PENDING.signal(());
+}
+
+/// Restores the stored contexts into `handler`.
+///
+/// The restored contexts are returned, to be passed on to [`persist()`].
+///
+/// # Panics
+///
+/// Panics if the storage is not accessible.
+pub(crate) async fn restore(handler: &mut impl PersistenceSource) -> Contexts {
+    let mut contexts = Contexts::new();
+
+    let mut reader = match blob::encrypted_reader(CONTEXTS_KEY).await {
+        Ok(Some(reader)) => reader,
+        Ok(None) => return contexts,
+        Err(blob::Error::Tampered) => {
+            warn!("Stored OSCORE contexts fail authentication, ignoring them.");
+            return contexts;
+        }
+        Err(_) => panic!("flash error prevents startup"),
+    };
+
+    let mut buffer = [0; MAX_CONTEXTS * MAX_ENCODED_LEN];
+    let Some(stored) = usize::try_from(reader.len())
+        .ok()
+        .and_then(|len| buffer.get_mut(..len))
+    else {
+        warn!("Stored OSCORE contexts exceed the configured size, ignoring them.");
+        return contexts;
+    };
+    match reader.read_exact(stored).await {
+        Ok(()) => (),
+        Err(embedded_io_async::ReadExactError::Other(blob::Error::Tampered)) => {
+            warn!("Stored OSCORE contexts fail authentication, ignoring them.");
+            return contexts;
+        }
+        Err(_) => panic!("flash error prevents startup"),
+    }
+    let stored: &[u8] = stored;
+
+    let mut decoder = minicbor::Decoder::new(stored);
+    while decoder.position() < stored.len() && !contexts.is_full() {
+        let start = decoder.position();
+        if decoder.skip().is_err() {
+            warn!("Stored OSCORE contexts are truncated.");
+            break;
+        }
+        let Some(context) = stored
+            .get(start..decoder.position())
+            .and_then(|encoded| PersistedContext::decode(encoded).ok())
+        else {
+            warn!("Stored OSCORE context is unreadable, ignoring it.");
+            continue;
+        };
+        match handler.restore_context(&context) {
+            Ok(()) => {
+                let _ = contexts.push(context);
+            }
+            Err(RestoreError::UnknownCredential) => {
+                info!("Peer of a stored OSCORE context is not known any more, dropping it.");
+            }
+            Err(_) => warn!("Stored OSCORE context could not be restored, dropping it."),
+        }
+    }
+
+    info!("Restored {} OSCORE contexts from storage.", contexts.len());
+    contexts
+}
+
+/// Persists the handler's contexts whenever it has any pending.
+///
+/// This needs to run alongside the CoAP server, as the handler can not access storage. Contexts
+/// are confirmed to the handler only once they are written, so the handler never uses sequence
+/// numbers that are not reserved in storage.
+pub(crate) async fn persist(
+    handler: &RefCell<impl PersistenceSource>,
+    mut contexts: Contexts,
+) -> core::convert::Infallible {
+    loop {
+        PENDING.wait().await;
+
+        loop {
+            let pending = handler.borrow_mut().pending_persistence();
+            let Some(pending) = pending else {
+                break;
+            };
+
+            // A newer state of a context replaces the older one; otherwise, the least recently
+            // stored context makes room.
+            if let Some(index) = contexts
+                .iter()
+                .position(|c| c.recipient_id() == pending.recipient_id())
+            {
+                contexts.remove(index);
+            } else if contexts.is_full() {
+                contexts.remove(0);
+            }
+            let _ = contexts.push(pending.clone());
+
+            if store(&contexts).await.is_err() {
+                error!("Failed to persist OSCORE contexts.");
+                break;
+            }
+            handler.borrow_mut().confirm_persisted(&pending);
+        }
+    }
+}
+
+async fn store(contexts: &Contexts) -> Result<(), blob::Error> {
+    let mut buffer = [0; MAX_ENCODED_LEN];
+    let mut writer = blob::encrypted_writer(CONTEXTS_KEY).await?;
+    for context in contexts {
+        writer.write_all(context.encode(&mut buffer)).await?;
+    }
+    writer.finish().await
+}
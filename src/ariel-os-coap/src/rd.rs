@@ -0,0 +1,445 @@
+//! Registration with a CoRE Resource Directory ([RFC9176](https://www.rfc-editor.org/rfc/rfc9176.html)).
+//!
+//! The links the server advertises in `/.well-known/core` are rendered once at startup, and
+//! registered with a Resource Directory (RD) that is either configured through
+//! `CONFIG_COAP_RD_ADDRESS`, or discovered through a multicast request to the "All CoRE Resources"
+//! address. The registration is refreshed before its lifetime expires, and repeated after the
+//! network configuration was lost and regained.
+//!
+//! Registrations are sent without protection, as the system's CoAP client does not secure its
+//! requests yet.
+
+use core::fmt::Write as _;
+use core::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use ariel_os_debug::log::{debug, info, warn};
+use coap_message::{
+    Code as _, MessageOption as _, MinimalWritableMessage, OptionNumber as _, ReadableMessage,
+    error::RenderableOnMinimal as _,
+};
+use coap_message_implementations::{inmemory, inmemory_write};
+use coap_numbers::{code, option};
+use embassy_futures::select::{Either, select};
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_time::{Duration, Timer};
+use rand_core::RngCore as _;
+
+use crate::CONCURRENT_REQUESTS;
+
+/// Space for the links registered with the RD.
+///
+/// Links beyond that are not registered.
+const LINKS_SIZE: usize = ariel_os_utils::usize_from_env_or!(
+    "CONFIG_COAP_RD_LINKS_SIZE",
+    512,
+    "maximum length of the link-format document registered with a CoAP resource directory"
+);
+
+/// Lifetime of the registration in seconds.
+const LIFETIME: u32 = ariel_os_utils::u32_from_env_or!(
+    "CONFIG_COAP_RD_LIFETIME",
+    3600,
+    "lifetime of the registration with a CoAP resource directory in seconds"
+);
+
+/// Address of the RD (e.g., `[2001:db8::1]:5683`, or `192.0.2.1` for the default port); if empty,
+/// the RD is discovered.
+const RD_ADDRESS: &str = ariel_os_utils::str_from_env_or!(
+    "CONFIG_COAP_RD_ADDRESS",
+    "",
+    "address of the CoAP resource directory (discovered if empty)"
+);
+
+/// Endpoint name under which the device registers; if empty, it is derived from the device ID.
+const ENDPOINT_NAME: &str = ariel_os_utils::str_from_env_or!(
+    "CONFIG_COAP_RD_ENDPOINT",
+    "",
+    "endpoint name under which the device registers with a CoAP resource directory"
+);
+
+/// Path of the registration interface if a configured RD is used, or if discovery does not
+/// indicate one.
+const DEFAULT_REGISTRATION_PATH: &str = "/rd";
+
+/// Time after which failed discovery or registration is retried.
+const RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Time for which responses to a discovery request are awaited.
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Content-Format number of `application/link-format`.
+const CONTENT_FORMAT_LINK_FORMAT: u16 = 40;
+
+/// Length of paths (registration interface and registration resource) that are supported.
+const MAX_PATH_LEN: usize = 64;
+
+/// Length of endpoint names (as limited by RFC9176 Section 9.3).
+const MAX_ENDPOINT_NAME_LEN: usize = 63;
+
+// Used while discovering the resource directory.
+ariel_os_embassy::net::declare_sockets!("coap-rd", 1);
+
+type Path = heapless::String<MAX_PATH_LEN>;
+
+/// The link-format document that is registered.
+pub(crate) type Links = heapless::Vec<u8, LINKS_SIZE>;
+
+/// Renders the links that `handler` serves at `/.well-known/core`.
+///
+/// An empty document is produced if the handler does not serve it successfully, or if it does
+/// not fit.
+pub(crate) fn render_links<H: coap_handler::Handler>(handler: &mut H) -> Links {
+    let mut request_code = code::GET;
+    let mut request_buffer = [0u8; 16];
+    let mut request = inmemory_write::Message::new(&mut request_code, &mut request_buffer);
+    request
+        .add_option(option::URI_PATH, b".well-known")
+        .and_then(|()| request.add_option(option::URI_PATH, b"core"))
+        .expect("Buffer is large enough");
+    let request_len = request.finish();
+    #[allow(
+        clippy::indexing_slicing,
+        reason = "length is populated by the message"
+    )]
+    let request = inmemory::Message::new(request_code, &request_buffer[..request_len]);
+
+    let mut response_code = 0;
+    let mut response_buffer = [0u8; LINKS_SIZE + 16];
+    let mut response = inmemory_write::Message::new(&mut response_code, &mut response_buffer);
+    let built = match handler.extract_request_data(&request) {
+        Ok(data) => handler.build_response(&mut response, data).is_ok(),
+        Err(e) => {
+            let _ = e.render(&mut response);
+            false
+        }
+    };
+    let response_len = response.finish();
+    #[allow(
+        clippy::indexing_slicing,
+        reason = "length is populated by the message"
+    )]
+    let response = inmemory::Message::new(response_code, &response_buffer[..response_len]);
+
+    if !built || response_code != code::CONTENT {
+        warn!("Links for the resource directory could not be rendered.");
+        return Links::new();
+    }
+    Links::from_slice(response.payload()).unwrap_or_else(|()| {
+        warn!("Links for the resource directory exceed CONFIG_COAP_RD_LINKS_SIZE.");
+        Links::new()
+    })
+}
+
+/// Keeps the device registered with an RD.
+pub(crate) async fn run(
+    stack: embassy_net::Stack<'static>,
+    client: &embedded_nal_coap::CoAPRuntimeClient<'_, CONCURRENT_REQUESTS>,
+    links: &Links,
+) -> core::convert::Infallible {
+    use coap_request::Stack as _;
+
+    let endpoint_name = endpoint_name();
+    let mut lifetime_query = heapless::String::<16>::new();
+    let _ = write!(lifetime_query, "lt={LIFETIME}");
+    let mut endpoint_query = heapless::String::<{ MAX_ENDPOINT_NAME_LEN + 3 }>::new();
+    let _ = write!(endpoint_query, "ep={endpoint_name}");
+
+    loop {
+        stack.wait_config_up().await;
+
+        let Some((rd, registration_path)) = locate(stack).await else {
+            debug!("No resource directory found.");
+            Timer::after(RETRY_DELAY).await;
+            continue;
+        };
+
+        let registration = client
+            .to(rd)
+            .request(RdRequest {
+                path: &registration_path,
+                queries: &[&endpoint_query, &lifetime_query],
+                payload: links,
+            })
+            .await;
+        let location = match registration {
+            Ok(RdResponse {
+                code: code::CREATED,
+                location: Some(location),
+            }) => location,
+            _ => {
+                warn!("Registration with the resource directory failed.");
+                Timer::after(RETRY_DELAY).await;
+                continue;
+            }
+        };
+        info!(
+            "Registered with the resource directory at {}",
+            location.as_str()
+        );
+
+        loop {
+            let refresh = Timer::after(Duration::from_secs(u64::from(LIFETIME) * 3 / 4));
+            if let Either::Second(()) = select(refresh, stack.wait_config_down()).await {
+                debug!("Network configuration lost; registering again once it is back.");
+                break;
+            }
+
+            // An empty POST to the registration resource extends its lifetime.
+            let refreshed = client
+                .to(rd)
+                .request(RdRequest {
+                    path: &location,
+                    queries: &[],
+                    payload: &[],
+                })
+                .await;
+            if !matches!(
+                refreshed,
+                Ok(RdResponse {
+                    code: code::CHANGED,
+                    ..
+                })
+            ) {
+                debug!("Refreshing the registration failed; registering again.");
+                break;
+            }
+        }
+    }
+}
+
+/// Produces the endpoint name, either from configuration or from the device ID.
+fn endpoint_name() -> heapless::String<MAX_ENDPOINT_NAME_LEN> {
+    let mut name = heapless::String::new();
+    if !ENDPOINT_NAME.is_empty() {
+        let _ = name.push_str(ENDPOINT_NAME);
+        return name;
+    }
+
+    let _ = name.push_str("ariel-");
+    match ariel_os_identity::device_id_bytes() {
+        Ok(id) => {
+            for byte in id.as_ref() {
+                // Overly long device IDs are truncated.
+                let _ = write!(name, "{byte:02x}");
+            }
+        }
+        Err(_) => {
+            let _ = name.push_str("os");
+        }
+    }
+    name
+}
+
+/// Determines the RD's address and registration path, either from configuration or through
+/// discovery.
+async fn locate(stack: embassy_net::Stack<'static>) -> Option<(SocketAddr, Path)> {
+    if RD_ADDRESS.is_empty() {
+        return discover(stack).await;
+    }
+
+    let address = RD_ADDRESS.parse::<SocketAddr>().ok().or_else(|| {
+        RD_ADDRESS
+            .parse::<IpAddr>()
+            .ok()
+            .map(|ip| SocketAddr::new(ip, 5683))
+    });
+    if address.is_none() {
+        warn!("CONFIG_COAP_RD_ADDRESS is not a valid address.");
+    }
+    Some((address?, Path::try_from(DEFAULT_REGISTRATION_PATH).ok()?))
+}
+
+/// Sends a multicast query for the RD's registration interface, and picks the first response.
+async fn discover(stack: embassy_net::Stack<'static>) -> Option<(SocketAddr, Path)> {
+    let mut rx_meta = [PacketMetadata::EMPTY; 1];
+    let mut rx_buffer = [0; 1152];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut tx_buffer = [0; 64];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    socket.bind(0).ok()?;
+
+    // "All CoRE Resources" addresses of RFC7252 Section 12.8
+    let group: IpAddr = if stack.config_v6().is_some() {
+        Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xfe).into()
+    } else {
+        Ipv4Addr::new(224, 0, 1, 187).into()
+    };
+
+    let mut token = [0u8; 4];
+    ariel_os_random::fast_rng().fill_bytes(&mut token);
+    let mut message_id = [0u8; 2];
+    ariel_os_random::fast_rng().fill_bytes(&mut message_id);
+
+    // Non-confirmable GET /.well-known/core?rt=core.rd
+    let mut query = [0u8; 48];
+    let (header, tail) = query.split_at_mut(8);
+    header.copy_from_slice(&[
+        0x54,
+        code::GET,
+        message_id[0],
+        message_id[1],
+        token[0],
+        token[1],
+        token[2],
+        token[3],
+    ]);
+    let mut query_code = code::GET;
+    let mut message = inmemory_write::Message::new(&mut query_code, tail);
+    message
+        .add_option(option::URI_PATH, b".well-known")
+        .and_then(|()| message.add_option(option::URI_PATH, b"core"))
+        .and_then(|()| message.add_option(option::URI_QUERY, b"rt=core.rd"))
+        .expect("Buffer is large enough");
+    let query_len = 8 + message.finish();
+
+    #[allow(
+        clippy::indexing_slicing,
+        reason = "length is populated by the message"
+    )]
+    socket
+        .send_to(
+            &query[..query_len],
+            embassy_net::IpEndpoint::from(SocketAddr::new(group, 5683)),
+        )
+        .await
+        .ok()?;
+
+    let mut buffer = [0u8; 1152];
+    let deadline = embassy_time::Instant::now() + DISCOVERY_TIMEOUT;
+    loop {
+        let (len, metadata) = embassy_time::with_deadline(deadline, socket.recv_from(&mut buffer))
+            .await
+            .ok()?
+            .ok()?;
+        let Some(received) = buffer.get(..len) else {
+            continue;
+        };
+        let Some((header, tail)) = received.split_at_checked(8) else {
+            continue;
+        };
+        // Version 1 with a 4-byte token, 2.05 Content, and our token
+        if header.first().map(|first| first & 0xcf) != Some(0x44)
+            || header.get(1) != Some(&code::CONTENT)
+            || header.get(4..) != Some(&token[..])
+        {
+            continue;
+        }
+
+        let links = inmemory::Message::new(code::CONTENT, tail);
+        let path = registration_path(links.payload()).or_else(|| {
+            debug!("Resource directory did not indicate a registration interface.");
+            Path::try_from(DEFAULT_REGISTRATION_PATH).ok()
+        })?;
+        let address = match metadata.endpoint.addr {
+            embassy_net::IpAddress::Ipv4(addr) => IpAddr::V4(addr),
+            embassy_net::IpAddress::Ipv6(addr) => IpAddr::V6(addr),
+        };
+        let rd = SocketAddr::new(address, metadata.endpoint.port);
+        info!("Discovered resource directory at {:?}", metadata.endpoint);
+        return Some((rd, path));
+    }
+}
+
+/// Finds the path of the first link in a link-format document whose resource type is `core.rd`.
+///
+/// Only links with absolute paths are considered; attribute values containing commas or
+/// semicolons are not supported.
+fn registration_path(links: &[u8]) -> Option<Path> {
+    let links = core::str::from_utf8(links).ok()?;
+    links.split(',').find_map(|link| {
+        let mut parts = link.split(';');
+        let target = parts.next()?.trim().strip_prefix('<')?.strip_suffix('>')?;
+        if !target.starts_with('/') {
+            return None;
+        }
+        let is_rd = parts.any(|attribute| {
+            attribute
+                .trim()
+                .strip_prefix("rt=")
+                .is_some_and(|rt| rt.trim_matches('"').split(' ').any(|rt| rt == "core.rd"))
+        });
+        if is_rd {
+            Path::try_from(target).ok()
+        } else {
+            None
+        }
+    })
+}
+
+/// A registration or refresh request sent to the RD.
+struct RdRequest<'a> {
+    path: &'a str,
+    queries: &'a [&'a str],
+    /// Link-format payload; if empty, no Content-Format is indicated.
+    payload: &'a [u8],
+}
+
+struct RdResponse {
+    code: u8,
+    /// Location-Path of a newly created registration resource.
+    location: Option<Path>,
+}
+
+impl<S: coap_request::Stack> coap_request::Request<S> for RdRequest<'_> {
+    type Output = RdResponse;
+    type Carry = ();
+
+    async fn build_request(
+        &mut self,
+        request: &mut S::RequestMessage<'_>,
+    ) -> Result<(), S::RequestUnionError> {
+        build(self, request)
+    }
+
+    async fn process_response(
+        &mut self,
+        response: &S::ResponseMessage<'_>,
+        _carry: (),
+    ) -> RdResponse {
+        let mut location = Path::new();
+        let mut complete = true;
+        for o in response.options() {
+            if o.number() == option::LOCATION_PATH {
+                let segment = core::str::from_utf8(o.value()).unwrap_or("");
+                complete &= location.push('/').is_ok() && location.push_str(segment).is_ok();
+            }
+        }
+
+        RdResponse {
+            code: response.code().into(),
+            location: (complete && !location.is_empty()).then_some(location),
+        }
+    }
+}
+
+fn build<M: MinimalWritableMessage>(
+    rd: &RdRequest<'_>,
+    request: &mut M,
+) -> Result<(), M::UnionError> {
+    request.set_code(M::Code::new(code::POST)?);
+    let uri_path = M::OptionNumber::new(option::URI_PATH)?;
+    let path = rd.path.strip_prefix('/').unwrap_or(rd.path);
+    if !path.is_empty() {
+        for segment in path.split('/') {
+            request.add_option(uri_path, segment.as_bytes())?;
+        }
+    }
+    if !rd.payload.is_empty() {
+        request.add_option_uint(
+            M::OptionNumber::new(option::CONTENT_FORMAT)?,
+            CONTENT_FORMAT_LINK_FORMAT,
+        )?;
+    }
+    let uri_query = M::OptionNumber::new(option::URI_QUERY)?;
+    for query in rd.queries {
+        request.add_option(uri_query, query.as_bytes())?;
+    }
+    request.set_payload(rd.payload)?;
+    Ok(())
+}
@@ -0,0 +1,181 @@
+//! A CoAP client handle usable from any executor, thread or core.
+//!
+//! Requests are copied into a static slot and picked up by the CoAP task, which sends them through
+//! its [`CoAPRuntimeClient`][embedded_nal_coap::CoAPRuntimeClient] and copies the response back.
+//! Callers take turns; only one request is in flight through this handle at any time.
+
+use core::net::SocketAddr;
+
+use ariel_os_debug::log::debug;
+use coap_message::{Code as _, MinimalWritableMessage, OptionNumber as _, ReadableMessage};
+use embassy_sync::{
+    blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel, mutex::Mutex, signal::Signal,
+};
+
+use crate::CONCURRENT_REQUESTS;
+
+/// Maximum length of request and response payloads sent through the [`SharedClient`].
+pub const MAX_PAYLOAD_LEN: usize = ariel_os_utils::usize_from_env_or!(
+    "CONFIG_COAP_SHARED_CLIENT_PAYLOAD",
+    256,
+    "maximum payload length of requests through the shared CoAP client"
+);
+
+/// Maximum length of the path of requests sent through the [`SharedClient`].
+const MAX_PATH_LEN: usize = 64;
+
+/// A request handed over to the CoAP task.
+struct Job {
+    /// Sequence number that identifies the result belonging to this job.
+    ticket: u32,
+    peer: SocketAddr,
+    code: u8,
+    path: heapless::String<MAX_PATH_LEN>,
+    payload: heapless::Vec<u8, MAX_PAYLOAD_LEN>,
+}
+
+/// Serializes callers, and holds the next [`Job::ticket`].
+static CALLER: Mutex<CriticalSectionRawMutex, u32> = Mutex::new(0);
+static JOB: Channel<CriticalSectionRawMutex, Job, 1> = Channel::new();
+static RESULT: Signal<CriticalSectionRawMutex, (u32, Result<Response, RequestError>)> =
+    Signal::new();
+
+/// Response to a request sent through the [`SharedClient`].
+#[derive(Debug, Clone)]
+pub struct Response {
+    /// The response code (e.g., `0x45` for 2.05 Content).
+    pub code: u8,
+    /// The response payload.
+    pub payload: heapless::Vec<u8, MAX_PAYLOAD_LEN>,
+}
+
+/// Errors that can occur when sending a request through the [`SharedClient`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RequestError {
+    /// The request path exceeds the maximum length.
+    PathTooLong,
+    /// The request payload exceeds [`MAX_PAYLOAD_LEN`].
+    PayloadTooLarge,
+    /// The response payload exceeds [`MAX_PAYLOAD_LEN`].
+    ResponseTooLarge,
+    /// The request could not be sent, or no response was received.
+    Transport,
+}
+
+/// Handle to the system's CoAP client that can be used from any executor, thread or core.
+///
+/// Obtain it through [`shared_client()`](crate::shared_client).
+pub struct SharedClient {
+    _private: (),
+}
+
+pub(crate) static SHARED_CLIENT: SharedClient = SharedClient { _private: () };
+
+impl SharedClient {
+    /// Sends a request to `peer`, and returns its response.
+    ///
+    /// `code` is the request method (e.g., `1` for GET, `2` for POST), and `path` is split into
+    /// Uri-Path options at slashes (a leading slash is ignored).
+    ///
+    /// This asynchronously blocks until the CoAP stack is operational, and until requests sent
+    /// concurrently through this handle have completed.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the request or response do not fit into the available buffers, or if the request
+    /// fails on the transport level.
+    pub async fn request(
+        &self,
+        peer: SocketAddr,
+        code: u8,
+        path: &str,
+        payload: &[u8],
+    ) -> Result<Response, RequestError> {
+        let mut next_ticket = CALLER.lock().await;
+        let ticket = *next_ticket;
+        *next_ticket = ticket.wrapping_add(1);
+
+        let job = Job {
+            ticket,
+            peer,
+            code,
+            path: path.try_into().map_err(|()| RequestError::PathTooLong)?,
+            payload: payload
+                .try_into()
+                .map_err(|()| RequestError::PayloadTooLarge)?,
+        };
+
+        JOB.send(job).await;
+        // Results of earlier callers that were dropped while waiting are discarded.
+        loop {
+            let (result_ticket, result) = RESULT.wait().await;
+            if result_ticket == ticket {
+                return result;
+            }
+        }
+    }
+}
+
+/// Serves requests from the [`SharedClient`] through the CoAP task's `client`.
+pub(crate) async fn serve(
+    client: &embedded_nal_coap::CoAPRuntimeClient<'_, CONCURRENT_REQUESTS>,
+) -> core::convert::Infallible {
+    use coap_request::Stack as _;
+
+    loop {
+        let job = JOB.receive().await;
+        debug!("Forwarding shared client request");
+        let result = client
+            .to(job.peer)
+            .request(ForwardedRequest { job: &job })
+            .await
+            .map_err(|_| RequestError::Transport)
+            .and_then(|response| response);
+        RESULT.signal((job.ticket, result));
+    }
+}
+
+/// Builds a [`Job`] into a request, and copies the response.
+struct ForwardedRequest<'a> {
+    job: &'a Job,
+}
+
+impl<S: coap_request::Stack> coap_request::Request<S> for ForwardedRequest<'_> {
+    type Output = Result<Response, RequestError>;
+    type Carry = ();
+
+    async fn build_request(
+        &mut self,
+        request: &mut S::RequestMessage<'_>,
+    ) -> Result<(), S::RequestUnionError> {
+        build(self.job, request)
+    }
+
+    async fn process_response(
+        &mut self,
+        response: &S::ResponseMessage<'_>,
+        _carry: (),
+    ) -> Self::Output {
+        Ok(Response {
+            code: response.code().into(),
+            payload: response
+                .payload()
+                .try_into()
+                .map_err(|()| RequestError::ResponseTooLarge)?,
+        })
+    }
+}
+
+fn build<M: MinimalWritableMessage>(job: &Job, request: &mut M) -> Result<(), M::UnionError> {
+    request.set_code(M::Code::new(job.code)?);
+    let uri_path = M::OptionNumber::new(coap_numbers::option::URI_PATH)?;
+    let path = job.path.strip_prefix('/').unwrap_or(&job.path);
+    if !path.is_empty() {
+        for segment in path.split('/') {
+            request.add_option(uri_path, segment.as_bytes())?;
+        }
+    }
+    request.set_payload(&job.payload)?;
+    Ok(())
+}
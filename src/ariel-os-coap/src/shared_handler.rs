@@ -0,0 +1,34 @@
+//! Sharing of the server's handler between transports and notifications.
+
+use core::cell::RefCell;
+
+/// A [`coap_handler::Handler`] that is shared between transports.
+///
+/// Each call borrows the handler. As handler calls are synchronous, and transports do not hold
+/// the request data across await points, there are no conflicting borrows.
+pub(crate) struct SharedHandler<'a, H>(pub(crate) &'a RefCell<H>);
+
+impl<H: coap_handler::Handler> coap_handler::Handler for SharedHandler<'_, H> {
+    type RequestData = H::RequestData;
+    type ExtractRequestError = H::ExtractRequestError;
+    type BuildResponseError<M: coap_message::MinimalWritableMessage> = H::BuildResponseError<M>;
+
+    fn extract_request_data<M: coap_message::ReadableMessage>(
+        &mut self,
+        request: &M,
+    ) -> Result<Self::RequestData, Self::ExtractRequestError> {
+        self.0.borrow_mut().extract_request_data(request)
+    }
+
+    fn estimate_length(&mut self, request: &Self::RequestData) -> usize {
+        self.0.borrow_mut().estimate_length(request)
+    }
+
+    fn build_response<M: coap_message::MutableWritableMessage>(
+        &mut self,
+        response: &mut M,
+        request: Self::RequestData,
+    ) -> Result<(), Self::BuildResponseError<M>> {
+        self.0.borrow_mut().build_response(response, request)
+    }
+}
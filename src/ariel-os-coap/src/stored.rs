@@ -2,7 +2,11 @@
 
 use ariel_os_debug::log::{Cbor, debug, info};
 use cbor_macro::cbo;
+use coapcore::scope::Scope;
 use coapcore::seccfg::ServerSecurityConfig;
+use coapcore::{CredentialError, CredentialErrorKind};
+
+pub(crate) mod peers;
 
 mod flash_peers {
     include!(concat!(env!("OUT_DIR"), "/peers.rs"));
@@ -19,11 +23,45 @@ struct StoredPolicy {
 }
 
 impl ServerSecurityConfig for StoredPolicy {
-    // FIXME: Decide based on peers.rs input (but so far tokens are not implemented)
-    const PARSES_TOKENS: bool = false;
+    const PARSES_TOKENS: bool = flash_peers::PARSES_TOKENS;
     const HAS_EDHOC: bool = true;
     type GeneralClaims = StoredClaims;
 
+    fn decrypt_symmetric_token<'buf>(
+        &self,
+        headers: &coapcore::ace::HeaderMap<'_>,
+        aad: &[u8],
+        ciphertext_buffer: &'buf mut [u8],
+    ) -> Result<(StoredClaims, coapcore::ace::CwtClaimsSet<'buf>), CredentialError> {
+        // Failed decryption may leave the buffer altered, so only a single AS gets to try: the
+        // first one whose configured key ID (if any) matches.
+        let (_, issuer) = flash_peers::symmetric_issuers()
+            .find(|(kid, _)| kid.is_none_or(|kid| headers.kid == Some(kid)))
+            .ok_or(CredentialErrorKind::KeyNotPresent)?;
+
+        let (claims, claims_set) =
+            issuer.decrypt_symmetric_token(headers, aad, ciphertext_buffer)?;
+        debug!("Token from symmetric AS accepted.");
+        Ok((claims.into(), claims_set))
+    }
+
+    fn verify_asymmetric_token<'b>(
+        &self,
+        headers: &coapcore::ace::HeaderMap<'_>,
+        signed_data: &[u8],
+        signature: &[u8],
+        signed_payload: &'b [u8],
+    ) -> Result<(StoredClaims, coapcore::ace::CwtClaimsSet<'b>), CredentialError> {
+        let (claims, claims_set) = flash_peers::asymmetric_issuers()
+            .map(|issuer| {
+                issuer.verify_asymmetric_token(headers, signed_data, signature, signed_payload)
+            })
+            .find(Result::is_ok)
+            .unwrap_or_else(|| Err(CredentialErrorKind::KeyNotPresent.into()))?;
+        debug!("Token from asymmetric AS accepted.");
+        Ok((claims.into(), claims_set))
+    }
+
     fn own_edhoc_credential(&self) -> Option<(lakers::Credential, lakers::BytesP256ElemLen)> {
         Some(self.own_edhoc_credential)
     }
@@ -38,16 +76,17 @@ impl ServerSecurityConfig for StoredPolicy {
         );
 
         for (credential, scope) in flash_peers::kccs() {
-            if credential.by_kid().is_ok_and(|by_kid| by_kid == id_cred_x)
-                || credential
-                    .by_value()
-                    .is_ok_and(|by_value| by_value == id_cred_x)
-            {
+            if credential_matches(&credential, &id_cred_x) {
                 debug!("Credential recognized.");
-                return Some((credential, StoredClaims { scope }));
+                return Some((credential, StoredClaims::unbounded(scope)));
             }
         }
 
+        if let Some((credential, scope)) = peers::find(&id_cred_x) {
+            debug!("Credential recognized from runtime peers.");
+            return Some((credential, StoredClaims::unbounded(scope)));
+        }
+
         // FIXME: This should be a default behavior -- but should it be part of a utility function
         // for expand_id_cred_x, or should it be where that is called?
         if let Some(credential_by_value) = id_cred_x.get_ccs() {
@@ -61,20 +100,60 @@ impl ServerSecurityConfig for StoredPolicy {
         None
     }
 
+    fn expand_id_cred_r(&self, id_cred_r: lakers::IdCred) -> Option<lakers::Credential> {
+        debug!(
+            "Server presented ID_CRED_R {}",
+            Cbor(id_cred_r.as_full_value())
+        );
+
+        // Only configured peers are trusted as servers; a credential sent by value is not.
+        flash_peers::kccs()
+            .map(|(credential, _scope)| credential)
+            .find(|credential| credential_matches(credential, &id_cred_r))
+            .or_else(|| peers::find(&id_cred_r).map(|(credential, _scope)| credential))
+    }
+
     fn nosec_authorization(&self) -> Option<Self::GeneralClaims> {
-        flash_peers::unauthenticated_scope().map(|scope| StoredClaims { scope })
+        flash_peers::unauthenticated_scope().map(StoredClaims::unbounded)
     }
 }
 
-/// Generates a private key and some credential matching it.
+/// Returns whether the peer presenting `id_cred_x` is the holder of `credential`.
+fn credential_matches(credential: &lakers::Credential, id_cred_x: &lakers::IdCred) -> bool {
+    credential.by_kid().is_ok_and(|by_kid| by_kid == *id_cred_x)
+        || credential
+            .by_value()
+            .is_ok_and(|by_value| by_value == *id_cred_x)
+}
+
+/// Storage key of the server's credential and private key.
 ///
-/// The 60 byte is kind of arbitrary; it's long enough for this, but needs to also accommodate
-/// anything that gets loaded. It currently contains an Key ID b"", which is convenient because it
-/// enables sending the key by reference.
+/// Storage format: ([u8], [u8; 32]), where the former is a CCS, and the latter the corresponding
+/// key. We may need to extend the latter to be a COSE_Key when crypto agility becomes a thing.
+const OWN_CREDENTIAL_KEY: &str = "ariel-os-coap.own-edhoc-credential";
+
+/// Storage key of the server's private key in encrypted storage.
+///
+/// Only the key is stored, as the credential together with it would not fit an encrypted record;
+/// the credential is derived from it.
+#[cfg(feature = "storage-encrypted")]
+const OWN_PRIVATE_KEY_KEY: &str = "ariel-os-coap.own-edhoc-key";
+
+/// Generates a private key and some credential matching it.
 fn generate_credpair() -> (heapless::Vec<u8, 60>, lakers::BytesP256ElemLen) {
     use lakers::CryptoTrait;
     let mut crypto = lakers_crypto_rustcrypto::Crypto::new(ariel_os_random::crypto_rng());
     let (private, public) = crypto.p256_generate_key_pair();
+    debug!("Generated private/public key pair.");
+    (credential_for(&public), private)
+}
+
+/// Builds the credential for the public key whose x coordinate is `public`.
+///
+/// The 60 byte is kind of arbitrary; it's long enough for this, but needs to also accommodate
+/// anything that gets loaded. It currently contains an Key ID b"", which is convenient because it
+/// enables sending the key by reference.
+fn credential_for(public: &lakers::BytesP256ElemLen) -> heapless::Vec<u8, 60> {
     let mut credential = heapless::Vec::from_slice(&cbo!(
         r#"{
         /cnf/ 8: {/ COSE_Key / 1: {
@@ -87,31 +166,88 @@ fn generate_credpair() -> (heapless::Vec<u8, 60>, lakers::BytesP256ElemLen) {
     ))
     .expect("Fits by construction");
     let public_start = credential.len() - 32;
-    credential[public_start..].copy_from_slice(&public);
-    debug!("Generated private/public key pair.");
-    (credential, private)
+    credential[public_start..].copy_from_slice(public);
+    credential
 }
 
-impl StoredPolicy {
-    async fn load() -> Self {
-        // Storage format: ([u8], [u8; 32]), where the former is a CCS, and the latter the
-        // corresponding key. We may need to extend the latter to be a COSE_Key when crypto agility
-        // becomes a thing.
-        const OWN_CREDENTIAL_KEY: &str = "ariel-os-coap.own-edhoc-credential";
-
-        let (credential, key) = match ariel_os_storage::get(OWN_CREDENTIAL_KEY)
-            .await
-            .expect("flash error prevents startup")
-        {
-            Some(credpair) => credpair,
-            None => {
-                let credpair = generate_credpair();
-                ariel_os_storage::insert(OWN_CREDENTIAL_KEY, credpair.clone())
+/// Loads the server's credential and private key from storage, creating them if needed.
+#[cfg(not(feature = "storage-encrypted"))]
+async fn load_credpair() -> (heapless::Vec<u8, 60>, lakers::BytesP256ElemLen) {
+    if let Some(credpair) = ariel_os_storage::get(OWN_CREDENTIAL_KEY)
+        .await
+        .expect("flash error prevents startup")
+    {
+        return credpair;
+    }
+
+    let credpair = generate_credpair();
+    ariel_os_storage::insert(OWN_CREDENTIAL_KEY, credpair.clone())
+        .await
+        .expect("flash error prevents startup");
+    credpair
+}
+
+/// Loads the server's private key from encrypted storage, creating it if needed, and derives the
+/// credential from it.
+///
+/// The private key is the most sensitive item in storage; this way, it does not sit in plain text.
+/// A key stored in plain text by a firmware built without the `storage-encrypted` feature is
+/// taken over, and a key that can not be read any more (e.g., because the build-time secret of
+/// encrypted storage changed) is replaced by a new one.
+#[cfg(feature = "storage-encrypted")]
+async fn load_credpair() -> (heapless::Vec<u8, 60>, lakers::BytesP256ElemLen) {
+    use ariel_os_debug::log::warn;
+    use ariel_os_storage::encrypted;
+    use p256::elliptic_curve::sec1::ToEncodedPoint as _;
+
+    let stored = match encrypted::get(OWN_PRIVATE_KEY_KEY).await {
+        Ok(stored) => stored,
+        Err(error) if encrypted::is_tampered(&error) => {
+            warn!("Stored private key fails authentication, replacing it.");
+            None
+        }
+        Err(_) => panic!("flash error prevents startup"),
+    };
+
+    let private = match stored {
+        Some(private) => private,
+        None => {
+            let plain: Option<(heapless::Vec<u8, 60>, lakers::BytesP256ElemLen)> =
+                ariel_os_storage::get(OWN_CREDENTIAL_KEY)
                     .await
-                    .expect("flash error prevents startup");
-                credpair
+                    .ok()
+                    .flatten();
+            let private = match plain {
+                Some((_, private)) => {
+                    info!("Moving private key into encrypted storage.");
+                    private
+                }
+                None => generate_credpair().1,
+            };
+            encrypted::insert(OWN_PRIVATE_KEY_KEY, private)
+                .await
+                .expect("flash error prevents startup");
+            // The plain-text record stays in flash until its page gets erased, and records can
+            // not be removed on STM32 at all.
+            #[cfg(not(context = "stm32"))]
+            if plain.is_some() && ariel_os_storage::remove(OWN_CREDENTIAL_KEY).await.is_err() {
+                warn!("Failed to remove plain-text private key.");
             }
-        };
+            private
+        }
+    };
+
+    let public = p256::SecretKey::from_bytes(&private.into())
+        .expect("stored keys are valid")
+        .public_key()
+        .to_encoded_point(false);
+    let public = (*public.x().expect("encoded uncompressed")).into();
+    (credential_for(&public), private)
+}
+
+impl StoredPolicy {
+    async fn load() -> Self {
+        let (credential, key) = load_credpair().await;
 
         info!("CoAP server identity: {}", Cbor(&credential));
 
@@ -119,6 +255,8 @@ impl StoredPolicy {
             lakers::Credential::parse_ccs(&credential).expect("Processable by construction");
         let own_edhoc_credential = (credential, key);
 
+        peers::load().await;
+
         Self {
             own_edhoc_credential,
         }
@@ -127,18 +265,50 @@ impl StoredPolicy {
 
 #[derive(Debug)]
 struct StoredClaims {
-    scope: coapcore::scope::UnionScope,
+    scope: StoredScope,
+    time_constraint: coapcore::time::TimeConstraint,
+}
+
+impl StoredClaims {
+    /// Claims from configured credentials, which are valid at any time.
+    fn unbounded(scope: coapcore::scope::UnionScope) -> Self {
+        Self {
+            scope: StoredScope(scope),
+            time_constraint: coapcore::time::TimeConstraint::unbounded(),
+        }
+    }
+}
+
+impl From<coapcore::seccfg::ConfigBuilderClaims> for StoredClaims {
+    /// Takes over claims from an ACE token, including its expiry.
+    fn from(claims: coapcore::seccfg::ConfigBuilderClaims) -> Self {
+        Self {
+            scope: StoredScope(claims.scope),
+            time_constraint: claims.time_constraint,
+        }
+    }
+}
+
+/// The scope of [`StoredClaims`], which additionally limits the permissions a requester can grant
+/// to peers through [`peers::PeerManagement`] to its own.
+#[derive(Debug)]
+struct StoredScope(coapcore::scope::UnionScope);
+
+impl Scope for StoredScope {
+    fn request_is_allowed<M: coap_message::ReadableMessage>(&self, request: &M) -> bool {
+        self.0.request_is_allowed(request) && peers::grants_are_held(&self.0, request)
+    }
 }
 
 impl coapcore::GeneralClaims for StoredClaims {
-    type Scope = coapcore::scope::UnionScope;
+    type Scope = StoredScope;
 
     fn scope(&self) -> &Self::Scope {
         &self.scope
     }
 
     fn time_constraint(&self) -> coapcore::time::TimeConstraint {
-        coapcore::time::TimeConstraint::unbounded()
+        self.time_constraint
     }
 
     fn is_important(&self) -> bool {
@@ -0,0 +1,631 @@
+//! Peers that are managed at runtime, in addition to those configured at build time in
+//! `peers.yml`.
+//!
+//! The list is kept in RAM, where the synchronous security policy can consult it, and is persisted
+//! as a storage blob from the server task whenever it is changed through [`PeerManagement`].
+
+use core::cell::RefCell;
+
+use ariel_os_debug::log::{error, info, warn};
+use ariel_os_storage::blob;
+use coap_message::{
+    Code as _, MessageOption as _, MinimalWritableMessage, MutableWritableMessage,
+    OptionNumber as _, ReadableMessage,
+};
+use coap_message_utils::{Error as CoAPError, OptionsExt as _};
+use coapcore::scope::UnionScope;
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use embassy_sync::signal::Signal;
+use embedded_io_async::{Read as _, Write as _};
+use serde::{Deserialize, Serialize};
+
+/// Maximum number of peers that can be added at runtime.
+const MAX_PEERS: usize = ariel_os_utils::usize_from_env_or!(
+    "CONFIG_COAP_MAX_RUNTIME_PEERS",
+    4,
+    "maximum number of CoAP peers that can be added at runtime"
+);
+
+/// Maximum length of a peer's CCS.
+const MAX_CREDENTIAL_LEN: usize = 128;
+/// Maximum length of a peer's scope; this matches what [`coapcore::scope::AifValue`] can hold.
+const MAX_SCOPE_LEN: usize = 64;
+
+/// Upper bound of the serialized peer list (postcard uses at most 2 bytes for each length at the
+/// given sizes).
+const SERIALIZED_LEN: usize = 2 + MAX_PEERS * (2 + MAX_CREDENTIAL_LEN + 2 + MAX_SCOPE_LEN);
+
+const _: () = assert!(
+    MAX_PEERS < 1 << 14,
+    "peer count exceeds a 2-byte postcard length"
+);
+
+/// Blob key under which the peer list is persisted.
+const PEERS_KEY: &str = "ariel-os-coap.peers";
+
+/// Content-Format number of `application/cbor`.
+const CONTENT_FORMAT_CBOR: u16 = 60;
+
+type Peers = heapless::Vec<Peer, MAX_PEERS>;
+
+static PEERS: Mutex<CriticalSectionRawMutex, RefCell<Peers>> =
+    Mutex::new(RefCell::new(heapless::Vec::new()));
+
+/// Signaled whenever [`PEERS`] changed and needs to be persisted.
+static CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Path at which [`with_system_resources()`][crate::with_system_resources] mounts
+/// [`PeerManagement`].
+pub(crate) const PATH: &[&str] = &["peers"];
+
+/// A peer's credential along with the permissions it is granted.
+///
+/// Both items are validated at construction time, so they can be parsed again infallibly when
+/// they are used.
+#[derive(Clone, Serialize, Deserialize)]
+struct Peer {
+    /// A CCS (CWT Claims Set) containing the peer's public key.
+    credential: heapless::Vec<u8, MAX_CREDENTIAL_LEN>,
+    /// An AIF scope in the REST-specific model.
+    scope: heapless::Vec<u8, MAX_SCOPE_LEN>,
+}
+
+impl Peer {
+    fn new(credential: &[u8], scope: &[u8]) -> Option<Self> {
+        lakers::Credential::parse_ccs(credential).ok()?;
+        coapcore::scope::AifValue::parse(scope).ok()?;
+
+        Some(Self {
+            credential: heapless::Vec::from_slice(credential).ok()?,
+            scope: heapless::Vec::from_slice(scope).ok()?,
+        })
+    }
+
+    fn parse(&self) -> Option<(lakers::Credential, coapcore::scope::UnionScope)> {
+        Some((
+            lakers::Credential::parse_ccs(&self.credential).ok()?,
+            coapcore::scope::AifValue::parse(&self.scope).ok()?.into(),
+        ))
+    }
+
+    /// Decodes a peer from its CBOR representation, `[credential: bstr, scope: AIF]`.
+    fn decode(decoder: &mut minicbor::Decoder<'_>) -> Option<Self> {
+        if decoder.array().ok()? != Some(2) {
+            return None;
+        }
+        let credential = decoder.bytes().ok()?;
+        let scope_start = decoder.position();
+        decoder.skip().ok()?;
+        let scope = decoder.input().get(scope_start..decoder.position())?;
+        Self::new(credential, scope)
+    }
+
+    /// Length of the CBOR representation produced by [`encode_list()`].
+    fn encoded_len(&self) -> usize {
+        1 + cbor_head_len(self.credential.len()) + self.credential.len() + self.scope.len()
+    }
+
+    /// Returns whether `requester` holds all the permissions granted to this peer.
+    fn is_granted_by(&self, requester: &UnionScope) -> bool {
+        coapcore::scope::AifValue::parse(&self.scope).is_ok_and(|scope| requester.covers(&scope))
+    }
+}
+
+/// Length of the head of a CBOR item whose argument is `len` (e.g. the length of a byte string).
+fn cbor_head_len(len: usize) -> usize {
+    match len {
+        0..24 => 1,
+        24..0x100 => 2,
+        0x100..0x1_0000 => 3,
+        _ if u32::try_from(len).is_ok() => 5,
+        _ => 9,
+    }
+}
+
+/// Length of the CBOR representation of `peers` produced by [`encode_list()`].
+fn encoded_list_len(peers: &[Peer]) -> usize {
+    cbor_head_len(peers.len()) + peers.iter().map(Peer::encoded_len).sum::<usize>()
+}
+
+/// Encodes `peers` as `[* [credential, scope]]` into `buffer`, returning the used length.
+///
+/// # Panics
+///
+/// Panics if `buffer` is shorter than [`encoded_list_len()`].
+fn encode_list(peers: &[Peer], buffer: &mut [u8]) -> usize {
+    let mut encoder = minicbor::Encoder::new(minicbor::encode::write::Cursor::new(buffer));
+    encoder
+        .array(peers.len() as u64)
+        .expect("Sufficient size was requested");
+    for peer in peers {
+        encoder
+            .array(2)
+            .and_then(|encoder| encoder.bytes(&peer.credential))
+            .expect("Sufficient size was requested");
+        minicbor::encode::Write::write_all(encoder.writer_mut(), &peer.scope)
+            .expect("Sufficient size was requested");
+    }
+    encoder.into_writer().position()
+}
+
+/// Returns whether `requester` holds all the permissions that `request` grants to peers.
+///
+/// This is part of the authorization of a request, and is evaluated by the server along with the
+/// requester's scope. Requests to other paths than [`PATH`] grant nothing, and neither do malformed
+/// requests, which [`PeerManagement`] rejects.
+pub(super) fn grants_are_held<M: ReadableMessage>(requester: &UnionScope, request: &M) -> bool {
+    let mut path = request
+        .options()
+        .filter(|o| o.number() == coap_numbers::option::URI_PATH);
+    let at_path = PATH
+        .iter()
+        .all(|segment| path.next().is_some_and(|o| o.value() == segment.as_bytes()))
+        && path.next().is_none();
+    if !at_path {
+        return true;
+    }
+
+    let held = granted_peers(request.code().into(), request.payload())
+        .is_none_or(|peers| peers.iter().all(|peer| peer.is_granted_by(requester)));
+    if !held {
+        warn!("CoAP peer rejected: its scope exceeds that of the requester.");
+    }
+    held
+}
+
+/// Decodes the peers to which a request with the given method and payload grants permissions.
+///
+/// Returns `None` if the payload is malformed.
+fn granted_peers(method: u8, payload: &[u8]) -> Option<Peers> {
+    use coap_numbers::code;
+
+    match method {
+        code::POST => decode_peer(payload).map(|peer| core::iter::once(peer).collect()),
+        code::PUT => decode_list(payload),
+        code::IPATCH => Some(
+            decode_patch(payload)?
+                .into_iter()
+                .filter_map(|(_, replacement)| replacement)
+                .collect(),
+        ),
+        _ => Some(Peers::new()),
+    }
+}
+
+/// Finds the runtime peer whose credential matches `id_cred_x`.
+pub(super) fn find(
+    id_cred_x: &lakers::IdCred,
+) -> Option<(lakers::Credential, coapcore::scope::UnionScope)> {
+    PEERS.lock(|peers| {
+        peers
+            .borrow()
+            .iter()
+            .filter_map(Peer::parse)
+            .find(|(credential, _)| super::credential_matches(credential, id_cred_x))
+    })
+}
+
+/// Loads the persisted peer list.
+///
+/// # Panics
+///
+/// Panics if the storage is not accessible.
+pub(super) async fn load() {
+    let Some(mut reader) = blob::reader(PEERS_KEY)
+        .await
+        .expect("flash error prevents startup")
+    else {
+        return;
+    };
+
+    let mut buffer = [0; SERIALIZED_LEN];
+    let Some(serialized) = usize::try_from(reader.len())
+        .ok()
+        .and_then(|len| buffer.get_mut(..len))
+    else {
+        warn!("Stored CoAP peer list exceeds the configured size, ignoring it.");
+        return;
+    };
+    reader
+        .read_exact(serialized)
+        .await
+        .expect("flash error prevents startup");
+
+    match postcard::from_bytes::<Peers>(serialized) {
+        Ok(peers) => {
+            info!("Loaded {} CoAP peers from storage.", peers.len());
+            PEERS.lock(|stored| *stored.borrow_mut() = peers);
+        }
+        Err(_) => warn!("Stored CoAP peer list is unreadable, ignoring it."),
+    }
+}
+
+/// Persists the peer list whenever it was changed.
+///
+/// This needs to run alongside the CoAP server, as the resource handler can not access storage.
+pub(crate) async fn persist() -> core::convert::Infallible {
+    let mut buffer = [0; SERIALIZED_LEN];
+
+    loop {
+        CHANGED.wait().await;
+
+        let len = PEERS.lock(|peers| {
+            postcard::to_slice(&*peers.borrow(), &mut buffer)
+                .expect("buffer is sized for the largest peer list")
+                .len()
+        });
+        let (serialized, _) = buffer.split_at(len);
+
+        if store(serialized).await.is_err() {
+            error!("Failed to persist the CoAP peer list.");
+        }
+    }
+}
+
+async fn store(serialized: &[u8]) -> Result<(), blob::Error> {
+    let mut writer = blob::writer(PEERS_KEY).await?;
+    writer.write_all(serialized).await?;
+    writer.finish().await
+}
+
+/// Applies `change` to the peer list, and schedules persisting the result if it succeeded.
+fn modify(change: impl FnOnce(&mut Peers) -> Option<()>) -> Result<(), CoAPError> {
+    PEERS
+        .lock(|peers| change(&mut peers.borrow_mut()))
+        .ok_or_else(CoAPError::bad_request)?;
+    CHANGED.signal(());
+    Ok(())
+}
+
+/// Decodes a single peer that makes up all of `payload`.
+fn decode_peer(payload: &[u8]) -> Option<Peer> {
+    let mut decoder = minicbor::Decoder::new(payload);
+    Peer::decode(&mut decoder).filter(|_| decoder.position() == payload.len())
+}
+
+/// Decodes a full peer list, `[* [credential, scope]]`.
+fn decode_list(payload: &[u8]) -> Option<Peers> {
+    let mut decoder = minicbor::Decoder::new(payload);
+    let mut peers = Peers::new();
+    for _ in 0..decoder.array().ok()?? {
+        peers.push(Peer::decode(&mut decoder)?).ok()?;
+    }
+    (decoder.position() == payload.len()).then_some(peers)
+}
+
+/// Entries of a patch, each of which replaces or removes the peer at an index.
+type Patch = heapless::Vec<(usize, Option<Peer>), MAX_PEERS>;
+
+/// Decodes a patch, `{* index => [credential, scope] / null}`.
+fn decode_patch(patch: &[u8]) -> Option<Patch> {
+    let mut decoder = minicbor::Decoder::new(patch);
+    let mut entries = Patch::new();
+    for _ in 0..decoder.map().ok()?? {
+        let index = usize::try_from(decoder.u32().ok()?).ok()?;
+        let replacement = if decoder.datatype().ok()? == minicbor::data::Type::Null {
+            decoder.null().ok()?;
+            None
+        } else {
+            Some(Peer::decode(&mut decoder)?)
+        };
+        entries.push((index, replacement)).ok()?;
+    }
+    (decoder.position() == patch.len()).then_some(entries)
+}
+
+/// Applies a patch to `peers`.
+///
+/// Entries are replaced before any are removed, so all indices refer to the list as it was
+/// before the patch; the patch is applied completely or not at all.
+fn apply_patch(peers: &mut Peers, patch: Patch) -> Option<()> {
+    let mut slots: heapless::Vec<Option<Peer>, MAX_PEERS> =
+        peers.iter().cloned().map(Some).collect();
+    for (index, replacement) in patch {
+        *slots.get_mut(index)? = replacement;
+    }
+    *peers = slots.into_iter().flatten().collect();
+    Some(())
+}
+
+/// Outcome of a request to [`PeerManagement`].
+#[doc(hidden)]
+pub enum Outcome {
+    /// The peer list is to be sent.
+    List,
+    /// The peer list was changed; the response is empty with the given code.
+    Changed(u8),
+}
+
+/// CoAP resource through which the EDHOC peers known to the device are managed at runtime.
+///
+/// The resource is mounted at `/peers` by [`with_system_resources()`][crate::with_system_resources];
+/// access to it is granted through the scopes in `peers.yml` like to any other resource.
+/// A requester can only grant permissions to peers that it holds itself; requests adding peers
+/// with a wider scope are rejected like requests outside of the requester's scope.
+/// Peers are represented as CBOR arrays `[credential, scope]` of a CCS (CWT Claims Set) in a byte
+/// string and an AIF scope in the REST-specific model (as in ACE tokens), and it supports these
+/// methods:
+///
+/// * `GET` lists all peers added at runtime as an array of peers.
+/// * `POST` with a peer adds it.
+/// * `PUT` with an array of peers replaces all peers added at runtime.
+/// * `iPATCH` with a map from list indices to peers (or `null`) replaces (e.g. for key rotation)
+///   or removes the peers at those indices.
+/// * `DELETE` removes all peers added at runtime.
+///
+/// Peers configured in `peers.yml` are not listed, and can not be changed. Changes take effect
+/// for new EDHOC sessions; established security contexts stay active until they are evicted.
+pub struct PeerManagement(());
+
+impl PeerManagement {
+    /// Creates the resource; it is only mounted at [`PATH`], where its requests are authorized.
+    pub(crate) const fn new() -> Self {
+        Self(())
+    }
+}
+
+impl coap_handler::Handler for PeerManagement {
+    type RequestData = Outcome;
+    type ExtractRequestError = CoAPError;
+    type BuildResponseError<M: MinimalWritableMessage> = M::UnionError;
+
+    fn extract_request_data<M: ReadableMessage>(
+        &mut self,
+        request: &M,
+    ) -> Result<Self::RequestData, Self::ExtractRequestError> {
+        use coap_numbers::{code, option};
+
+        request
+            .options()
+            .filter(|o| o.number() != option::URI_PATH)
+            .ignore_elective_others()?;
+
+        let payload = request.payload();
+        let method: u8 = request.code().into();
+        match method {
+            code::GET => Ok(Outcome::List),
+            code::POST => {
+                let peer = decode_peer(payload).ok_or_else(CoAPError::bad_request)?;
+                modify(|peers| peers.push(peer).ok())?;
+                info!("CoAP peer added.");
+                Ok(Outcome::Changed(code::CREATED))
+            }
+            code::PUT => {
+                let new = decode_list(payload).ok_or_else(CoAPError::bad_request)?;
+                modify(|peers| {
+                    *peers = new;
+                    Some(())
+                })?;
+                info!("CoAP peers replaced.");
+                Ok(Outcome::Changed(code::CHANGED))
+            }
+            code::IPATCH => {
+                let patch = decode_patch(payload).ok_or_else(CoAPError::bad_request)?;
+                modify(|peers| apply_patch(peers, patch))?;
+                info!("CoAP peers updated.");
+                Ok(Outcome::Changed(code::CHANGED))
+            }
+            code::DELETE => {
+                modify(|peers| {
+                    peers.clear();
+                    Some(())
+                })?;
+                info!("CoAP peers removed.");
+                Ok(Outcome::Changed(code::DELETED))
+            }
+            _ => Err(CoAPError::method_not_allowed()),
+        }
+    }
+
+    fn estimate_length(&mut self, _request: &Self::RequestData) -> usize {
+        SERIALIZED_LEN
+    }
+
+    fn build_response<M: MutableWritableMessage>(
+        &mut self,
+        response: &mut M,
+        request: Self::RequestData,
+    ) -> Result<(), Self::BuildResponseError<M>> {
+        use coap_numbers::{code, option};
+
+        match request {
+            Outcome::Changed(changed) => {
+                response.set_code(M::Code::new(changed)?);
+            }
+            Outcome::List => {
+                response.set_code(M::Code::new(code::CONTENT)?);
+                response.add_option_uint(
+                    M::OptionNumber::new(option::CONTENT_FORMAT)?,
+                    CONTENT_FORMAT_CBOR,
+                )?;
+
+                PEERS.lock(|peers| -> Result<(), M::UnionError> {
+                    let peers = peers.borrow();
+                    let payload = response.payload_mut_with_len(encoded_list_len(&peers))?;
+                    let written = encode_list(&peers, payload);
+                    response.truncate(written)?;
+                    Ok(())
+                })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::indexing_slicing, reason = "panicking is fine in tests")]
+mod test {
+    use super::*;
+
+    use hexlit::hex;
+
+    const CREDENTIAL: &[u8] = &hex!(
+        "A2026008A101A5010202410A2001215820BBC34960526EA4D32E940CAD2A234148DDC21791A12AFBCBAC93622046DD44F02258204519E257236B2A0CE2023F0931F1F386CA7AFDA64FCDE0108C224C51EABF6072"
+    );
+    /// `[["/a", GET | PUT]]`
+    const SCOPE_A: &[u8] = &[0x81, 0x82, 0x62, b'/', b'a', 0x05];
+    /// `[["/a", GET]]`
+    const SCOPE_A_GET: &[u8] = &[0x81, 0x82, 0x62, b'/', b'a', 0x01];
+    /// `[["/b", GET]]`
+    const SCOPE_B: &[u8] = &[0x81, 0x82, 0x62, b'/', b'b', 0x01];
+
+    fn peer(scope: &[u8]) -> Peer {
+        Peer::new(CREDENTIAL, scope).unwrap()
+    }
+
+    fn encode(peers: &[Peer]) -> heapless::Vec<u8, SERIALIZED_LEN> {
+        let mut buffer = heapless::Vec::new();
+        buffer.resize(encoded_list_len(peers), 0).unwrap();
+        let written = encode_list(peers, &mut buffer);
+        buffer.truncate(written);
+        buffer
+    }
+
+    fn encode_patch(entries: &[(u8, Option<&[u8]>)]) -> heapless::Vec<u8, 1024> {
+        let mut buffer = heapless::Vec::new();
+        buffer.resize(1024, 0).unwrap();
+        let mut encoder =
+            minicbor::Encoder::new(minicbor::encode::write::Cursor::new(&mut buffer[..]));
+        encoder.map(entries.len() as u64).unwrap();
+        for (index, scope) in entries {
+            encoder.u8(*index).unwrap();
+            match scope {
+                Some(scope) => {
+                    encoder.array(2).unwrap().bytes(CREDENTIAL).unwrap();
+                    minicbor::encode::Write::write_all(encoder.writer_mut(), scope).unwrap();
+                }
+                None => {
+                    encoder.null().unwrap();
+                }
+            }
+        }
+        let written = encoder.into_writer().position();
+        buffer.truncate(written);
+        buffer
+    }
+
+    fn scopes(peers: &Peers) -> heapless::Vec<&[u8], MAX_PEERS> {
+        peers.iter().map(|peer| &peer.scope[..]).collect()
+    }
+
+    #[test]
+    fn head_len() {
+        for len in [0, 23, 24, 255, 256, 65535, 65536, 1 << 20] {
+            let mut buffer = [0u8; 9];
+            let mut encoder =
+                minicbor::Encoder::new(minicbor::encode::write::Cursor::new(&mut buffer[..]));
+            encoder.array(len as u64).unwrap();
+            assert_eq!(
+                cbor_head_len(len),
+                encoder.into_writer().position(),
+                "{len}"
+            );
+        }
+    }
+
+    #[test]
+    fn list_round_trip() {
+        let mut peers = Peers::new();
+        for _ in 0..=MAX_PEERS {
+            let encoded = encode(&peers);
+            assert_eq!(encoded.len(), encoded_list_len(&peers));
+            assert_eq!(scopes(&decode_list(&encoded).unwrap()), scopes(&peers));
+            let _ = peers.push(peer(SCOPE_A));
+        }
+    }
+
+    #[test]
+    fn list_rejects_malformed() {
+        let encoded = encode(&[peer(SCOPE_A)]);
+        assert!(decode_list(&encoded[..encoded.len() - 1]).is_none());
+
+        let mut trailing = encoded.clone();
+        trailing.push(0).unwrap();
+        assert!(decode_list(&trailing).is_none());
+
+        // A peer with an additional item.
+        let mut invalid = encoded.clone();
+        invalid[1] = 0x83;
+        assert!(decode_list(&invalid).is_none());
+    }
+
+    #[test]
+    fn patch() {
+        let mut peers: Peers = [peer(SCOPE_A), peer(SCOPE_A_GET), peer(SCOPE_B)]
+            .into_iter()
+            .collect();
+
+        // Removing index 0 does not shift the index of the peer replaced afterwards.
+        let patch = decode_patch(&encode_patch(&[(0, None), (2, Some(SCOPE_A))])).unwrap();
+        apply_patch(&mut peers, patch).unwrap();
+        assert_eq!(scopes(&peers), [SCOPE_A_GET, SCOPE_A]);
+
+        // Out-of-range indices leave the list unchanged.
+        let patch = decode_patch(&encode_patch(&[(0, None), (2, None)])).unwrap();
+        assert!(apply_patch(&mut peers, patch).is_none());
+        assert_eq!(scopes(&peers), [SCOPE_A_GET, SCOPE_A]);
+
+        let mut trailing = encode_patch(&[(0, None)]);
+        trailing.push(0).unwrap();
+        assert!(decode_patch(&trailing).is_none());
+    }
+
+    #[test]
+    fn grants() {
+        let held = UnionScope::AifValue(coapcore::scope::AifValue::parse(SCOPE_A).unwrap());
+
+        assert!(peer(SCOPE_A).is_granted_by(&held));
+        assert!(peer(SCOPE_A_GET).is_granted_by(&held));
+        assert!(!peer(SCOPE_B).is_granted_by(&held));
+        assert!(peer(SCOPE_B).is_granted_by(&UnionScope::AllowAll));
+        assert!(!peer(SCOPE_A_GET).is_granted_by(&UnionScope::DenyAll));
+    }
+
+    /// Encodes the options and payload of a request to the single-segment `path`.
+    fn request(path: &str, payload: &[u8]) -> heapless::Vec<u8, 1024> {
+        // Uri-Path (11) with a short value, followed by the payload marker.
+        let mut encoded = heapless::Vec::new();
+        encoded
+            .push(0xb0 | u8::try_from(path.len()).unwrap())
+            .unwrap();
+        encoded.extend_from_slice(path.as_bytes()).unwrap();
+        encoded.push(0xff).unwrap();
+        encoded.extend_from_slice(payload).unwrap();
+        encoded
+    }
+
+    #[test]
+    fn request_grants() {
+        use coap_message_implementations::inmemory::Message;
+        use coap_numbers::code::{DELETE, IPATCH, POST, PUT};
+
+        let held = UnionScope::AifValue(coapcore::scope::AifValue::parse(SCOPE_A).unwrap());
+        let single = &encode(&[peer(SCOPE_B)])[1..];
+
+        let post = request("peers", single);
+        assert!(!grants_are_held(&held, &Message::new(POST, &post)));
+        assert!(grants_are_held(
+            &UnionScope::AllowAll,
+            &Message::new(POST, &post)
+        ));
+
+        let put = request("peers", &encode(&[peer(SCOPE_A), peer(SCOPE_A_GET)]));
+        assert!(grants_are_held(&held, &Message::new(PUT, &put)));
+        let put = request("peers", &encode(&[peer(SCOPE_A), peer(SCOPE_B)]));
+        assert!(!grants_are_held(&held, &Message::new(PUT, &put)));
+
+        let patch = request("peers", &encode_patch(&[(0, None), (1, Some(SCOPE_A_GET))]));
+        assert!(grants_are_held(&held, &Message::new(IPATCH, &patch)));
+        let patch = request("peers", &encode_patch(&[(0, Some(SCOPE_B))]));
+        assert!(!grants_are_held(&held, &Message::new(IPATCH, &patch)));
+
+        // Removing peers grants nothing, and neither do requests to other resources.
+        let delete = request("peers", &[]);
+        assert!(grants_are_held(
+            &UnionScope::DenyAll,
+            &Message::new(DELETE, &delete)
+        ));
+        let other = request("other", single);
+        assert!(grants_are_held(&held, &Message::new(POST, &other)));
+    }
+}
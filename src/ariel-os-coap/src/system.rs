@@ -0,0 +1,442 @@
+//! CoAP resources provided by the operating system.
+//!
+//! [`with_system_resources()`] mounts all resources that are available with the enabled features
+//! next to the application's resources:
+//!
+//! * `/sys/id` (GET): The device ID as a CBOR byte string, or `null` if the device has none.
+//! * `/sys/build` (GET): A CBOR map with the operating system's name (`"os"`) and the board
+//!   (`"board"`).
+//! * `/sys/reboot` (POST): Reboots the device shortly after the response was sent.
+//! * `/sys/storage` (GET): The keys in storage as a CBOR array of text strings (with the `storage`
+//!   feature).
+//! * `/sys/threads` (GET): A CBOR array with an array of thread ID, priority and stack size for
+//!   each thread (with the `threading` feature).
+//! * `/sys/heap` (GET): A CBOR map with the heap's size (`"size"`) and, if known, its allocated
+//!   bytes (`"used"`) (with the `alloc` feature).
+//! * `/peers` (see [`PeerManagement`][crate::PeerManagement]; with the
+//!   `coap-server-config-storage` feature).
+//!
+//! Like all resources, they are guarded by the server access policy: only peers whose scope
+//! allows the respective method on the path can use them. For example, a scope of `/sys/build:
+//! GET` in `peers.yml` allows reading the build information, and `/sys/reboot: POST` allows
+//! rebooting the device.
+
+use coap_handler_implementations::ReportingHandlerBuilder as _;
+use coap_message::{
+    Code as _, MessageOption as _, MinimalWritableMessage, MutableWritableMessage,
+    OptionNumber as _, ReadableMessage,
+};
+use coap_message_utils::{Error as CoAPError, OptionsExt as _};
+use coap_numbers::{code, option};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use minicbor::encode::write::Cursor;
+
+/// Content-Format number of `application/cbor`.
+const CONTENT_FORMAT_CBOR: u8 = 60;
+
+/// Time between responding to a reboot request and rebooting, so that the response can be sent.
+const REBOOT_DELAY: embassy_time::Duration = embassy_time::Duration::from_millis(500);
+
+/// Signaled when a reboot was requested.
+static REBOOT: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Adds the resources provided by the operating system to `handler`.
+///
+/// See the [module level documentation][self] for the available resources.
+pub fn with_system_resources(
+    handler: impl coap_handler::Handler + coap_handler::Reporting,
+) -> impl coap_handler::Handler + coap_handler::Reporting {
+    let handler = handler
+        .at_with_attributes(&["sys", "id"], &[], ReadOnly(Identity))
+        .at_with_attributes(&["sys", "build"], &[], ReadOnly(BuildInfo))
+        .at_with_attributes(&["sys", "reboot"], &[], Reboot);
+    #[cfg(feature = "storage")]
+    let handler = handler.at_with_attributes(&["sys", "storage"], &[], storage_keys::StorageKeys);
+    #[cfg(feature = "threading")]
+    let handler = handler.at_with_attributes(&["sys", "threads"], &[], ReadOnly(Threads));
+    #[cfg(feature = "alloc")]
+    let handler = handler.at_with_attributes(&["sys", "heap"], &[], ReadOnly(Heap));
+    #[cfg(feature = "coap-server-config-storage")]
+    let handler = handler.at_with_attributes(
+        crate::stored::peers::PATH,
+        &[],
+        crate::PeerManagement::new(),
+    );
+    handler
+}
+
+/// Runs the actions requested through the system resources that can not be performed inside the
+/// handler.
+pub(crate) async fn run() -> core::convert::Infallible {
+    cfg_if::cfg_if! {
+        if #[cfg(feature = "storage")] {
+            use embassy_futures::select::{Either, select};
+
+            match select(reboot_when_requested(), storage_keys::refresh()).await {
+                Either::First(never) | Either::Second(never) => never,
+            }
+        } else {
+            reboot_when_requested().await
+        }
+    }
+}
+
+async fn reboot_when_requested() -> core::convert::Infallible {
+    REBOOT.wait().await;
+    ariel_os_debug::log::info!("Rebooting as requested through CoAP.");
+    embassy_time::Timer::after(REBOOT_DELAY).await;
+    ariel_os_power::reboot()
+}
+
+/// Accepts only GET requests without critical options other than Uri-Path.
+fn extract_get<M: ReadableMessage>(request: &M) -> Result<(), CoAPError> {
+    request
+        .options()
+        .filter(|o| o.number() != option::URI_PATH)
+        .ignore_elective_others()?;
+    let method: u8 = request.code().into();
+    if method == code::GET {
+        Ok(())
+    } else {
+        Err(CoAPError::method_not_allowed())
+    }
+}
+
+/// A representation of system state in CBOR.
+trait CborRepresentation {
+    /// Upper bound of the encoded length.
+    const MAX_LEN: usize;
+
+    fn encode<W: minicbor::encode::Write>(
+        &mut self,
+        encoder: &mut minicbor::Encoder<W>,
+    ) -> Result<(), minicbor::encode::Error<W::Error>>;
+}
+
+/// A resource that serves a [`CborRepresentation`] on GET.
+struct ReadOnly<R>(R);
+
+impl<R: CborRepresentation> coap_handler::Handler for ReadOnly<R> {
+    type RequestData = ();
+    type ExtractRequestError = CoAPError;
+    type BuildResponseError<M: MinimalWritableMessage> = M::UnionError;
+
+    fn extract_request_data<M: ReadableMessage>(
+        &mut self,
+        request: &M,
+    ) -> Result<Self::RequestData, Self::ExtractRequestError> {
+        extract_get(request)
+    }
+
+    fn estimate_length(&mut self, _request: &Self::RequestData) -> usize {
+        R::MAX_LEN + 2
+    }
+
+    fn build_response<M: MutableWritableMessage>(
+        &mut self,
+        response: &mut M,
+        _request: Self::RequestData,
+    ) -> Result<(), Self::BuildResponseError<M>> {
+        response.set_code(M::Code::new(code::CONTENT)?);
+        response.add_option_uint(
+            M::OptionNumber::new(option::CONTENT_FORMAT)?,
+            CONTENT_FORMAT_CBOR,
+        )?;
+        let payload = response.payload_mut_with_len(R::MAX_LEN)?;
+        let mut encoder = minicbor::Encoder::new(Cursor::new(payload));
+        self.0
+            .encode(&mut encoder)
+            .expect("Sufficient size was requested");
+        let written = encoder.into_writer().position();
+        response.truncate(written)?;
+        Ok(())
+    }
+}
+
+/// The device ID, see [`ariel_os_identity::device_id_bytes()`].
+struct Identity;
+
+impl Identity {
+    /// Longest device ID that is served; longer IDs are truncated.
+    const MAX_ID_LEN: usize = 32;
+}
+
+impl CborRepresentation for Identity {
+    const MAX_LEN: usize = 2 + Self::MAX_ID_LEN;
+
+    fn encode<W: minicbor::encode::Write>(
+        &mut self,
+        encoder: &mut minicbor::Encoder<W>,
+    ) -> Result<(), minicbor::encode::Error<W::Error>> {
+        match ariel_os_identity::device_id_bytes() {
+            Ok(id) => {
+                let id = id.as_ref();
+                encoder.bytes(id.get(..Self::MAX_ID_LEN).unwrap_or(id))?;
+            }
+            Err(_) => {
+                encoder.null()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Build information, see [`ariel_os_buildinfo`].
+struct BuildInfo;
+
+impl CborRepresentation for BuildInfo {
+    // Map header, two keys, and two text strings with headers of up to 3 bytes.
+    const MAX_LEN: usize =
+        1 + 3 + 6 + 3 + ariel_os_buildinfo::OS_NAME.len() + 3 + ariel_os_buildinfo::BOARD.len();
+
+    fn encode<W: minicbor::encode::Write>(
+        &mut self,
+        encoder: &mut minicbor::Encoder<W>,
+    ) -> Result<(), minicbor::encode::Error<W::Error>> {
+        encoder
+            .map(2)?
+            .str("os")?
+            .str(ariel_os_buildinfo::OS_NAME)?
+            .str("board")?
+            .str(ariel_os_buildinfo::BOARD)?;
+        Ok(())
+    }
+}
+
+/// Resource that triggers a reboot on POST.
+struct Reboot;
+
+impl coap_handler::Handler for Reboot {
+    type RequestData = ();
+    type ExtractRequestError = CoAPError;
+    type BuildResponseError<M: MinimalWritableMessage> = M::UnionError;
+
+    fn extract_request_data<M: ReadableMessage>(
+        &mut self,
+        request: &M,
+    ) -> Result<Self::RequestData, Self::ExtractRequestError> {
+        request
+            .options()
+            .filter(|o| o.number() != option::URI_PATH)
+            .ignore_elective_others()?;
+        let method: u8 = request.code().into();
+        if method != code::POST {
+            return Err(CoAPError::method_not_allowed());
+        }
+        REBOOT.signal(());
+        Ok(())
+    }
+
+    fn estimate_length(&mut self, _request: &Self::RequestData) -> usize {
+        1
+    }
+
+    fn build_response<M: MutableWritableMessage>(
+        &mut self,
+        response: &mut M,
+        _request: Self::RequestData,
+    ) -> Result<(), Self::BuildResponseError<M>> {
+        response.set_code(M::Code::new(code::CHANGED)?);
+        Ok(())
+    }
+}
+
+/// Thread statistics, see [`ariel_os_threads`].
+#[cfg(feature = "threading")]
+struct Threads;
+
+#[cfg(feature = "threading")]
+impl CborRepresentation for Threads {
+    // Indefinite-length array, and for each thread an array header and three unsigned integers.
+    const MAX_LEN: usize = 2 + ariel_os_threads::THREAD_COUNT * (1 + 2 + 2 + 9);
+
+    fn encode<W: minicbor::encode::Write>(
+        &mut self,
+        encoder: &mut minicbor::Encoder<W>,
+    ) -> Result<(), minicbor::encode::Error<W::Error>> {
+        use ariel_os_threads::{THREAD_COUNT, ThreadId};
+
+        encoder.begin_array()?;
+        for tid in 0..THREAD_COUNT {
+            #[allow(
+                clippy::cast_possible_truncation,
+                reason = "thread IDs are limited by THREAD_COUNT"
+            )]
+            let thread_id = ThreadId::new(tid as u8);
+            let (Some(priority), Some((lowest, highest))) = (
+                ariel_os_threads::get_priority(thread_id),
+                ariel_os_threads::stack_limits(thread_id),
+            ) else {
+                continue;
+            };
+            encoder
+                .array(3)?
+                .u64(tid as u64)?
+                .u64(usize::from(priority) as u64)?
+                .u64((highest - lowest) as u64)?;
+        }
+        encoder.end()?;
+        Ok(())
+    }
+}
+
+/// Heap statistics, see [`ariel_os_alloc::stats()`].
+#[cfg(feature = "alloc")]
+struct Heap;
+
+#[cfg(feature = "alloc")]
+impl CborRepresentation for Heap {
+    // Map header, two keys, and two unsigned integers.
+    const MAX_LEN: usize = 1 + 5 + 9 + 5 + 9;
+
+    fn encode<W: minicbor::encode::Write>(
+        &mut self,
+        encoder: &mut minicbor::Encoder<W>,
+    ) -> Result<(), minicbor::encode::Error<W::Error>> {
+        let stats = ariel_os_alloc::stats();
+        encoder
+            .map(if stats.used.is_some() { 2 } else { 1 })?
+            .str("size")?
+            .u64(stats.size as u64)?;
+        if let Some(used) = stats.used {
+            encoder.str("used")?.u64(used as u64)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "storage")]
+mod storage_keys {
+    //! The storage key browser.
+    //!
+    //! As storage can only be accessed asynchronously, the resource serves a snapshot of the keys
+    //! that is refreshed in the background after every request.
+
+    use core::cell::RefCell;
+
+    use ariel_os_debug::log::warn;
+    use ariel_os_storage::MAX_KEY_LEN;
+    use coap_message::{
+        Code as _, MinimalWritableMessage, MutableWritableMessage, OptionNumber as _,
+        ReadableMessage,
+    };
+    use coap_message_utils::Error as CoAPError;
+    use coap_numbers::{code, option};
+    use embassy_sync::{
+        blocking_mutex::{Mutex, raw::CriticalSectionRawMutex},
+        signal::Signal,
+    };
+    use minicbor::encode::write::Cursor;
+
+    use super::CONTENT_FORMAT_CBOR;
+
+    /// Maximum number of keys that are listed.
+    const MAX_KEYS: usize = ariel_os_utils::usize_from_env_or!(
+        "CONFIG_COAP_STORAGE_BROWSER_KEYS",
+        8,
+        "maximum number of storage keys listed by the CoAP storage key browser"
+    );
+
+    /// Upper bound of the encoded list (array header, and keys with headers of up to 2 bytes).
+    const MAX_LEN: usize = 3 + MAX_KEYS * (2 + MAX_KEY_LEN);
+
+    type Keys = heapless::Vec<heapless::String<MAX_KEY_LEN>, MAX_KEYS>;
+
+    /// Most recent snapshot of the keys in storage; `None` until it was first read.
+    static KEYS: Mutex<CriticalSectionRawMutex, RefCell<Option<Keys>>> =
+        Mutex::new(RefCell::new(None));
+
+    /// Signaled when the snapshot should be refreshed.
+    static REFRESH: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+    /// Resource listing the keys in storage.
+    pub(super) struct StorageKeys;
+
+    pub(super) enum Outcome {
+        List,
+        /// The keys have not been read yet.
+        NotReady,
+    }
+
+    impl coap_handler::Handler for StorageKeys {
+        type RequestData = Outcome;
+        type ExtractRequestError = CoAPError;
+        type BuildResponseError<M: MinimalWritableMessage> = M::UnionError;
+
+        fn extract_request_data<M: ReadableMessage>(
+            &mut self,
+            request: &M,
+        ) -> Result<Self::RequestData, Self::ExtractRequestError> {
+            super::extract_get(request)?;
+            REFRESH.signal(());
+            Ok(if KEYS.lock(|keys| keys.borrow().is_some()) {
+                Outcome::List
+            } else {
+                Outcome::NotReady
+            })
+        }
+
+        fn estimate_length(&mut self, _request: &Self::RequestData) -> usize {
+            MAX_LEN + 2
+        }
+
+        fn build_response<M: MutableWritableMessage>(
+            &mut self,
+            response: &mut M,
+            request: Self::RequestData,
+        ) -> Result<(), Self::BuildResponseError<M>> {
+            if let Outcome::NotReady = request {
+                response.set_code(M::Code::new(code::SERVICE_UNAVAILABLE)?);
+                response.add_option_uint(M::OptionNumber::new(option::MAX_AGE)?, 1u8)?;
+                return Ok(());
+            }
+
+            response.set_code(M::Code::new(code::CONTENT)?);
+            response.add_option_uint(
+                M::OptionNumber::new(option::CONTENT_FORMAT)?,
+                CONTENT_FORMAT_CBOR,
+            )?;
+            KEYS.lock(|keys| -> Result<(), M::UnionError> {
+                let keys = keys.borrow();
+                let keys = keys.as_deref().unwrap_or_default();
+                let payload = response.payload_mut_with_len(MAX_LEN)?;
+                let mut encoder = minicbor::Encoder::new(Cursor::new(payload));
+                encoder
+                    .array(keys.len() as u64)
+                    .expect("Sufficient size was requested");
+                for key in keys {
+                    encoder.str(key).expect("Sufficient size was requested");
+                }
+                let written = encoder.into_writer().position();
+                response.truncate(written)?;
+                Ok(())
+            })
+        }
+    }
+
+    /// Refreshes the snapshot of keys whenever it was requested.
+    pub(super) async fn refresh() -> core::convert::Infallible {
+        loop {
+            let mut keys = Keys::new();
+            let mut complete = true;
+            let result = ariel_os_storage::for_each_key(|key| {
+                if keys.iter().any(|known| known == key) {
+                    return;
+                }
+                complete &= heapless::String::try_from(key)
+                    .ok()
+                    .and_then(|key| keys.push(key).ok())
+                    .is_some();
+            })
+            .await;
+            if result.is_err() {
+                warn!("Reading storage keys failed.");
+            } else if !complete {
+                warn!("More storage keys than CONFIG_COAP_STORAGE_BROWSER_KEYS; list truncated.");
+            }
+            KEYS.lock(|current| *current.borrow_mut() = Some(keys));
+
+            REFRESH.wait().await;
+        }
+    }
+}
@@ -0,0 +1,454 @@
+//! CoAP over TCP ([RFC8323](https://www.rfc-editor.org/rfc/rfc8323.html)) server transport.
+//!
+//! This serves the same handler as the UDP transport; a
+//! [`SharedHandler`][crate::shared_handler::SharedHandler] allows both transports to use it in
+//! turns.
+//!
+//! Only the TCP transport of RFC8323 is implemented; CoAP over WebSockets is not.
+
+use core::cell::RefCell;
+
+use ariel_os_debug::log::{debug, info};
+use coap_message::{MinimalWritableMessage as _, error::RenderableOnMinimal as _};
+use coap_message_implementations::{inmemory, inmemory_write};
+use embassy_futures::select::{Either, select};
+use embassy_net::tcp::TcpSocket;
+use embassy_time::Timer;
+use embedded_io_async::{Read, Write as _};
+
+/// Port on which CoAP over TCP is served.
+const PORT: u16 = 5683;
+
+/// Number of connections that are served concurrently.
+///
+/// Each of them takes a socket from the network stack.
+const CONNECTIONS: usize = ariel_os_utils::usize_from_env_or!(
+    "CONFIG_COAP_TCP_CONNECTIONS",
+    1,
+    "number of concurrent CoAP over TCP connections"
+);
+
+ariel_os_embassy::net::declare_sockets!("coap-tcp", CONNECTIONS);
+ariel_os_embassy::net::mdns::declare_service!("_coap._tcp", PORT);
+
+/// Size of the options and payload of the largest message that is processed.
+///
+/// This is the default maximum message size of RFC8323; as we do not announce a larger size in
+/// our Capabilities and Settings Message, peers do not send larger messages.
+const MESSAGE_BUFFER_SIZE: usize = 1152;
+
+/// Time after which a connection on which no message arrives is closed.
+///
+/// This is also the time after which the TCP stack gives up on a peer that does not acknowledge
+/// data sent to it.
+const IDLE_TIMEOUT: embassy_time::Duration = embassy_time::Duration::from_secs(120);
+
+// Signaling codes from RFC8323 Section 11.1
+const CSM: u8 = 0xe1;
+const PING: u8 = 0xe2;
+const PONG: u8 = 0xe3;
+const RELEASE: u8 = 0xe4;
+const ABORT: u8 = 0xe5;
+
+/// Token of a message; tokens are at most 8 bytes long.
+type Token = heapless::Vec<u8, 8>;
+
+/// Reasons for which a connection is terminated.
+#[derive(Debug)]
+enum ConnectionError {
+    Tcp(embassy_net::tcp::Error),
+    Eof,
+    /// The peer sent no message for [`IDLE_TIMEOUT`].
+    Idle,
+    /// The peer sent a message that violates the framing or exceeds the message size limit.
+    Malformed,
+}
+
+impl From<embassy_net::tcp::Error> for ConnectionError {
+    fn from(e: embassy_net::tcp::Error) -> Self {
+        Self::Tcp(e)
+    }
+}
+
+impl From<embedded_io_async::ReadExactError<embassy_net::tcp::Error>> for ConnectionError {
+    fn from(e: embedded_io_async::ReadExactError<embassy_net::tcp::Error>) -> Self {
+        match e {
+            embedded_io_async::ReadExactError::UnexpectedEof => Self::Eof,
+            embedded_io_async::ReadExactError::Other(e) => Self::Tcp(e),
+        }
+    }
+}
+
+/// Serves CoAP over TCP on [`PORT`] with `handler`.
+pub(crate) async fn run<H: coap_handler::Handler>(
+    stack: embassy_net::Stack<'static>,
+    handler: &RefCell<H>,
+) -> core::convert::Infallible {
+    info!("Starting up CoAP over TCP server");
+
+    let [never, ..] =
+        embassy_futures::join::join_array(core::array::from_fn::<_, CONNECTIONS, _>(|_| {
+            serve_connections(stack, handler)
+        }))
+        .await;
+    never
+}
+
+/// Accepts connections one at a time, and serves them.
+async fn serve_connections<H: coap_handler::Handler>(
+    stack: embassy_net::Stack<'static>,
+    handler: &RefCell<H>,
+) -> core::convert::Infallible {
+    // RFC8323 headers take up to 14 bytes in addition to options and payload.
+    let mut rx_buffer = [0; MESSAGE_BUFFER_SIZE + 14];
+    let mut tx_buffer = [0; MESSAGE_BUFFER_SIZE + 14];
+
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        socket.set_timeout(Some(IDLE_TIMEOUT));
+
+        if let Err(e) = socket.accept(PORT).await {
+            debug!("Accepting CoAP over TCP connection failed: {:?}", e);
+            continue;
+        }
+        debug!(
+            "CoAP over TCP connection from {:?}",
+            socket.remote_endpoint()
+        );
+
+        match serve_connection(&mut socket, handler).await {
+            Ok(()) => debug!("CoAP over TCP connection released"),
+            Err(ConnectionError::Tcp(e)) => debug!("CoAP over TCP connection failed: {:?}", e),
+            Err(ConnectionError::Eof) => debug!("CoAP over TCP connection closed by peer"),
+            Err(ConnectionError::Idle) => debug!("CoAP over TCP connection closed while idle"),
+            Err(ConnectionError::Malformed) => {
+                debug!("CoAP over TCP connection aborted after malformed message");
+            }
+        }
+
+        socket.close();
+        // Errors are irrelevant here: the socket is discarded either way.
+        let _ = socket.flush().await;
+        socket.abort();
+    }
+}
+
+/// Serves a single connection until it is closed or released.
+async fn serve_connection<H: coap_handler::Handler>(
+    socket: &mut TcpSocket<'_>,
+    handler: &RefCell<H>,
+) -> Result<(), ConnectionError> {
+    // An empty Capabilities and Settings Message, as we only use the defaults.
+    write_message(socket, CSM, &[], &[]).await?;
+
+    let mut incoming = [0u8; MESSAGE_BUFFER_SIZE];
+    let mut outgoing = [0u8; MESSAGE_BUFFER_SIZE];
+
+    loop {
+        let (code, token, len) =
+            next_message(socket, &mut incoming, Timer::after(IDLE_TIMEOUT)).await?;
+        let tail = incoming.get(..len).ok_or(ConnectionError::Malformed)?;
+        let token = &token[..];
+
+        match code {
+            PING => write_message(socket, PONG, token, &[]).await?,
+            RELEASE | ABORT => return Ok(()),
+            // Our defaults suffice for the CSM, and all other signals are elective.
+            0xe0..=0xff => (),
+            // Requests
+            1..=31 => {
+                let request = inmemory::Message::new(code, tail);
+                let mut response_code = 0;
+                let mut response = inmemory_write::Message::new(&mut response_code, &mut outgoing);
+                respond(&mut *handler.borrow_mut(), &request, &mut response);
+                let response_len = response.finish();
+                #[allow(
+                    clippy::indexing_slicing,
+                    reason = "length is populated by the message"
+                )]
+                write_message(socket, response_code, token, &outgoing[..response_len]).await?;
+            }
+            // Responses and empty messages are not expected on a server connection.
+            _ => debug!("Ignoring unexpected message with code {}", code),
+        }
+    }
+}
+
+/// Reads the next message like [`read_message()`], failing if none arrived by the time `idle`
+/// completes.
+///
+/// The socket's own timeout only applies while sent data is unacknowledged, so without this, a
+/// peer that silently went away would occupy the connection indefinitely.
+async fn next_message<R: Read<Error = embassy_net::tcp::Error>>(
+    reader: &mut R,
+    incoming: &mut [u8],
+    idle: impl Future<Output = ()>,
+) -> Result<(u8, Token, usize), ConnectionError> {
+    match select(read_message(reader, incoming), idle).await {
+        Either::First(read) => read,
+        Either::Second(()) => Err(ConnectionError::Idle),
+    }
+}
+
+/// Reads a message, returning its code, its token and the length of its options and payload,
+/// which are placed at the start of `incoming`.
+async fn read_message<R: Read<Error = embassy_net::tcp::Error>>(
+    reader: &mut R,
+    incoming: &mut [u8],
+) -> Result<(u8, Token, usize), ConnectionError> {
+    let mut first = [0u8; 1];
+    reader.read_exact(&mut first).await?;
+    let [first] = first;
+    let tkl = usize::from(first & 0x0f);
+
+    let mut extended = [0u8; 4];
+    let extended = extended
+        .get_mut(..extended_length_len(first))
+        .ok_or(ConnectionError::Malformed)?;
+    reader.read_exact(extended).await?;
+    let len = decode_length(first, extended);
+
+    let mut code = [0u8; 1];
+    reader.read_exact(&mut code).await?;
+    let [code] = code;
+
+    let mut token = Token::new();
+    token
+        .resize_default(tkl)
+        .map_err(|()| ConnectionError::Malformed)?;
+    reader.read_exact(&mut token).await?;
+
+    let tail = incoming.get_mut(..len).ok_or(ConnectionError::Malformed)?;
+    reader.read_exact(tail).await?;
+
+    Ok((code, token, len))
+}
+
+/// Runs the handler on the request, rendering any errors into the response.
+fn respond<H: coap_handler::Handler>(
+    handler: &mut H,
+    request: &inmemory::Message<'_>,
+    response: &mut inmemory_write::Message<'_>,
+) {
+    let rendered = match handler.extract_request_data(request) {
+        Ok(data) => match handler.build_response(response, data) {
+            Ok(()) => return,
+            Err(e) => e.render(response),
+        },
+        Err(e) => e.render(response),
+    };
+    if rendered.is_err() {
+        // FIXME rewind message
+        response.set_code(coap_numbers::code::INTERNAL_SERVER_ERROR);
+    }
+}
+
+/// Writes a message with the given code and token, and options and payload encoded in `tail`.
+async fn write_message(
+    socket: &mut TcpSocket<'_>,
+    code: u8,
+    token: &[u8],
+    tail: &[u8],
+) -> Result<(), ConnectionError> {
+    let tkl = u8::try_from(token.len()).expect("token lengths are limited on parsing");
+    let header = encode_header(code, tkl, tail.len());
+
+    socket.write_all(&header).await?;
+    socket.write_all(token).await?;
+    socket.write_all(tail).await?;
+    Ok(())
+}
+
+/// Returns the number of extended length bytes that follow the first byte of a message.
+fn extended_length_len(first: u8) -> usize {
+    match first >> 4 {
+        13 => 1,
+        14 => 2,
+        15 => 4,
+        _ => 0,
+    }
+}
+
+/// Decodes the length of the options and payload of a message from its first byte and the
+/// [`extended_length_len()`] bytes that follow it.
+fn decode_length(first: u8, extended: &[u8]) -> usize {
+    match *extended {
+        [] => usize::from(first >> 4),
+        [a] => usize::from(a) + 13,
+        [a, b] => usize::from(u16::from_be_bytes([a, b])) + 269,
+        [a, b, c, d] => usize::try_from(u32::from_be_bytes([a, b, c, d]))
+            .unwrap_or(usize::MAX)
+            .saturating_add(65805),
+        _ => unreachable!("length is selected by extended_length_len()"),
+    }
+}
+
+/// Encodes the header of a message up to and including its code, for a token of length `tkl` and
+/// `len` bytes of options and payload.
+fn encode_header(code: u8, tkl: u8, len: usize) -> heapless::Vec<u8, 6> {
+    let mut header = heapless::Vec::new();
+    // Pushes are infallible: the header is at most 6 bytes long.
+    #[allow(
+        clippy::cast_possible_truncation,
+        reason = "values are checked against their range"
+    )]
+    if len < 13 {
+        let _ = header.push(((len as u8) << 4) | tkl);
+    } else if len < 269 {
+        let _ = header.push((13 << 4) | tkl);
+        let _ = header.push((len - 13) as u8);
+    } else if len < 65805 {
+        let _ = header.push((14 << 4) | tkl);
+        let _ = header.extend_from_slice(&((len - 269) as u16).to_be_bytes());
+    } else {
+        let _ = header.push((15 << 4) | tkl);
+        let _ = header.extend_from_slice(&((len - 65805) as u32).to_be_bytes());
+    }
+    let _ = header.push(code);
+    header
+}
+
+#[cfg(test)]
+#[allow(clippy::indexing_slicing, reason = "panicking is fine in tests")]
+mod test {
+    use super::*;
+
+    /// Decodes a header produced by [`encode_header()`] into code, token length and length.
+    fn decode_header(header: &[u8]) -> (u8, usize, usize) {
+        let extended_len = extended_length_len(header[0]);
+        assert_eq!(header.len(), 1 + extended_len + 1);
+        (
+            header[1 + extended_len],
+            usize::from(header[0] & 0x0f),
+            decode_length(header[0], &header[1..=extended_len]),
+        )
+    }
+
+    #[test]
+    fn length_boundaries() {
+        for (len, header_len) in [
+            (0, 2),
+            (12, 2),
+            (13, 3),
+            (268, 3),
+            (269, 4),
+            (65804, 4),
+            (65805, 6),
+            (1 << 20, 6),
+        ] {
+            let header = encode_header(coap_numbers::code::CONTENT, 3, len);
+            assert_eq!(header.len(), header_len, "{len}");
+            assert_eq!(
+                decode_header(&header),
+                (coap_numbers::code::CONTENT, 3, len),
+                "{len}"
+            );
+        }
+    }
+
+    #[test]
+    fn encodings() {
+        // A 2.05 Content with an empty token and nothing else.
+        assert_eq!(encode_header(0x45, 0, 0), [0x00, 0x45]);
+        // Lengths are offset by the smallest value that needs the extended length.
+        assert_eq!(encode_header(0x45, 8, 13), [0xd8, 0x00, 0x45]);
+        assert_eq!(encode_header(0x45, 1, 300), [0xe1, 0x00, 0x1f, 0x45]);
+        assert_eq!(
+            encode_header(0x45, 0, 65805 + 0x0102_0304),
+            [0xf0, 0x01, 0x02, 0x03, 0x04, 0x45]
+        );
+        // The Ping signal of RFC8323 Section 5.4, with a single byte token.
+        assert_eq!(encode_header(PING, 1, 0), [0x01, 0xe2]);
+    }
+
+    /// A peer that sends the given bytes, and then closes the connection.
+    struct Peer<'a>(&'a [u8]);
+
+    impl embedded_io_async::ErrorType for Peer<'_> {
+        type Error = embassy_net::tcp::Error;
+    }
+
+    impl Read for Peer<'_> {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            let len = buf.len().min(self.0.len());
+            let (read, rest) = self.0.split_at(len);
+            buf[..len].copy_from_slice(read);
+            self.0 = rest;
+            Ok(len)
+        }
+    }
+
+    /// A peer that went away silently.
+    struct Silent;
+
+    impl embedded_io_async::ErrorType for Silent {
+        type Error = embassy_net::tcp::Error;
+    }
+
+    impl Read for Silent {
+        async fn read(&mut self, _buf: &mut [u8]) -> Result<usize, Self::Error> {
+            core::future::pending().await
+        }
+    }
+
+    #[test]
+    fn read_messages() {
+        // A GET request with token 0x2a and a Uri-Path option, followed by a Ping without token.
+        let mut peer = Peer(&[0x41, 0x01, 0x2a, 0xb3, b'f', b'o', b'o', 0x00, 0xe2]);
+        let mut incoming = [0u8; MESSAGE_BUFFER_SIZE];
+
+        let (code, token, len) =
+            embassy_futures::block_on(read_message(&mut peer, &mut incoming)).unwrap();
+        assert_eq!((code, &token[..]), (coap_numbers::code::GET, &[0x2a][..]));
+        assert_eq!(&incoming[..len], [0xb3, b'f', b'o', b'o']);
+
+        let (code, token, len) =
+            embassy_futures::block_on(read_message(&mut peer, &mut incoming)).unwrap();
+        assert_eq!((code, token.len(), len), (PING, 0, 0));
+
+        assert!(matches!(
+            embassy_futures::block_on(read_message(&mut peer, &mut incoming)),
+            Err(ConnectionError::Eof)
+        ));
+
+        // Token lengths beyond 8 are reserved.
+        let mut peer = Peer(&[0x09, 0x01]);
+        assert!(matches!(
+            embassy_futures::block_on(read_message(&mut peer, &mut incoming)),
+            Err(ConnectionError::Malformed)
+        ));
+    }
+
+    #[test]
+    fn idle_peer() {
+        let mut incoming = [0u8; MESSAGE_BUFFER_SIZE];
+
+        assert!(matches!(
+            embassy_futures::block_on(next_message(
+                &mut Silent,
+                &mut incoming,
+                core::future::ready(())
+            )),
+            Err(ConnectionError::Idle)
+        ));
+
+        // A message that arrives in time is read.
+        let mut peer = Peer(&[0x00, PING]);
+        assert!(matches!(
+            embassy_futures::block_on(next_message(
+                &mut peer,
+                &mut incoming,
+                core::future::pending()
+            )),
+            Ok((PING, _, 0))
+        ));
+    }
+
+    #[test]
+    fn oversized_lengths() {
+        // Lengths beyond what fits the buffer are decoded rather than wrapped, so they are
+        // rejected when the message is read.
+        assert_eq!(decode_length(0xf0, &[0xff; 4]), 65805 + 0xffff_ffff);
+        assert!(decode_length(0xe0, &[0xff; 2]) > MESSAGE_BUFFER_SIZE);
+    }
+}
@@ -146,7 +146,9 @@ impl nal::UnconnectedUdp for UnconnectedUdp<'_> {
         );
 
         let remote_endpoint = udp::UdpMetadata {
-            local_address: if is_unspec_ip(local) {
+            // Responses to multicast requests can not be sent from the multicast address, so the
+            // stack picks a unicast address like it does for unspecified ones.
+            local_address: if is_unspec_ip(local) || local.ip().is_multicast() {
                 None
             } else {
                 // A conversion of the addr part only might be cheaper, but would also mean we need
@@ -23,6 +23,9 @@ pub mod identity;
 #[cfg(feature = "spi")]
 pub mod spi;
 
+#[cfg(feature = "wifi")]
+pub mod wifi;
+
 pub mod reexports {
     //! Crate re-exports.
 
@@ -0,0 +1,184 @@
+//! Wi-Fi network credentials and the list of networks to join.
+//!
+//! See `ariel_os::wifi` for general documentation; that module also represents the public parts
+//! of this API.
+#![deny(missing_docs)]
+
+use core::cell::RefCell;
+
+use embassy_sync::{
+    blocking_mutex::{Mutex, raw::CriticalSectionRawMutex},
+    signal::Signal,
+};
+
+/// Maximum length of an SSID, in bytes.
+pub const MAX_SSID_LEN: usize = 32;
+
+/// Maximum length of a password, in bytes.
+pub const MAX_PASSWORD_LEN: usize = 64;
+
+/// Minimum length of a WPA passphrase, in bytes.
+const MIN_PASSWORD_LEN: usize = 8;
+
+/// Maximum number of networks in the list of networks.
+pub const MAX_NETWORKS: usize = ariel_os_utils::usize_from_env_or!(
+    "CONFIG_WIFI_MAX_NETWORKS",
+    4,
+    "maximum number of Wi-Fi networks that can be provisioned at runtime"
+);
+
+/// SSID of the network joined when none of the provisioned ones is available.
+const BUILD_TIME_NETWORK: &str = ariel_os_utils::str_from_env_or!(
+    "CONFIG_WIFI_NETWORK",
+    "",
+    "Wi-Fi SSID (network name) joined when no provisioned network is available"
+);
+/// Password of [`BUILD_TIME_NETWORK`].
+const BUILD_TIME_PASSWORD: &str = ariel_os_utils::str_from_env_or!(
+    "CONFIG_WIFI_PASSWORD",
+    "",
+    "Wi-Fi password of the network set in CONFIG_WIFI_NETWORK"
+);
+
+/// List of provisioned networks, in order of preference.
+pub type Networks = heapless::Vec<Credentials, MAX_NETWORKS>;
+
+static NETWORKS: Mutex<CriticalSectionRawMutex, RefCell<Networks>> =
+    Mutex::new(RefCell::new(Networks::new()));
+
+/// Signaled whenever the list of provisioned networks changes.
+static CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Error returned when credentials are not valid for a Wi-Fi network.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum InvalidCredentials {
+    /// The SSID is empty or longer than [`MAX_SSID_LEN`].
+    Ssid,
+    /// The password is shorter than 8 bytes or longer than [`MAX_PASSWORD_LEN`].
+    Password,
+}
+
+impl core::fmt::Display for InvalidCredentials {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Ssid => write!(f, "invalid SSID"),
+            Self::Password => write!(f, "invalid password"),
+        }
+    }
+}
+
+impl core::error::Error for InvalidCredentials {}
+
+/// Credentials of a Wi-Fi network.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Credentials {
+    ssid: heapless::String<MAX_SSID_LEN>,
+    password: heapless::String<MAX_PASSWORD_LEN>,
+}
+
+impl Credentials {
+    /// Creates credentials for the network `ssid`.
+    ///
+    /// An empty `password` denotes an open network.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the SSID or the password do not fit a Wi-Fi network.
+    pub fn new(ssid: &str, password: &str) -> Result<Self, InvalidCredentials> {
+        if ssid.is_empty() {
+            return Err(InvalidCredentials::Ssid);
+        }
+        if !password.is_empty() && password.len() < MIN_PASSWORD_LEN {
+            return Err(InvalidCredentials::Password);
+        }
+
+        Ok(Self {
+            ssid: ssid.try_into().map_err(|()| InvalidCredentials::Ssid)?,
+            password: password
+                .try_into()
+                .map_err(|()| InvalidCredentials::Password)?,
+        })
+    }
+
+    /// Returns the SSID (network name).
+    #[must_use]
+    pub fn ssid(&self) -> &str {
+        &self.ssid
+    }
+
+    /// Returns the password, which is empty for open networks.
+    #[must_use]
+    pub fn password(&self) -> &str {
+        &self.password
+    }
+
+    /// Returns whether the network is open, i.e., does not require a password.
+    #[must_use]
+    pub fn is_open(&self) -> bool {
+        self.password.is_empty()
+    }
+}
+
+// The password is deliberately left out.
+impl core::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Credentials")
+            .field("ssid", &self.ssid)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Credentials {
+    fn format(&self, f: defmt::Formatter<'_>) {
+        defmt::write!(f, "Credentials {{ ssid: {}, .. }}", self.ssid.as_str());
+    }
+}
+
+/// A Wi-Fi interface operating as a station, i.e., joining networks.
+///
+/// This is implemented by the HALs; the supervisor of `ariel_os::wifi` decides which networks to
+/// join, and when.
+#[allow(
+    async_fn_in_trait,
+    reason = "only used on concrete types, which do not need to be `Send`"
+)]
+pub trait Station {
+    /// Tries to join `network`, returning whether that succeeded.
+    async fn join(&mut self, network: &Credentials) -> bool;
+}
+
+/// Returns the provisioned networks, in order of preference.
+#[must_use]
+pub fn networks() -> Networks {
+    NETWORKS.lock(|networks| networks.borrow().clone())
+}
+
+/// Replaces the provisioned networks.
+///
+/// Note: this is used by `ariel_os::wifi`, which also keeps the networks in storage.
+#[doc(hidden)]
+pub fn set_networks(networks: Networks) {
+    NETWORKS.lock(|current| *current.borrow_mut() = networks);
+    CHANGED.signal(());
+}
+
+/// Returns the networks to try joining, in order of preference.
+///
+/// These are the provisioned networks, followed by the network configured at build time through
+/// `CONFIG_WIFI_NETWORK` and `CONFIG_WIFI_PASSWORD`, if any.
+pub fn candidates() -> impl Iterator<Item = Credentials> {
+    let networks = networks();
+    let build_time = Credentials::new(BUILD_TIME_NETWORK, BUILD_TIME_PASSWORD)
+        .ok()
+        .filter(|build_time| networks.iter().all(|n| n.ssid() != build_time.ssid()));
+    networks.into_iter().chain(build_time)
+}
+
+/// Waits until the provisioned networks change.
+///
+/// Only one task at a time may wait; this is used by the supervisor that joins networks.
+pub async fn wait_for_change() {
+    CHANGED.wait().await;
+}
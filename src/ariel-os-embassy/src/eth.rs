@@ -1,2 +1,14 @@
-#[cfg(feature = "eth-stm32")]
 pub(crate) use crate::hal::eth::NetworkDevice;
+
+use crate::net::if_index;
+
+/// Creates the Ethernet device, with the MAC address of its interface.
+pub(crate) fn device(peripherals: &mut crate::hal::OptionalPeripherals) -> NetworkDevice {
+    use ariel_os_embassy_common::identity::DeviceId as _;
+
+    let mac_addr = crate::hal::identity::DeviceId::get()
+        .map(|d| d.interface_eui48(if_index::ETHERNET).0)
+        .unwrap_or([0xCA, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC]);
+
+    crate::hal::eth::device(peripherals, mac_addr)
+}
@@ -24,11 +24,14 @@ pub mod ble;
 pub mod net;
 
 #[cfg(feature = "wifi")]
-mod wifi;
+pub mod wifi;
 
 #[cfg(feature = "eth")]
 mod eth;
 
+#[cfg(feature = "time")]
+pub mod wallclock;
+
 use ariel_os_debug::log::debug;
 
 use linkme::distributed_slice;
@@ -69,6 +72,8 @@ pub mod api {
         pub use embassy_time::{
             Delay, Duration, Instant, TICK_HZ, TimeoutError, Timer, with_timeout,
         };
+
+        pub use crate::wallclock;
     }
 
     #[cfg(feature = "ble")]
@@ -79,6 +84,8 @@ pub mod api {
     pub use crate::spi;
     #[cfg(feature = "usb")]
     pub use crate::usb;
+    #[cfg(feature = "wifi")]
+    pub use crate::wifi;
 }
 
 // These are made available in `ariel_os::reexports`.
@@ -101,20 +108,12 @@ pub mod reexports {
     pub use linkme;
 }
 
-#[cfg(feature = "net")]
-cfg_if::cfg_if! {
-    if #[cfg(feature = "usb-ethernet")] {
-        use usb::ethernet::NetworkDevice;
-    } else if #[cfg(feature = "wifi")] {
-        use wifi::NetworkDevice;
-    } else if #[cfg(feature = "eth")] {
-        use eth::NetworkDevice;
-    } else if #[cfg(context = "ariel-os")] {
-        compile_error!("no backend for net is active");
-    } else {
-        use net::DummyDriver as NetworkDevice;
-    }
-}
+#[cfg(all(
+    feature = "net",
+    context = "ariel-os",
+    not(any(feature = "usb-ethernet", feature = "wifi", feature = "eth"))
+))]
+compile_error!("no backend for net is active");
 
 #[cfg(feature = "net")]
 pub use net::NetworkStack;
@@ -224,6 +223,9 @@ async fn init_task(mut peripherals: hal::OptionalPeripherals) {
     #[cfg(feature = "storage")]
     embassy_futures::block_on(ariel_os_storage::init(&mut peripherals));
 
+    #[cfg(all(feature = "wifi", feature = "storage"))]
+    embassy_futures::block_on(wifi::load());
+
     #[cfg(all(feature = "usb", context = "nrf"))]
     hal::usb::init();
 
@@ -271,7 +273,7 @@ async fn init_task(mut peripherals: hal::OptionalPeripherals) {
     };
 
     #[cfg(feature = "usb-ethernet")]
-    let device = {
+    let usb_ethernet_device = {
         use ariel_os_embassy_common::identity::DeviceId as _;
         use embassy_usb::class::cdc_ncm::{
             CdcNcmClass, State as CdcNcmState, embassy_net::State as NetState,
@@ -283,7 +285,7 @@ async fn init_task(mut peripherals: hal::OptionalPeripherals) {
 
         // Host's MAC addr. This is the MAC the host "thinks" its USB-to-ethernet adapter has.
         let host_mac_addr = crate::hal::identity::DeviceId::get()
-            .map(|d| d.interface_eui48(1).0)
+            .map(|d| d.interface_eui48(net::if_index::USB_ETHERNET_HOST).0)
             .unwrap_or([0x8A, 0x88, 0x88, 0x88, 0x88, 0x88]);
 
         // Create classes on the builder.
@@ -295,7 +297,7 @@ async fn init_task(mut peripherals: hal::OptionalPeripherals) {
         );
 
         let our_mac_addr = crate::hal::identity::DeviceId::get()
-            .map(|d| d.interface_eui48(0).0)
+            .map(|d| d.interface_eui48(net::if_index::USB_ETHERNET).0)
             .unwrap_or([0xCA, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC]);
 
         let (runner, device) = usb_cdc_ecm.into_embassy_net_device::<{ net::ETHERNET_MTU }, 4, 4>(
@@ -308,8 +310,16 @@ async fn init_task(mut peripherals: hal::OptionalPeripherals) {
         device
     };
 
-    #[cfg(feature = "eth-stm32")]
-    let device = hal::eth::device(&mut peripherals);
+    #[cfg(feature = "eth")]
+    let eth_device = eth::device(&mut peripherals);
+
+    #[cfg(all(feature = "wifi", feature = "wifi-provisioning-usb"))]
+    {
+        let class = wifi::provisioning::usb::class(&mut usb_builder);
+        spawner
+            .spawn(wifi::provisioning::usb::usb_serial_task(class))
+            .unwrap();
+    }
 
     #[cfg(feature = "usb")]
     {
@@ -326,67 +336,136 @@ async fn init_task(mut peripherals: hal::OptionalPeripherals) {
     #[cfg(all(feature = "ble-cyw43", not(feature = "wifi-cyw43")))]
     let _ = hal::cyw43::device(&mut peripherals, &spawner, ble_config).await;
     #[cfg(all(feature = "wifi-cyw43", not(feature = "ble-cyw43")))]
-    let (device, control) = hal::cyw43::device(&mut peripherals, &spawner).await;
+    let (wifi_device, station) = {
+        let (device, control) = hal::cyw43::device(&mut peripherals, &spawner).await;
+        (device, wifi::WifiStation::new(control))
+    };
     #[cfg(all(feature = "ble-cyw43", feature = "wifi-cyw43"))]
-    let (device, control) = hal::cyw43::device(&mut peripherals, &spawner, ble_config).await;
+    let (wifi_device, station) = {
+        let (device, control) = hal::cyw43::device(&mut peripherals, &spawner, ble_config).await;
+        (device, wifi::WifiStation::new(control))
+    };
 
     #[cfg(feature = "wifi-esp")]
-    let device = hal::wifi::esp_wifi::init(&mut peripherals, spawner);
+    let (wifi_device, station) = hal::wifi::esp_wifi::init(&mut peripherals);
 
     #[cfg(feature = "net")]
     {
         use embassy_net::StackResources;
         use static_cell::StaticCell;
 
-        use crate::cell::SameExecutorCell;
+        use crate::{
+            cell::SameExecutorCell,
+            net::{Interface, Interfaces, MAX_CONCURRENT_SOCKETS, if_index},
+        };
 
-        const MAX_CONCURRENT_SOCKETS: usize = ariel_os_utils::usize_from_env_or!(
-            "CONFIG_NETWORK_MAX_CONCURRENT_SOCKETS",
-            4,
-            "maximum number of concurrent sockets allowed by the network stack"
-        );
+        type Resources = StackResources<MAX_CONCURRENT_SOCKETS>;
 
-        static RESOURCES: StaticCell<StackResources<MAX_CONCURRENT_SOCKETS>> = StaticCell::new();
-
-        #[cfg(not(any(
-            feature = "usb-ethernet",
-            feature = "wifi-cyw43",
-            feature = "wifi-esp",
-            feature = "eth"
-        )))]
-        // The creation of `device` is not organized in such a way that they could be put in a
-        // cfg-if without larger refactoring; relying on unused variable lints to keep the
-        // condition list up to date.
-        let device: NetworkDevice = net::new_dummy();
-
-        let config = net::config();
-
-        let seed = net::unique_seed();
-        debug!("Network stack seed: {:#x}", seed);
-
-        // Init network stack
-        let (stack, runner) = embassy_net::new(
-            device,
-            config,
-            RESOURCES.init_with(StackResources::new),
-            seed,
-        );
+        net::sockets::check();
 
-        spawner.spawn(net::net_task(runner)).unwrap();
+        // Interfaces are registered in the order of their indices, so the primary one comes
+        // first.
+        let mut interfaces = Interfaces::new();
 
-        if crate::net::STACK
-            .init(SameExecutorCell::new(stack, spawner))
+        #[cfg(feature = "eth")]
+        {
+            static RESOURCES: StaticCell<Resources> = StaticCell::new();
+
+            let (stack, runner) = net::new_stack(
+                eth_device,
+                if_index::ETHERNET,
+                RESOURCES.init_with(StackResources::new),
+            );
+            spawner.spawn(net::eth_net_task(runner)).unwrap();
+            let _ = interfaces.push(Interface::new(
+                Interface::ETHERNET,
+                if_index::ETHERNET,
+                stack,
+            ));
+        }
+
+        #[cfg(feature = "wifi")]
+        {
+            static RESOURCES: StaticCell<Resources> = StaticCell::new();
+
+            let (stack, runner) = net::new_stack(
+                wifi_device,
+                if_index::WIFI,
+                RESOURCES.init_with(StackResources::new),
+            );
+            spawner.spawn(net::wifi_net_task(runner)).unwrap();
+            spawner
+                .spawn(wifi::supervisor_task(station, stack))
+                .unwrap();
+            let _ = interfaces.push(Interface::new(Interface::WIFI, if_index::WIFI, stack));
+        }
+
+        #[cfg(feature = "usb-ethernet")]
+        {
+            static RESOURCES: StaticCell<Resources> = StaticCell::new();
+
+            let (stack, runner) = net::new_stack(
+                usb_ethernet_device,
+                if_index::USB_ETHERNET,
+                RESOURCES.init_with(StackResources::new),
+            );
+            spawner.spawn(net::usb_ethernet_net_task(runner)).unwrap();
+            let _ = interfaces.push(Interface::new(
+                Interface::USB_ETHERNET,
+                if_index::USB_ETHERNET,
+                stack,
+            ));
+        }
+
+        #[cfg(not(any(feature = "eth", feature = "wifi", feature = "usb-ethernet")))]
+        {
+            static RESOURCES: StaticCell<Resources> = StaticCell::new();
+
+            let (stack, runner) = net::new_stack(
+                net::new_dummy(),
+                0,
+                RESOURCES.init_with(StackResources::new),
+            );
+            spawner.spawn(net::dummy_net_task(runner)).unwrap();
+            let _ = interfaces.push(Interface::new("dummy", 0, stack));
+        }
+
+        for interface in &interfaces {
+            spawner
+                .spawn(net::events::monitor_task(*interface))
+                .unwrap();
+
+            #[cfg(feature = "network-config-ipv6-slaac")]
+            spawner
+                .spawn(net::ipv6::slaac::slaac_task(
+                    interface.stack(),
+                    interface.index(),
+                ))
+                .unwrap();
+        }
+
+        #[cfg(feature = "sntp")]
+        if let Some(primary) = interfaces.first() {
+            spawner
+                .spawn(net::sntp::sntp_task(primary.stack()))
+                .unwrap();
+        }
+
+        #[cfg(feature = "mdns-responder")]
+        if let Some(primary) = interfaces.first() {
+            spawner
+                .spawn(net::mdns::responder::responder_task(*primary))
+                .unwrap();
+        }
+
+        if crate::net::INTERFACES
+            .init(SameExecutorCell::new(interfaces, spawner))
             .is_err()
         {
             unreachable!();
         }
     }
 
-    #[cfg(feature = "wifi-cyw43")]
-    {
-        hal::cyw43::join(control).await;
-    };
-
     // mark used
     let _ = peripherals;
 
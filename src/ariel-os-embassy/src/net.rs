@@ -1,31 +1,156 @@
 //! Provides network access.
 //!
-//! The network link to use is selected through Cargo features.
+//! The network links to use are selected through Cargo features; each of them is a separate
+//! [`Interface`] with its own network stack.
 //! Additionally, the [`ariel_os::config`](ariel_os_macros::config) attribute macro allows to provide
 //! custom network configuration.
+//!
+//! Changes of the network state, e.g., loss of the link, are reported through [`events`].
+//! Components declare the sockets they use through [`declare_sockets!`], see [`sockets`].
+//! The device and its services can be advertised on the local link through [`mdns`].
 
 #![deny(missing_docs)]
 
-use embassy_net::{Runner, Stack};
+pub mod events;
+#[cfg(feature = "ipv6")]
+pub mod ipv6;
+pub mod mdns;
+#[cfg(feature = "sntp")]
+pub mod sntp;
+pub mod sockets;
+#[cfg(feature = "tls")]
+pub mod tls;
+
+pub use crate::declare_sockets;
+
+use ariel_os_debug::log::debug;
+use embassy_net::{Runner, Stack, StackResources, driver::Driver};
 use embassy_sync::once_lock::OnceLock;
 
-use crate::{NetworkDevice, cell::SameExecutorCell};
+use crate::cell::SameExecutorCell;
 
 #[allow(dead_code)]
 pub(crate) const ETHERNET_MTU: usize = 1514;
 
+/// Maximum number of sockets open at the same time on each interface, which is the sum of the
+/// [declared sockets](sockets) as computed by laze.
+pub(crate) const MAX_CONCURRENT_SOCKETS: usize = ariel_os_utils::usize_from_env_or!(
+    "CONFIG_NETWORK_MAX_CONCURRENT_SOCKETS",
+    4,
+    "maximum number of concurrent sockets allowed by the network stack"
+);
+
+const ETHERNET_ENABLED: u32 = if cfg!(feature = "eth") { 1 } else { 0 };
+const WIFI_ENABLED: u32 = if cfg!(feature = "wifi") { 1 } else { 0 };
+const USB_ETHERNET_ENABLED: u32 = if cfg!(feature = "usb-ethernet") { 1 } else { 0 };
+
+/// Maximum number of network interfaces, which is the number of enabled network links.
+pub const MAX_INTERFACES: usize = {
+    let count = ETHERNET_ENABLED + WIFI_ENABLED + USB_ETHERNET_ENABLED;
+    // A dummy interface stands in when no network link is enabled, outside of builds.
+    if count == 0 { 1 } else { count as usize }
+};
+
+/// Indices of the interfaces, in order of preference.
+///
+/// The index is passed to [`interface_eui48()`](ariel_os_identity::interface_eui48), so that each
+/// interface has a distinct MAC address; the interface with index 0 is the primary one.
+pub(crate) mod if_index {
+    use super::{ETHERNET_ENABLED, MAX_INTERFACES, WIFI_ENABLED};
+
+    #[allow(dead_code, reason = "use depends on enabled features")]
+    pub(crate) const ETHERNET: u32 = 0;
+    #[allow(dead_code, reason = "use depends on enabled features")]
+    pub(crate) const WIFI: u32 = ETHERNET_ENABLED;
+    #[allow(dead_code, reason = "use depends on enabled features")]
+    pub(crate) const USB_ETHERNET: u32 = ETHERNET_ENABLED + WIFI_ENABLED;
+    /// The MAC address the host sees for its end of the Ethernet-over-USB link.
+    #[allow(dead_code, reason = "use depends on enabled features")]
+    #[expect(
+        clippy::cast_possible_truncation,
+        reason = "there are only few interfaces"
+    )]
+    pub(crate) const USB_ETHERNET_HOST: u32 = MAX_INTERFACES as u32;
+}
+
 /// A network stack.
 ///
 /// Required to create a UDP or TCP socket.
 pub type NetworkStack = Stack<'static>;
 
-pub(crate) static STACK: OnceLock<SameExecutorCell<NetworkStack>> = OnceLock::new();
+/// A network interface, along with the network stack operating on it.
+///
+/// Sockets are bound to an interface by creating them on its [`stack()`](Interface::stack).
+#[derive(Copy, Clone)]
+pub struct Interface {
+    name: &'static str,
+    index: u32,
+    stack: NetworkStack,
+}
+
+impl Interface {
+    /// Name of the Ethernet interface.
+    pub const ETHERNET: &'static str = "eth";
+    /// Name of the Wi-Fi interface.
+    pub const WIFI: &'static str = "wifi";
+    /// Name of the Ethernet-over-USB interface.
+    pub const USB_ETHERNET: &'static str = "usb";
+
+    pub(crate) fn new(name: &'static str, index: u32, stack: NetworkStack) -> Self {
+        Self { name, index, stack }
+    }
 
-/// Returns a new [`NetworkStack`].
+    /// Returns the name of the interface, e.g., [`Interface::WIFI`].
+    #[must_use]
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Returns the index of the interface, from which its MAC address and IPv6 interface
+    /// identifier are derived.
+    #[must_use]
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    /// Returns the network stack of the interface.
+    #[must_use]
+    pub fn stack(&self) -> NetworkStack {
+        self.stack
+    }
+}
+
+/// The network interfaces, primary one first.
+pub type Interfaces = heapless::Vec<Interface, MAX_INTERFACES>;
+
+pub(crate) static INTERFACES: OnceLock<SameExecutorCell<Interfaces>> = OnceLock::new();
+
+/// Returns the network interfaces, primary one first.
+///
+/// When several network links are enabled, the primary interface is the first of Ethernet,
+/// Wi-Fi and Ethernet-over-USB.
+///
+/// Returns [`None`] if networking is not yet initialized.
+pub async fn interfaces() -> Option<Interfaces> {
+    INTERFACES.get().await.get_async().await.cloned()
+}
+
+/// Returns the [`NetworkStack`] of the primary interface.
 ///
 /// Returns [`None`] if networking is not yet initialized.
 pub async fn network_stack() -> Option<NetworkStack> {
-    STACK.get().await.get_async().await.copied()
+    interfaces().await?.first().map(Interface::stack)
+}
+
+/// Returns the [`NetworkStack`] of the interface named `name`, e.g., [`Interface::USB_ETHERNET`].
+///
+/// Returns [`None`] if networking is not yet initialized, or if there is no such interface.
+pub async fn network_stack_by_name(name: &str) -> Option<NetworkStack> {
+    interfaces()
+        .await?
+        .iter()
+        .find(|interface| interface.name() == name)
+        .map(Interface::stack)
 }
 
 /// Returns a seed suitable for [`embassy_net::new()`], on a best-effort basis.
@@ -63,16 +188,51 @@ pub(crate) fn unique_seed() -> u64 {
     1234
 }
 
-#[embassy_executor::task]
-pub(crate) async fn net_task(mut runner: Runner<'static, NetworkDevice>) -> ! {
-    runner.run().await
+/// Creates the network stack of the interface with index `if_index`.
+///
+/// The primary interface uses the configuration selected through laze modules or provided by the
+/// application, the others use [`additional_config()`].
+pub(crate) fn new_stack<D: Driver>(
+    device: D,
+    if_index: u32,
+    resources: &'static mut StackResources<MAX_CONCURRENT_SOCKETS>,
+) -> (NetworkStack, Runner<'static, D>) {
+    let config = if if_index == 0 {
+        config()
+    } else {
+        additional_config(if_index)
+    };
+
+    // Stacks on the same device only need to differ from each other.
+    let seed = unique_seed() ^ u64::from(if_index);
+    debug!("Network stack {} seed: {:#x}", if_index, seed);
+
+    embassy_net::new(device, config, resources, seed)
+}
+
+macro_rules! net_task {
+    ($name:ident, $device:ty) => {
+        #[embassy_executor::task]
+        pub(crate) async fn $name(mut runner: Runner<'static, $device>) -> ! {
+            runner.run().await
+        }
+    };
 }
 
+#[cfg(feature = "eth")]
+net_task!(eth_net_task, crate::eth::NetworkDevice);
+#[cfg(feature = "wifi")]
+net_task!(wifi_net_task, crate::wifi::NetworkDevice);
+#[cfg(feature = "usb-ethernet")]
+net_task!(usb_ethernet_net_task, crate::usb::ethernet::NetworkDevice);
+#[cfg(not(any(feature = "eth", feature = "wifi", feature = "usb-ethernet")))]
+net_task!(dummy_net_task, DummyDriver);
+
 #[allow(dead_code, reason = "false positive during builds outside of laze")]
 pub(crate) fn config() -> embassy_net::Config {
     #[cfg(not(feature = "network-config-override"))]
     {
-        embassy_net::Config::dhcpv4(embassy_net::DhcpConfig::default())
+        default_config()
     }
     #[cfg(feature = "network-config-override")]
     {
@@ -158,35 +318,78 @@ impl embassy_net::driver::RxToken for DummyDriver {
     }
 }
 
-#[cfg(feature = "network-config-static")]
-// SAFETY: the compiler prevents from defining multiple functions with the same name in the
-// same crate; the function signature is checked by the compiler as it is in the same crate as the
-// FFI declaration.
-#[unsafe(no_mangle)]
-fn __ariel_os_network_config() -> embassy_net::Config {
-    use ariel_os_utils::{ipv4_addr_from_env_or, u8_from_env_or};
-
-    let ipaddr = ipv4_addr_from_env_or!(
-        "CONFIG_NET_IPV4_STATIC_ADDRESS",
-        "10.42.0.61",
-        "static IPv4 address",
-    );
-
-    let gw_addr = ipv4_addr_from_env_or!(
-        "CONFIG_NET_IPV4_STATIC_GATEWAY_ADDRESS",
-        "10.42.0.1",
-        "static IPv4 gateway address",
-    );
-
-    let prefix_len = u8_from_env_or!(
-        "CONFIG_NET_IPV4_STATIC_CIDR_PREFIX_LEN",
-        24,
-        "static IPv4 CIDR prefix length"
-    );
-
-    embassy_net::Config::ipv4_static(embassy_net::StaticConfigV4 {
-        address: embassy_net::Ipv4Cidr::new(ipaddr, prefix_len),
-        dns_servers: heapless::Vec::new(),
-        gateway: Some(gw_addr),
-    })
+/// Returns the network configuration of the primary interface selected through laze modules.
+///
+/// This is the configuration used unless a custom one is provided through the
+/// [`ariel_os::config`](ariel_os_macros::config) attribute macro, which may start out from this
+/// one.
+#[must_use]
+#[allow(
+    clippy::needless_update,
+    reason = "IPv6 may be enabled in embassy-net by other crates"
+)]
+pub fn default_config() -> embassy_net::Config {
+    embassy_net::Config {
+        ipv4: config_v4(),
+        #[cfg(feature = "ipv6")]
+        ipv6: ipv6::config(),
+        ..Default::default()
+    }
+}
+
+/// Returns the network configuration of the interfaces other than the primary one.
+///
+/// These use DHCPv4 and, with IPv6 enabled, their own link-local address.
+#[must_use]
+#[allow(
+    clippy::needless_update,
+    reason = "IPv6 may be enabled in embassy-net by other crates"
+)]
+#[cfg_attr(
+    not(feature = "ipv6"),
+    expect(unused_variables, reason = "only IPv6 differs between interfaces")
+)]
+pub fn additional_config(if_index: u32) -> embassy_net::Config {
+    embassy_net::Config {
+        ipv4: embassy_net::ConfigV4::Dhcp(embassy_net::DhcpConfig::default()),
+        #[cfg(feature = "ipv6")]
+        ipv6: ipv6::link_local_config(if_index),
+        ..Default::default()
+    }
+}
+
+/// Returns the IPv4 configuration selected through laze modules.
+fn config_v4() -> embassy_net::ConfigV4 {
+    #[cfg(not(feature = "network-config-static"))]
+    {
+        embassy_net::ConfigV4::Dhcp(embassy_net::DhcpConfig::default())
+    }
+    #[cfg(feature = "network-config-static")]
+    {
+        use ariel_os_utils::{ipv4_addr_from_env_or, u8_from_env_or};
+
+        let ipaddr = ipv4_addr_from_env_or!(
+            "CONFIG_NET_IPV4_STATIC_ADDRESS",
+            "10.42.0.61",
+            "static IPv4 address",
+        );
+
+        let gw_addr = ipv4_addr_from_env_or!(
+            "CONFIG_NET_IPV4_STATIC_GATEWAY_ADDRESS",
+            "10.42.0.1",
+            "static IPv4 gateway address",
+        );
+
+        let prefix_len = u8_from_env_or!(
+            "CONFIG_NET_IPV4_STATIC_CIDR_PREFIX_LEN",
+            24,
+            "static IPv4 CIDR prefix length"
+        );
+
+        embassy_net::ConfigV4::Static(embassy_net::StaticConfigV4 {
+            address: embassy_net::Ipv4Cidr::new(ipaddr, prefix_len),
+            dns_servers: heapless::Vec::new(),
+            gateway: Some(gw_addr),
+        })
+    }
 }
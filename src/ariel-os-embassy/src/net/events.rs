@@ -0,0 +1,187 @@
+//! Reports changes of the network state.
+//!
+//! Tasks obtain a [`NetworkEventSubscriber`] through [`subscribe()`], which receives an
+//! [`InterfaceEvent`] whenever the link of an interface goes up or down, an address is acquired or
+//! lost (e.g., through DHCP), or the DNS servers change:
+//!
+//! ```
+//! # use ariel_os_embassy::net::events::{self, NetworkEvent};
+//! # async fn example() {
+//! let mut events = events::subscribe().unwrap();
+//! loop {
+//!     if let NetworkEvent::AddressAcquired(_) = events.next_message_pure().await.event {
+//!         // Reconnect to servers from the new address.
+//!     }
+//! }
+//! # }
+//! ```
+//!
+//! Subscribers that do not keep up miss the oldest events; this is reported by
+//! [`next_message()`](embassy_sync::pubsub::Subscriber::next_message) as
+//! [`WaitResult::Lagged`](embassy_sync::pubsub::WaitResult::Lagged), after which the current
+//! state should be read from the [`NetworkStack`] directly.
+
+use embassy_futures::select::select3;
+use embassy_net::{IpAddress, IpCidr};
+use embassy_sync::{
+    blocking_mutex::raw::CriticalSectionRawMutex,
+    pubsub::{PubSubChannel, Subscriber},
+};
+use embassy_time::{Duration, Timer};
+
+use super::{Interface, MAX_INTERFACES, NetworkStack};
+
+/// Number of events kept for each subscriber.
+const CAPACITY: usize = 4;
+
+/// Maximum number of concurrent subscribers.
+const MAX_SUBSCRIBERS: usize = ariel_os_utils::usize_from_env_or!(
+    "CONFIG_NETWORK_EVENT_SUBSCRIBERS",
+    4,
+    "maximum number of tasks concurrently subscribed to network events"
+);
+
+/// Interval in which the configuration is checked for changes that do not take it up or down,
+/// e.g., a DHCP lease with a different address.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+// Events are only published through an immediate publisher, so no publishers need to be counted.
+static EVENTS: PubSubChannel<
+    CriticalSectionRawMutex,
+    InterfaceEvent,
+    CAPACITY,
+    MAX_SUBSCRIBERS,
+    0,
+> = PubSubChannel::new();
+
+/// Receives [`InterfaceEvent`]s.
+pub type NetworkEventSubscriber =
+    Subscriber<'static, CriticalSectionRawMutex, InterfaceEvent, CAPACITY, MAX_SUBSCRIBERS, 0>;
+
+/// A [`NetworkEvent`] on a network interface.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct InterfaceEvent {
+    /// Name of the interface, see [`Interface::name()`].
+    pub interface: &'static str,
+    /// The change of the state of the interface.
+    pub event: NetworkEvent,
+}
+
+/// A change of the network state.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum NetworkEvent {
+    /// The link went up, e.g., Wi-Fi joined a network.
+    LinkUp,
+    /// The link went down, e.g., Wi-Fi lost the connection.
+    LinkDown,
+    /// An address was assigned to the interface.
+    AddressAcquired(IpCidr),
+    /// An address was removed from the interface.
+    AddressLost(IpCidr),
+    /// The DNS servers changed.
+    DnsServersChanged,
+}
+
+/// Subscribes to network events.
+///
+/// Returns [`None`] if `CONFIG_NETWORK_EVENT_SUBSCRIBERS` (default: 4) tasks are subscribed
+/// already.
+#[must_use]
+pub fn subscribe() -> Option<NetworkEventSubscriber> {
+    EVENTS.subscriber().ok()
+}
+
+/// The parts of the network state that events are reported for.
+#[derive(Default)]
+struct State {
+    link_up: bool,
+    config_up: bool,
+    /// One address per IP version.
+    addresses: heapless::Vec<IpCidr, 2>,
+    /// Up to three DNS servers per IP version.
+    dns_servers: heapless::Vec<IpAddress, 6>,
+}
+
+impl State {
+    fn of(stack: NetworkStack) -> Self {
+        let mut state = Self {
+            link_up: stack.is_link_up(),
+            config_up: stack.is_config_up(),
+            ..Self::default()
+        };
+        // Pushing cannot fail, as the capacities fit all IP versions.
+        if let Some(config) = stack.config_v4() {
+            let _ = state.addresses.push(IpCidr::Ipv4(config.address));
+            for server in config.dns_servers {
+                let _ = state.dns_servers.push(IpAddress::Ipv4(server));
+            }
+        }
+        #[cfg(feature = "ipv6")]
+        if let Some(config) = stack.config_v6() {
+            let _ = state.addresses.push(IpCidr::Ipv6(config.address));
+            for server in config.dns_servers {
+                let _ = state.dns_servers.push(IpAddress::Ipv6(server));
+            }
+        }
+        state
+    }
+}
+
+/// Publishes events whenever the state of `interface` changes.
+#[embassy_executor::task(pool_size = MAX_INTERFACES)]
+pub(crate) async fn monitor_task(interface: Interface) -> ! {
+    let stack = interface.stack();
+    let publisher = EVENTS.immediate_publisher();
+    let publish = |event| {
+        publisher.publish_immediate(InterfaceEvent {
+            interface: interface.name(),
+            event,
+        });
+    };
+    let mut previous = State::default();
+
+    loop {
+        let current = State::of(stack);
+
+        if current.link_up && !previous.link_up {
+            publish(NetworkEvent::LinkUp);
+        }
+        for address in &previous.addresses {
+            if !current.addresses.contains(address) {
+                publish(NetworkEvent::AddressLost(*address));
+            }
+        }
+        for address in &current.addresses {
+            if !previous.addresses.contains(address) {
+                publish(NetworkEvent::AddressAcquired(*address));
+            }
+        }
+        if current.dns_servers != previous.dns_servers {
+            publish(NetworkEvent::DnsServersChanged);
+        }
+        if !current.link_up && previous.link_up {
+            publish(NetworkEvent::LinkDown);
+        }
+
+        previous = current;
+
+        // The stack reports transitions of the link and the configuration, but not changes within
+        // a configuration.
+        let link_change = async {
+            if previous.link_up {
+                stack.wait_link_down().await;
+            } else {
+                stack.wait_link_up().await;
+            }
+        };
+        let config_change = async {
+            if previous.config_up {
+                stack.wait_config_down().await;
+            } else {
+                stack.wait_config_up().await;
+            }
+        };
+        select3(link_change, config_change, Timer::after(POLL_INTERVAL)).await;
+    }
+}
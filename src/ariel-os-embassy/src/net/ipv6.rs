@@ -0,0 +1,398 @@
+//! Provides IPv6 network configuration.
+//!
+//! The IPv6 configuration is selected through laze modules, alongside the IPv4 configuration:
+//!
+//! - `network-config-ipv6-link-local`: a link-local address derived from the device identity (see
+//!   [`link_local_address()`]).
+//! - `network-config-ipv6-slaac`: the link-local address, replaced by an address from stateless
+//!   address autoconfiguration (SLAAC, [RFC 4862]) once a router advertises a prefix.
+//! - `network-config-ipv6-static`: a static address, customized through the
+//!   `CONFIG_NET_IPV6_STATIC_*` environment variables.
+//!
+//! Except for the static address, which only applies to the primary interface, this applies to
+//! each network interface, whose addresses are derived from its own identifier.
+//!
+//! The functions of this module can also be used when providing a custom network configuration
+//! through the [`ariel_os::config`](ariel_os_macros::config) attribute macro; the primary
+//! interface has index 0.
+//!
+//! <div class="warning">
+//! The network stack currently supports a single IPv6 address.
+//! Once SLAAC configured an address, the link-local address is thus not available any more.
+//! </div>
+//!
+//! [RFC 4862]: https://www.rfc-editor.org/rfc/rfc4862
+
+use core::net::Ipv6Addr;
+
+use embassy_net::{ConfigV6, Ipv6Cidr, StaticConfigV6};
+
+/// Identifier used when the device does not provide an identity, as for the network devices.
+const FALLBACK_EUI48: [u8; 6] = [0xCA, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC];
+
+/// Length of the prefixes addresses are formed in.
+const PREFIX_LEN: u8 = 64;
+
+/// Returns the identifier of the interface with index `if_index`, as a modified EUI-64.
+///
+/// It is derived from [`interface_eui48(if_index)`](ariel_os_identity::interface_eui48), as
+/// described in [RFC 4291, Appendix A](https://www.rfc-editor.org/rfc/rfc4291#appendix-A).
+#[must_use]
+pub fn interface_id(if_index: u32) -> [u8; 8] {
+    let [a, b, c, d, e, f] =
+        ariel_os_identity::interface_eui48(if_index).map_or(FALLBACK_EUI48, |eui48| eui48.0);
+    // The universal/local bit is inverted.
+    [a ^ 0x02, b, c, 0xff, 0xfe, d, e, f]
+}
+
+/// Returns the address formed from the /64 `prefix` and the [`interface_id()`] of the interface
+/// with index `if_index`.
+#[must_use]
+pub fn address_in(prefix: Ipv6Addr, if_index: u32) -> Ipv6Addr {
+    let prefix = u128::from(prefix) & !u128::from(u64::MAX);
+    Ipv6Addr::from(prefix | u128::from(u64::from_be_bytes(interface_id(if_index))))
+}
+
+/// Returns the link-local address of the interface with index `if_index`.
+#[must_use]
+pub fn link_local_address(if_index: u32) -> Ipv6Addr {
+    address_in(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 0), if_index)
+}
+
+/// Returns a configuration with only the [`link_local_address()`] of the interface with index
+/// `if_index`.
+#[must_use]
+pub fn link_local_config(if_index: u32) -> ConfigV6 {
+    ConfigV6::Static(StaticConfigV6 {
+        address: Ipv6Cidr::new(link_local_address(if_index), PREFIX_LEN),
+        gateway: None,
+        dns_servers: heapless::Vec::new(),
+    })
+}
+
+/// Returns the IPv6 configuration of the primary interface selected through laze modules.
+pub(crate) fn config() -> ConfigV6 {
+    #[cfg(not(feature = "network-config-ipv6-static"))]
+    {
+        // SLAAC starts out from the link-local address.
+        link_local_config(0)
+    }
+    #[cfg(feature = "network-config-ipv6-static")]
+    {
+        use ariel_os_utils::{ipv6_addr_from_env_or, u8_from_env_or};
+
+        let address = ipv6_addr_from_env_or!(
+            "CONFIG_NET_IPV6_STATIC_ADDRESS",
+            "fd00::61",
+            "static IPv6 address",
+        );
+
+        let gateway = ipv6_addr_from_env_or!(
+            "CONFIG_NET_IPV6_STATIC_GATEWAY_ADDRESS",
+            "fd00::1",
+            "static IPv6 gateway address",
+        );
+
+        let prefix_len = u8_from_env_or!(
+            "CONFIG_NET_IPV6_STATIC_CIDR_PREFIX_LEN",
+            64,
+            "static IPv6 CIDR prefix length"
+        );
+
+        ConfigV6::Static(StaticConfigV6 {
+            address: Ipv6Cidr::new(address, prefix_len),
+            gateway: Some(gateway),
+            dns_servers: heapless::Vec::new(),
+        })
+    }
+}
+
+#[cfg(feature = "network-config-ipv6-slaac")]
+pub(crate) mod slaac {
+    //! Stateless address autoconfiguration from router advertisements ([RFC 4861], [RFC 4862]),
+    //! including DNS servers ([RFC 8106]).
+    //!
+    //! [RFC 4861]: https://www.rfc-editor.org/rfc/rfc4861
+    //! [RFC 4862]: https://www.rfc-editor.org/rfc/rfc4862
+    //! [RFC 8106]: https://www.rfc-editor.org/rfc/rfc8106
+
+    use core::net::Ipv6Addr;
+
+    use ariel_os_debug::log::{debug, info};
+    use embassy_futures::select::{Either3, select3};
+    use embassy_net::{
+        ConfigV6, Ipv6Cidr, StaticConfigV6,
+        raw::{IpProtocol, IpVersion, PacketMetadata, RawSocket},
+    };
+    use embassy_time::{Duration, Instant, Timer, with_timeout};
+
+    use super::{PREFIX_LEN, address_in, link_local_address, link_local_config};
+    use crate::net::{MAX_INTERFACES, NetworkStack};
+
+    crate::declare_sockets!("slaac", 1);
+
+    const IPV6_HEADER_LEN: usize = 40;
+    const NEXT_HEADER_ICMPV6: u8 = 58;
+    /// Hop limit of neighbor discovery messages, which ensures they originate on-link.
+    const HOP_LIMIT: u8 = 255;
+
+    const ROUTER_SOLICITATION: u8 = 133;
+    const ROUTER_ADVERTISEMENT: u8 = 134;
+    const ROUTER_ADVERTISEMENT_LEN: usize = 16;
+
+    const OPTION_PREFIX_INFORMATION: u8 = 3;
+    const OPTION_RECURSIVE_DNS_SERVER: u8 = 25;
+    /// The autonomous address-configuration flag of the prefix information option.
+    const FLAG_AUTONOMOUS: u8 = 0x40;
+
+    const ALL_ROUTERS: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 2);
+
+    const MAX_ROUTER_SOLICITATIONS: usize = 3;
+    const ROUTER_SOLICITATION_INTERVAL: Duration = Duration::from_secs(4);
+
+    /// Maximum length of a received router advertisement.
+    const MAX_PACKET_LEN: usize = 512;
+
+    /// The parts of a router advertisement used for configuration.
+    struct Advertisement {
+        config: StaticConfigV6,
+        valid_for: Duration,
+    }
+
+    /// Configures an address of the interface with index `if_index` from router advertisements
+    /// while the link is up.
+    #[embassy_executor::task(pool_size = MAX_INTERFACES)]
+    pub(crate) async fn slaac_task(stack: NetworkStack, if_index: u32) -> ! {
+        let mut rx_meta = [PacketMetadata::EMPTY; 2];
+        let mut rx_buffer = [0; 2 * MAX_PACKET_LEN];
+        let mut tx_meta = [PacketMetadata::EMPTY; 1];
+        let mut tx_buffer = [0; IPV6_HEADER_LEN + 8];
+        let socket = RawSocket::new(
+            stack,
+            IpVersion::Ipv6,
+            IpProtocol::Icmpv6,
+            &mut rx_meta,
+            &mut rx_buffer,
+            &mut tx_meta,
+            &mut tx_buffer,
+        );
+        let mut packet = [0; MAX_PACKET_LEN];
+
+        loop {
+            stack.wait_link_up().await;
+
+            // Routers only advertise every few minutes unless solicited.
+            let mut advertisement = None;
+            for _ in 0..MAX_ROUTER_SOLICITATIONS {
+                let _ = socket.send(&solicitation(if_index)).await;
+                if let Ok(received) = with_timeout(
+                    ROUTER_SOLICITATION_INTERVAL,
+                    receive(&socket, &mut packet, if_index),
+                )
+                .await
+                {
+                    advertisement = Some(received);
+                    break;
+                }
+            }
+            if advertisement.is_none() {
+                debug!("No IPv6 router advertised a prefix.");
+            }
+
+            let mut valid_until = Instant::MAX;
+            loop {
+                if let Some(advertisement) = advertisement.take() {
+                    valid_until = Instant::now()
+                        .checked_add(advertisement.valid_for)
+                        .unwrap_or(Instant::MAX);
+                    apply(stack, advertisement.config);
+                }
+
+                match select3(
+                    receive(&socket, &mut packet, if_index),
+                    Timer::at(valid_until),
+                    stack.wait_link_down(),
+                )
+                .await
+                {
+                    Either3::First(received) => advertisement = Some(received),
+                    Either3::Second(()) => {
+                        info!("IPv6 prefix expired.");
+                        stack.set_config_v6(link_local_config(if_index));
+                        valid_until = Instant::MAX;
+                    }
+                    Either3::Third(()) => {
+                        // The link may come back up on a different network.
+                        stack.set_config_v6(link_local_config(if_index));
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Applies `config` unless it is in place already.
+    fn apply(stack: NetworkStack, config: StaticConfigV6) {
+        if let Some(current) = stack.config_v6() {
+            if current.address == config.address
+                && current.gateway == config.gateway
+                && current.dns_servers == config.dns_servers
+            {
+                return;
+            }
+        }
+        info!("IPv6 address configured through SLAAC: {}", config.address);
+        stack.set_config_v6(ConfigV6::Static(config));
+    }
+
+    /// Waits for a router advertisement with a prefix suitable for SLAAC.
+    async fn receive(socket: &RawSocket<'_>, packet: &mut [u8], if_index: u32) -> Advertisement {
+        loop {
+            let Ok(len) = socket.recv(packet).await else {
+                continue;
+            };
+            if let Some(advertisement) =
+                packet.get(..len).and_then(|packet| parse(packet, if_index))
+            {
+                return advertisement;
+            }
+        }
+    }
+
+    /// Parses an IPv6 packet carrying a router advertisement, forming the address of the
+    /// interface with index `if_index`.
+    fn parse(packet: &[u8], if_index: u32) -> Option<Advertisement> {
+        let header = packet.get(..IPV6_HEADER_LEN)?;
+        let message = packet.get(IPV6_HEADER_LEN..)?;
+
+        if header.get(6..8)? != [NEXT_HEADER_ICMPV6, HOP_LIMIT] {
+            return None;
+        }
+        let source = address(header, 8)?;
+        let destination = address(header, 24)?;
+        // Routers send advertisements from their link-local address.
+        if source.segments()[0] & 0xffc0 != 0xfe80 {
+            return None;
+        }
+        if message.get(..2)? != [ROUTER_ADVERTISEMENT, 0]
+            || message.len() < ROUTER_ADVERTISEMENT_LEN
+            || checksum(source, destination, message) != 0
+        {
+            return None;
+        }
+        let router_lifetime = u16::from_be_bytes(message.get(6..8)?.try_into().ok()?);
+
+        let mut prefix = None;
+        let mut dns_servers = heapless::Vec::new();
+        let mut options = message.get(ROUTER_ADVERTISEMENT_LEN..)?;
+        while let [kind, len, ..] = *options {
+            let len = usize::from(len) * 8;
+            let option = options.get(..len).filter(|_| len > 0)?;
+            options = options.get(len..)?;
+
+            match kind {
+                OPTION_PREFIX_INFORMATION if prefix.is_none() && len == 32 => {
+                    let valid_lifetime = u32_at(option, 4)?;
+                    let candidate = address(option, 16)?;
+                    if option.get(2) == Some(&PREFIX_LEN)
+                        && option
+                            .get(3)
+                            .is_some_and(|flags| flags & FLAG_AUTONOMOUS != 0)
+                        && valid_lifetime > 0
+                        && candidate.segments()[0] & 0xffc0 != 0xfe80
+                    {
+                        prefix = Some((candidate, valid_lifetime));
+                    }
+                }
+                OPTION_RECURSIVE_DNS_SERVER if u32_at(option, 4)? > 0 => {
+                    let mut offset = 8;
+                    while let Some(server) = address(option, offset) {
+                        // Further servers are ignored once the list is full.
+                        let _ = dns_servers.push(server);
+                        offset += 16;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let (prefix, valid_lifetime) = prefix?;
+        Some(Advertisement {
+            config: StaticConfigV6 {
+                address: Ipv6Cidr::new(address_in(prefix, if_index), PREFIX_LEN),
+                // A router lifetime of zero indicates that the router is not a default router.
+                gateway: (router_lifetime > 0).then_some(source),
+                dns_servers,
+            },
+            valid_for: if valid_lifetime == u32::MAX {
+                Duration::MAX
+            } else {
+                Duration::from_secs(u64::from(valid_lifetime))
+            },
+        })
+    }
+
+    /// Returns a router solicitation, as an IPv6 packet.
+    fn solicitation(if_index: u32) -> [u8; IPV6_HEADER_LEN + 8] {
+        let source = link_local_address(if_index);
+
+        let mut packet = [0; IPV6_HEADER_LEN + 8];
+        let header = [0x60, 0, 0, 0, 0, 8, NEXT_HEADER_ICMPV6, HOP_LIMIT];
+        let message = [ROUTER_SOLICITATION, 0, 0, 0, 0, 0, 0, 0];
+        for (byte, value) in packet.iter_mut().zip(
+            header
+                .into_iter()
+                .chain(source.octets())
+                .chain(ALL_ROUTERS.octets())
+                .chain(message),
+        ) {
+            *byte = value;
+        }
+
+        let [high, low] = checksum(source, ALL_ROUTERS, &packet[IPV6_HEADER_LEN..]).to_be_bytes();
+        packet[IPV6_HEADER_LEN + 2] = high;
+        packet[IPV6_HEADER_LEN + 3] = low;
+        packet
+    }
+
+    /// Computes the ICMPv6 checksum of `message`, which is zero for a received message with a
+    /// correct checksum.
+    fn checksum(source: Ipv6Addr, destination: Ipv6Addr, message: &[u8]) -> u16 {
+        #[expect(
+            clippy::cast_possible_truncation,
+            reason = "messages are shorter than MAX_PACKET_LEN"
+        )]
+        let len = message.len() as u32;
+
+        let mut sum = 0;
+        for bytes in [
+            &source.octets()[..],
+            &destination.octets()[..],
+            &len.to_be_bytes()[..],
+            &[0, 0, 0, NEXT_HEADER_ICMPV6][..],
+            message,
+        ] {
+            for chunk in bytes.chunks(2) {
+                let high = chunk.first().copied().unwrap_or(0);
+                let low = chunk.get(1).copied().unwrap_or(0);
+                sum += u32::from(u16::from_be_bytes([high, low]));
+            }
+        }
+        while sum > 0xffff {
+            sum = (sum & 0xffff) + (sum >> 16);
+        }
+        #[expect(clippy::cast_possible_truncation, reason = "folded into 16 bits above")]
+        !(sum as u16)
+    }
+
+    fn address(bytes: &[u8], offset: usize) -> Option<Ipv6Addr> {
+        let octets: [u8; 16] = bytes.get(offset..offset + 16)?.try_into().ok()?;
+        Some(Ipv6Addr::from(octets))
+    }
+
+    fn u32_at(bytes: &[u8], offset: usize) -> Option<u32> {
+        Some(u32::from_be_bytes(
+            bytes.get(offset..offset + 4)?.try_into().ok()?,
+        ))
+    }
+}
@@ -0,0 +1,266 @@
+//! Advertises the device and its services on the local link through multicast DNS ([RFC 6762])
+//! and DNS-based service discovery (DNS-SD, [RFC 6763]).
+//!
+//! With the `mdns-responder` Cargo feature enabled, a responder answers the queries received on
+//! the primary interface for:
+//!
+//! - the [`hostname()`] of the device within the `.local` domain, with its addresses;
+//! - the services declared through [`declare_service!`], each with an instance named after the
+//!   host name, and the list of these services.
+//!
+//! The records are announced once the interface is configured, and again whenever it acquires an
+//! address.
+//! Tools can then discover devices without knowing their addresses, e.g., through
+//! `avahi-browse --resolve _coap._udp` or `dns-sd -B _coap._udp`.
+//!
+//! Services are declared by the components providing them, e.g., the CoAP server declares
+//! `_coap._udp`, and by applications, optionally with `key=value` entries of their TXT record:
+//!
+//! ```ignore
+//! ariel_os::net::mdns::declare_service!("_http._tcp", 80, "path=/");
+//! ```
+//!
+//! Before announcing them, the responder probes whether another host on the link uses the host
+//! name ([RFC 6762, Section 8.1]).
+//! If so, it renames the device by appending `-2`, `-3`, etc. to the host name and probes again
+//! ([RFC 6762, Section 9]); [`hostname()`] returns the name the device ended up with.
+//! Only the host name is probed for: service instances are named after it, and are taken to be
+//! unique along with it.
+//!
+//! [RFC 6762]: https://www.rfc-editor.org/rfc/rfc6762
+//! [RFC 6762, Section 8.1]: https://www.rfc-editor.org/rfc/rfc6762#section-8.1
+//! [RFC 6762, Section 9]: https://www.rfc-editor.org/rfc/rfc6762#section-9
+//! [RFC 6763]: https://www.rfc-editor.org/rfc/rfc6763
+
+#[cfg(feature = "mdns-responder")]
+mod message;
+#[cfg(feature = "mdns-responder")]
+pub(crate) mod responder;
+
+pub use crate::declare_service;
+
+use core::{cell::RefCell, fmt::Write as _};
+
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+
+/// Host name under which the device is advertised; if empty, it is derived from the board name
+/// and the device ID.
+const HOSTNAME: &str = ariel_os_utils::str_from_env_or!(
+    "CONFIG_MDNS_HOSTNAME",
+    "",
+    "host name advertised through mDNS (derived from the board name and device ID if empty)"
+);
+
+/// Maximum length of a label of a DNS name, which limits the length of the host name.
+pub const MAX_LABEL_LEN: usize = 63;
+
+/// Maximum length of a device ID included in the host name as is; longer ones are hashed.
+const MAX_ID_LEN: usize = 16;
+
+type Hostname = heapless::String<MAX_LABEL_LEN>;
+
+/// Host name of the device once derived, which the responder replaces after conflicts.
+static HOSTNAME_IN_USE: Mutex<CriticalSectionRawMutex, RefCell<Option<Hostname>>> =
+    Mutex::new(RefCell::new(None));
+
+/// A service advertised through DNS-SD.
+#[doc(hidden)]
+pub struct ServiceDeclaration {
+    pub service: &'static str,
+    pub port: u16,
+    pub txt: &'static [&'static str],
+}
+
+#[doc(hidden)]
+#[linkme::distributed_slice]
+pub static SERVICES: [ServiceDeclaration] = [..];
+
+/// Declares that the device provides `service` (e.g., `"_http._tcp"`) on `port`, optionally
+/// followed by the `key=value` entries of its TXT record.
+///
+/// Services are only advertised when the `mdns-responder` Cargo feature is enabled; see the
+/// [`mdns`](crate::net::mdns) module.
+#[macro_export]
+macro_rules! declare_service {
+    ($service:literal, $port:expr $(, $txt:literal)* $(,)?) => {
+        const _: () = {
+            #[$crate::reexports::linkme::distributed_slice($crate::net::mdns::SERVICES)]
+            #[linkme(crate = $crate::reexports::linkme)]
+            static DECLARATION: $crate::net::mdns::ServiceDeclaration =
+                $crate::net::mdns::ServiceDeclaration {
+                    service: $service,
+                    port: $port,
+                    txt: &[$($txt),*],
+                };
+        };
+    };
+}
+
+/// Returns the host name of the device, without the `.local` domain.
+///
+/// Unless set through `CONFIG_MDNS_HOSTNAME`, it consists of the board name (see
+/// [`ariel_os_buildinfo::BOARD`]) followed by the device ID (see
+/// [`ariel_os_identity::device_id_bytes()`]) in hexadecimal, e.g., `nrf52840dk-1a2b3c4d5e6f7081`.
+/// Device IDs longer than 16 bytes are replaced by a 64-bit hash of them.
+/// On devices without an ID, 8 random hexadecimal digits are used instead when the `random`
+/// Cargo feature is enabled, so that the host name differs across boots; otherwise, the board
+/// name is used as is.
+/// Characters other than ASCII letters and digits are replaced by `-`, and the board name is
+/// shortened so that the host name fits into a DNS label.
+///
+/// If the responder had to rename the device because another host on the link uses that name,
+/// the new name is returned instead (see the [`mdns`](crate::net::mdns) module).
+#[must_use]
+pub fn hostname() -> heapless::String<MAX_LABEL_LEN> {
+    HOSTNAME_IN_USE.lock(|name| {
+        name.borrow_mut()
+            .get_or_insert_with(|| {
+                let id = ariel_os_identity::device_id_bytes().ok();
+                #[cfg(feature = "random")]
+                let random = Some(rand_core::RngCore::next_u32(
+                    &mut ariel_os_random::fast_rng(),
+                ));
+                #[cfg(not(feature = "random"))]
+                let random = None;
+                derive_hostname(
+                    HOSTNAME,
+                    ariel_os_buildinfo::BOARD,
+                    id.as_ref().map(AsRef::as_ref),
+                    random,
+                )
+            })
+            .clone()
+    })
+}
+
+/// Records that the responder renamed the device to `name`.
+#[cfg(feature = "mdns-responder")]
+fn set_hostname(name: Hostname) {
+    HOSTNAME_IN_USE.lock(|current| *current.borrow_mut() = Some(name));
+}
+
+/// Derives the host name from the `configured` one, or from the `board` name and the device
+/// `id`, falling back to `random` bits without one.
+fn derive_hostname(
+    configured: &str,
+    board: &str,
+    id: Option<&[u8]>,
+    random: Option<u32>,
+) -> Hostname {
+    let mut name = Hostname::new();
+    if !configured.is_empty() {
+        push_sanitized(&mut name, configured, MAX_LABEL_LEN);
+        return name;
+    }
+
+    let mut suffix = heapless::String::<{ 2 * MAX_ID_LEN }>::new();
+    match (id, random) {
+        (Some(id), _) if id.len() <= MAX_ID_LEN => {
+            for byte in id {
+                let _ = write!(suffix, "{byte:02x}");
+            }
+        }
+        (Some(id), _) => {
+            let _ = write!(suffix, "{:016x}", fnv1a(id));
+        }
+        (None, Some(random)) => {
+            let _ = write!(suffix, "{random:08x}");
+        }
+        (None, None) => {}
+    }
+
+    if suffix.is_empty() {
+        push_sanitized(&mut name, board, MAX_LABEL_LEN);
+    } else {
+        push_sanitized(&mut name, board, MAX_LABEL_LEN - 1 - suffix.len());
+        let _ = name.push('-');
+        let _ = name.push_str(&suffix);
+    }
+    name
+}
+
+/// Returns the 64-bit FNV-1a hash of `bytes`.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Appends up to `max_len` characters of `source` to `name`, replacing those that are not
+/// allowed in host names.
+fn push_sanitized(name: &mut Hostname, source: &str, max_len: usize) {
+    for c in source.chars().take(max_len) {
+        let c = if c.is_ascii_alphanumeric() {
+            c.to_ascii_lowercase()
+        } else {
+            '-'
+        };
+        let _ = name.push(c);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn configured() {
+        assert_eq!(
+            derive_hostname("My_Device", "nrf52840dk", Some(&[1, 2]), Some(3)),
+            "my-device"
+        );
+        let long = core::str::from_utf8(&[b'a'; 100]).unwrap();
+        assert_eq!(derive_hostname(long, "", None, None).len(), MAX_LABEL_LEN);
+    }
+
+    #[test]
+    fn device_id() {
+        let id = [0x1a, 0x2b, 0x3c, 0x4d, 0x5e, 0x6f, 0x70, 0x81];
+        assert_eq!(
+            derive_hostname("", "nrf52840dk", Some(&id), Some(0)),
+            "nrf52840dk-1a2b3c4d5e6f7081"
+        );
+
+        // IDs that a 32-bit XOR fold would map to the same host name differ.
+        assert_ne!(
+            derive_hostname("", "board", Some(&[1, 0, 0, 0, 1, 0, 0, 0]), None),
+            derive_hostname("", "board", Some(&[0; 8]), None)
+        );
+
+        // Long IDs are hashed, and distinguishable by their last byte.
+        let mut long = [0xaa; 32];
+        let hashed = derive_hostname("", "board", Some(&long), None);
+        assert_eq!(hashed.len(), "board-".len() + 16);
+        long[31] = 0xab;
+        assert_ne!(derive_hostname("", "board", Some(&long), None), hashed);
+    }
+
+    #[test]
+    fn board_shortened() {
+        let board = core::str::from_utf8(&[b'b'; 60]).unwrap();
+        let name = derive_hostname("", board, Some(&[0xff; MAX_ID_LEN]), None);
+        assert_eq!(name.len(), MAX_LABEL_LEN);
+        let (board, id) = name.split_once('-').unwrap();
+        assert!(board.len() == 30 && board.bytes().all(|c| c == b'b'));
+        assert!(id.len() == 32 && id.bytes().all(|c| c == b'f'));
+    }
+
+    #[test]
+    fn without_device_id() {
+        assert_eq!(
+            derive_hostname("", "Native", None, Some(0xdead_beef)),
+            "native-deadbeef"
+        );
+        assert_eq!(
+            derive_hostname("", "st-nucleo_wb55", None, None),
+            "st-nucleo-wb55"
+        );
+    }
+
+    #[test]
+    fn fnv1a_vectors() {
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(fnv1a(b"foobar"), 0x8594_4171_f739_67e8);
+    }
+}
@@ -0,0 +1,888 @@
+//! Decodes queries and the records of received messages, and encodes responses and probes
+//! ([RFC 1035, Section 4], [RFC 6762, Section 18]).
+//!
+//! Names are decoded into their dotted form, and encoded from parts in that form without
+//! compression.
+//!
+//! [RFC 1035, Section 4]: https://www.rfc-editor.org/rfc/rfc1035#section-4
+//! [RFC 6762, Section 18]: https://www.rfc-editor.org/rfc/rfc6762#section-18
+
+use core::net::Ipv4Addr;
+#[cfg(feature = "ipv6")]
+use core::net::Ipv6Addr;
+
+const HEADER_LEN: usize = 12;
+
+pub(crate) const TYPE_A: u16 = 1;
+pub(crate) const TYPE_PTR: u16 = 12;
+pub(crate) const TYPE_TXT: u16 = 16;
+#[cfg(feature = "ipv6")]
+pub(crate) const TYPE_AAAA: u16 = 28;
+pub(crate) const TYPE_SRV: u16 = 33;
+pub(crate) const TYPE_ANY: u16 = 255;
+
+pub(crate) const CLASS_IN: u16 = 1;
+const CLASS_ANY: u16 = 255;
+/// Bit of the class of a question asking for a unicast response.
+const CLASS_UNICAST_RESPONSE: u16 = 0x8000;
+/// Bit of the class of a record unique to this host, which replaces cached records.
+const CLASS_CACHE_FLUSH: u16 = 0x8000;
+
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_AUTHORITATIVE: u16 = 0x0400;
+const OPCODE_MASK: u16 = 0x7800;
+
+/// Maximum TTL of records in responses to legacy unicast queries ([RFC 6762, Section 6.7]).
+///
+/// [RFC 6762, Section 6.7]: https://www.rfc-editor.org/rfc/rfc6762#section-6.7
+const LEGACY_MAX_TTL: u32 = 10;
+
+/// Maximum number of compression pointers followed in a name, which also prevents loops.
+const MAX_POINTERS: usize = 16;
+
+const MAX_NAME_LEN: usize = 255;
+
+pub(crate) type Name = heapless::String<MAX_NAME_LEN>;
+
+/// A question of a query.
+pub(crate) struct Question {
+    pub(crate) name: Name,
+    pub(crate) qtype: u16,
+    pub(crate) unicast_response: bool,
+}
+
+/// A query, which iterates over its questions of the `IN` class.
+///
+/// Iteration ends early when a question is malformed.
+#[derive(Clone)]
+pub(crate) struct Query<'a> {
+    message: &'a [u8],
+    id: u16,
+    remaining: u16,
+    offset: usize,
+}
+
+impl<'a> Query<'a> {
+    /// Parses the header of `message`, returning `None` unless it is a standard query.
+    pub(crate) fn parse(message: &'a [u8]) -> Option<Self> {
+        let flags = u16_at(message, 2)?;
+        if flags & (FLAG_RESPONSE | OPCODE_MASK) != 0 {
+            return None;
+        }
+        Some(Self {
+            message,
+            id: u16_at(message, 0)?,
+            remaining: u16_at(message, 4)?,
+            offset: HEADER_LEN,
+        })
+    }
+
+    pub(crate) fn id(&self) -> u16 {
+        self.id
+    }
+}
+
+impl Iterator for Query<'_> {
+    type Item = Question;
+
+    fn next(&mut self) -> Option<Question> {
+        while self.remaining > 0 {
+            self.remaining -= 1;
+
+            let Some((name, end)) = read_name(self.message, self.offset) else {
+                self.remaining = 0;
+                return None;
+            };
+            let (Some(qtype), Some(qclass)) =
+                (u16_at(self.message, end), u16_at(self.message, end + 2))
+            else {
+                self.remaining = 0;
+                return None;
+            };
+            self.offset = end + 4;
+
+            let class = qclass & !CLASS_UNICAST_RESPONSE;
+            if class == CLASS_IN || class == CLASS_ANY {
+                return Some(Question {
+                    name,
+                    qtype,
+                    unicast_response: qclass & CLASS_UNICAST_RESPONSE != 0,
+                });
+            }
+        }
+        None
+    }
+}
+
+/// A record of a received message.
+pub(crate) struct Record<'a> {
+    pub(crate) section: Section,
+    /// The name, unless it cannot be decoded (see [`read_name()`]).
+    pub(crate) name: Option<Name>,
+    pub(crate) rtype: u16,
+    /// The class, without the cache-flush bit.
+    pub(crate) class: u16,
+    pub(crate) data: &'a [u8],
+}
+
+/// The records of a received message, which iterates over them in order.
+///
+/// Iteration ends early when a record is malformed.
+#[derive(Clone)]
+pub(crate) struct Records<'a> {
+    message: &'a [u8],
+    is_response: bool,
+    /// Number of records remaining in the answer, authority and additional sections.
+    remaining: [u16; 3],
+    offset: usize,
+}
+
+impl<'a> Records<'a> {
+    /// Parses the header of `message` and skips its questions, returning `None` unless it is a
+    /// standard query or response.
+    pub(crate) fn parse(message: &'a [u8]) -> Option<Self> {
+        let flags = u16_at(message, 2)?;
+        if flags & OPCODE_MASK != 0 {
+            return None;
+        }
+        let mut offset = HEADER_LEN;
+        for _ in 0..u16_at(message, 4)? {
+            offset = skip_name(message, offset)? + 4;
+        }
+        Some(Self {
+            message,
+            is_response: flags & FLAG_RESPONSE != 0,
+            remaining: [
+                u16_at(message, 6)?,
+                u16_at(message, 8)?,
+                u16_at(message, 10)?,
+            ],
+            offset,
+        })
+    }
+
+    pub(crate) fn is_response(&self) -> bool {
+        self.is_response
+    }
+
+    fn read(&mut self, section: Section) -> Option<Record<'a>> {
+        let end = skip_name(self.message, self.offset)?;
+        let rtype = u16_at(self.message, end)?;
+        let class = u16_at(self.message, end + 2)? & !CLASS_CACHE_FLUSH;
+        let len = usize::from(u16_at(self.message, end + 8)?);
+        let data = self.message.get(end + 10..end + 10 + len)?;
+        let name = read_name(self.message, self.offset).map(|(name, _)| name);
+        self.offset = end + 10 + len;
+        Some(Record {
+            section,
+            name,
+            rtype,
+            class,
+            data,
+        })
+    }
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = Record<'a>;
+
+    fn next(&mut self) -> Option<Record<'a>> {
+        let sections = [Section::Answer, Section::Authority, Section::Additional];
+        let (section, remaining) = sections
+            .into_iter()
+            .zip(&mut self.remaining)
+            .find(|(_, remaining)| **remaining > 0)?;
+        *remaining -= 1;
+
+        let record = self.read(section);
+        if record.is_none() {
+            self.remaining = [0; 3];
+        }
+        record
+    }
+}
+
+/// Returns the offset following the name at `offset`, without decoding it.
+fn skip_name(message: &[u8], mut offset: usize) -> Option<usize> {
+    loop {
+        let len = *message.get(offset)?;
+        match len & 0xc0 {
+            0x00 if len == 0 => return Some(offset + 1),
+            0x00 => offset += 1 + usize::from(len),
+            0xc0 => {
+                message.get(offset + 1)?;
+                return Some(offset + 2);
+            }
+            _ => return None,
+        }
+    }
+}
+
+/// Reads the name at `offset`, returning it along with the offset following it.
+///
+/// Returns `None` if the name is malformed or too long, or if a label is not valid UTF-8 or
+/// contains a dot, which names answered by the responder never do.
+fn read_name(message: &[u8], mut offset: usize) -> Option<(Name, usize)> {
+    let mut name = Name::new();
+    let mut end = None;
+    let mut pointers = 0;
+
+    loop {
+        let len = *message.get(offset)?;
+        if len == 0 {
+            break;
+        }
+        match len & 0xc0 {
+            0x00 => {
+                let label = message.get(offset + 1..offset + 1 + usize::from(len))?;
+                let label = core::str::from_utf8(label).ok()?;
+                if label.contains('.') {
+                    return None;
+                }
+                if !name.is_empty() {
+                    name.push('.').ok()?;
+                }
+                name.push_str(label).ok()?;
+                offset += 1 + usize::from(len);
+            }
+            0xc0 => {
+                pointers += 1;
+                if pointers > MAX_POINTERS {
+                    return None;
+                }
+                if end.is_none() {
+                    end = Some(offset + 2);
+                }
+                offset = usize::from(u16_at(message, offset)? & 0x3fff);
+            }
+            _ => return None,
+        }
+    }
+
+    Some((name, end.unwrap_or(offset + 1)))
+}
+
+/// Whether the dotted `name` consists of the labels of `parts`, ignoring ASCII case.
+pub(crate) fn name_eq(name: &str, parts: &[&str]) -> bool {
+    let mut labels = parts.iter().flat_map(|part| part.split('.'));
+    for label in name.split('.') {
+        match labels.next() {
+            Some(expected) if expected.eq_ignore_ascii_case(label) => {}
+            _ => return false,
+        }
+    }
+    labels.next().is_none()
+}
+
+/// The data of a record.
+pub(crate) enum Data<'a> {
+    A(Ipv4Addr),
+    #[cfg(feature = "ipv6")]
+    Aaaa(Ipv6Addr),
+    Ptr(&'a [&'a str]),
+    Srv {
+        port: u16,
+        target: &'a [&'a str],
+    },
+    Txt(&'a [&'a str]),
+}
+
+impl Data<'_> {
+    fn rtype(&self) -> u16 {
+        match self {
+            Self::A(_) => TYPE_A,
+            #[cfg(feature = "ipv6")]
+            Self::Aaaa(_) => TYPE_AAAA,
+            Self::Ptr(_) => TYPE_PTR,
+            Self::Srv { .. } => TYPE_SRV,
+            Self::Txt(_) => TYPE_TXT,
+        }
+    }
+
+    /// Whether the record is unique to this host, as opposed to shared with other hosts.
+    fn is_unique(&self) -> bool {
+        !matches!(self, Self::Ptr(_))
+    }
+}
+
+/// Section of a message a record is in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Section {
+    Answer,
+    Authority,
+    Additional,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Kind {
+    Response,
+    /// A response to a legacy unicast query.
+    Legacy,
+    /// A query probing whether names are in use ([RFC 6762, Section 8.1]).
+    ///
+    /// [RFC 6762, Section 8.1]: https://www.rfc-editor.org/rfc/rfc6762#section-8.1
+    Probe,
+}
+
+/// Writes a response, or a probe, into a buffer.
+///
+/// Questions have to be written before answers, answers before authority records, and these
+/// before additional records.
+pub(crate) struct Response<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+    id: u16,
+    kind: Kind,
+    questions: u16,
+    answers: u16,
+    authorities: u16,
+    additionals: u16,
+}
+
+impl<'a> Response<'a> {
+    /// Starts a response with the message identifier `id`.
+    ///
+    /// Responses to legacy unicast queries, i.e., from resolvers not implementing mDNS, limit the
+    /// TTLs and do not mark unique records.
+    pub(crate) fn new(buffer: &'a mut [u8], id: u16, legacy: bool) -> Self {
+        Self::with_kind(
+            buffer,
+            id,
+            if legacy { Kind::Legacy } else { Kind::Response },
+        )
+    }
+
+    /// Starts a probe, which carries the proposed records in its authority section.
+    pub(crate) fn probe(buffer: &'a mut [u8]) -> Self {
+        Self::with_kind(buffer, 0, Kind::Probe)
+    }
+
+    fn with_kind(buffer: &'a mut [u8], id: u16, kind: Kind) -> Self {
+        Self {
+            buffer,
+            len: HEADER_LEN,
+            id,
+            kind,
+            questions: 0,
+            answers: 0,
+            authorities: 0,
+            additionals: 0,
+        }
+    }
+
+    /// Repeats `question`, as done in responses to legacy unicast queries, returning whether it
+    /// fit.
+    pub(crate) fn question(&mut self, question: &Question) -> bool {
+        self.put_question(&[question.name.as_str()], question.qtype, CLASS_IN)
+    }
+
+    /// Asks for all records of `name`, as done in probes, returning whether it fit.
+    pub(crate) fn probe_question(&mut self, name: &[&str], unicast_response: bool) -> bool {
+        let class = if unicast_response {
+            CLASS_IN | CLASS_UNICAST_RESPONSE
+        } else {
+            CLASS_IN
+        };
+        self.put_question(name, TYPE_ANY, class)
+    }
+
+    fn put_question(&mut self, name: &[&str], qtype: u16, class: u16) -> bool {
+        debug_assert!(self.answers == 0 && self.authorities == 0 && self.additionals == 0);
+        let written = self.append(|response| {
+            response.put_name(name)?;
+            response.put_u16(qtype)?;
+            response.put_u16(class)
+        });
+        if written {
+            self.questions += 1;
+        }
+        written
+    }
+
+    /// Appends a record to `section`, returning whether it fit.
+    pub(crate) fn record(
+        &mut self,
+        section: Section,
+        name: &[&str],
+        ttl: u32,
+        data: &Data<'_>,
+    ) -> bool {
+        debug_assert!(match section {
+            Section::Answer => self.authorities == 0 && self.additionals == 0,
+            Section::Authority => self.additionals == 0,
+            Section::Additional => true,
+        });
+        let (ttl, class) = match self.kind {
+            Kind::Legacy => (ttl.min(LEGACY_MAX_TTL), CLASS_IN),
+            Kind::Response if data.is_unique() => (ttl, CLASS_IN | CLASS_CACHE_FLUSH),
+            // The cache-flush bit is not set in probes, whose records are only proposed.
+            Kind::Response | Kind::Probe => (ttl, CLASS_IN),
+        };
+
+        let written = self.append(|response| {
+            response.put_name(name)?;
+            response.put_u16(data.rtype())?;
+            response.put_u16(class)?;
+            response.put_u32(ttl)?;
+
+            let length_offset = response.len;
+            response.put_u16(0)?;
+            match data {
+                Data::A(address) => response.put(&address.octets())?,
+                #[cfg(feature = "ipv6")]
+                Data::Aaaa(address) => response.put(&address.octets())?,
+                Data::Ptr(target) => response.put_name(target)?,
+                Data::Srv { port, target } => {
+                    // Priority and weight.
+                    response.put_u16(0)?;
+                    response.put_u16(0)?;
+                    response.put_u16(*port)?;
+                    response.put_name(target)?;
+                }
+                // An empty TXT record consists of a single empty entry.
+                Data::Txt([]) => response.put(&[0])?,
+                Data::Txt(entries) => {
+                    for entry in *entries {
+                        response.put(&[u8::try_from(entry.len()).ok()?])?;
+                        response.put(entry.as_bytes())?;
+                    }
+                }
+            }
+            let length = u16::try_from(response.len - length_offset - 2).ok()?;
+            response
+                .buffer
+                .get_mut(length_offset..length_offset + 2)?
+                .copy_from_slice(&length.to_be_bytes());
+            Some(())
+        });
+        if written {
+            match section {
+                Section::Answer => self.answers += 1,
+                Section::Authority => self.authorities += 1,
+                Section::Additional => self.additionals += 1,
+            }
+        }
+        written
+    }
+
+    /// Returns the number of answers written.
+    pub(crate) fn answers(&self) -> u16 {
+        self.answers
+    }
+
+    /// Completes the header, returning the encoded message.
+    pub(crate) fn finish(self) -> &'a [u8] {
+        let mut header = [0; HEADER_LEN];
+        let flags = if self.kind == Kind::Probe {
+            0
+        } else {
+            FLAG_RESPONSE | FLAG_AUTHORITATIVE
+        };
+        for (field, value) in header.chunks_exact_mut(2).zip([
+            self.id,
+            flags,
+            self.questions,
+            self.answers,
+            self.authorities,
+            self.additionals,
+        ]) {
+            field.copy_from_slice(&value.to_be_bytes());
+        }
+
+        let buffer = self.buffer;
+        let Some(header_bytes) = buffer.get_mut(..HEADER_LEN) else {
+            return &[];
+        };
+        header_bytes.copy_from_slice(&header);
+        let buffer: &'a [u8] = buffer;
+        buffer.get(..self.len).unwrap_or_default()
+    }
+
+    /// Runs `write`, undoing its partial output if it does not fit.
+    fn append(&mut self, write: impl FnOnce(&mut Self) -> Option<()>) -> bool {
+        let len = self.len;
+        if write(self).is_none() {
+            self.len = len;
+            return false;
+        }
+        true
+    }
+
+    fn put(&mut self, bytes: &[u8]) -> Option<()> {
+        self.buffer
+            .get_mut(self.len..self.len + bytes.len())?
+            .copy_from_slice(bytes);
+        self.len += bytes.len();
+        Some(())
+    }
+
+    fn put_u16(&mut self, value: u16) -> Option<()> {
+        self.put(&value.to_be_bytes())
+    }
+
+    fn put_u32(&mut self, value: u32) -> Option<()> {
+        self.put(&value.to_be_bytes())
+    }
+
+    /// Writes the name made of the labels of `parts`, each of which may consist of several
+    /// labels in dotted form.
+    fn put_name(&mut self, parts: &[&str]) -> Option<()> {
+        for label in parts.iter().flat_map(|part| part.split('.')) {
+            let len = u8::try_from(label.len())
+                .ok()
+                .filter(|len| (1..=63).contains(len))?;
+            self.put(&[len])?;
+            self.put(label.as_bytes())?;
+        }
+        self.put(&[0])
+    }
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(
+        bytes.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    #![allow(clippy::indexing_slicing, reason = "panicking is fine in tests")]
+
+    use super::*;
+
+    /// Returns a message with the given flags and section counts, followed by `body`.
+    fn message(flags: u16, counts: [u16; 4], body: &[u8]) -> heapless::Vec<u8, 1024> {
+        let mut message = heapless::Vec::new();
+        for field in [0x1234, flags].into_iter().chain(counts) {
+            message.extend_from_slice(&field.to_be_bytes()).unwrap();
+        }
+        message.extend_from_slice(body).unwrap();
+        message
+    }
+
+    /// Returns a name of `labels` labels `a`, which is `2 * labels - 1` long in dotted form.
+    fn long_name(labels: usize) -> heapless::Vec<u8, 512> {
+        let mut name = heapless::Vec::new();
+        for _ in 0..labels {
+            name.extend_from_slice(b"\x01a").unwrap();
+        }
+        name.push(0).unwrap();
+        name
+    }
+
+    #[test]
+    fn questions() {
+        let message = message(
+            0,
+            [4, 0, 0, 0],
+            b"\x05Hello\x05local\x00\x00\x01\x00\x01\
+              \xc0\x0c\x00\xff\x80\x01\
+              \xc0\x0c\x00\x01\x00\x03\
+              \x01x\xc0\x0c\x00\x21\x00\xff",
+        );
+        let query = Query::parse(&message).unwrap();
+        assert_eq!(query.id(), 0x1234);
+
+        let questions: heapless::Vec<_, 4> = query.collect();
+        // The question of the CH class is skipped.
+        assert_eq!(questions.len(), 3);
+        assert_eq!(questions[0].name, "Hello.local");
+        assert_eq!(questions[0].qtype, TYPE_A);
+        assert!(!questions[0].unicast_response);
+        assert_eq!(questions[1].name, "Hello.local");
+        assert_eq!(questions[1].qtype, TYPE_ANY);
+        assert!(questions[1].unicast_response);
+        assert_eq!(questions[2].name, "x.Hello.local");
+        assert_eq!(questions[2].qtype, TYPE_SRV);
+    }
+
+    #[test]
+    fn not_queries() {
+        let question = b"\x01a\x00\x00\x01\x00\x01";
+        assert!(Query::parse(&message(FLAG_RESPONSE, [1, 0, 0, 0], question)).is_none());
+        assert!(Query::parse(&message(0x0800, [1, 0, 0, 0], question)).is_none());
+        assert!(Query::parse(&message(0, [1, 0, 0, 0], question)[..5]).is_none());
+        assert!(Query::parse(&[]).is_none());
+    }
+
+    #[test]
+    fn compression_loops() {
+        // A pointer to itself.
+        let looping = message(0, [1, 0, 0, 0], b"\xc0\x0c\x00\x01\x00\x01");
+        assert!(read_name(&looping, HEADER_LEN).is_none());
+        assert_eq!(Query::parse(&looping).unwrap().count(), 0);
+
+        // Two pointers to each other.
+        let looping = message(0, [1, 0, 0, 0], b"\xc0\x0e\xc0\x0c");
+        assert!(read_name(&looping, HEADER_LEN).is_none());
+
+        // A chain of pointers, each to the previous one, ending in the name `a`.
+        let mut chain = heapless::Vec::<u8, 64>::from_slice(b"\x01a\x00").unwrap();
+        for i in 0..=MAX_POINTERS {
+            let target = if i == 0 {
+                HEADER_LEN
+            } else {
+                HEADER_LEN + 1 + 2 * i
+            };
+            let target = u16::try_from(target).unwrap() | 0xc000;
+            chain.extend_from_slice(&target.to_be_bytes()).unwrap();
+        }
+        let chain = message(0, [0; 4], &chain);
+        let last = chain.len() - 2;
+        // Following `MAX_POINTERS` pointers is fine, one more is not.
+        let (name, end) = read_name(&chain, last - 2).unwrap();
+        assert_eq!((name.as_str(), end), ("a", last));
+        assert!(read_name(&chain, last).is_none());
+    }
+
+    #[test]
+    fn truncated() {
+        // A label exceeding the message.
+        let truncated = message(0, [1, 0, 0, 0], b"\x05hel");
+        assert!(read_name(&truncated, HEADER_LEN).is_none());
+        assert_eq!(Query::parse(&truncated).unwrap().count(), 0);
+
+        // A name without its terminating root label.
+        assert!(read_name(&message(0, [0; 4], b"\x01a"), HEADER_LEN).is_none());
+
+        // A pointer lacking its second byte, or pointing beyond the message.
+        assert!(read_name(&message(0, [0; 4], b"\xc0"), HEADER_LEN).is_none());
+        assert!(read_name(&message(0, [0; 4], b"\xc1\x00"), HEADER_LEN).is_none());
+
+        // A question lacking its class.
+        let truncated = message(0, [2, 0, 0, 0], b"\x01a\x00\x00\x01");
+        assert_eq!(Query::parse(&truncated).unwrap().count(), 0);
+
+        // More questions announced than present.
+        let missing = message(0, [2, 0, 0, 0], b"\x01a\x00\x00\x01\x00\x01");
+        assert_eq!(Query::parse(&missing).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn names() {
+        let (name, end) = read_name(&message(0, [0; 4], &long_name(128)), HEADER_LEN).unwrap();
+        assert_eq!((name.len(), end), (MAX_NAME_LEN, HEADER_LEN + 257));
+        // Names longer than 255 characters in dotted form.
+        assert!(read_name(&message(0, [0; 4], &long_name(129)), HEADER_LEN).is_none());
+
+        // Labels containing dots, or that are not valid UTF-8.
+        assert!(read_name(&message(0, [0; 4], b"\x03a.b\x00"), HEADER_LEN).is_none());
+        assert!(read_name(&message(0, [0; 4], b"\x02\xff\xfe\x00"), HEADER_LEN).is_none());
+        // Reserved label types.
+        assert!(read_name(&message(0, [0; 4], b"\x41a\x00"), HEADER_LEN).is_none());
+        assert!(read_name(&message(0, [0; 4], b"\x81a\x00"), HEADER_LEN).is_none());
+    }
+
+    #[test]
+    fn names_equal() {
+        assert!(name_eq("Host.LOCAL", &["host", "local"]));
+        assert!(name_eq(
+            "host._coap._udp.local",
+            &["host", "_coap._udp", "local"]
+        ));
+        assert!(!name_eq("host.local", &["host"]));
+        assert!(!name_eq("host", &["host", "local"]));
+        assert!(!name_eq("other.local", &["host", "local"]));
+    }
+
+    #[test]
+    fn records() {
+        let message = message(
+            FLAG_RESPONSE | FLAG_AUTHORITATIVE,
+            [1, 1, 1, 2],
+            // The question is skipped even though its name is not valid UTF-8.
+            b"\x02\xff\xfe\x00\x00\xff\x00\x01\
+              \x04host\x05local\x00\x00\x01\x80\x01\x00\x00\x00\x78\x00\x04\x0a\x00\x00\x01\
+              \xc0\x14\x00\x1c\x00\x01\x00\x00\x00\x78\x00\x01\x00\
+              \x02\xff\xfe\x00\x00\x10\x00\x01\x00\x00\x00\x78\x00\x00\
+              \xc0\x14\x00\x10\x00\x01\x00\x00\x00\x78\x00\x05\x00",
+        );
+        let records = Records::parse(&message).unwrap();
+        assert!(records.is_response());
+
+        let records: heapless::Vec<_, 4> = records.collect();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].section, Section::Answer);
+        assert_eq!(records[0].name.as_deref(), Some("host.local"));
+        assert_eq!((records[0].rtype, records[0].class), (TYPE_A, CLASS_IN));
+        assert_eq!(records[0].data, [10, 0, 0, 1]);
+        assert_eq!(records[1].section, Section::Authority);
+        assert_eq!(records[1].name.as_deref(), Some("host.local"));
+        assert_eq!(records[1].data, [0]);
+        assert_eq!(records[2].section, Section::Additional);
+        assert!(records[2].name.is_none());
+        assert_eq!(records[2].data, []);
+        // The last record exceeds the message, and so do all records of a truncated one.
+        assert_eq!(Records::parse(&message[..30]).unwrap().count(), 0);
+        assert!(Records::parse(&message[..5]).is_none());
+    }
+
+    #[test]
+    fn response() {
+        let mut buffer = [0; 512];
+        let mut response = Response::new(&mut buffer, 0, false);
+        assert!(response.record(
+            Section::Answer,
+            &["host", "local"],
+            120,
+            &Data::A(Ipv4Addr::new(10, 0, 0, 1)),
+        ));
+        assert!(response.record(
+            Section::Answer,
+            &["_coap._udp", "local"],
+            4500,
+            &Data::Ptr(&["host", "_coap._udp", "local"]),
+        ));
+        assert!(response.record(
+            Section::Additional,
+            &["host", "_coap._udp", "local"],
+            120,
+            &Data::Srv {
+                port: 5683,
+                target: &["host", "local"],
+            },
+        ));
+        assert!(response.record(
+            Section::Additional,
+            &["host", "_coap._udp", "local"],
+            4500,
+            &Data::Txt(&[]),
+        ));
+        assert!(response.record(
+            Section::Additional,
+            &["host", "_http._tcp", "local"],
+            4500,
+            &Data::Txt(&["path=/", "a"]),
+        ));
+        assert_eq!(response.answers(), 2);
+        let message = response.finish();
+
+        assert_eq!(
+            message[..HEADER_LEN],
+            [0, 0, 0x84, 0, 0, 0, 0, 2, 0, 0, 0, 3]
+        );
+        // Unique records replace cached ones, shared ones do not.
+        assert_eq!(
+            message[HEADER_LEN..HEADER_LEN + 26],
+            *b"\x04host\x05local\x00\x00\x01\x80\x01\x00\x00\x00\x78\x00\x04\x0a\x00\x00\x01"
+        );
+
+        let records: heapless::Vec<_, 5> = Records::parse(message).unwrap().collect();
+        assert_eq!(records.len(), 5);
+        assert_eq!(records[1].rtype, TYPE_PTR);
+        assert_eq!(records[1].data, *b"\x04host\x05_coap\x04_udp\x05local\x00");
+        assert_eq!(
+            message[HEADER_LEN + 46..HEADER_LEN + 48],
+            CLASS_IN.to_be_bytes()
+        );
+        assert_eq!(records[2].rtype, TYPE_SRV);
+        assert_eq!(
+            records[2].data,
+            *b"\x00\x00\x00\x00\x16\x33\x04host\x05local\x00"
+        );
+        assert_eq!(records[3].data, [0]);
+        assert_eq!(records[4].data, *b"\x06path=/\x01a");
+    }
+
+    #[test]
+    fn legacy_response() {
+        let query = message(0, [1, 0, 0, 0], b"\x04host\x05local\x00\x00\x01\x00\x01");
+        let question = Query::parse(&query).unwrap().next().unwrap();
+
+        let mut buffer = [0; 512];
+        let mut response = Response::new(&mut buffer, 0x1234, true);
+        assert!(response.question(&question));
+        assert!(response.record(
+            Section::Answer,
+            &["host", "local"],
+            120,
+            &Data::A(Ipv4Addr::new(10, 0, 0, 1)),
+        ));
+        let message = response.finish();
+
+        assert_eq!(
+            message[..HEADER_LEN],
+            [0x12, 0x34, 0x84, 0, 0, 1, 0, 1, 0, 0, 0, 0]
+        );
+        assert_eq!(message[HEADER_LEN..query.len()], query[HEADER_LEN..]);
+        // The TTL is capped, and the cache-flush bit not set.
+        assert_eq!(
+            message[query.len() + 12..query.len() + 20],
+            [0, 1, 0, 1, 0, 0, 0, 10]
+        );
+    }
+
+    #[test]
+    fn probe() {
+        let mut buffer = [0; 512];
+        let mut probe = Response::probe(&mut buffer);
+        assert!(probe.probe_question(&["host", "local"], true));
+        assert!(probe.record(
+            Section::Authority,
+            &["host", "local"],
+            120,
+            &Data::A(Ipv4Addr::new(10, 0, 0, 1)),
+        ));
+        let message = probe.finish();
+
+        assert_eq!(message[..HEADER_LEN], [0, 0, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0]);
+        assert_eq!(
+            message[HEADER_LEN + 12..HEADER_LEN + 16],
+            [0, 0xff, 0x80, 1]
+        );
+        let question = Query::parse(message).unwrap().next().unwrap();
+        assert!(question.unicast_response);
+
+        let record = Records::parse(message).unwrap().next().unwrap();
+        assert_eq!(record.section, Section::Authority);
+        // The cache-flush bit is not set.
+        assert_eq!(
+            message[HEADER_LEN + 30..HEADER_LEN + 32],
+            CLASS_IN.to_be_bytes()
+        );
+        assert_eq!(record.data, [10, 0, 0, 1]);
+    }
+
+    #[test]
+    fn overflow() {
+        let mut buffer = [0; HEADER_LEN + 40];
+        let mut response = Response::new(&mut buffer, 0, false);
+        assert!(response.record(
+            Section::Answer,
+            &["host", "local"],
+            120,
+            &Data::A(Ipv4Addr::new(10, 0, 0, 1)),
+        ));
+        // Records that do not fit are left out entirely.
+        assert!(!response.record(
+            Section::Answer,
+            &["host", "local"],
+            120,
+            &Data::A(Ipv4Addr::new(10, 0, 0, 2)),
+        ));
+        assert!(response.record(Section::Answer, &["a"], 120, &Data::Txt(&[])));
+        assert_eq!(response.answers(), 2);
+        let message = response.finish();
+        assert_eq!(message.len(), HEADER_LEN + 26 + 14);
+        assert_eq!(
+            Records::parse(message)
+                .unwrap()
+                .map(|r| r.data.len())
+                .sum::<usize>(),
+            5
+        );
+    }
+
+    #[test]
+    fn invalid_labels() {
+        let mut buffer = [0; 512];
+        let mut response = Response::new(&mut buffer, 0, false);
+        let long = core::str::from_utf8(&[b'a'; 64]).unwrap();
+        let txt = core::str::from_utf8(&[b'a'; 256]).unwrap();
+        assert!(!response.record(Section::Answer, &[long, "local"], 120, &Data::Txt(&[])));
+        assert!(!response.record(Section::Answer, &["a..b"], 120, &Data::Txt(&[])));
+        assert!(!response.record(Section::Answer, &["a"], 120, &Data::Txt(&[txt])));
+        assert_eq!(response.answers(), 0);
+        assert_eq!(response.finish().len(), HEADER_LEN);
+    }
+}
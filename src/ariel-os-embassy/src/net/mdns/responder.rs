@@ -0,0 +1,790 @@
+//! The responder answering mDNS queries on the primary interface.
+
+#[cfg(feature = "ipv6")]
+use core::net::Ipv6Addr;
+use core::{cmp::Ordering, fmt::Write as _, net::Ipv4Addr};
+
+use ariel_os_debug::log::{debug, info, warn};
+use embassy_futures::select::{Either, select};
+use embassy_net::{
+    IpAddress, IpEndpoint,
+    udp::{PacketMetadata, UdpSocket},
+};
+use embassy_time::{Duration, Instant, Timer};
+
+#[cfg(feature = "ipv6")]
+use super::message::TYPE_AAAA;
+use super::{
+    Hostname, MAX_LABEL_LEN, SERVICES, ServiceDeclaration,
+    message::{
+        CLASS_IN, Data, Query, Question, Records, Response, Section, TYPE_A, TYPE_ANY, TYPE_PTR,
+        TYPE_SRV, TYPE_TXT, name_eq,
+    },
+};
+use crate::net::{
+    Interface, NetworkStack,
+    events::{self, NetworkEvent, NetworkEventSubscriber},
+};
+
+crate::declare_sockets!("mdns", 1);
+
+const PORT: u16 = 5353;
+
+const MULTICAST_V4: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+#[cfg(feature = "ipv6")]
+const MULTICAST_V6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xfb);
+
+const LOCAL: &str = "local";
+/// Name listing the services of the host ([RFC 6763, Section 9]).
+///
+/// [RFC 6763, Section 9]: https://www.rfc-editor.org/rfc/rfc6763#section-9
+const SERVICES_NAME: &str = "_services._dns-sd._udp";
+
+/// TTL of the records referring to the host name, and of the other records
+/// ([RFC 6762, Section 10]).
+///
+/// [RFC 6762, Section 10]: https://www.rfc-editor.org/rfc/rfc6762#section-10
+const HOST_TTL: u32 = 120;
+const OTHER_TTL: u32 = 4500;
+
+/// Number of announcements after the interface is configured, and the interval between them
+/// ([RFC 6762, Section 8.3]).
+///
+/// [RFC 6762, Section 8.3]: https://www.rfc-editor.org/rfc/rfc6762#section-8.3
+const ANNOUNCEMENTS: usize = 2;
+const ANNOUNCEMENT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Number of probes sent before claiming the host name, the interval between them, and the
+/// maximum random delay before the first one ([RFC 6762, Section 8.1]).
+///
+/// [RFC 6762, Section 8.1]: https://www.rfc-editor.org/rfc/rfc6762#section-8.1
+const PROBES: usize = 3;
+const PROBE_INTERVAL: Duration = Duration::from_millis(250);
+const MAX_PROBE_DELAY_MS: u32 = 250;
+/// Delay before probing again after losing a tie-break with another host probing for the same
+/// name at the same time ([RFC 6762, Section 8.2]).
+///
+/// [RFC 6762, Section 8.2]: https://www.rfc-editor.org/rfc/rfc6762#section-8.2
+const LOST_TIE_BREAK_DELAY: Duration = Duration::from_secs(1);
+/// Number of conflicts after which probing is slowed down, and the delay before each further
+/// probe ([RFC 6762, Section 8.1]).
+///
+/// [RFC 6762, Section 8.1]: https://www.rfc-editor.org/rfc/rfc6762#section-8.1
+const MAX_FAST_CONFLICTS: u32 = 15;
+const CONFLICT_DELAY: Duration = Duration::from_secs(5);
+
+/// Maximum number of records of another host compared when breaking a tie.
+const MAX_TIE_BREAK_RECORDS: usize = 4;
+
+/// Maximum length of a received query; longer ones are dropped.
+const MAX_QUERY_LEN: usize = 512;
+/// Maximum length of a response, beyond which additional records are left out.
+const MAX_RESPONSE_LEN: usize = 1024;
+
+/// Maximum length of a service name, excluding its leading underscore ([RFC 6763, Section 7]).
+///
+/// [RFC 6763, Section 7]: https://www.rfc-editor.org/rfc/rfc6763#section-7
+const MAX_SERVICE_NAME_LEN: usize = 15;
+
+/// Answers queries for the host name and the declared services.
+#[embassy_executor::task]
+pub(crate) async fn responder_task(interface: Interface) -> ! {
+    let stack = interface.stack();
+    for service in SERVICES.iter().filter(|service| !is_valid(service)) {
+        warn!("Not advertising invalid mDNS service {}", service.service);
+    }
+    let base = super::hostname();
+    let mut responder = Responder {
+        stack,
+        hostname: base.clone(),
+        base,
+        renames: 0,
+    };
+
+    let mut events = events::subscribe();
+    if events.is_none() {
+        warn!("mDNS responder not subscribed to network events, not announcing address changes");
+    }
+
+    let mut rx_meta = [PacketMetadata::EMPTY; 2];
+    let mut rx_buffer = [0; MAX_QUERY_LEN];
+    let mut tx_meta = [PacketMetadata::EMPTY; 2];
+    let mut tx_buffer = [0; MAX_RESPONSE_LEN];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    // The port can only be in use if an application bound it.
+    socket.bind(PORT).unwrap();
+
+    let groups = [IpAddress::Ipv4(MULTICAST_V4)].into_iter();
+    #[cfg(feature = "ipv6")]
+    let groups = groups.chain([IpAddress::Ipv6(MULTICAST_V6)]);
+    for group in groups {
+        if stack.join_multicast_group(group).is_err() {
+            warn!("Joining multicast group {} failed", group);
+        }
+    }
+
+    let mut query_buffer = [0; MAX_QUERY_LEN];
+    let mut response_buffer = [0; MAX_RESPONSE_LEN];
+
+    loop {
+        stack.wait_config_up().await;
+        responder
+            .claim(&socket, &mut query_buffer, &mut response_buffer)
+            .await;
+        info!(
+            "Advertising {}.local through mDNS",
+            responder.hostname.as_str()
+        );
+        responder.announce(&socket, &mut response_buffer).await;
+
+        loop {
+            match select(
+                socket.recv_from(&mut query_buffer),
+                address_acquired(events.as_mut(), interface.name()),
+            )
+            .await
+            {
+                Either::First(Ok((len, meta))) => {
+                    let message = query_buffer.get(..len).unwrap_or_default();
+                    if responder.with_own_records(|own| {
+                        is_conflict(message, responder.hostname.as_str(), own)
+                    }) {
+                        warn!(
+                            "mDNS host name {} used by another host, probing again",
+                            responder.hostname.as_str()
+                        );
+                        break;
+                    }
+                    let Some(query) = Query::parse(message) else {
+                        continue;
+                    };
+                    responder
+                        .respond(&socket, &query, meta.endpoint, &mut response_buffer)
+                        .await;
+                }
+                // Queries exceeding the buffer are dropped.
+                Either::First(Err(_)) => {}
+                Either::Second(()) => break,
+            }
+        }
+    }
+}
+
+/// Outcome of a message received while probing.
+#[derive(Debug, PartialEq, Eq)]
+enum ProbeOutcome {
+    /// Another host uses the name.
+    Conflict,
+    /// Another host probes for the name at the same time, and wins the tie-break.
+    LostTieBreak,
+}
+
+/// Class, type and data of a record, in the order tie-breaks compare them.
+type RecordKey<'a> = (u16, u16, &'a [u8]);
+
+struct Responder {
+    stack: NetworkStack,
+    /// Host name in use.
+    hostname: Hostname,
+    /// Host name the alternatives are derived from after conflicts.
+    base: Hostname,
+    renames: u32,
+}
+
+impl Responder {
+    /// Probes until no other host on the link uses the host name, renaming the host after each
+    /// conflict ([RFC 6762, Section 8.1], [RFC 6762, Section 9]).
+    ///
+    /// [RFC 6762, Section 8.1]: https://www.rfc-editor.org/rfc/rfc6762#section-8.1
+    /// [RFC 6762, Section 9]: https://www.rfc-editor.org/rfc/rfc6762#section-9
+    async fn claim(
+        &mut self,
+        socket: &UdpSocket<'_>,
+        receive_buffer: &mut [u8],
+        buffer: &mut [u8],
+    ) {
+        let mut conflicts = 0;
+        let mut delay = probe_delay();
+        'probing: loop {
+            Timer::after(delay).await;
+            delay = probe_delay();
+
+            for i in 0..PROBES {
+                // Only the first probe asks for unicast responses ([RFC 6762, Section 8.1]).
+                let message = self.probe(buffer, i == 0);
+                for group in self.groups() {
+                    if socket.send_to(message, group).await.is_err() {
+                        debug!("Sending mDNS probe to {} failed", group);
+                    }
+                }
+
+                let deadline = Instant::now() + PROBE_INTERVAL;
+                loop {
+                    let Either::Second(received) =
+                        select(Timer::at(deadline), socket.recv_from(receive_buffer)).await
+                    else {
+                        break;
+                    };
+                    let Ok((len, _)) = received else {
+                        continue;
+                    };
+                    let message = receive_buffer.get(..len).unwrap_or_default();
+                    match self
+                        .with_own_records(|own| probe_outcome(message, self.hostname.as_str(), own))
+                    {
+                        Some(ProbeOutcome::Conflict) => {
+                            conflicts += 1;
+                            self.renames += 1;
+                            let previous = core::mem::replace(
+                                &mut self.hostname,
+                                alternative_hostname(&self.base, self.renames + 1),
+                            );
+                            warn!(
+                                "mDNS host name {} used by another host, trying {}",
+                                previous.as_str(),
+                                self.hostname.as_str()
+                            );
+                            if conflicts >= MAX_FAST_CONFLICTS {
+                                delay = CONFLICT_DELAY;
+                            }
+                            continue 'probing;
+                        }
+                        Some(ProbeOutcome::LostTieBreak) => {
+                            delay = LOST_TIE_BREAK_DELAY;
+                            continue 'probing;
+                        }
+                        None => {}
+                    }
+                }
+            }
+
+            break;
+        }
+        super::set_hostname(self.hostname.clone());
+    }
+
+    /// Writes a probe for the host name, which proposes the address records.
+    fn probe<'b>(&self, buffer: &'b mut [u8], unicast_response: bool) -> &'b [u8] {
+        let mut probe = Response::probe(buffer);
+        probe.probe_question(&[self.hostname.as_str(), LOCAL], unicast_response);
+        self.address_records(TYPE_ANY, &mut probe, Section::Authority);
+        probe.finish()
+    }
+
+    /// Runs `f` with the class, type and data of the address records of the host.
+    fn with_own_records<T>(&self, f: impl FnOnce(&[RecordKey<'_>]) -> T) -> T {
+        let v4 = self
+            .stack
+            .config_v4()
+            .map(|config| config.address.address().octets());
+        #[cfg(feature = "ipv6")]
+        let v6 = self
+            .stack
+            .config_v6()
+            .map(|config| config.address.address().octets());
+
+        let mut own = heapless::Vec::<RecordKey<'_>, 2>::new();
+        if let Some(v4) = &v4 {
+            let _ = own.push((CLASS_IN, TYPE_A, &v4[..]));
+        }
+        #[cfg(feature = "ipv6")]
+        if let Some(v6) = &v6 {
+            let _ = own.push((CLASS_IN, TYPE_AAAA, &v6[..]));
+        }
+        f(&own)
+    }
+
+    /// Sends unsolicited responses with all records, so that caches are updated
+    /// ([RFC 6762, Section 8.3]).
+    ///
+    /// [RFC 6762, Section 8.3]: https://www.rfc-editor.org/rfc/rfc6762#section-8.3
+    async fn announce(&self, socket: &UdpSocket<'_>, buffer: &mut [u8]) {
+        let mut response = Response::new(buffer, 0, false);
+        self.address_records(TYPE_ANY, &mut response, Section::Answer);
+        for service in services() {
+            let service_name = [service.service, LOCAL];
+            let instance = [self.hostname.as_str(), service.service, LOCAL];
+            response.record(
+                Section::Answer,
+                &[SERVICES_NAME, LOCAL],
+                OTHER_TTL,
+                &Data::Ptr(&service_name),
+            );
+            response.record(
+                Section::Answer,
+                &service_name,
+                OTHER_TTL,
+                &Data::Ptr(&instance),
+            );
+            self.instance_records(service, TYPE_ANY, &mut response, Section::Answer);
+        }
+        let message = response.finish();
+
+        for i in 0..ANNOUNCEMENTS {
+            if i > 0 {
+                Timer::after(ANNOUNCEMENT_INTERVAL).await;
+            }
+            for group in self.groups() {
+                if socket.send_to(message, group).await.is_err() {
+                    debug!("Sending mDNS announcement to {} failed", group);
+                }
+            }
+        }
+    }
+
+    /// Answers `query` received from `source`, if it asks for any of the records.
+    async fn respond(
+        &self,
+        socket: &UdpSocket<'_>,
+        query: &Query<'_>,
+        source: IpEndpoint,
+        buffer: &mut [u8],
+    ) {
+        // Queries from resolvers not implementing mDNS are answered like unicast DNS queries
+        // ([RFC 6762, Section 6.7]).
+        let legacy = source.port != PORT;
+        let mut response = Response::new(buffer, if legacy { query.id() } else { 0 }, legacy);
+
+        if legacy && !query.clone().all(|question| response.question(&question)) {
+            return;
+        }
+        for question in query.clone() {
+            self.answer(&question, &mut response);
+        }
+        if response.answers() == 0 {
+            return;
+        }
+        for question in query.clone() {
+            self.add_additional_records(&question, &mut response);
+        }
+
+        let destination = if legacy || query.clone().all(|question| question.unicast_response) {
+            source
+        } else {
+            multicast_group(source.addr)
+        };
+        if socket
+            .send_to(response.finish(), destination)
+            .await
+            .is_err()
+        {
+            debug!("Sending mDNS response to {} failed", destination);
+        }
+    }
+
+    /// Writes the records `question` asks for.
+    fn answer(&self, question: &Question, response: &mut Response<'_>) {
+        let name = question.name.as_str();
+        let qtype = question.qtype;
+
+        if name_eq(name, &[self.hostname.as_str(), LOCAL]) {
+            self.address_records(qtype, response, Section::Answer);
+        }
+        if name_eq(name, &[SERVICES_NAME, LOCAL]) && wants(qtype, TYPE_PTR) {
+            for service in services() {
+                response.record(
+                    Section::Answer,
+                    &[SERVICES_NAME, LOCAL],
+                    OTHER_TTL,
+                    &Data::Ptr(&[service.service, LOCAL]),
+                );
+            }
+        }
+        for service in services() {
+            if name_eq(name, &[service.service, LOCAL]) && wants(qtype, TYPE_PTR) {
+                response.record(
+                    Section::Answer,
+                    &[service.service, LOCAL],
+                    OTHER_TTL,
+                    &Data::Ptr(&[self.hostname.as_str(), service.service, LOCAL]),
+                );
+            }
+            if name_eq(name, &[self.hostname.as_str(), service.service, LOCAL]) {
+                self.instance_records(service, qtype, response, Section::Answer);
+            }
+        }
+    }
+
+    /// Writes the records that resolving the answers to `question` requires
+    /// ([RFC 6763, Section 12]).
+    ///
+    /// [RFC 6763, Section 12]: https://www.rfc-editor.org/rfc/rfc6763#section-12
+    fn add_additional_records(&self, question: &Question, response: &mut Response<'_>) {
+        let name = question.name.as_str();
+        let qtype = question.qtype;
+
+        let mut refers_to_host = false;
+        for service in services() {
+            if name_eq(name, &[service.service, LOCAL]) && wants(qtype, TYPE_PTR) {
+                self.instance_records(service, TYPE_ANY, response, Section::Additional);
+                refers_to_host = true;
+            }
+            if name_eq(name, &[self.hostname.as_str(), service.service, LOCAL])
+                && wants(qtype, TYPE_SRV)
+            {
+                refers_to_host = true;
+            }
+        }
+        if refers_to_host {
+            self.address_records(TYPE_ANY, response, Section::Additional);
+        }
+    }
+
+    /// Writes the address records of the host `qtype` asks for.
+    fn address_records(&self, qtype: u16, response: &mut Response<'_>, section: Section) {
+        let host = [self.hostname.as_str(), LOCAL];
+        if let Some(config) = self.stack.config_v4().filter(|_| wants(qtype, TYPE_A)) {
+            response.record(section, &host, HOST_TTL, &Data::A(config.address.address()));
+        }
+        #[cfg(feature = "ipv6")]
+        if let Some(config) = self.stack.config_v6().filter(|_| wants(qtype, TYPE_AAAA)) {
+            response.record(
+                section,
+                &host,
+                HOST_TTL,
+                &Data::Aaaa(config.address.address()),
+            );
+        }
+    }
+
+    /// Writes the SRV and TXT records of the instance of `service` `qtype` asks for.
+    fn instance_records(
+        &self,
+        service: &ServiceDeclaration,
+        qtype: u16,
+        response: &mut Response<'_>,
+        section: Section,
+    ) {
+        let instance = [self.hostname.as_str(), service.service, LOCAL];
+        if wants(qtype, TYPE_SRV) {
+            response.record(
+                section,
+                &instance,
+                HOST_TTL,
+                &Data::Srv {
+                    port: service.port,
+                    target: &[self.hostname.as_str(), LOCAL],
+                },
+            );
+        }
+        if wants(qtype, TYPE_TXT) {
+            response.record(section, &instance, OTHER_TTL, &Data::Txt(service.txt));
+        }
+    }
+
+    /// Returns the multicast groups of the IP versions the interface has an address of.
+    fn groups(&self) -> impl Iterator<Item = IpEndpoint> {
+        let v4 = self
+            .stack
+            .config_v4()
+            .map(|_| IpEndpoint::new(IpAddress::Ipv4(MULTICAST_V4), PORT));
+        #[cfg(feature = "ipv6")]
+        let v6 = self
+            .stack
+            .config_v6()
+            .map(|_| IpEndpoint::new(IpAddress::Ipv6(MULTICAST_V6), PORT));
+        #[cfg(not(feature = "ipv6"))]
+        let v6 = None;
+        v4.into_iter().chain(v6)
+    }
+}
+
+/// Returns a random delay before the first probe.
+fn probe_delay() -> Duration {
+    let random = rand_core::RngCore::next_u32(&mut ariel_os_random::fast_rng());
+    Duration::from_millis(u64::from(random % MAX_PROBE_DELAY_MS))
+}
+
+/// Returns the `n`th alternative to the host name `base`, shortening it as needed.
+fn alternative_hostname(base: &str, n: u32) -> Hostname {
+    let mut suffix = heapless::String::<11>::new();
+    let _ = write!(suffix, "-{n}");
+    let mut name = Hostname::new();
+    let base_len = base.len().min(MAX_LABEL_LEN - suffix.len());
+    let _ = name.push_str(base.get(..base_len).unwrap_or(base));
+    let _ = name.push_str(&suffix);
+    name
+}
+
+/// Returns what `message` received while probing for `hostname` means, given the `own` address
+/// records proposed for it.
+///
+/// A response with a record of that name conflicts unless the record is identical to one of
+/// ours; a probe for the same name is decided by comparing the proposed records
+/// ([RFC 6762, Section 8.2]).
+///
+/// [RFC 6762, Section 8.2]: https://www.rfc-editor.org/rfc/rfc6762#section-8.2
+fn probe_outcome(message: &[u8], hostname: &str, own: &[RecordKey<'_>]) -> Option<ProbeOutcome> {
+    let records = Records::parse(message)?;
+    let host = [hostname, LOCAL];
+    let is_response = records.is_response();
+    let mut records = records.filter(|record| {
+        record
+            .name
+            .as_ref()
+            .is_some_and(|name| name_eq(name, &host))
+    });
+
+    if is_response {
+        return records
+            .any(|record| !own.contains(&(record.class, record.rtype, record.data)))
+            .then_some(ProbeOutcome::Conflict);
+    }
+
+    let mut theirs = heapless::Vec::<RecordKey<'_>, MAX_TIE_BREAK_RECORDS>::new();
+    for record in records.filter(|record| record.section == Section::Authority) {
+        if theirs
+            .push((record.class, record.rtype, record.data))
+            .is_err()
+        {
+            break;
+        }
+    }
+    if theirs.is_empty() {
+        return None;
+    }
+    let mut own = heapless::Vec::<RecordKey<'_>, 2>::from_slice(own).ok()?;
+    own.sort_unstable();
+    theirs.sort_unstable();
+    // The host whose records compare greater wins; identical records are our own.
+    (own.iter().cmp(theirs.iter()) == Ordering::Less).then_some(ProbeOutcome::LostTieBreak)
+}
+
+/// Whether `message` is a response with a record of `hostname` that contradicts the `own`
+/// address records, i.e., of the same class and type, but with other data
+/// ([RFC 6762, Section 9]).
+///
+/// [RFC 6762, Section 9]: https://www.rfc-editor.org/rfc/rfc6762#section-9
+fn is_conflict(message: &[u8], hostname: &str, own: &[RecordKey<'_>]) -> bool {
+    let Some(records) = Records::parse(message).filter(Records::is_response) else {
+        return false;
+    };
+    let host = [hostname, LOCAL];
+    records
+        .filter(|record| record.section != Section::Authority)
+        .any(|record| {
+            record
+                .name
+                .as_ref()
+                .is_some_and(|name| name_eq(name, &host))
+                && own
+                    .iter()
+                    .any(|(class, rtype, _)| (*class, *rtype) == (record.class, record.rtype))
+                && !own.contains(&(record.class, record.rtype, record.data))
+        })
+}
+
+/// Returns the services to advertise.
+fn services() -> impl Iterator<Item = &'static ServiceDeclaration> {
+    SERVICES.iter().filter(|service| is_valid(service))
+}
+
+/// Whether the service name is of the form `_<name>._tcp` or `_<name>._udp`, and the entries of
+/// the TXT record fit.
+fn is_valid(service: &ServiceDeclaration) -> bool {
+    let Some((name, protocol)) = service.service.split_once('.') else {
+        return false;
+    };
+    let name_valid = name.strip_prefix('_').is_some_and(|name| {
+        !name.is_empty()
+            && name.len() <= MAX_SERVICE_NAME_LEN
+            && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-')
+    });
+    name_valid
+        && (protocol == "_tcp" || protocol == "_udp")
+        && service
+            .txt
+            .iter()
+            .all(|entry| !entry.is_empty() && entry.len() <= usize::from(u8::MAX))
+}
+
+/// Whether a question of type `qtype` asks for records of type `rtype`.
+fn wants(qtype: u16, rtype: u16) -> bool {
+    qtype == rtype || qtype == TYPE_ANY
+}
+
+/// Returns the multicast group responses to queries from `source` are sent to.
+#[cfg_attr(
+    not(feature = "ipv6"),
+    expect(unused_variables, reason = "only IPv6 has another group")
+)]
+fn multicast_group(source: IpAddress) -> IpEndpoint {
+    #[cfg(feature = "ipv6")]
+    if matches!(source, IpAddress::Ipv6(_)) {
+        return IpEndpoint::new(IpAddress::Ipv6(MULTICAST_V6), PORT);
+    }
+    IpEndpoint::new(IpAddress::Ipv4(MULTICAST_V4), PORT)
+}
+
+/// Waits until `interface` acquires an address; never returns without `events`.
+async fn address_acquired(events: Option<&mut NetworkEventSubscriber>, interface: &str) {
+    let Some(events) = events else {
+        return core::future::pending().await;
+    };
+    loop {
+        let event = events.next_message_pure().await;
+        if event.interface == interface && matches!(event.event, NetworkEvent::AddressAcquired(_)) {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const OWN: [u8; 4] = [10, 0, 0, 1];
+
+    /// Returns a response, or a probe, with the given A records of `host.local` in `section`.
+    fn message<'b>(
+        buffer: &'b mut [u8],
+        probe: bool,
+        section: Section,
+        addresses: &[[u8; 4]],
+    ) -> &'b [u8] {
+        let mut message = if probe {
+            Response::probe(buffer)
+        } else {
+            Response::new(buffer, 0, false)
+        };
+        if probe {
+            message.probe_question(&["host", LOCAL], true);
+        }
+        for address in addresses {
+            message.record(
+                section,
+                &["host", LOCAL],
+                HOST_TTL,
+                &Data::A(Ipv4Addr::from(*address)),
+            );
+        }
+        message.finish()
+    }
+
+    #[test]
+    fn alternatives() {
+        assert_eq!(alternative_hostname("host", 2), "host-2");
+        assert_eq!(alternative_hostname("host", 10), "host-10");
+        let long = core::str::from_utf8(&[b'a'; MAX_LABEL_LEN]).unwrap();
+        let alternative = alternative_hostname(long, 12);
+        assert_eq!(alternative.len(), MAX_LABEL_LEN);
+        assert!(alternative.ends_with("a-12"));
+    }
+
+    #[test]
+    fn responses_while_probing() {
+        let own = [(CLASS_IN, TYPE_A, &OWN[..])];
+        let mut buffer = [0; 512];
+
+        let other = message(&mut buffer, false, Section::Answer, &[[10, 0, 0, 2]]);
+        assert_eq!(
+            probe_outcome(other, "host", &own),
+            Some(ProbeOutcome::Conflict)
+        );
+        // Names are compared ignoring case.
+        assert_eq!(
+            probe_outcome(other, "HOST", &own),
+            Some(ProbeOutcome::Conflict)
+        );
+        assert_eq!(probe_outcome(other, "other", &own), None);
+
+        // Records of the name conflict whatever their type, unless they are our own.
+        let mut response = Response::new(&mut buffer, 0, false);
+        response.record(
+            Section::Additional,
+            &["host", LOCAL],
+            HOST_TTL,
+            &Data::Txt(&[]),
+        );
+        let txt = response.finish();
+        assert_eq!(
+            probe_outcome(txt, "host", &own),
+            Some(ProbeOutcome::Conflict)
+        );
+        let ours = message(&mut buffer, false, Section::Answer, &[OWN]);
+        assert_eq!(probe_outcome(ours, "host", &own), None);
+
+        assert_eq!(probe_outcome(&[], "host", &own), None);
+    }
+
+    #[test]
+    fn tie_breaks() {
+        let own = [(CLASS_IN, TYPE_A, &OWN[..])];
+        let mut buffer = [0; 512];
+
+        let greater = message(&mut buffer, true, Section::Authority, &[[10, 0, 0, 2]]);
+        assert_eq!(
+            probe_outcome(greater, "host", &own),
+            Some(ProbeOutcome::LostTieBreak)
+        );
+        let lesser = message(&mut buffer, true, Section::Authority, &[[9, 255, 0, 1]]);
+        assert_eq!(probe_outcome(lesser, "host", &own), None);
+        let ours = message(&mut buffer, true, Section::Authority, &[OWN]);
+        assert_eq!(probe_outcome(ours, "host", &own), None);
+        // More records win over a common prefix.
+        let own_more = [(CLASS_IN, TYPE_A, &[10, 0, 0, 3][..]), own[0]];
+        assert_eq!(probe_outcome(ours, "host", &own_more), None);
+
+        // Records are compared in sorted order.
+        let more = message(&mut buffer, true, Section::Authority, &[[10, 0, 0, 3], OWN]);
+        assert_eq!(
+            probe_outcome(more, "host", &own),
+            Some(ProbeOutcome::LostTieBreak)
+        );
+
+        // Queries without proposed records are not probes.
+        let mut query = Response::probe(&mut buffer);
+        query.probe_question(&["host", LOCAL], false);
+        assert_eq!(probe_outcome(query.finish(), "host", &own), None);
+    }
+
+    #[test]
+    fn conflicts() {
+        let own = [(CLASS_IN, TYPE_A, &OWN[..])];
+        let mut buffer = [0; 512];
+
+        let other = message(&mut buffer, false, Section::Answer, &[[10, 0, 0, 2]]);
+        assert!(is_conflict(other, "host", &own));
+        assert!(!is_conflict(other, "other", &own));
+        let additional = message(&mut buffer, false, Section::Additional, &[[10, 0, 0, 2]]);
+        assert!(is_conflict(additional, "host", &own));
+
+        let ours = message(&mut buffer, false, Section::Answer, &[OWN]);
+        assert!(!is_conflict(ours, "host", &own));
+        // Probes of other hosts are answered instead.
+        let probe = message(&mut buffer, true, Section::Authority, &[[10, 0, 0, 2]]);
+        assert!(!is_conflict(probe, "host", &own));
+
+        // Only records of the types of our own records can contradict them.
+        let mut response = Response::new(&mut buffer, 0, false);
+        response.record(Section::Answer, &["host", LOCAL], HOST_TTL, &Data::Txt(&[]));
+        assert!(!is_conflict(response.finish(), "host", &own));
+    }
+
+    #[test]
+    fn services() {
+        let service = |service, txt| ServiceDeclaration {
+            service,
+            port: 80,
+            txt,
+        };
+        assert!(is_valid(&service("_http._tcp", &["path=/"])));
+        assert!(is_valid(&service("_coap-s._udp", &[])));
+        assert!(!is_valid(&service("http._tcp", &[])));
+        assert!(!is_valid(&service("_._tcp", &[])));
+        assert!(!is_valid(&service("_http._sctp", &[])));
+        assert!(!is_valid(&service("_http", &[])));
+        assert!(!is_valid(&service("_0123456789abcdef._tcp", &[])));
+        assert!(!is_valid(&service("_http._tcp", &[""])));
+
+        assert!(wants(TYPE_A, TYPE_A));
+        assert!(wants(TYPE_ANY, TYPE_SRV));
+        assert!(!wants(TYPE_PTR, TYPE_SRV));
+    }
+}
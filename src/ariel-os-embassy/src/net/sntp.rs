@@ -0,0 +1,377 @@
+//! Keeps the [wall-clock time](crate::wallclock) synchronized through SNTP ([RFC 4330]).
+//!
+//! Once the primary interface is configured, the time is queried from the server set in
+//! `CONFIG_SNTP_SERVER` (default: `pool.ntp.org`), either a host name or an IP address, on port
+//! `CONFIG_SNTP_SERVER_PORT` (default: 123).
+//! It is queried again every `CONFIG_SNTP_RESYNC_INTERVAL_SECS` (default: 3600) seconds, and
+//! sooner after a failed attempt.
+//!
+//! The time obtained is reported through
+//! [`wallclock::report_unauthenticated()`](crate::wallclock::report_unauthenticated), with the
+//! round-trip time and the uncertainty declared by the server as its uncertainty.
+//!
+//! # Security
+//!
+//! SNTP is not authenticated: anyone on the path to the server can forge its responses, and
+//! off-path attackers can try to, which the random transmit timestamp of each request makes
+//! unlikely to succeed.
+//! As the wall-clock time decides, e.g., whether ACE tokens have expired, the time obtained is
+//! only used to narrow the bounds established by trusted sources (see [`crate::wallclock`]),
+//! and never to widen or override them.
+//! Without any trusted source, a forged response can still make expired tokens appear valid;
+//! applications relying on token expiry should thus report the time from a trusted source.
+//!
+//! [RFC 4330]: https://www.rfc-editor.org/rfc/rfc4330
+
+use core::net::IpAddr;
+
+use ariel_os_debug::log::{debug, info, warn};
+use embassy_net::{
+    IpAddress, IpEndpoint,
+    dns::DnsQueryType,
+    udp::{PacketMetadata, UdpSocket},
+};
+use embassy_time::{Duration, Instant, Timer, with_timeout};
+
+use super::NetworkStack;
+use crate::wallclock;
+
+crate::declare_sockets!("sntp", 1);
+
+/// Host name or IP address of the SNTP server.
+const SERVER: &str = ariel_os_utils::str_from_env_or!(
+    "CONFIG_SNTP_SERVER",
+    "pool.ntp.org",
+    "host name or IP address of the SNTP server"
+);
+
+#[expect(
+    clippy::cast_possible_truncation,
+    reason = "port numbers are 16 bits long"
+)]
+const SERVER_PORT: u16 = ariel_os_utils::u32_from_env_or!(
+    "CONFIG_SNTP_SERVER_PORT",
+    123,
+    "UDP port of the SNTP server"
+) as u16;
+
+/// Interval between successful synchronizations.
+const RESYNC_INTERVAL: Duration = Duration::from_secs(ariel_os_utils::u32_from_env_or!(
+    "CONFIG_SNTP_RESYNC_INTERVAL_SECS",
+    3600,
+    "interval in seconds between synchronizations of the wall-clock time through SNTP"
+) as u64);
+
+/// Delay before retrying after a failed attempt, which doubles up to [`RESYNC_INTERVAL`].
+const MIN_RETRY_DELAY: Duration = Duration::from_secs(4);
+
+/// Time to wait for the response of the server.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+const MESSAGE_LEN: usize = 48;
+
+/// Leap indicator 0 (no warning), version 4, mode 3 (client).
+const CLIENT_HEADER: u8 = 0x23;
+const MODE_SERVER: u8 = 4;
+/// Leap indicator of a server whose clock is not synchronized.
+const LEAP_UNSYNCHRONIZED: u8 = 3;
+/// Strata 0 (kiss-o'-death) and 16 and above (unsynchronized) do not provide usable time.
+const MAX_STRATUM: u8 = 15;
+
+/// Seconds from the NTP epoch (1900) to the Unix epoch (1970).
+const NTP_TO_UNIX: u64 = 2_208_988_800;
+
+/// Periodically synchronizes the wall-clock time.
+#[embassy_executor::task]
+pub(crate) async fn sntp_task(stack: NetworkStack) -> ! {
+    let mut rx_meta = [PacketMetadata::EMPTY; 1];
+    let mut rx_buffer = [0; MESSAGE_LEN];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut tx_buffer = [0; MESSAGE_LEN];
+
+    let mut retry_delay = MIN_RETRY_DELAY;
+
+    loop {
+        stack.wait_config_up().await;
+
+        let mut socket = UdpSocket::new(
+            stack,
+            &mut rx_meta,
+            &mut rx_buffer,
+            &mut tx_meta,
+            &mut tx_buffer,
+        );
+
+        match synchronize(stack, &mut socket).await {
+            Ok((unix_time, uncertainty)) => {
+                info!(
+                    "Wall-clock time synchronized through SNTP: {} +/- {} s",
+                    unix_time, uncertainty
+                );
+                wallclock::report_unauthenticated(unix_time, uncertainty);
+                retry_delay = MIN_RETRY_DELAY;
+                drop(socket);
+                Timer::after(RESYNC_INTERVAL).await;
+            }
+            Err(e) => {
+                warn!(
+                    "SNTP synchronization failed ({}), retrying in {}s",
+                    e.as_str(),
+                    retry_delay.as_secs()
+                );
+                drop(socket);
+                Timer::after(retry_delay).await;
+                retry_delay = (retry_delay * 2).min(RESYNC_INTERVAL);
+            }
+        }
+    }
+}
+
+/// Reason for a failed synchronization.
+#[derive(Debug, Copy, Clone)]
+enum Error {
+    Resolve,
+    Socket,
+    Timeout,
+    InvalidResponse,
+}
+
+impl Error {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Resolve => "server address could not be resolved",
+            Self::Socket => "socket error",
+            Self::Timeout => "no response",
+            Self::InvalidResponse => "invalid response",
+        }
+    }
+}
+
+/// Queries the server, returning the current Unix time and its uncertainty in seconds.
+async fn synchronize(stack: NetworkStack, socket: &mut UdpSocket<'_>) -> Result<(u64, u64), Error> {
+    let server = IpEndpoint::new(resolve(stack).await?, SERVER_PORT);
+    debug!("Querying SNTP server {}", server);
+
+    socket.bind(0).map_err(|_| Error::Socket)?;
+
+    // The transmit timestamp of the request is echoed as the originate timestamp of the
+    // response; a random one keeps off-path attackers from forging responses.
+    let mut nonce = [0; 8];
+    rand_core::RngCore::fill_bytes(&mut ariel_os_random::crypto_rng(), &mut nonce);
+    let mut request = [0; MESSAGE_LEN];
+    request[0] = CLIENT_HEADER;
+    request[40..].copy_from_slice(&nonce);
+
+    let sent = Instant::now();
+    socket
+        .send_to(&request, server)
+        .await
+        .map_err(|_| Error::Socket)?;
+
+    let mut buffer = [0; MESSAGE_LEN];
+    let response = with_timeout(RESPONSE_TIMEOUT, async {
+        loop {
+            match socket.recv_from(&mut buffer).await {
+                Ok((MESSAGE_LEN, meta)) if meta.endpoint == server => break Ok(buffer),
+                Ok(_) => {}
+                Err(_) => break Err(Error::Socket),
+            }
+        }
+    })
+    .await
+    .map_err(|_| Error::Timeout)??;
+    let round_trip = Instant::now().saturating_duration_since(sent);
+
+    parse(&response, nonce, round_trip).ok_or(Error::InvalidResponse)
+}
+
+/// Returns the address of [`SERVER`].
+async fn resolve(stack: NetworkStack) -> Result<IpAddress, Error> {
+    match SERVER.parse::<IpAddr>() {
+        Ok(IpAddr::V4(address)) => return Ok(IpAddress::Ipv4(address)),
+        #[cfg(feature = "ipv6")]
+        Ok(IpAddr::V6(address)) => return Ok(IpAddress::Ipv6(address)),
+        #[cfg(not(feature = "ipv6"))]
+        Ok(IpAddr::V6(_)) => return Err(Error::Resolve),
+        Err(_) => {}
+    }
+
+    let addresses = stack
+        .dns_query(SERVER, DnsQueryType::A)
+        .await
+        .map_err(|_| Error::Resolve)?;
+    addresses.first().copied().ok_or(Error::Resolve)
+}
+
+/// Parses a response to the request with the transmit timestamp `nonce`, returning the Unix time
+/// at which it was received and its uncertainty in seconds.
+fn parse(response: &[u8; MESSAGE_LEN], nonce: [u8; 8], round_trip: Duration) -> Option<(u64, u64)> {
+    let [header, stratum, ..] = *response;
+    let leap = header >> 6;
+    let mode = header & 0x07;
+    if leap == LEAP_UNSYNCHRONIZED
+        || mode != MODE_SERVER
+        || stratum == 0
+        || stratum > MAX_STRATUM
+        || response.get(24..32)? != nonce
+    {
+        return None;
+    }
+
+    let root_delay = u32_at(response, 4)?;
+    let root_dispersion = u32_at(response, 8)?;
+    let seconds = u32_at(response, 40)?;
+    let fraction = u32_at(response, 44)?;
+    if seconds == 0 && fraction == 0 {
+        return None;
+    }
+
+    // Timestamps wrap around in 2036; times before 1968 are taken to be in the next era.
+    let ntp_seconds = if seconds & 0x8000_0000 == 0 {
+        u64::from(seconds) + (1 << 32)
+    } else {
+        u64::from(seconds)
+    };
+
+    // The server sent its timestamp about half the round trip before it was received.
+    let half_round_trip_ms = round_trip.as_millis() / 2;
+    let fraction_ms = (u64::from(fraction) * 1000) >> 32;
+    let unix_time_ms =
+        (ntp_seconds.checked_sub(NTP_TO_UNIX)? * 1000) + fraction_ms + half_round_trip_ms;
+
+    // The root delay and dispersion are in seconds, as 16.16 fixed point numbers.
+    let uncertainty_ms = half_round_trip_ms
+        + (((u64::from(root_delay) / 2 + u64::from(root_dispersion)) * 1000) >> 16);
+
+    Some((
+        (unix_time_ms + 500) / 1000,
+        // Rounding to whole seconds adds up to half a second.
+        (uncertainty_ms + 500).div_ceil(1000),
+    ))
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        bytes.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    #![allow(clippy::indexing_slicing, reason = "panicking is fine in tests")]
+
+    use super::*;
+
+    const NONCE: [u8; 8] = [1, 2, 3, 4, 5, 6, 7, 8];
+
+    /// Seconds of the NTP timestamp of Unix time `1_700_000_000`.
+    const NTP_SECONDS: u32 = 3_908_988_800;
+
+    fn message(seconds: u32, fraction: u32) -> [u8; MESSAGE_LEN] {
+        let mut response = [0; MESSAGE_LEN];
+        // Leap indicator 0, version 4, mode 4 (server).
+        response[0] = 0x24;
+        response[1] = 2;
+        response[24..32].copy_from_slice(&NONCE);
+        response[40..44].copy_from_slice(&seconds.to_be_bytes());
+        response[44..48].copy_from_slice(&fraction.to_be_bytes());
+        response
+    }
+
+    #[test]
+    fn valid() {
+        let response = message(NTP_SECONDS, 0);
+        assert_eq!(
+            parse(&response, NONCE, Duration::from_ticks(0)),
+            Some((1_700_000_000, 1))
+        );
+
+        // Half a second into the second, received a second after it was sent.
+        let response = message(NTP_SECONDS, 0x8000_0000);
+        assert_eq!(
+            parse(&response, NONCE, Duration::from_secs(2)),
+            Some((1_700_000_002, 2))
+        );
+    }
+
+    #[test]
+    fn root_delay_and_dispersion() {
+        let mut response = message(NTP_SECONDS, 0);
+        // 2 s of root delay, of which half counts, and 1 s of root dispersion.
+        response[4..8].copy_from_slice(&0x0002_0000_u32.to_be_bytes());
+        response[8..12].copy_from_slice(&0x0001_0000_u32.to_be_bytes());
+        assert_eq!(
+            parse(&response, NONCE, Duration::from_ticks(0)),
+            Some((1_700_000_000, 3))
+        );
+    }
+
+    #[test]
+    fn era_rollover() {
+        // The last second of era 0, in 2036.
+        let response = message(u32::MAX, 0);
+        assert_eq!(
+            parse(&response, NONCE, Duration::from_ticks(0)).map(|(time, _)| time),
+            Some(u64::from(u32::MAX) - NTP_TO_UNIX)
+        );
+
+        // Timestamps that would lie before 1968 are in era 1.
+        let response = message(0x1000, 0);
+        assert_eq!(
+            parse(&response, NONCE, Duration::from_ticks(0)).map(|(time, _)| time),
+            Some((1 << 32) + 0x1000 - NTP_TO_UNIX)
+        );
+        let response = message(0x7fff_ffff, 0);
+        assert_eq!(
+            parse(&response, NONCE, Duration::from_ticks(0)).map(|(time, _)| time),
+            Some((1 << 32) + 0x7fff_ffff - NTP_TO_UNIX)
+        );
+    }
+
+    #[test]
+    fn kiss_o_death() {
+        let mut response = message(NTP_SECONDS, 0);
+        response[1] = 0;
+        // The kiss code takes the place of the reference ID.
+        response[12..16].copy_from_slice(b"RATE");
+        assert_eq!(parse(&response, NONCE, Duration::from_ticks(0)), None);
+    }
+
+    #[test]
+    fn unsynchronized() {
+        let mut response = message(NTP_SECONDS, 0);
+        response[1] = 16;
+        assert_eq!(parse(&response, NONCE, Duration::from_ticks(0)), None);
+
+        let mut response = message(NTP_SECONDS, 0);
+        response[0] |= LEAP_UNSYNCHRONIZED << 6;
+        assert_eq!(parse(&response, NONCE, Duration::from_ticks(0)), None);
+
+        let response = message(0, 0);
+        assert_eq!(parse(&response, NONCE, Duration::from_ticks(0)), None);
+    }
+
+    #[test]
+    fn mode() {
+        let mut response = message(NTP_SECONDS, 0);
+        response[0] = CLIENT_HEADER;
+        assert_eq!(parse(&response, NONCE, Duration::from_ticks(0)), None);
+
+        // Broadcast mode.
+        response[0] = 0x25;
+        assert_eq!(parse(&response, NONCE, Duration::from_ticks(0)), None);
+
+        response[0] = 0x20 | MODE_SERVER;
+        assert!(parse(&response, NONCE, Duration::from_ticks(0)).is_some());
+    }
+
+    #[test]
+    fn origin_mismatch() {
+        let response = message(NTP_SECONDS, 0);
+        assert_eq!(parse(&response, [0; 8], Duration::from_ticks(0)), None);
+
+        let mut nonce = NONCE;
+        nonce[7] ^= 1;
+        assert_eq!(parse(&response, nonce, Duration::from_ticks(0)), None);
+    }
+}
@@ -0,0 +1,171 @@
+//! Accounts for the sockets of the network stacks.
+//!
+//! Components, including applications, declare how many sockets they keep open at the same time
+//! through [`declare_sockets!`](crate::net::declare_sockets); sockets used by the system, e.g.,
+//! for DHCP or DNS, are declared by the system itself.
+//!
+//! Each network stack has room for the sum of these declarations, which is computed at build
+//! time: every laze module and application appends the sockets it declares to the
+//! `network_sockets_required` laze variable, whose sum is passed as
+//! `CONFIG_NETWORK_MAX_CONCURRENT_SOCKETS` (default outside of laze: 4).
+//! The declarations themselves are collected at link time and checked at startup: when they
+//! exceed the room available, e.g., because an application did not append its sockets, startup
+//! fails with an error listing them, along with the number of sockets missing.
+//!
+//! Sockets are not counted against the declarations at run time.
+//! A component opening more sockets than it declared goes unnoticed until the network stack runs
+//! out of room, which makes opening a socket panic.
+//!
+//! ```ignore
+//! // Sockets of an HTTP server serving two connections concurrently.
+//! ariel_os::net::declare_sockets!("http-server", 2);
+//! ```
+
+use ariel_os_debug::log::{debug, error};
+
+use super::{MAX_CONCURRENT_SOCKETS, MAX_INTERFACES};
+
+/// The number of sockets used by a component.
+#[doc(hidden)]
+pub struct SocketDeclaration {
+    pub component: &'static str,
+    pub sockets: usize,
+}
+
+#[doc(hidden)]
+#[linkme::distributed_slice]
+pub static SOCKET_DECLARATIONS: [SocketDeclaration] = [..];
+
+/// Declares that `component` keeps up to `sockets` sockets open at the same time on a network
+/// stack.
+///
+/// See the [`sockets`](crate::net::sockets) module.
+#[macro_export]
+macro_rules! declare_sockets {
+    ($component:literal, $sockets:expr $(,)?) => {
+        const _: () = {
+            #[$crate::reexports::linkme::distributed_slice(
+                $crate::net::sockets::SOCKET_DECLARATIONS
+            )]
+            #[linkme(crate = $crate::reexports::linkme)]
+            static DECLARATION: $crate::net::sockets::SocketDeclaration =
+                $crate::net::sockets::SocketDeclaration {
+                    component: $component,
+                    sockets: $sockets,
+                };
+        };
+    };
+}
+
+crate::declare_sockets!(
+    "dhcpv4",
+    dhcpv4_sockets(
+        cfg!(feature = "network-config-static"),
+        cfg!(feature = "network-config-override"),
+        MAX_INTERFACES,
+    )
+);
+
+#[cfg(feature = "dns")]
+crate::declare_sockets!("dns", 1);
+
+/// Returns the number of sockets used for DHCPv4.
+///
+/// The primary interface may be configured statically, the other ones always use DHCPv4.
+/// A configuration provided by the application may use DHCPv4 even when the static
+/// configuration is selected.
+const fn dhcpv4_sockets(static_config: bool, config_override: bool, interfaces: usize) -> usize {
+    if static_config && !config_override && interfaces == 1 {
+        0
+    } else {
+        1
+    }
+}
+
+/// Returns the number of sockets declared.
+#[must_use]
+pub fn declared() -> usize {
+    count(&SOCKET_DECLARATIONS)
+}
+
+fn count(declarations: &[SocketDeclaration]) -> usize {
+    declarations
+        .iter()
+        .map(|declaration| declaration.sockets)
+        .sum()
+}
+
+/// Checks that the declared sockets fit into each network stack.
+///
+/// # Panics
+///
+/// Panics if more sockets are declared than the network stacks have room for.
+pub(crate) fn check() {
+    let declared = declared();
+
+    if declared <= MAX_CONCURRENT_SOCKETS {
+        debug!(
+            "{} of {} network sockets declared",
+            declared, MAX_CONCURRENT_SOCKETS
+        );
+        return;
+    }
+
+    error!("Network sockets declared:");
+    for declaration in SOCKET_DECLARATIONS {
+        error!("  {}: {}", declaration.component, declaration.sockets);
+    }
+    panic!(
+        "{} network sockets declared, but only {} available: {} more need to be appended to `network_sockets_required` in laze",
+        declared,
+        MAX_CONCURRENT_SOCKETS,
+        declared - MAX_CONCURRENT_SOCKETS
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dhcpv4() {
+        assert_eq!(dhcpv4_sockets(false, false, 1), 1);
+        assert_eq!(dhcpv4_sockets(true, false, 1), 0);
+        // Other interfaces use DHCPv4 anyway.
+        assert_eq!(dhcpv4_sockets(true, false, 2), 1);
+        // The application may configure DHCPv4 instead.
+        assert_eq!(dhcpv4_sockets(true, true, 1), 1);
+        assert_eq!(dhcpv4_sockets(false, true, 1), 1);
+    }
+
+    #[test]
+    fn counting() {
+        assert_eq!(count(&[]), 0);
+        let declarations = [
+            SocketDeclaration {
+                component: "dhcpv4",
+                sockets: 1,
+            },
+            SocketDeclaration {
+                component: "http-server",
+                sockets: 2,
+            },
+            SocketDeclaration {
+                component: "unused",
+                sockets: 0,
+            },
+        ];
+        assert_eq!(count(&declarations), 3);
+    }
+
+    #[test]
+    fn declarations() {
+        // The declarations of this crate are collected.
+        assert!(
+            SOCKET_DECLARATIONS
+                .iter()
+                .any(|declaration| declaration.component == "dhcpv4")
+        );
+        assert_eq!(declared(), count(&SOCKET_DECLARATIONS));
+    }
+}
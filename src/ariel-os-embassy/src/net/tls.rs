@@ -0,0 +1,1130 @@
+//! Provides TLS 1.3 client connections over TCP.
+//!
+//! A [`TlsConnection`] is established over a connected
+//! [`TcpSocket`](embassy_net::tcp::TcpSocket) through [`connect()`], and then read from and
+//! written to through the [`embedded_io_async`] traits.
+//! Servers are always authenticated, as described by [`Authentication`]: either through a
+//! pre-shared key (PSK), or through key pinning.
+//! The pinned keys are passed to [`connect()`] as the certificates carrying them, or kept in
+//! storage through the [`pinned_keys`] module.
+//!
+//! Only the `TLS_AES_128_GCM_SHA256` cipher suite and ECDSA P-256 keys are supported, both for
+//! the server and for the certificate authorities in its certificate chain.
+//!
+//! # Key pinning
+//!
+//! The pinned keys are the trust anchors of certificate validation. Servers presenting a
+//! certificate are accepted if they prove possession of the private key of that certificate, and
+//! if either
+//!
+//! * the public key of that certificate is pinned, which identifies the server whichever name its
+//!   certificate carries, or
+//! * the certificate is issued for the server name passed to [`connect()`], and is signed by a
+//!   pinned key, directly or through a chain of certificate authorities whose certificates the
+//!   server presents along with its own.
+//!
+//! Pinning the key of a certificate authority thus accepts the servers it issued certificates to.
+//! The server name is matched against the DNS names and IP addresses of the subject alternative
+//! name extension, or, without one, against the common name of the subject.
+//! The validity period of all certificates is checked against the bounds of the
+//! [wall-clock time](crate::wallclock): a certificate is only refused if it is known to be expired
+//! or not yet valid.
+//! Revocation is not checked: a server whose key is compromised stays accepted until its key, or
+//! that of its issuer, is removed from the pinned keys.
+//!
+//! The randomness of the handshake is obtained from
+//! [`ariel_os_random::crypto_rng()`](ariel_os_random::crypto_rng), which needs to be seeded, e.g.,
+//! through the `random` laze module.
+//!
+//! # Use with an HTTP client
+//!
+//! Connections are compatible with the TLS connections of `reqwless`, so that requests can be
+//! sent over them:
+//!
+//! ```ignore
+//! let mut connection = reqwless::client::HttpConnection::Tls(connection);
+//! let request = reqwless::request::Request::get("/").host("example.org").build();
+//! let response = connection.send(request, &mut rx_buffer).await?;
+//! ```
+//!
+//! # Buffer sizes
+//!
+//! The read buffer needs to fit a full TLS record, which is 16640 bytes, unless the server is
+//! known to send smaller records.
+//! The write buffer can be smaller, at the cost of splitting large writes into several records.
+
+use core::net::IpAddr;
+
+use ariel_os_random::CryptoRng;
+use embassy_net::tcp::TcpSocket;
+use embedded_tls::{
+    Aes128GcmSha256, Certificate, CertificateEntryRef, CertificateRef, CryptoProvider,
+    HandshakeVerifyRef, SignatureScheme, TlsConfig, TlsContext, TlsError, TlsVerifier,
+};
+use p256::{
+    ecdsa::{DerSignature, Signature, SigningKey, VerifyingKey, signature::Verifier as _},
+    pkcs8::DecodePrivateKey as _,
+};
+use sha2::{Digest as _, Sha256};
+
+use crate::wallclock::{self, Bounds};
+
+/// A TLS connection over a TCP socket.
+pub type TlsConnection<'a> = embedded_tls::TlsConnection<'a, TcpSocket<'a>, Aes128GcmSha256>;
+
+/// Maximum number of keys pinned for a single connection.
+pub const MAX_PINNED_KEYS: usize = ariel_os_utils::usize_from_env_or!(
+    "CONFIG_TLS_MAX_PINNED_KEYS",
+    4,
+    "maximum number of pinned keys for TLS connections"
+);
+
+/// Maximum number of certificate authorities between the server's certificate and a pinned key.
+const MAX_CHAIN_LEN: usize = 4;
+
+/// How the server, and possibly the client, are authenticated.
+#[derive(Debug, Copy, Clone)]
+pub enum Authentication<'a> {
+    /// A pre-shared key, which authenticates both the server and the client.
+    Psk {
+        /// The identity of the key, as known to the server.
+        identity: &'a [u8],
+        /// The key.
+        key: &'a [u8],
+    },
+    /// The server presents a certificate whose public key is that of one of the DER-encoded
+    /// certificates in `certificates`, see [key pinning](self#key-pinning).
+    PinnedKeys {
+        /// The certificates carrying the pinned keys.
+        certificates: &'a [&'a [u8]],
+        /// The certificate the client authenticates with, if requested by the server.
+        client: Option<ClientCertificate<'a>>,
+    },
+    /// Same as [`Authentication::PinnedKeys`], with the pinned keys kept in storage.
+    #[cfg(feature = "storage")]
+    StoredPinnedKeys {
+        /// The certificate the client authenticates with, if requested by the server.
+        client: Option<ClientCertificate<'a>>,
+    },
+}
+
+/// A certificate the client authenticates with.
+#[derive(Debug, Copy, Clone)]
+pub struct ClientCertificate<'a> {
+    /// The DER-encoded X.509 certificate.
+    pub certificate: &'a [u8],
+    /// The DER-encoded PKCS #8 private key, which has to be an ECDSA P-256 key.
+    pub private_key: &'a [u8],
+}
+
+/// Error returned when establishing a TLS connection fails.
+#[derive(Debug)]
+pub enum Error {
+    /// A certificate to pin the key of does not carry an ECDSA P-256 public key.
+    InvalidPinnedKey,
+    /// More than [`MAX_PINNED_KEYS`] keys were given to pin.
+    TooManyPinnedKeys,
+    /// The pinned keys could not be read from storage.
+    Storage,
+    /// The handshake failed, e.g., because the server could not be authenticated.
+    Tls(TlsError),
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::InvalidPinnedKey => write!(f, "invalid pinned key"),
+            Self::TooManyPinnedKeys => write!(f, "too many pinned keys"),
+            Self::Storage => write!(f, "storage error"),
+            Self::Tls(e) => write!(f, "TLS error: {e:?}"),
+        }
+    }
+}
+
+impl core::error::Error for Error {}
+
+/// Pinned public keys.
+type Keys = heapless::Vec<VerifyingKey, MAX_PINNED_KEYS>;
+
+/// Establishes a TLS connection with `server_name` over the connected `socket`.
+///
+/// # Errors
+///
+/// Returns an error if the keys to pin are invalid, or if the handshake fails.
+pub async fn connect<'a>(
+    socket: TcpSocket<'a>,
+    server_name: &str,
+    authentication: Authentication<'_>,
+    read_buffer: &'a mut [u8],
+    write_buffer: &'a mut [u8],
+) -> Result<TlsConnection<'a>, Error> {
+    let identities;
+    // Without pinned keys, servers presenting certificates are refused.
+    let mut keys = Keys::new();
+    let mut config = TlsConfig::new().with_server_name(server_name);
+    let mut client = None;
+
+    match authentication {
+        Authentication::Psk { identity, key } => {
+            identities = [identity];
+            config = config.with_psk(key, &identities);
+        }
+        Authentication::PinnedKeys {
+            certificates,
+            client: client_certificate,
+        } => {
+            for certificate in certificates {
+                let key = public_key(certificate).ok_or(Error::InvalidPinnedKey)?;
+                keys.push(key).map_err(|_| Error::TooManyPinnedKeys)?;
+            }
+            client = client_certificate;
+        }
+        #[cfg(feature = "storage")]
+        Authentication::StoredPinnedKeys {
+            client: client_certificate,
+        } => {
+            keys = pinned_keys::load().await?;
+            client = client_certificate;
+        }
+    }
+
+    if let Some(client) = client {
+        config = config
+            .with_cert(Certificate::X509(client.certificate))
+            .with_priv_key(client.private_key);
+    }
+
+    let provider = Provider {
+        rng: ariel_os_random::crypto_rng(),
+        verifier: KeyPinningVerifier {
+            keys: &keys,
+            server_name,
+            server_key: None,
+            transcript: None,
+        },
+    };
+
+    let mut connection = TlsConnection::new(socket, read_buffer, write_buffer);
+    connection
+        .open(TlsContext::new(&config, provider))
+        .await
+        .map_err(Error::Tls)?;
+    Ok(connection)
+}
+
+/// Provides randomness, verification of the server and signatures by the client.
+struct Provider<'a> {
+    rng: CryptoRng,
+    verifier: KeyPinningVerifier<'a>,
+}
+
+impl CryptoProvider for Provider<'_> {
+    type CipherSuite = Aes128GcmSha256;
+    type Signature = DerSignature;
+
+    fn rng(&mut self) -> impl rand_core::CryptoRngCore {
+        &mut self.rng
+    }
+
+    fn verifier(&mut self) -> Result<&mut impl TlsVerifier<Self::CipherSuite>, TlsError> {
+        Ok(&mut self.verifier)
+    }
+
+    fn signer(
+        &mut self,
+        key_der: &[u8],
+    ) -> Result<
+        (
+            impl p256::ecdsa::signature::SignerMut<Self::Signature>,
+            SignatureScheme,
+        ),
+        TlsError,
+    > {
+        let key = SigningKey::from_pkcs8_der(key_der).map_err(|_| TlsError::InvalidPrivateKey)?;
+        Ok((key, SignatureScheme::EcdsaSecp256r1Sha256))
+    }
+}
+
+/// Accepts servers whose certificate carries a pinned public key or is issued by one, and that
+/// prove possession of the corresponding private key, see [key pinning](self#key-pinning).
+struct KeyPinningVerifier<'a> {
+    keys: &'a [VerifyingKey],
+    /// The name the certificate has to be issued for, unless its key is pinned.
+    server_name: &'a str,
+    /// The key of the accepted certificate.
+    server_key: Option<VerifyingKey>,
+    /// The transcript up to the certificate, which the server signs.
+    transcript: Option<Sha256>,
+}
+
+impl KeyPinningVerifier<'_> {
+    /// Validates the DER-encoded `chain` of the server's certificate followed by the certificates
+    /// of its issuers at time `now`, returning the public key of the server's certificate.
+    fn validate(&self, chain: &[&[u8]], now: Bounds) -> Result<VerifyingKey, TlsError> {
+        let mut chain = chain.iter().map(|certificate| parse(certificate));
+        let server = chain
+            .next()
+            .flatten()
+            .filter(|server| server.is_valid_at(now))
+            .ok_or(TlsError::InvalidCertificate)?;
+        if self.keys.contains(&server.key) {
+            return Ok(server.key);
+        }
+        if !server.is_issued_for(self.server_name) {
+            return Err(TlsError::InvalidCertificate);
+        }
+
+        let mut issuers = chain.take(MAX_CHAIN_LEN).enumerate();
+        let mut current = server;
+        loop {
+            // This includes pinned keys of certificate authorities in the chain.
+            if self.keys.iter().any(|key| current.is_signed_by(key)) {
+                return Ok(server.key);
+            }
+            let (depth, issuer) = issuers.next().ok_or(TlsError::InvalidCertificate)?;
+            current = issuer
+                .filter(|issuer| {
+                    issuer.subject == current.issuer
+                        && issuer.may_issue(depth)
+                        && issuer.is_valid_at(now)
+                        && current.is_signed_by(&issuer.key)
+                })
+                .ok_or(TlsError::InvalidCertificate)?;
+        }
+    }
+}
+
+impl TlsVerifier<Aes128GcmSha256> for KeyPinningVerifier<'_> {
+    fn set_hostname_verification(&mut self, _hostname: &str) -> Result<(), TlsError> {
+        // The server name was passed on construction already.
+        Ok(())
+    }
+
+    fn verify_certificate(
+        &mut self,
+        transcript: &Sha256,
+        _ca: &Option<Certificate<'_>>,
+        certificate: CertificateRef<'_>,
+    ) -> Result<(), TlsError> {
+        let mut chain = heapless::Vec::<&[u8], { MAX_CHAIN_LEN + 1 }>::new();
+        for entry in &certificate.entries {
+            let CertificateEntryRef::X509(certificate) = entry else {
+                return Err(TlsError::InvalidCertificate);
+            };
+            // Certificates beyond the maximum chain length are not needed.
+            let _ = chain.push(certificate);
+        }
+        let key = self.validate(&chain, wallclock::now())?;
+
+        self.server_key = Some(key);
+        self.transcript = Some(transcript.clone());
+        Ok(())
+    }
+
+    fn verify_signature(&mut self, verify: HandshakeVerifyRef<'_>) -> Result<(), TlsError> {
+        let (Some(key), Some(transcript)) = (self.server_key.take(), self.transcript.take()) else {
+            return Err(TlsError::InvalidCertificate);
+        };
+        verify_certificate_verify(
+            &key,
+            &transcript.finalize(),
+            verify.signature_scheme,
+            verify.signature,
+        )
+    }
+}
+
+/// Returns the content the server signs in its `CertificateVerify` message, given the hash of the
+/// `transcript` up to its certificate (see RFC 8446, Section 4.4.3).
+fn certificate_verify_content(transcript: &[u8]) -> heapless::Vec<u8, 130> {
+    let mut content = heapless::Vec::new();
+    let _ = content.resize(64, b' ');
+    let _ = content.extend_from_slice(b"TLS 1.3, server CertificateVerify\0");
+    let _ = content.extend_from_slice(transcript);
+    content
+}
+
+/// Verifies the `signature` of a `CertificateVerify` message by the server holding `key`.
+fn verify_certificate_verify(
+    key: &VerifyingKey,
+    transcript: &[u8],
+    scheme: SignatureScheme,
+    signature: &[u8],
+) -> Result<(), TlsError> {
+    if scheme != SignatureScheme::EcdsaSecp256r1Sha256 {
+        return Err(TlsError::InvalidSignatureScheme);
+    }
+    let signature = Signature::from_der(signature).map_err(|_| TlsError::InvalidSignature)?;
+    key.verify(&certificate_verify_content(transcript), &signature)
+        .map_err(|_| TlsError::InvalidSignature)
+}
+
+const TAG_BOOLEAN: u8 = 0x01;
+const TAG_INTEGER: u8 = 0x02;
+const TAG_BIT_STRING: u8 = 0x03;
+const TAG_OCTET_STRING: u8 = 0x04;
+const TAG_OID: u8 = 0x06;
+const TAG_UTC_TIME: u8 = 0x17;
+const TAG_GENERALIZED_TIME: u8 = 0x18;
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_SET: u8 = 0x31;
+/// The explicitly tagged version of a certificate.
+const TAG_VERSION: u8 = 0xa0;
+/// The implicitly tagged unique identifiers of a certificate's issuer and subject.
+const TAG_ISSUER_UNIQUE_ID: u8 = 0x81;
+const TAG_SUBJECT_UNIQUE_ID: u8 = 0x82;
+/// The explicitly tagged extensions of a certificate.
+const TAG_EXTENSIONS: u8 = 0xa3;
+/// The implicitly tagged alternatives of a general name.
+const TAG_DNS_NAME: u8 = 0x82;
+const TAG_IP_ADDRESS: u8 = 0x87;
+
+/// The algorithm identifier of ECDSA with SHA-256, without parameters (RFC 5758 Section 3.2).
+const ECDSA_WITH_SHA256: &[u8] = &[
+    TAG_OID, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02,
+];
+const OID_COMMON_NAME: &[u8] = &[0x55, 0x04, 0x03];
+const OID_KEY_USAGE: &[u8] = &[0x55, 0x1d, 0x0f];
+const OID_SUBJECT_ALT_NAME: &[u8] = &[0x55, 0x1d, 0x11];
+const OID_BASIC_CONSTRAINTS: &[u8] = &[0x55, 0x1d, 0x13];
+const OID_EXTENDED_KEY_USAGE: &[u8] = &[0x55, 0x1d, 0x25];
+
+/// The parts of a DER-encoded X.509 certificate that are needed to validate it.
+#[derive(Clone, Copy)]
+struct ParsedCertificate<'a> {
+    /// The encoded to-be-signed part, which the issuer's signature covers.
+    tbs_certificate: &'a [u8],
+    /// The encoded name of the issuer.
+    issuer: &'a [u8],
+    /// The encoded name of the subject.
+    subject: &'a [u8],
+    /// Start of the validity period in seconds of Unix time.
+    not_before: u64,
+    /// End of the validity period in seconds of Unix time.
+    not_after: u64,
+    key: VerifyingKey,
+    /// The contents of the extensions, which are empty if there are none.
+    extensions: &'a [u8],
+    /// The encoded algorithm identifier of the issuer's signature.
+    signature_algorithm: &'a [u8],
+    /// The encoded signature of the issuer.
+    signature: &'a [u8],
+}
+
+impl ParsedCertificate<'_> {
+    fn is_valid_at(&self, now: Bounds) -> bool {
+        // Refused only if it is certain to be outside its validity period.
+        self.not_after >= now.earliest && now.latest.is_none_or(|latest| self.not_before <= latest)
+    }
+
+    fn is_signed_by(&self, key: &VerifyingKey) -> bool {
+        self.signature_algorithm == ECDSA_WITH_SHA256
+            && Signature::from_der(self.signature)
+                .is_ok_and(|signature| key.verify(self.tbs_certificate, &signature).is_ok())
+    }
+
+    /// Returns whether this certificate is that of a certificate authority that may issue
+    /// certificates with `depth` other certificate authorities below it.
+    fn may_issue(&self, depth: usize) -> bool {
+        let Some((constraints, _)) = extension(self.extensions, OID_BASIC_CONSTRAINTS)
+            .and_then(|value| element(TAG_SEQUENCE, value))
+        else {
+            return false;
+        };
+        let Some(([0xff], constraints)) = element(TAG_BOOLEAN, constraints) else {
+            return false;
+        };
+        match element(TAG_INTEGER, constraints) {
+            Some((path_len, _)) => path_len
+                .iter()
+                .try_fold(0usize, |len, &byte| {
+                    len.checked_mul(256)?.checked_add(usize::from(byte))
+                })
+                .is_some_and(|path_len| depth <= path_len),
+            None => true,
+        }
+    }
+
+    /// Returns whether this certificate is issued for `server_name`.
+    fn is_issued_for(&self, server_name: &str) -> bool {
+        let Some(names) = extension(self.extensions, OID_SUBJECT_ALT_NAME) else {
+            return common_name(self.subject)
+                .is_some_and(|name| dns_name_matches(name, server_name));
+        };
+        let Some((mut names, _)) = element(TAG_SEQUENCE, names) else {
+            return false;
+        };
+        let address = server_name.parse::<IpAddr>().ok();
+        while let Some(&tag) = names.first() {
+            let Some((name, rest)) = element(tag, names) else {
+                return false;
+            };
+            names = rest;
+            let matches = match (tag, address) {
+                (TAG_DNS_NAME, None) => dns_name_matches(name, server_name),
+                (TAG_IP_ADDRESS, Some(IpAddr::V4(address))) => name == address.octets(),
+                (TAG_IP_ADDRESS, Some(IpAddr::V6(address))) => name == address.octets(),
+                _ => false,
+            };
+            if matches {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+/// Parses a DER-encoded X.509 `certificate`, which has to carry an ECDSA P-256 public key.
+///
+/// Certificates with critical extensions other than the ones evaluated or the key usages are
+/// refused.
+fn parse(certificate: &[u8]) -> Option<ParsedCertificate<'_>> {
+    let (certificate, _) = element(TAG_SEQUENCE, certificate)?;
+    let (tbs_certificate, rest) = encoded_element(TAG_SEQUENCE, certificate)?;
+    let (signature_algorithm, rest) = element(TAG_SEQUENCE, rest)?;
+    let (signature, _) = element(TAG_BIT_STRING, rest)?;
+    let (&0, signature) = signature.split_first()? else {
+        return None;
+    };
+
+    let (fields, _) = element(TAG_SEQUENCE, tbs_certificate)?;
+    let fields = element(TAG_VERSION, fields).map_or(fields, |(_, rest)| rest);
+    let (_serial_number, fields) = element(TAG_INTEGER, fields)?;
+    let (_signature, fields) = element(TAG_SEQUENCE, fields)?;
+    let (issuer, fields) = encoded_element(TAG_SEQUENCE, fields)?;
+    let (validity, fields) = element(TAG_SEQUENCE, fields)?;
+    let (subject, fields) = encoded_element(TAG_SEQUENCE, fields)?;
+    let (public_key_info, fields) = element(TAG_SEQUENCE, fields)?;
+    let fields = element(TAG_ISSUER_UNIQUE_ID, fields).map_or(fields, |(_, rest)| rest);
+    let fields = element(TAG_SUBJECT_UNIQUE_ID, fields).map_or(fields, |(_, rest)| rest);
+    let extensions = match element(TAG_EXTENSIONS, fields) {
+        Some((extensions, _)) => element(TAG_SEQUENCE, extensions)?.0,
+        None => &[],
+    };
+    if !extensions_are_understood(extensions) {
+        return None;
+    }
+
+    let (not_before, validity) = time(validity)?;
+    let (not_after, _) = time(validity)?;
+
+    // Decoding the key fails for other algorithms and curves.
+    let (_algorithm, public_key_info) = element(TAG_SEQUENCE, public_key_info)?;
+    let (public_key, _) = element(TAG_BIT_STRING, public_key_info)?;
+    let (&0, point) = public_key.split_first()? else {
+        return None;
+    };
+    let key = VerifyingKey::from_sec1_bytes(point).ok()?;
+
+    Some(ParsedCertificate {
+        tbs_certificate,
+        issuer,
+        subject,
+        not_before,
+        not_after,
+        key,
+        extensions,
+        signature_algorithm,
+        signature,
+    })
+}
+
+/// Returns the ECDSA P-256 public key of a DER-encoded X.509 `certificate`.
+fn public_key(certificate: &[u8]) -> Option<VerifyingKey> {
+    parse(certificate).map(|certificate| certificate.key)
+}
+
+/// Iterates over the `(id, critical, value)` of the DER-encoded `extensions`, stopping at the
+/// first malformed one.
+fn extensions(mut extensions: &[u8]) -> impl Iterator<Item = Option<(&[u8], bool, &[u8])>> {
+    core::iter::from_fn(move || {
+        if extensions.is_empty() {
+            return None;
+        }
+        let Some((extension, rest)) = element(TAG_SEQUENCE, extensions) else {
+            extensions = &[];
+            return Some(None);
+        };
+        extensions = rest;
+        Some(parse_extension(extension))
+    })
+}
+
+/// Parses the contents of an extension into its `(id, critical, value)`.
+fn parse_extension(extension: &[u8]) -> Option<(&[u8], bool, &[u8])> {
+    let (id, fields) = element(TAG_OID, extension)?;
+    let (critical, fields) = match element(TAG_BOOLEAN, fields) {
+        Some((critical, fields)) => (critical == [0xff], fields),
+        None => (false, fields),
+    };
+    let (value, _) = element(TAG_OCTET_STRING, fields)?;
+    Some((id, critical, value))
+}
+
+/// Returns whether the DER-encoded `extensions` are well-formed, and all critical ones are
+/// understood.
+fn extensions_are_understood(extensions: &[u8]) -> bool {
+    self::extensions(extensions).all(|extension| {
+        extension.is_some_and(|(id, critical, _)| {
+            !critical
+                || [
+                    OID_BASIC_CONSTRAINTS,
+                    OID_SUBJECT_ALT_NAME,
+                    OID_KEY_USAGE,
+                    OID_EXTENDED_KEY_USAGE,
+                ]
+                .contains(&id)
+        })
+    })
+}
+
+/// Returns the value of the extension `id` in the DER-encoded `extensions`.
+fn extension<'a>(extensions: &'a [u8], id: &[u8]) -> Option<&'a [u8]> {
+    self::extensions(extensions)
+        .flatten()
+        .find(|&(extension, _, _)| extension == id)
+        .map(|(_, _, value)| value)
+}
+
+/// Returns the last common name of the DER-encoded `name`.
+fn common_name(name: &[u8]) -> Option<&[u8]> {
+    let (mut relative_names, _) = element(TAG_SEQUENCE, name)?;
+    let mut common_name = None;
+    while !relative_names.is_empty() {
+        let (mut attributes, rest) = element(TAG_SET, relative_names)?;
+        relative_names = rest;
+        while !attributes.is_empty() {
+            let (attribute, rest) = element(TAG_SEQUENCE, attributes)?;
+            attributes = rest;
+            let (id, value) = element(TAG_OID, attribute)?;
+            if id == OID_COMMON_NAME {
+                // Any string type.
+                common_name = Some(element(*value.first()?, value)?.0);
+            }
+        }
+    }
+    common_name
+}
+
+/// Returns whether the DNS name `pattern` of a certificate matches `name`.
+///
+/// A leading `*` label matches any single label (RFC 6125 Section 6.4.3).
+fn dns_name_matches(pattern: &[u8], name: &str) -> bool {
+    match pattern.strip_prefix(b"*.") {
+        Some(suffix) => name.split_once('.').is_some_and(|(label, rest)| {
+            !label.is_empty() && rest.as_bytes().eq_ignore_ascii_case(suffix)
+        }),
+        None => pattern.eq_ignore_ascii_case(name.as_bytes()),
+    }
+}
+
+/// Splits a `UTCTime` or `GeneralizedTime` off `der`, returning it in seconds of Unix time and the
+/// rest.
+///
+/// Only the forms required by RFC 5280 Section 4.1.2.5 are accepted; times before 1970 are
+/// returned as 0.
+fn time(der: &[u8]) -> Option<(u64, &[u8])> {
+    let (year, time, rest) = if let Some((time, rest)) = element(TAG_UTC_TIME, der) {
+        let (year, time) = time.split_at_checked(2)?;
+        // Two-digit years stand for 1950 to 2049.
+        let year = decimal(year)?;
+        (year + if year < 50 { 2000 } else { 1900 }, time, rest)
+    } else {
+        let (time, rest) = element(TAG_GENERALIZED_TIME, der)?;
+        let (year, time) = time.split_at_checked(4)?;
+        (decimal(year)?, time, rest)
+    };
+
+    let (digits, b"Z") = time.split_at_checked(10)? else {
+        return None;
+    };
+    let [month, day, hour, minute, second] =
+        [0, 2, 4, 6, 8].map(|i| digits.get(i..i + 2).and_then(decimal));
+    let (Some(month), Some(day), Some(hour), Some(minute), Some(second)) =
+        (month, day, hour, minute, second)
+    else {
+        return None;
+    };
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 59
+    {
+        return None;
+    }
+
+    let seconds = days_from_civil(year, month, day) * 86400 + (hour * 60 + minute) * 60 + second;
+    Some((u64::try_from(seconds).unwrap_or(0), rest))
+}
+
+/// Parses ASCII decimal `digits`.
+fn decimal(digits: &[u8]) -> Option<i64> {
+    digits.iter().try_fold(0, |value, &digit| {
+        digit
+            .is_ascii_digit()
+            .then(|| value * 10 + i64::from(digit - b'0'))
+    })
+}
+
+/// Returns the number of days between the Unix epoch and the given date of the proleptic Gregorian
+/// calendar, for years from 0.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    // Years start in March, so that leap days are at their end.
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Splits a DER element with the given `tag` off `der`, returning its encoding and the rest.
+fn encoded_element(tag: u8, der: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (_, rest) = element(tag, der)?;
+    der.split_at_checked(der.len() - rest.len())
+}
+
+/// Splits a DER element with the given `tag` off `der`, returning its contents and the rest.
+fn element(tag: u8, der: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (&actual_tag, rest) = der.split_first()?;
+    if actual_tag != tag {
+        return None;
+    }
+    let (&first, rest) = rest.split_first()?;
+    let (len, rest) = if first < 0x80 {
+        (usize::from(first), rest)
+    } else {
+        // Certificates are shorter than 64 KiB.
+        let (len, rest) = rest.split_at_checked(usize::from(first & 0x7f))?;
+        if len.is_empty() || len.len() > 2 {
+            return None;
+        }
+        (
+            len.iter()
+                .fold(0, |len, &byte| (len << 8) | usize::from(byte)),
+            rest,
+        )
+    };
+    rest.split_at_checked(len)
+}
+
+#[cfg(feature = "storage")]
+pub mod pinned_keys {
+    //! Keeps the pinned keys of [`Authentication::StoredPinnedKeys`] in storage.
+    //!
+    //! Only the public keys are stored, not the certificates carrying them.
+    //!
+    //! [`Authentication::StoredPinnedKeys`]: super::Authentication::StoredPinnedKeys
+
+    use core::fmt::Write as _;
+
+    use ariel_os_storage::blob;
+    use embedded_io_async::{Read as _, Write as _};
+    use p256::ecdsa::VerifyingKey;
+
+    use super::{Error, Keys, MAX_PINNED_KEYS, public_key};
+
+    /// Length of an uncompressed P-256 public key.
+    const KEY_LEN: usize = 65;
+
+    /// Returns the storage key of the pinned key at `index`.
+    fn key(index: usize) -> heapless::String<32> {
+        let mut key = heapless::String::new();
+        // Cannot fail, as the index has at most 20 digits.
+        let _ = write!(key, "ariel-os-embassy.tls.{index}");
+        key
+    }
+
+    /// Pins the public key of the DER-encoded X.509 `certificate`.
+    ///
+    /// Pinning a key that is already pinned has no effect.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the certificate does not carry an ECDSA P-256 public key, if
+    /// [`MAX_PINNED_KEYS`] keys are pinned already, or if storage fails.
+    pub async fn add(certificate: &[u8]) -> Result<(), Error> {
+        let new = public_key(certificate).ok_or(Error::InvalidPinnedKey)?;
+
+        let mut free = None;
+        for index in 0..MAX_PINNED_KEYS {
+            match read(index).await? {
+                Some(key) if key == new => return Ok(()),
+                Some(_) => {}
+                None if free.is_none() => free = Some(index),
+                None => {}
+            }
+        }
+        let index = free.ok_or(Error::TooManyPinnedKeys)?;
+
+        let mut writer = blob::writer(&key(index))
+            .await
+            .map_err(|_| Error::Storage)?;
+        writer
+            .write_all(new.to_encoded_point(false).as_bytes())
+            .await
+            .map_err(|_| Error::Storage)?;
+        writer.finish().await.map_err(|_| Error::Storage)
+    }
+
+    /// Unpins all keys.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if storage fails.
+    pub async fn clear() -> Result<(), Error> {
+        for index in 0..MAX_PINNED_KEYS {
+            blob::remove(&key(index))
+                .await
+                .map_err(|_| Error::Storage)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the pinned keys.
+    pub(super) async fn load() -> Result<Keys, Error> {
+        let mut keys = Keys::new();
+        for index in 0..MAX_PINNED_KEYS {
+            if let Some(key) = read(index).await? {
+                // Cannot fail, as there is one slot per key.
+                let _ = keys.push(key);
+            }
+        }
+        Ok(keys)
+    }
+
+    async fn read(index: usize) -> Result<Option<VerifyingKey>, Error> {
+        let Some(mut reader) = blob::reader(&key(index))
+            .await
+            .map_err(|_| Error::Storage)?
+        else {
+            return Ok(None);
+        };
+        let mut point = [0; KEY_LEN];
+        reader
+            .read_exact(&mut point)
+            .await
+            .map_err(|_| Error::Storage)?;
+        VerifyingKey::from_sec1_bytes(&point)
+            .map(Some)
+            .map_err(|_| Error::Storage)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #![allow(clippy::indexing_slicing, reason = "panicking is fine in tests")]
+
+    use p256::ecdsa::signature::Signer as _;
+
+    use super::*;
+
+    const P256_CERTIFICATE: &[u8] = include_bytes!("tls/test-p256.der");
+    /// The private key of [`P256_CERTIFICATE`].
+    const P256_SECRET: [u8; 32] = [
+        0xcc, 0x99, 0x46, 0x25, 0xe9, 0x25, 0x58, 0xa4, 0xc5, 0xb4, 0x9f, 0x6f, 0xb2, 0xc4, 0x07,
+        0xfc, 0xfd, 0x96, 0x58, 0xea, 0xa1, 0x4f, 0x06, 0x28, 0x0e, 0x04, 0xef, 0x1f, 0x4a, 0x68,
+        0x27, 0x72,
+    ];
+    const P384_CERTIFICATE: &[u8] = include_bytes!("tls/test-p384.der");
+    const CA_CERTIFICATE: &[u8] = include_bytes!("tls/test-ca.der");
+    /// Issued by [`CA_CERTIFICATE`].
+    const INTERMEDIATE_CERTIFICATE: &[u8] = include_bytes!("tls/test-intermediate.der");
+    /// Issued by [`INTERMEDIATE_CERTIFICATE`] for `server.example.org` and
+    /// `*.devices.example.org`.
+    const SERVER_CERTIFICATE: &[u8] = include_bytes!("tls/test-server.der");
+    /// Issued by [`CA_CERTIFICATE`] for `server.example.org` with the key of
+    /// [`SERVER_CERTIFICATE`], and expired in 2001.
+    const EXPIRED_CERTIFICATE: &[u8] = include_bytes!("tls/test-expired.der");
+    /// Issued by [`CA_CERTIFICATE`] with the common name `cn.example.org`, and no alternative
+    /// names.
+    const COMMON_NAME_CERTIFICATE: &[u8] = include_bytes!("tls/test-common-name.der");
+    /// Issued for `server.example.org` by [`SERVER_CERTIFICATE`], which is not a certificate
+    /// authority.
+    const BY_SERVER_CERTIFICATE: &[u8] = include_bytes!("tls/test-by-server.der");
+
+    fn signing_key() -> SigningKey {
+        SigningKey::from_slice(&P256_SECRET).unwrap()
+    }
+
+    #[test]
+    fn certificate_key() {
+        assert_eq!(
+            public_key(P256_CERTIFICATE),
+            Some(*signing_key().verifying_key())
+        );
+        // Other curves are not supported.
+        assert_eq!(public_key(P384_CERTIFICATE), None);
+    }
+
+    #[test]
+    fn malformed_certificate() {
+        assert_eq!(public_key(&[]), None);
+        for len in 0..P256_CERTIFICATE.len() {
+            assert_eq!(public_key(&P256_CERTIFICATE[..len]), None);
+        }
+        // The to-be-signed part alone is not a certificate.
+        assert_eq!(public_key(&P256_CERTIFICATE[4..]), None);
+    }
+
+    #[test]
+    fn der_element() {
+        assert_eq!(
+            element(TAG_INTEGER, &[0x02, 0x01, 0x05, 0xff]),
+            Some((&[0x05][..], &[0xff][..]))
+        );
+        assert_eq!(element(TAG_SEQUENCE, &[0x02, 0x01, 0x05]), None);
+        // Long form lengths.
+        let mut long = [0; 132];
+        long[..3].copy_from_slice(&[0x30, 0x81, 0x80]);
+        assert_eq!(
+            element(TAG_SEQUENCE, &long).map(|(content, rest)| (content.len(), rest.len())),
+            Some((128, 1))
+        );
+        assert_eq!(element(TAG_SEQUENCE, &[0x30, 0x81, 0x80, 0x00]), None);
+        assert_eq!(element(TAG_SEQUENCE, &[0x30, 0x80]), None);
+        assert_eq!(element(TAG_SEQUENCE, &[0x30, 0x83, 0x01, 0x00, 0x00]), None);
+    }
+
+    /// A time at which all test certificates but [`EXPIRED_CERTIFICATE`] are valid.
+    const NOW: Bounds = Bounds {
+        earliest: 1_800_000_000,
+        latest: Some(1_800_000_010),
+    };
+
+    fn verifier<'a>(keys: &'a [VerifyingKey], server_name: &'a str) -> KeyPinningVerifier<'a> {
+        KeyPinningVerifier {
+            keys,
+            server_name,
+            server_key: None,
+            transcript: None,
+        }
+    }
+
+    fn key_of(certificate: &[u8]) -> VerifyingKey {
+        public_key(certificate).unwrap()
+    }
+
+    #[test]
+    fn pinning() {
+        let key = *signing_key().verifying_key();
+
+        // A pinned key identifies the server whichever name it has.
+        let keys = [key];
+        let verifier = verifier(&keys, "server.example.org");
+        assert_eq!(verifier.validate(&[P256_CERTIFICATE], NOW).ok(), Some(key));
+        assert!(verifier.validate(&[P384_CERTIFICATE], NOW).is_err());
+        assert!(verifier.validate(&[], NOW).is_err());
+
+        let other = *SigningKey::from_slice(&[1; 32]).unwrap().verifying_key();
+        let keys = [other];
+        let verifier = self::verifier(&keys, "a");
+        assert!(verifier.validate(&[P256_CERTIFICATE], NOW).is_err());
+
+        let verifier = self::verifier(&[], "a");
+        assert!(verifier.validate(&[P256_CERTIFICATE], NOW).is_err());
+    }
+
+    #[test]
+    fn chain() {
+        let server = key_of(SERVER_CERTIFICATE);
+        let chain = [SERVER_CERTIFICATE, INTERMEDIATE_CERTIFICATE, CA_CERTIFICATE];
+
+        for keys in [[key_of(CA_CERTIFICATE)], [key_of(INTERMEDIATE_CERTIFICATE)]] {
+            let verifier = verifier(&keys, "server.example.org");
+            assert_eq!(verifier.validate(&chain, NOW).ok(), Some(server));
+            // The root certificate need not be sent.
+            assert_eq!(verifier.validate(&chain[..2], NOW).ok(), Some(server));
+        }
+
+        let keys = [key_of(CA_CERTIFICATE)];
+        let verifier = verifier(&keys, "server.example.org");
+        // Without the intermediate certificate, there is no path to the pinned key.
+        assert!(
+            verifier
+                .validate(&[SERVER_CERTIFICATE, CA_CERTIFICATE], NOW)
+                .is_err()
+        );
+        assert!(verifier.validate(&[SERVER_CERTIFICATE], NOW).is_err());
+
+        let keys = [key_of(P256_CERTIFICATE)];
+        let verifier = self::verifier(&keys, "server.example.org");
+        assert!(verifier.validate(&chain, NOW).is_err());
+    }
+
+    #[test]
+    fn server_name() {
+        let keys = [key_of(CA_CERTIFICATE)];
+        let chain = [SERVER_CERTIFICATE, INTERMEDIATE_CERTIFICATE];
+
+        for name in [
+            "server.example.org",
+            "SERVER.example.org",
+            "a.devices.example.org",
+        ] {
+            assert!(
+                verifier(&keys, name).validate(&chain, NOW).is_ok(),
+                "{name}"
+            );
+        }
+        for name in [
+            "other.example.org",
+            "example.org",
+            "devices.example.org",
+            "a.b.devices.example.org",
+            ".devices.example.org",
+            "server",
+            "192.0.2.1",
+        ] {
+            assert!(
+                verifier(&keys, name).validate(&chain, NOW).is_err(),
+                "{name}"
+            );
+        }
+
+        // Without alternative names, the common name is used.
+        let chain = [COMMON_NAME_CERTIFICATE];
+        assert!(
+            verifier(&keys, "cn.example.org")
+                .validate(&chain, NOW)
+                .is_ok()
+        );
+        assert!(
+            verifier(&keys, "other.example.org")
+                .validate(&chain, NOW)
+                .is_err()
+        );
+
+        // The name of a server whose key is pinned is not checked.
+        let keys = [key_of(SERVER_CERTIFICATE)];
+        assert!(
+            verifier(&keys, "other.example.org")
+                .validate(&[SERVER_CERTIFICATE], NOW)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn validity() {
+        let keys = [key_of(CA_CERTIFICATE)];
+        let verifier = verifier(&keys, "server.example.org");
+        // Valid from 2000-01-01 to 2001-01-01.
+        let chain = [EXPIRED_CERTIFICATE];
+
+        assert!(verifier.validate(&chain, NOW).is_err());
+        let valid = Bounds {
+            earliest: 946_684_800,
+            latest: Some(946_684_800),
+        };
+        assert!(verifier.validate(&chain, valid).is_ok());
+        // Too early.
+        let early = Bounds {
+            earliest: 946_684_000,
+            latest: Some(946_684_799),
+        };
+        assert!(verifier.validate(&chain, early).is_err());
+        // Without knowing the time, only certificates that are certain to be expired are refused.
+        let unknown = Bounds {
+            earliest: 0,
+            latest: None,
+        };
+        assert!(verifier.validate(&chain, unknown).is_ok());
+        assert!(
+            verifier
+                .validate(&[SERVER_CERTIFICATE, INTERMEDIATE_CERTIFICATE], unknown)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn issuer_constraints() {
+        // The server's certificate is not that of a certificate authority.
+        let keys = [key_of(CA_CERTIFICATE)];
+        let chain = [
+            BY_SERVER_CERTIFICATE,
+            SERVER_CERTIFICATE,
+            INTERMEDIATE_CERTIFICATE,
+        ];
+        assert!(
+            verifier(&keys, "server.example.org")
+                .validate(&chain, NOW)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn times() {
+        assert_eq!(time(b"\x17\x0d700101000000Z"), Some((0, &[][..])));
+        assert_eq!(
+            time(b"\x17\x0d000101000000Z\xff"),
+            Some((946_684_800, &[0xff][..]))
+        );
+        // Two-digit years from 50 are in the 20th century.
+        assert_eq!(time(b"\x17\x0d500101000000Z"), Some((0, &[][..])));
+        assert_eq!(
+            time(b"\x18\x0f20240229235959Z"),
+            Some((1_709_251_199, &[][..]))
+        );
+        assert_eq!(
+            time(b"\x18\x0f21260101000000Z"),
+            Some((4_922_899_200, &[][..]))
+        );
+
+        // Other forms are not allowed in certificates.
+        assert_eq!(time(b"\x17\x0b0001010000Z"), None);
+        assert_eq!(time(b"\x17\x11000101000000+0100"), None);
+        assert_eq!(time(b"\x18\x1120240229235959.5Z"), None);
+        assert_eq!(time(b"\x18\x0f20241301000000Z"), None);
+        assert_eq!(time(b"\x18\x0f2024012a000000Z"), None);
+    }
+
+    #[test]
+    fn certificate_verify() {
+        let signing_key = signing_key();
+        let key = *signing_key.verifying_key();
+        let transcript = Sha256::digest(b"handshake");
+
+        let signature: DerSignature = signing_key.sign(&certificate_verify_content(&transcript));
+        assert!(
+            verify_certificate_verify(
+                &key,
+                &transcript,
+                SignatureScheme::EcdsaSecp256r1Sha256,
+                signature.as_bytes(),
+            )
+            .is_ok()
+        );
+
+        assert!(matches!(
+            verify_certificate_verify(
+                &key,
+                &Sha256::digest(b"other handshake"),
+                SignatureScheme::EcdsaSecp256r1Sha256,
+                signature.as_bytes(),
+            ),
+            Err(TlsError::InvalidSignature)
+        ));
+        assert!(matches!(
+            verify_certificate_verify(
+                &key,
+                &transcript,
+                SignatureScheme::EcdsaSecp384r1Sha384,
+                signature.as_bytes(),
+            ),
+            Err(TlsError::InvalidSignatureScheme)
+        ));
+
+        // A signature over the bare transcript, as by a client, is not accepted.
+        let signature: DerSignature = signing_key.sign(&transcript);
+        assert!(matches!(
+            verify_certificate_verify(
+                &key,
+                &transcript,
+                SignatureScheme::EcdsaSecp256r1Sha256,
+                signature.as_bytes(),
+            ),
+            Err(TlsError::InvalidSignature)
+        ));
+    }
+}
@@ -0,0 +1,346 @@
+//! Tracks wall-clock time as learned from trusted and unauthenticated sources.
+//!
+//! Wall-clock time is given in seconds of Unix time, and is never known precisely: sources report
+//! it with some uncertainty, and it is then tracked using the local clock (see
+//! [`Instant`]), whose drift widens the uncertainty over time.
+//! [`now()`] thus returns an interval in which the actual time is known to lie.
+//!
+//! Sources of time include:
+//!
+//! * Authoritative sources, which report the time along with its uncertainty through
+//!   [`report()`].
+//!   Applications can set the time manually in the same way.
+//! * Sources that only attest that a point in time has passed, such as the issue time of a
+//!   credential from a trusted issuer, which report through [`report_past()`].
+//! * On `native`, the host's clock, which is used until another source reports.
+//! * Unauthenticated sources such as the SNTP client of `net::sntp`, which report through
+//!   [`report_unauthenticated()`].
+//!   As their responses can be forged by anyone on the path, they only narrow the bounds
+//!   established by the sources above, and are ignored where they contradict them.
+//!
+//! The bounds are used to check the validity of credentials, e.g., the expiry of ACE tokens; an
+//! attacker able to move them could get expired credentials accepted.
+
+use core::cell::Cell;
+
+use ariel_os_debug::log::{debug, warn};
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use embassy_time::Instant;
+
+/// Maximum drift of the local clock in parts per million.
+const DRIFT_PPM: u64 = ariel_os_utils::usize_from_env_or!(
+    "CONFIG_WALLCLOCK_DRIFT_PPM",
+    500,
+    "maximum drift of the local clock in parts per million"
+) as u64;
+
+/// Interval of Unix time (in seconds) in which the current time lies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bounds {
+    /// The earliest the current time can be; this is 0 if nothing is known.
+    pub earliest: u64,
+    /// The latest the current time can be, if known.
+    pub latest: Option<u64>,
+}
+
+impl Bounds {
+    const UNKNOWN: Self = Self {
+        earliest: 0,
+        latest: None,
+    };
+
+    fn around(unix_time: u64, uncertainty: u64) -> Self {
+        Self {
+            earliest: unix_time.saturating_sub(uncertainty),
+            latest: Some(unix_time.saturating_add(uncertainty)),
+        }
+    }
+
+    /// Returns the intersection of both bounds, if they overlap.
+    fn intersection(self, other: Self) -> Option<Self> {
+        let earliest = self.earliest.max(other.earliest);
+        let latest = match (self.latest, other.latest) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (latest, None) | (None, latest) => latest,
+        };
+        if latest.is_some_and(|latest| latest < earliest) {
+            return None;
+        }
+        Some(Self { earliest, latest })
+    }
+}
+
+/// Bounds as they were known at a given instant.
+#[derive(Clone, Copy)]
+struct Anchor {
+    at: Instant,
+    bounds: Bounds,
+}
+
+impl Anchor {
+    fn bounds_at(&self, now: Instant) -> Bounds {
+        let elapsed = now.saturating_duration_since(self.at).as_secs();
+        let drift = (elapsed * DRIFT_PPM).div_ceil(1_000_000);
+        Bounds {
+            earliest: self
+                .bounds
+                .earliest
+                .saturating_add(elapsed)
+                .saturating_sub(drift),
+            latest: self
+                .bounds
+                .latest
+                .map(|latest| latest.saturating_add(elapsed).saturating_add(drift)),
+        }
+    }
+}
+
+/// What is known about the wall-clock time.
+#[derive(Clone, Copy)]
+struct Clock {
+    /// Bounds established by trusted sources.
+    trusted: Option<Anchor>,
+    /// Bounds established by unauthenticated sources, which only narrow the trusted ones.
+    unauthenticated: Option<Anchor>,
+    /// Bounds used while no trusted source has reported.
+    fallback: fn() -> Option<Bounds>,
+}
+
+impl Clock {
+    const fn new(fallback: fn() -> Option<Bounds>) -> Self {
+        Self {
+            trusted: None,
+            unauthenticated: None,
+            fallback,
+        }
+    }
+
+    fn trusted_at(&self, now: Instant) -> Bounds {
+        match self.trusted {
+            Some(anchor) => anchor.bounds_at(now),
+            None => (self.fallback)().unwrap_or(Bounds::UNKNOWN),
+        }
+    }
+
+    fn bounds_at(&self, now: Instant) -> Bounds {
+        let trusted = self.trusted_at(now);
+        match self.unauthenticated {
+            // Where both contradict each other, the unauthenticated source is the one in error.
+            Some(anchor) => trusted
+                .intersection(anchor.bounds_at(now))
+                .unwrap_or(trusted),
+            None => trusted,
+        }
+    }
+
+    fn report(&mut self, now: Instant, bounds: Bounds) {
+        self.trusted = Some(Anchor { at: now, bounds });
+    }
+
+    /// Returns `false` if the reported bounds contradict the trusted ones and were discarded.
+    fn report_unauthenticated(&mut self, now: Instant, bounds: Bounds) -> bool {
+        if self.trusted_at(now).intersection(bounds).is_none() {
+            return false;
+        }
+        self.unauthenticated = Some(Anchor { at: now, bounds });
+        true
+    }
+
+    /// Returns `false` if the trusted upper bound was exceeded and discarded.
+    fn report_past(&mut self, now: Instant, unix_time: u64) -> bool {
+        let mut bounds = self.trusted_at(now);
+        if unix_time <= bounds.earliest {
+            return true;
+        }
+        bounds.earliest = unix_time;
+        let consistent = bounds.latest.is_none_or(|latest| latest >= unix_time);
+        if !consistent {
+            bounds.latest = None;
+        }
+        self.trusted = Some(Anchor { at: now, bounds });
+        consistent
+    }
+}
+
+static CLOCK: Mutex<CriticalSectionRawMutex, Cell<Clock>> =
+    Mutex::new(Cell::new(Clock::new(host_time)));
+
+/// Applies `f` to the shared clock state.
+fn update<R>(f: impl FnOnce(&mut Clock) -> R) -> R {
+    CLOCK.lock(|cell| {
+        let mut clock = cell.get();
+        let result = f(&mut clock);
+        cell.set(clock);
+        result
+    })
+}
+
+/// Returns the interval in which the current wall-clock time lies.
+#[must_use]
+pub fn now() -> Bounds {
+    let now = Instant::now();
+    CLOCK.lock(|cell| cell.get().bounds_at(now))
+}
+
+/// Reports the current wall-clock time from an authoritative source.
+///
+/// `uncertainty` is the maximum deviation of `unix_time` from the actual time in seconds.
+/// This replaces all bounds previously reported through [`report()`] and [`report_past()`].
+pub fn report(unix_time: u64, uncertainty: u64) {
+    debug!(
+        "Wall-clock time reported: {} +/- {} s",
+        unix_time, uncertainty
+    );
+    let now = Instant::now();
+    update(|clock| clock.report(now, Bounds::around(unix_time, uncertainty)));
+}
+
+/// Reports the current wall-clock time from a source that is not authenticated.
+///
+/// `uncertainty` is the maximum deviation of `unix_time` from the actual time in seconds.
+/// This replaces the bounds previously reported through this function; the resulting bounds
+/// only narrow those of trusted sources, and are discarded if they contradict them.
+pub fn report_unauthenticated(unix_time: u64, uncertainty: u64) {
+    let now = Instant::now();
+    if !update(|clock| clock.report_unauthenticated(now, Bounds::around(unix_time, uncertainty))) {
+        warn!(
+            "Unauthenticated wall-clock time {} +/- {} s contradicts trusted sources, discarding it.",
+            unix_time, uncertainty
+        );
+    }
+}
+
+/// Reports that `unix_time` is known to lie in the past.
+///
+/// This raises the lower bound of the current time if it is earlier.
+/// If the reported time is later than the current upper bound, the upper bound is discarded, as
+/// the source that established it is evidently wrong.
+pub fn report_past(unix_time: u64) {
+    let now = Instant::now();
+    if !update(|clock| clock.report_past(now, unix_time)) {
+        warn!("Wall-clock time reported in the past exceeds the upper bound, discarding it.");
+    }
+}
+
+/// Reads the host's clock, which is trusted on `native`.
+fn host_time() -> Option<Bounds> {
+    #[cfg(context = "native")]
+    {
+        extern crate std;
+
+        let unix_time = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .ok()?
+            .as_secs();
+        Some(Bounds {
+            earliest: unix_time,
+            latest: Some(unix_time + 1),
+        })
+    }
+    #[cfg(not(context = "native"))]
+    {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn at(secs: u64) -> Instant {
+        Instant::from_secs(secs)
+    }
+
+    fn bounds(earliest: u64, latest: u64) -> Bounds {
+        Bounds {
+            earliest,
+            latest: Some(latest),
+        }
+    }
+
+    #[test]
+    fn drift() {
+        let mut clock = Clock::new(|| None);
+        assert_eq!(clock.bounds_at(at(10)), Bounds::UNKNOWN);
+
+        clock.report(at(10), Bounds::around(1_000_000, 1));
+        assert_eq!(clock.bounds_at(at(10)), bounds(999_999, 1_000_001));
+        // 1000 s at 500 ppm drift by half a second, which is rounded up.
+        assert_eq!(clock.bounds_at(at(1010)), bounds(1_000_998, 1_001_002));
+    }
+
+    #[test]
+    fn saturates() {
+        let mut clock = Clock::new(|| None);
+        clock.report(at(10), Bounds::around(u64::MAX - 1, 10));
+        assert_eq!(clock.bounds_at(at(1010)), bounds(u64::MAX - 1, u64::MAX));
+    }
+
+    #[test]
+    fn unauthenticated_narrows() {
+        let mut clock = Clock::new(|| None);
+        assert!(clock.report_unauthenticated(at(0), Bounds::around(1000, 2)));
+        assert_eq!(clock.bounds_at(at(0)), bounds(998, 1002));
+
+        // A trusted lower bound is kept, and narrowed further.
+        assert!(clock.report_past(at(0), 1001));
+        assert_eq!(clock.bounds_at(at(0)), bounds(1001, 1002));
+
+        let mut clock = Clock::new(|| None);
+        clock.report(at(0), bounds(990, 1010));
+        assert!(clock.report_unauthenticated(at(0), Bounds::around(1005, 10)));
+        assert_eq!(clock.bounds_at(at(0)), bounds(995, 1010));
+    }
+
+    #[test]
+    fn unauthenticated_never_widens() {
+        let mut clock = Clock::new(|| None);
+        clock.report(at(0), bounds(1000, 1010));
+
+        // Wider on both ends.
+        assert!(clock.report_unauthenticated(at(0), Bounds::around(1005, 100)));
+        assert_eq!(clock.bounds_at(at(0)), bounds(1000, 1010));
+
+        // Contradicting the trusted bounds.
+        assert!(!clock.report_unauthenticated(at(0), Bounds::around(2000, 1)));
+        assert_eq!(clock.bounds_at(at(0)), bounds(1000, 1010));
+        assert!(!clock.report_unauthenticated(at(0), Bounds::around(10, 1)));
+        assert_eq!(clock.bounds_at(at(0)), bounds(1000, 1010));
+    }
+
+    #[test]
+    fn trusted_overrides_unauthenticated() {
+        let mut clock = Clock::new(|| None);
+        assert!(clock.report_unauthenticated(at(0), Bounds::around(1000, 1)));
+
+        // Later trusted reports take precedence over conflicting earlier ones.
+        clock.report(at(0), bounds(5000, 5010));
+        assert_eq!(clock.bounds_at(at(0)), bounds(5000, 5010));
+
+        let mut clock = Clock::new(|| None);
+        assert!(clock.report_unauthenticated(at(0), Bounds::around(1000, 1)));
+        assert!(clock.report_past(at(0), 5000));
+        assert_eq!(clock.bounds_at(at(0)).earliest, 5000);
+        assert_eq!(clock.bounds_at(at(0)).latest, None);
+    }
+
+    #[test]
+    fn fallback() {
+        let mut clock = Clock::new(|| Some(bounds(1000, 1001)));
+        assert_eq!(clock.bounds_at(at(50)), bounds(1000, 1001));
+        assert!(!clock.report_unauthenticated(at(50), Bounds::around(5000, 1)));
+        assert_eq!(clock.bounds_at(at(50)), bounds(1000, 1001));
+    }
+
+    #[test]
+    fn past() {
+        let mut clock = Clock::new(|| None);
+        clock.report(at(0), bounds(1000, 1010));
+        assert!(clock.report_past(at(0), 500));
+        assert_eq!(clock.bounds_at(at(0)), bounds(1000, 1010));
+        assert!(clock.report_past(at(0), 1005));
+        assert_eq!(clock.bounds_at(at(0)), bounds(1005, 1010));
+        assert!(!clock.report_past(at(0), 2000));
+        assert_eq!(clock.bounds_at(at(0)).latest, None);
+    }
+}
@@ -1,5 +1,290 @@
+//! Provides the list of Wi-Fi networks the device joins.
+//!
+//! The device joins the first available network out of the networks provisioned at runtime, in
+//! order of preference, followed by the network configured at build time through the
+//! `CONFIG_WIFI_NETWORK` and `CONFIG_WIFI_PASSWORD` environment variables, if any.
+//! The same list is tried again whenever the connection is lost; when none of the networks can be
+//! joined, the delay between attempts doubles up to `CONFIG_WIFI_MAX_RECONNECT_DELAY_SECS`
+//! (default: 60).
+//! Loss and recovery of the connection are reported as link events through
+//! [`net::events`](crate::net::events).
+//!
+//! Networks are provisioned by the application through [`add_network()`], or through the
+//! commands of the [`provisioning`] module.
+//! With storage enabled, provisioned networks are kept in storage and restored at startup; their
+//! passwords are stored encrypted through [`ariel_os_storage::encrypted`], which requires a device
+//! identity.
+
+#![deny(missing_docs)]
+
+pub mod provisioning;
+
+pub use ariel_os_embassy_common::wifi::{
+    Credentials, InvalidCredentials, MAX_NETWORKS, MAX_PASSWORD_LEN, MAX_SSID_LEN, Networks,
+    networks,
+};
+
+use ariel_os_debug::log::info;
+use ariel_os_embassy_common::wifi::{Station as _, candidates, set_networks, wait_for_change};
+use embassy_futures::select::select;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex};
+use embassy_time::{Duration, Timer, with_timeout};
+
+use crate::net::NetworkStack;
+
 #[cfg(feature = "wifi-cyw43")]
-pub(crate) use crate::hal::cyw43::NetworkDevice;
+pub(crate) use crate::hal::cyw43::{NetworkDevice, WifiStation};
 
 #[cfg(feature = "wifi-esp")]
-pub(crate) use crate::hal::wifi::esp_wifi::NetworkDevice;
+pub(crate) use crate::hal::wifi::esp_wifi::{NetworkDevice, WifiStation};
+
+/// Time for the link to come up after a network was joined.
+const LINK_UP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Delay before trying the networks again after none of them could be joined.
+const MIN_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Upper bound of the delay between attempts, which doubles after each failed attempt.
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(ariel_os_utils::u32_from_env_or!(
+    "CONFIG_WIFI_MAX_RECONNECT_DELAY_SECS",
+    60,
+    "maximum delay in seconds between attempts to join a Wi-Fi network"
+) as u64);
+
+/// Serializes changes to the provisioned networks, which read, store and then replace the list.
+static CHANGE: Mutex<CriticalSectionRawMutex, ()> = Mutex::new(());
+
+/// Error returned when changing the provisioned networks fails.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// [`MAX_NETWORKS`] networks are already provisioned.
+    TooManyNetworks,
+    /// The networks could not be written to storage.
+    Storage,
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::TooManyNetworks => write!(f, "too many networks"),
+            Self::Storage => write!(f, "storage error"),
+        }
+    }
+}
+
+impl core::error::Error for Error {}
+
+/// Provisions a network, with preference over the networks provisioned so far.
+///
+/// If a network with the same SSID is already provisioned, it is replaced.
+///
+/// # Errors
+///
+/// Returns an error if too many networks are provisioned, or if the networks could not be
+/// stored.
+pub async fn add_network(credentials: Credentials) -> Result<(), Error> {
+    let _guard = CHANGE.lock().await;
+
+    let previous = networks();
+    let mut networks = previous.clone();
+    networks.retain(|n| n.ssid() != credentials.ssid());
+    networks
+        .insert(0, credentials)
+        .map_err(|_| Error::TooManyNetworks)?;
+
+    #[cfg(feature = "storage")]
+    storage::store(&previous, &networks).await?;
+
+    set_networks(networks);
+    Ok(())
+}
+
+/// Removes the provisioned network `ssid`.
+///
+/// Returns whether the network was provisioned.
+///
+/// The network configured at build time cannot be removed.
+///
+/// # Errors
+///
+/// Returns an error if the networks could not be stored.
+pub async fn remove_network(ssid: &str) -> Result<bool, Error> {
+    let _guard = CHANGE.lock().await;
+
+    let previous = networks();
+    let mut networks = previous.clone();
+    networks.retain(|n| n.ssid() != ssid);
+    if networks.len() == previous.len() {
+        return Ok(false);
+    }
+
+    #[cfg(feature = "storage")]
+    storage::store(&previous, &networks).await?;
+
+    set_networks(networks);
+    Ok(true)
+}
+
+/// Keeps the device connected to one of the networks.
+#[embassy_executor::task]
+pub(crate) async fn supervisor_task(mut station: WifiStation, stack: NetworkStack) -> ! {
+    let mut delay = MIN_RECONNECT_DELAY;
+
+    loop {
+        let mut candidates = candidates().peekable();
+        if candidates.peek().is_none() {
+            info!("No Wi-Fi network is provisioned, waiting for one");
+            wait_for_change().await;
+            continue;
+        }
+
+        let mut joined = false;
+        for network in candidates {
+            if station.join(&network).await
+                && with_timeout(LINK_UP_TIMEOUT, stack.wait_link_up())
+                    .await
+                    .is_ok()
+            {
+                joined = true;
+                break;
+            }
+        }
+
+        if joined {
+            delay = MIN_RECONNECT_DELAY;
+            stack.wait_link_down().await;
+            info!("Wi-Fi connection lost, reconnecting");
+        } else {
+            info!(
+                "No Wi-Fi network could be joined, trying again in {}s",
+                delay.as_secs()
+            );
+            // Newly provisioned networks are tried right away.
+            select(Timer::after(delay), wait_for_change()).await;
+            delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+        }
+    }
+}
+
+#[cfg(feature = "storage")]
+pub(crate) use storage::load;
+
+#[cfg(feature = "storage")]
+mod storage {
+    use core::fmt::Write as _;
+
+    use ariel_os_debug::log::{info, warn};
+
+    use super::{Credentials, Error, MAX_NETWORKS, MAX_PASSWORD_LEN, MAX_SSID_LEN, Networks};
+
+    /// The representation of a network's SSID in storage.
+    ///
+    /// The password is stored separately, encrypted under [`password_key()`].
+    type Stored = Option<heapless::String<MAX_SSID_LEN>>;
+
+    /// Longest storage key of a password, whose encrypted record has to fit the longest password.
+    const LONGEST_PASSWORD_KEY: &str = "ariel-os-embassy.wifi.pw.99";
+
+    // A password is serialized with a single byte length prefix.
+    const _: () = assert!(
+        MAX_PASSWORD_LEN + 1 <= ariel_os_storage::encrypted::max_value_len(LONGEST_PASSWORD_KEY)
+    );
+    // Indices have at most as many digits as in `LONGEST_PASSWORD_KEY`.
+    const _: () = assert!(MAX_NETWORKS <= 100);
+
+    /// Returns the storage key of the SSID of the network at `index`.
+    fn key(index: usize) -> heapless::String<48> {
+        let mut key = heapless::String::new();
+        // Cannot fail, as the index has at most 20 digits.
+        let _ = write!(key, "ariel-os-embassy.wifi.{index}");
+        key
+    }
+
+    /// Returns the storage key of the password of the network at `index`.
+    fn password_key(index: usize) -> heapless::String<48> {
+        let mut key = heapless::String::new();
+        // Cannot fail, as the index has at most 20 digits.
+        let _ = write!(key, "ariel-os-embassy.wifi.pw.{index}");
+        key
+    }
+
+    /// Restores the provisioned networks from storage.
+    pub(crate) async fn load() {
+        let mut networks = Networks::new();
+
+        for index in 0..MAX_NETWORKS {
+            let ssid = match ariel_os_storage::get::<Stored>(&key(index)).await {
+                Ok(Some(Some(ssid))) => ssid,
+                Ok(_) => continue,
+                Err(_) => {
+                    warn!("Stored Wi-Fi network is unreadable, ignoring it.");
+                    continue;
+                }
+            };
+            let password = match ariel_os_storage::encrypted::get::<
+                heapless::String<MAX_PASSWORD_LEN>,
+            >(&password_key(index))
+            .await
+            {
+                Ok(Some(password)) => password,
+                Ok(None) => {
+                    warn!("Stored Wi-Fi network has no password, ignoring it.");
+                    continue;
+                }
+                Err(_) => {
+                    warn!("Stored Wi-Fi password is unreadable, ignoring the network.");
+                    continue;
+                }
+            };
+            match Credentials::new(&ssid, &password) {
+                Ok(credentials) => {
+                    // Cannot fail, as there is one slot per network.
+                    let _ = networks.push(credentials);
+                }
+                Err(_) => warn!("Stored Wi-Fi network is invalid, ignoring it."),
+            }
+        }
+
+        if !networks.is_empty() {
+            info!("Restored {} Wi-Fi networks from storage.", networks.len());
+            super::set_networks(networks);
+        }
+    }
+
+    /// Stores `networks`, only writing the slots that differ from `previous`.
+    pub(super) async fn store(previous: &Networks, networks: &Networks) -> Result<(), Error> {
+        for index in 0..MAX_NETWORKS {
+            let network = networks.get(index);
+            if network == previous.get(index) {
+                continue;
+            }
+            if let Some(network) = network {
+                // Conversions cannot fail, as the lengths are checked by `Credentials`.
+                let password: heapless::String<MAX_PASSWORD_LEN> =
+                    network.password().try_into().map_err(|()| Error::Storage)?;
+                let ssid: heapless::String<MAX_SSID_LEN> =
+                    network.ssid().try_into().map_err(|()| Error::Storage)?;
+                ariel_os_storage::encrypted::insert(&password_key(index), password)
+                    .await
+                    .map_err(|_| Error::Storage)?;
+                let stored: Stored = Some(ssid);
+                ariel_os_storage::insert(&key(index), stored)
+                    .await
+                    .map_err(|_| Error::Storage)?;
+            } else {
+                let stored: Stored = None;
+                ariel_os_storage::insert(&key(index), stored)
+                    .await
+                    .map_err(|_| Error::Storage)?;
+                // Overwritten rather than removed, which not all flash drivers support.
+                ariel_os_storage::encrypted::insert(
+                    &password_key(index),
+                    heapless::String::<MAX_PASSWORD_LEN>::new(),
+                )
+                .await
+                .map_err(|_| Error::Storage)?;
+            }
+        }
+        Ok(())
+    }
+}
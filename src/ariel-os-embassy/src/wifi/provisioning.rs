@@ -0,0 +1,182 @@
+//! Provisions Wi-Fi networks through text commands.
+//!
+//! A command is a line of words separated by spaces; a backslash escapes the following
+//! character, so that SSIDs and passwords can contain spaces:
+//!
+//! - `add <ssid> [<password>]`: provisions a network (see [`add_network()`](super::add_network));
+//!   open networks are added without a password.
+//! - `remove <ssid>`: removes a provisioned network.
+//! - `list`: lists the SSIDs of the provisioned networks, one per line.
+//!
+//! The response to a command ends with a line that is either `ok` or starts with `error:`.
+//!
+//! With the `wifi-provisioning-usb` laze module, commands are accepted on a USB serial port
+//! (CDC-ACM).
+//! Other transports, e.g., a BLE characteristic, can be implemented by the application through
+//! [`execute()`].
+
+use core::fmt::Write;
+
+use super::{Credentials, MAX_PASSWORD_LEN, networks};
+
+/// Maximum number of words in a command.
+const MAX_WORDS: usize = 3;
+
+/// Executes a single `command`, writing the response into `response`.
+///
+/// Empty commands are ignored, and produce no response.
+///
+/// # Errors
+///
+/// Returns an error if writing into `response` fails; the command may have been executed
+/// nevertheless.
+pub async fn execute(command: &str, response: &mut impl Write) -> core::fmt::Result {
+    let Some(words) = split(command) else {
+        return writeln!(response, "error: malformed command");
+    };
+    let words: heapless::Vec<&str, MAX_WORDS> =
+        words.iter().map(heapless::String::as_str).collect();
+
+    match words.as_slice() {
+        [] => Ok(()),
+        ["add", ssid] => add(ssid, "", response).await,
+        ["add", ssid, password] => add(ssid, password, response).await,
+        ["remove", ssid] => match super::remove_network(ssid).await {
+            Ok(true) => writeln!(response, "ok"),
+            Ok(false) => writeln!(response, "error: unknown network"),
+            Err(e) => writeln!(response, "error: {e}"),
+        },
+        ["list"] => {
+            for network in networks() {
+                writeln!(response, "{}", network.ssid())?;
+            }
+            writeln!(response, "ok")
+        }
+        _ => writeln!(response, "error: unknown command"),
+    }
+}
+
+async fn add(ssid: &str, password: &str, response: &mut impl Write) -> core::fmt::Result {
+    let credentials = match Credentials::new(ssid, password) {
+        Ok(credentials) => credentials,
+        Err(e) => return writeln!(response, "error: {e}"),
+    };
+    match super::add_network(credentials).await {
+        Ok(()) => writeln!(response, "ok"),
+        Err(e) => writeln!(response, "error: {e}"),
+    }
+}
+
+/// Splits `command` into words, resolving escapes.
+///
+/// Returns `None` if there are too many or too long words, or if the command ends in an escape.
+fn split(command: &str) -> Option<heapless::Vec<heapless::String<MAX_PASSWORD_LEN>, MAX_WORDS>> {
+    let mut words = heapless::Vec::new();
+    let mut word: Option<heapless::String<MAX_PASSWORD_LEN>> = None;
+
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        let c = match c {
+            ' ' | '\t' => {
+                if let Some(word) = word.take() {
+                    words.push(word).ok()?;
+                }
+                continue;
+            }
+            '\\' => chars.next()?,
+            c => c,
+        };
+        word.get_or_insert_with(heapless::String::new)
+            .push(c)
+            .ok()?;
+    }
+    if let Some(word) = word {
+        words.push(word).ok()?;
+    }
+
+    Some(words)
+}
+
+#[cfg(feature = "wifi-provisioning-usb")]
+pub(crate) mod usb {
+    use embassy_usb::{
+        class::cdc_acm::{CdcAcmClass, State},
+        driver::EndpointError,
+    };
+    use static_cell::StaticCell;
+
+    use crate::{
+        hal::usb::UsbDriver,
+        usb::UsbBuilder,
+        wifi::{MAX_NETWORKS, MAX_SSID_LEN},
+    };
+
+    const MAX_PACKET_SIZE: u16 = 64;
+    /// Maximum length of a command line, which fits SSID and password even if fully escaped.
+    const MAX_LINE_LEN: usize = 256;
+    /// Maximum length of a response, which fits the list of networks.
+    const MAX_RESPONSE_LEN: usize = MAX_NETWORKS * (MAX_SSID_LEN + 1) + 64;
+
+    pub(crate) fn class(builder: &mut UsbBuilder) -> CdcAcmClass<'static, UsbDriver> {
+        static STATE: StaticCell<State<'static>> = StaticCell::new();
+
+        CdcAcmClass::new(builder, STATE.init_with(State::new), MAX_PACKET_SIZE)
+    }
+
+    #[embassy_executor::task]
+    pub(crate) async fn usb_serial_task(mut class: CdcAcmClass<'static, UsbDriver>) -> ! {
+        loop {
+            class.wait_connection().await;
+            // Returns when the host disconnects.
+            let _ = serve(&mut class).await;
+        }
+    }
+
+    async fn serve(class: &mut CdcAcmClass<'static, UsbDriver>) -> Result<(), EndpointError> {
+        use core::fmt::Write as _;
+
+        let mut packet = [0; MAX_PACKET_SIZE as usize];
+        let mut line = heapless::Vec::<u8, MAX_LINE_LEN>::new();
+        let mut overflow = false;
+
+        loop {
+            let len = class.read_packet(&mut packet).await?;
+            for &byte in packet.get(..len).unwrap_or_default() {
+                if byte != b'\r' && byte != b'\n' {
+                    overflow |= line.push(byte).is_err();
+                    continue;
+                }
+
+                let mut response = heapless::String::<MAX_RESPONSE_LEN>::new();
+                if overflow {
+                    let _ = writeln!(response, "error: command too long");
+                } else if let Ok(command) = core::str::from_utf8(&line) {
+                    let _ = super::execute(command, &mut response).await;
+                } else {
+                    let _ = writeln!(response, "error: malformed command");
+                }
+                line.clear();
+                overflow = false;
+
+                write(class, response.as_bytes()).await?;
+            }
+        }
+    }
+
+    async fn write(
+        class: &mut CdcAcmClass<'static, UsbDriver>,
+        data: &[u8],
+    ) -> Result<(), EndpointError> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        for chunk in data.chunks(usize::from(MAX_PACKET_SIZE)) {
+            class.write_packet(chunk).await?;
+        }
+        // A full last packet needs to be followed by a short one to end the transfer.
+        if data.len() % usize::from(MAX_PACKET_SIZE) == 0 {
+            class.write_packet(&[]).await?;
+        }
+        Ok(())
+    }
+}
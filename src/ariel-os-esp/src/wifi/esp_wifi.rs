@@ -1,12 +1,10 @@
 use ariel_os_debug::log::{debug, info};
-use embassy_executor::Spawner;
-use embassy_time::{Duration, Timer};
+use ariel_os_embassy_common::wifi;
 use esp_wifi::{
     EspWifiController,
     config::PowerSaveMode,
     wifi::{
-        ClientConfiguration, Configuration, WifiController, WifiDevice, WifiEvent, WifiStaDevice,
-        WifiState,
+        AuthMethod, ClientConfiguration, Configuration, WifiController, WifiDevice, WifiStaDevice,
     },
 };
 use once_cell::sync::OnceCell;
@@ -20,7 +18,7 @@ pub type NetworkDevice = WifiDevice<'static, WifiStaDevice>;
 // sure.
 pub static WIFI_INIT: OnceCell<EspWifiController<'_>> = OnceCell::new();
 
-pub fn init(peripherals: &mut crate::OptionalPeripherals, spawner: Spawner) -> NetworkDevice {
+pub fn init(peripherals: &mut crate::OptionalPeripherals) -> (NetworkDevice, WifiStation) {
     let wifi = peripherals.WIFI.take().unwrap();
     let init = WIFI_INIT.get().unwrap();
     let (device, mut controller) =
@@ -28,35 +26,35 @@ pub fn init(peripherals: &mut crate::OptionalPeripherals, spawner: Spawner) -> N
 
     controller.set_power_saving(PowerSaveMode::None).unwrap();
 
-    spawner.spawn(connection(controller)).ok();
+    #[cfg(not(feature = "defmt"))]
+    debug!("Device capabilities: {:?}", controller.capabilities());
 
-    device
+    (device, WifiStation { controller })
 }
 
-#[embassy_executor::task]
-async fn connection(mut controller: WifiController<'static>) {
-    debug!("start connection task");
+/// The ESP Wi-Fi interface, joining networks as a station.
+pub struct WifiStation {
+    controller: WifiController<'static>,
+}
 
-    #[cfg(not(feature = "defmt"))]
-    debug!("Device capabilities: {:?}", controller.capabilities());
+impl wifi::Station for WifiStation {
+    async fn join(&mut self, network: &wifi::Credentials) -> bool {
+        let controller = &mut self.controller;
 
-    loop {
-        match esp_wifi::wifi::wifi_state() {
-            WifiState::StaConnected => {
-                // wait until we're no longer connected
-                controller.wait_for_event(WifiEvent::StaDisconnected).await;
-                Timer::after(Duration::from_secs(5)).await
-            }
-            _ => {}
-        }
+        debug!("Configuring Wi-Fi");
+        let client_config = Configuration::Client(ClientConfiguration {
+            // Length limits are checked by `Credentials`.
+            ssid: network.ssid().try_into().unwrap(),
+            password: network.password().try_into().unwrap(),
+            auth_method: if network.is_open() {
+                AuthMethod::None
+            } else {
+                AuthMethod::default()
+            },
+            ..Default::default()
+        });
+        controller.set_configuration(&client_config).unwrap();
         if !matches!(controller.is_started(), Ok(true)) {
-            debug!("Configuring Wi-Fi");
-            let client_config = Configuration::Client(ClientConfiguration {
-                ssid: crate::wifi::WIFI_NETWORK.try_into().unwrap(),
-                password: crate::wifi::WIFI_PASSWORD.try_into().unwrap(),
-                ..Default::default()
-            });
-            controller.set_configuration(&client_config).unwrap();
             debug!("Starting Wi-Fi");
             controller.start_async().await.unwrap();
             debug!("Wi-Fi started!");
@@ -64,10 +62,13 @@ async fn connection(mut controller: WifiController<'static>) {
         debug!("About to connect...");
 
         match controller.connect_async().await {
-            Ok(_) => info!("Wifi connected!"),
+            Ok(()) => {
+                info!("Wifi connected to {}!", network.ssid());
+                true
+            }
             Err(e) => {
-                info!("Failed to connect to Wi-Fi: {:?}", e);
-                Timer::after(Duration::from_millis(5000)).await
+                info!("Failed to connect to Wi-Fi {}: {:?}", network.ssid(), e);
+                false
             }
         }
     }
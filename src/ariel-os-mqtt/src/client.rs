@@ -0,0 +1,478 @@
+//! The task maintaining the connection to the broker.
+
+use core::convert::Infallible;
+
+use ariel_os_debug::log::{info, warn};
+use ariel_os_embassy::net::{
+    NetworkStack,
+    events::{self, NetworkEvent, NetworkEventSubscriber},
+};
+use embassy_futures::select::{Either3, Either4, select3, select4};
+use embassy_net::{dns::DnsQueryType, tcp::TcpSocket};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use embassy_time::{Duration, Instant, Timer, with_timeout};
+use embedded_io_async::{Read, Write};
+
+use crate::{
+    Config, MAX_PAYLOAD_LEN, MAX_TOPIC_LEN, OUTGOING, Outgoing, PUBLISHED, QoS, next_packet_id,
+    packet::{self, Connect, Packet, Receiver},
+    subscriptions::{self, Change},
+};
+
+/// Size of the buffers of packets, which fits a PUBLISH packet of the maximum sizes as well as
+/// typical CONNECT packets.
+const PACKET_BUFFER_LEN: usize = {
+    let publish_len = 5 + 2 + MAX_TOPIC_LEN + 2 + MAX_PAYLOAD_LEN;
+    if publish_len > 256 { publish_len } else { 256 }
+};
+
+const TCP_BUFFER_LEN: usize = 1024;
+
+/// Size of the buffer of received TLS records, which needs to fit the records sent by the broker.
+#[cfg(feature = "tls")]
+const TLS_READ_BUFFER_LEN: usize = ariel_os_utils::usize_from_env_or!(
+    "CONFIG_MQTT_TLS_READ_BUFFER_LEN",
+    16640,
+    "size of the buffer of TLS records received from the MQTT broker"
+);
+
+#[cfg(feature = "tls")]
+const TLS_WRITE_BUFFER_LEN: usize = 1024;
+
+/// Time to wait for the broker to accept the connection.
+const CONNACK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Delay before reconnecting, which doubles after each failed attempt up to [`MAX_RETRY_DELAY`].
+const MIN_RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(64);
+
+/// Return code of a SUBACK refusing the subscription.
+const SUBACK_FAILURE: u8 = 0x80;
+
+ariel_os_embassy::net::declare_sockets!("mqtt", 1);
+
+/// Signaled when the configuration changed, so that the client reconnects.
+pub(crate) static RECONFIGURED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Reason for a connection to end.
+#[derive(Debug, Copy, Clone)]
+enum ConnectionError {
+    Resolve,
+    Connect,
+    #[cfg(feature = "tls")]
+    Tls,
+    Io,
+    Closed,
+    TooLong,
+    Malformed,
+    Refused,
+    Timeout,
+    NetworkDown,
+    Reconfigured,
+}
+
+impl ConnectionError {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Resolve => "broker address could not be resolved",
+            Self::Connect => "TCP connection failed",
+            #[cfg(feature = "tls")]
+            Self::Tls => "TLS handshake failed",
+            Self::Io => "socket error",
+            Self::Closed => "connection closed by the broker",
+            Self::TooLong => "packet too long",
+            Self::Malformed => "malformed packet",
+            Self::Refused => "connection refused by the broker",
+            Self::Timeout => "no response",
+            Self::NetworkDown => "network down",
+            Self::Reconfigured => "configuration changed",
+        }
+    }
+}
+
+impl From<packet::Error> for ConnectionError {
+    fn from(error: packet::Error) -> Self {
+        match error {
+            packet::Error::TooLong => Self::TooLong,
+            packet::Error::Malformed => Self::Malformed,
+        }
+    }
+}
+
+/// Connects to the broker and keeps reconnecting.
+#[ariel_os_macros::task(autostart)]
+async fn mqtt_task() {
+    let interface = ariel_os_embassy::net::interfaces()
+        .await
+        .and_then(|interfaces| interfaces.first().copied())
+        .unwrap();
+    let stack = interface.stack();
+
+    let mut events = events::subscribe();
+    if events.is_none() {
+        warn!("MQTT client not subscribed to network events, reconnecting on timeouts only");
+    }
+
+    let mut tcp_rx_buffer = [0; TCP_BUFFER_LEN];
+    let mut tcp_tx_buffer = [0; TCP_BUFFER_LEN];
+    #[cfg(feature = "tls")]
+    let mut tls_read_buffer = [0; TLS_READ_BUFFER_LEN];
+    #[cfg(feature = "tls")]
+    let mut tls_write_buffer = [0; TLS_WRITE_BUFFER_LEN];
+    let mut rx_buffer = [0; PACKET_BUFFER_LEN];
+    let mut tx_buffer = [0; PACKET_BUFFER_LEN];
+
+    let mut client = Client {
+        receiver: Receiver::new(&mut rx_buffer),
+        tx_buffer: &mut tx_buffer,
+        pending: None,
+        last_sent: Instant::now(),
+        ping_sent: None,
+        interface: interface.name(),
+        events: events.as_mut(),
+        retry_delay: MIN_RETRY_DELAY,
+    };
+
+    loop {
+        stack.wait_config_up().await;
+
+        #[cfg(feature = "storage")]
+        let stored = crate::stored::load().await;
+        #[cfg(feature = "storage")]
+        let config = match &stored {
+            Some(stored) => stored.config(),
+            None => crate::config::config(),
+        };
+        #[cfg(not(feature = "storage"))]
+        let config = crate::config::config();
+
+        let Err(reason) = client
+            .connect(
+                stack,
+                &config,
+                &mut tcp_rx_buffer,
+                &mut tcp_tx_buffer,
+                #[cfg(feature = "tls")]
+                &mut tls_read_buffer,
+                #[cfg(feature = "tls")]
+                &mut tls_write_buffer,
+            )
+            .await;
+
+        let delay = match reason {
+            ConnectionError::Reconfigured => Duration::from_secs(0),
+            _ => client.retry_delay,
+        };
+        warn!(
+            "MQTT connection ended ({}), reconnecting in {}s",
+            reason.as_str(),
+            delay.as_secs()
+        );
+        Timer::after(delay).await;
+        client.retry_delay = (client.retry_delay * 2).min(MAX_RETRY_DELAY);
+    }
+}
+
+/// State of the client that is kept across connections.
+struct Client<'a> {
+    receiver: Receiver<'a>,
+    tx_buffer: &'a mut [u8],
+    /// A QoS 1 message whose delivery is not acknowledged yet.
+    pending: Option<Outgoing>,
+    last_sent: Instant,
+    /// When a PINGREQ was sent that is not answered yet.
+    ping_sent: Option<Instant>,
+    /// Name of the interface the client connects from.
+    interface: &'static str,
+    events: Option<&'a mut NetworkEventSubscriber>,
+    retry_delay: Duration,
+}
+
+impl Client<'_> {
+    /// Connects to the broker, and runs the connection until it ends.
+    async fn connect(
+        &mut self,
+        stack: NetworkStack,
+        config: &Config<'_>,
+        tcp_rx_buffer: &mut [u8],
+        tcp_tx_buffer: &mut [u8],
+        #[cfg(feature = "tls")] tls_read_buffer: &mut [u8],
+        #[cfg(feature = "tls")] tls_write_buffer: &mut [u8],
+    ) -> Result<Infallible, ConnectionError> {
+        // Events that occurred before connecting do not concern this connection.
+        if let Some(events) = self.events.as_deref_mut() {
+            while events.try_next_message_pure().is_some() {}
+        }
+
+        // IP addresses are recognized by the query and returned as such.
+        let query_type = if config.broker.contains(':') {
+            DnsQueryType::Aaaa
+        } else {
+            DnsQueryType::A
+        };
+        let address = *stack
+            .dns_query(config.broker, query_type)
+            .await
+            .map_err(|_| ConnectionError::Resolve)?
+            .first()
+            .ok_or(ConnectionError::Resolve)?;
+
+        let mut socket = TcpSocket::new(stack, tcp_rx_buffer, tcp_tx_buffer);
+        socket
+            .connect((address, config.port))
+            .await
+            .map_err(|_| ConnectionError::Connect)?;
+
+        #[cfg(feature = "tls")]
+        if let Some(authentication) = config.tls {
+            let mut connection = ariel_os_embassy::net::tls::connect(
+                socket,
+                config.broker,
+                authentication,
+                tls_read_buffer,
+                tls_write_buffer,
+            )
+            .await
+            .map_err(|_| ConnectionError::Tls)?;
+            let result = self.run(&mut connection, config).await;
+            let (Ok(mut socket) | Err((mut socket, _))) = connection.close().await;
+            socket.abort();
+            let _ = socket.flush().await;
+            return result;
+        }
+
+        let result = self.run(&mut socket, config).await;
+        socket.abort();
+        let _ = socket.flush().await;
+        result
+    }
+
+    /// Establishes the MQTT session over `connection`, and runs it until it ends.
+    async fn run<C: Read + Write>(
+        &mut self,
+        connection: &mut C,
+        config: &Config<'_>,
+    ) -> Result<Infallible, ConnectionError> {
+        self.receiver.clear();
+        self.ping_sent = None;
+
+        let connect = Connect {
+            client_id: config.client_id,
+            username: config.username,
+            password: config.password,
+            keep_alive_secs: config.keep_alive_secs,
+        };
+        let packet = packet::connect(self.tx_buffer, &connect)?;
+        send(connection, packet, &mut self.last_sent).await?;
+
+        let return_code = with_timeout(CONNACK_TIMEOUT, async {
+            loop {
+                if let Some(return_code) = self.handle_received(connection).await? {
+                    break Ok::<_, ConnectionError>(return_code);
+                }
+                self.fill(connection).await?;
+            }
+        })
+        .await
+        .map_err(|_| ConnectionError::Timeout)??;
+        if return_code != 0 {
+            warn!("MQTT broker refused the connection: {}", return_code);
+            return Err(ConnectionError::Refused);
+        }
+
+        info!("Connected to MQTT broker {}", config.broker);
+        self.retry_delay = MIN_RETRY_DELAY;
+
+        subscriptions::reset();
+        if let Some(message) = &self.pending {
+            let packet = packet::publish(
+                self.tx_buffer,
+                &message.topic,
+                &message.payload,
+                message.qos,
+                message.retain,
+                true,
+                message.packet_id,
+            )?;
+            send(connection, packet, &mut self.last_sent).await?;
+        }
+
+        let keep_alive = Duration::from_secs(u64::from(config.keep_alive_secs));
+
+        loop {
+            while let Some(change) = subscriptions::next_change() {
+                let packet = match &change {
+                    Change::Subscribe(filter, qos) => {
+                        packet::subscribe(self.tx_buffer, next_packet_id(), filter, *qos)?
+                    }
+                    Change::Unsubscribe(filter) => {
+                        packet::unsubscribe(self.tx_buffer, next_packet_id(), filter)?
+                    }
+                };
+                send(connection, packet, &mut self.last_sent).await?;
+            }
+
+            // Without an answer to a PINGREQ within the keep-alive interval, the connection is
+            // considered broken.
+            let deadline = self.ping_sent.unwrap_or(self.last_sent) + keep_alive;
+            let keep_alive_expired = async {
+                if config.keep_alive_secs == 0 {
+                    core::future::pending().await
+                } else {
+                    Timer::at(deadline).await;
+                }
+            };
+            let can_publish = self.pending.is_none();
+            let outgoing = async {
+                if can_publish {
+                    OUTGOING.receive().await
+                } else {
+                    core::future::pending().await
+                }
+            };
+            let interface = self.interface;
+            let network_down = network_down(self.events.as_deref_mut(), interface);
+
+            match select4(
+                self.receiver.fill(connection),
+                outgoing,
+                subscriptions::CHANGED.wait(),
+                select3(keep_alive_expired, network_down, RECONFIGURED.wait()),
+            )
+            .await
+            {
+                Either4::First(read) => {
+                    if read.map_err(|_| ConnectionError::Io)? == 0 {
+                        return Err(ConnectionError::Closed);
+                    }
+                    self.handle_received(connection).await?;
+                }
+                Either4::Second(message) => {
+                    let packet = packet::publish(
+                        self.tx_buffer,
+                        &message.topic,
+                        &message.payload,
+                        message.qos,
+                        message.retain,
+                        false,
+                        message.packet_id,
+                    )?;
+                    send(connection, packet, &mut self.last_sent).await?;
+                    match message.qos {
+                        QoS::AtMostOnce => PUBLISHED.signal(message.packet_id),
+                        QoS::AtLeastOnce => self.pending = Some(message),
+                    }
+                }
+                Either4::Third(()) => {}
+                Either4::Fourth(Either3::First(())) => {
+                    if self.ping_sent.is_some() {
+                        return Err(ConnectionError::Timeout);
+                    }
+                    let packet = packet::pingreq(self.tx_buffer)?;
+                    send(connection, packet, &mut self.last_sent).await?;
+                    self.ping_sent = Some(Instant::now());
+                }
+                Either4::Fourth(Either3::Second(())) => return Err(ConnectionError::NetworkDown),
+                Either4::Fourth(Either3::Third(())) => {
+                    let packet = packet::disconnect(self.tx_buffer)?;
+                    let _ = send(connection, packet, &mut self.last_sent).await;
+                    return Err(ConnectionError::Reconfigured);
+                }
+            }
+        }
+    }
+
+    async fn fill<C: Read>(&mut self, connection: &mut C) -> Result<(), ConnectionError> {
+        match self.receiver.fill(connection).await {
+            Ok(0) => Err(ConnectionError::Closed),
+            Ok(_) => Ok(()),
+            Err(_) => Err(ConnectionError::Io),
+        }
+    }
+
+    /// Handles the complete packets received, returning the return code of a CONNACK.
+    async fn handle_received<C: Write>(
+        &mut self,
+        connection: &mut C,
+    ) -> Result<Option<u8>, ConnectionError> {
+        let mut connack = None;
+
+        while let Some(len) = self.receiver.complete()? {
+            let mut puback = None;
+            match self.receiver.packet(len)? {
+                Packet::ConnAck { return_code } => connack = Some(return_code),
+                Packet::Publish {
+                    topic,
+                    packet_id,
+                    payload,
+                    retain,
+                } => {
+                    subscriptions::dispatch(topic, payload, retain);
+                    puback = packet_id;
+                }
+                Packet::PubAck { packet_id } => {
+                    if self
+                        .pending
+                        .as_ref()
+                        .is_some_and(|message| message.packet_id == packet_id)
+                    {
+                        self.pending = None;
+                        PUBLISHED.signal(packet_id);
+                    }
+                }
+                Packet::SubAck {
+                    packet_id,
+                    return_code,
+                } => {
+                    if return_code == SUBACK_FAILURE {
+                        warn!("MQTT broker refused subscription {}", packet_id);
+                    }
+                }
+                Packet::UnsubAck => {}
+                Packet::PingResp => self.ping_sent = None,
+            }
+            self.receiver.consume(len);
+
+            if let Some(packet_id) = puback {
+                let packet = packet::puback(self.tx_buffer, packet_id)?;
+                send(connection, packet, &mut self.last_sent).await?;
+            }
+        }
+
+        Ok(connack)
+    }
+}
+
+/// Sends `packet`, recording when it was sent.
+async fn send<C: Write>(
+    connection: &mut C,
+    packet: &[u8],
+    last_sent: &mut Instant,
+) -> Result<(), ConnectionError> {
+    connection
+        .write_all(packet)
+        .await
+        .map_err(|_| ConnectionError::Io)?;
+    // TLS connections only send records when flushed.
+    connection.flush().await.map_err(|_| ConnectionError::Io)?;
+    *last_sent = Instant::now();
+    Ok(())
+}
+
+/// Returns when `interface` loses its link or an address.
+async fn network_down(events: Option<&mut NetworkEventSubscriber>, interface: &str) {
+    let Some(events) = events else {
+        return core::future::pending().await;
+    };
+    loop {
+        let event = events.next_message_pure().await;
+        if event.interface == interface
+            && matches!(
+                event.event,
+                NetworkEvent::LinkDown | NetworkEvent::AddressLost(_)
+            )
+        {
+            return;
+        }
+    }
+}
@@ -0,0 +1,137 @@
+//! Configuration of the connection to the broker.
+
+#[cfg(feature = "tls")]
+use ariel_os_embassy::net::tls::Authentication;
+
+/// Port of MQTT brokers without TLS.
+pub const DEFAULT_PORT: u16 = 1883;
+/// Port of MQTT brokers with TLS.
+pub const DEFAULT_TLS_PORT: u16 = 8883;
+
+/// Default keep-alive interval, in seconds.
+const DEFAULT_KEEP_ALIVE_SECS: u16 = 60;
+
+/// Configuration of the connection to the MQTT broker.
+///
+/// The configuration is taken, in order of precedence:
+///
+/// 1. from storage, when set through `stored::set()` with the `storage` Cargo feature enabled,
+/// 2. from a constant provided through `#[ariel_os::config(mqtt)]`, with the
+///    `mqtt-config-override` Cargo feature enabled,
+/// 3. from the environment variables listed in [`Config::from_env()`].
+#[derive(Debug, Copy, Clone)]
+pub struct Config<'a> {
+    /// Host name or IP address of the broker.
+    pub broker: &'a str,
+    /// TCP port of the broker.
+    pub port: u16,
+    /// Client identifier, which the broker assigns if empty.
+    pub client_id: &'a str,
+    /// User name the client authenticates with.
+    pub username: Option<&'a str>,
+    /// Password the client authenticates with.
+    pub password: Option<&'a [u8]>,
+    /// Interval in seconds within which the client sends a packet, 0 to disable keep-alive.
+    pub keep_alive_secs: u16,
+    /// Connects over TLS when set, authenticating the broker as described.
+    #[cfg(feature = "tls")]
+    pub tls: Option<Authentication<'a>>,
+}
+
+impl<'a> Config<'a> {
+    /// Returns the configuration for `broker`, on [`DEFAULT_PORT`] without authentication.
+    #[must_use]
+    pub const fn new(broker: &'a str) -> Self {
+        Self {
+            broker,
+            port: DEFAULT_PORT,
+            client_id: "",
+            username: None,
+            password: None,
+            keep_alive_secs: DEFAULT_KEEP_ALIVE_SECS,
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
+}
+
+impl Config<'static> {
+    /// Returns the configuration set through environment variables at build time.
+    ///
+    /// | Variable                      | Default      |
+    /// | ----------------------------- | ------------ |
+    /// | `CONFIG_MQTT_BROKER`          | `10.42.0.1`  |
+    /// | `CONFIG_MQTT_BROKER_PORT`     | 1883         |
+    /// | `CONFIG_MQTT_CLIENT_ID`       | (assigned)   |
+    /// | `CONFIG_MQTT_USERNAME`        | (none)       |
+    /// | `CONFIG_MQTT_PASSWORD`        | (none)       |
+    /// | `CONFIG_MQTT_KEEP_ALIVE_SECS` | 60           |
+    #[must_use]
+    pub const fn from_env() -> Self {
+        const BROKER: &str = ariel_os_utils::str_from_env_or!(
+            "CONFIG_MQTT_BROKER",
+            "10.42.0.1",
+            "host name or IP address of the MQTT broker"
+        );
+        #[expect(
+            clippy::cast_possible_truncation,
+            reason = "port numbers are 16 bits long"
+        )]
+        const PORT: u16 = ariel_os_utils::u32_from_env_or!(
+            "CONFIG_MQTT_BROKER_PORT",
+            1883,
+            "TCP port of the MQTT broker"
+        ) as u16;
+        const USERNAME: &str =
+            ariel_os_utils::str_from_env_or!("CONFIG_MQTT_USERNAME", "", "MQTT user name");
+        const PASSWORD: &str =
+            ariel_os_utils::str_from_env_or!("CONFIG_MQTT_PASSWORD", "", "MQTT password");
+        #[expect(
+            clippy::cast_possible_truncation,
+            reason = "the keep-alive interval is 16 bits long"
+        )]
+        const KEEP_ALIVE_SECS: u16 = ariel_os_utils::u32_from_env_or!(
+            "CONFIG_MQTT_KEEP_ALIVE_SECS",
+            60,
+            "MQTT keep-alive interval in seconds"
+        ) as u16;
+
+        Self {
+            broker: BROKER,
+            port: PORT,
+            client_id: ariel_os_utils::str_from_env_or!(
+                "CONFIG_MQTT_CLIENT_ID",
+                "",
+                "MQTT client identifier"
+            ),
+            username: if USERNAME.is_empty() {
+                None
+            } else {
+                Some(USERNAME)
+            },
+            password: if PASSWORD.is_empty() {
+                None
+            } else {
+                Some(PASSWORD.as_bytes())
+            },
+            keep_alive_secs: KEEP_ALIVE_SECS,
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
+}
+
+/// Returns the configuration provided at build time.
+pub(crate) fn config() -> Config<'static> {
+    #[cfg(not(feature = "config-override"))]
+    {
+        Config::from_env()
+    }
+    #[cfg(feature = "config-override")]
+    {
+        unsafe extern "Rust" {
+            fn __ariel_os_mqtt_config() -> Config<'static>;
+        }
+        unsafe { __ariel_os_mqtt_config() }
+    }
+}
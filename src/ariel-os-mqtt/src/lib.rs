@@ -0,0 +1,200 @@
+//! An MQTT client for Ariel OS.
+//!
+//! The client connects to a single MQTT 3.1.1 broker over TCP from the primary network interface,
+//! optionally over TLS when the `tls` Cargo feature is enabled, and supports QoS 0 and 1.
+//! It runs in a task that is started automatically, which keeps the connection alive and
+//! reconnects when it is lost, including when the network goes down (see
+//! [`ariel_os_embassy::net::events`]).
+//! Subscriptions are sent again after reconnecting, and a QoS 1 message whose delivery was not
+//! acknowledged yet is sent again.
+//!
+//! Tasks publish messages through [`publish()`], and receive them through the [`Subscription`]s
+//! returned by [`subscribe()`]:
+//!
+//! ```ignore
+//! use ariel_os::mqtt::{self, QoS};
+//!
+//! let mut commands = mqtt::subscribe("devices/+/commands", QoS::AtLeastOnce)?;
+//! mqtt::publish("devices/status", b"online", QoS::AtLeastOnce, true).await?;
+//!
+//! loop {
+//!     let message = commands.next().await;
+//!     info!("{}: {:?}", message.topic(), message.payload());
+//! }
+//! ```
+//!
+//! The broker is configured through [`Config`].
+//!
+//! # Sizes
+//!
+//! Topics are limited to `CONFIG_MQTT_MAX_TOPIC_LEN` (default: 64) bytes, payloads to
+//! `CONFIG_MQTT_MAX_PAYLOAD_LEN` (default: 256) bytes, and at most
+//! `CONFIG_MQTT_MAX_SUBSCRIPTIONS` (default: 4) subscriptions can be active at the same time.
+//! Received messages that exceed these sizes are dropped.
+#![no_std]
+#![deny(missing_docs)]
+
+mod client;
+mod config;
+mod packet;
+#[cfg(feature = "storage")]
+pub mod stored;
+mod subscriptions;
+
+pub use config::{Config, DEFAULT_PORT, DEFAULT_TLS_PORT};
+pub use subscriptions::{Subscription, subscribe};
+
+use core::cell::Cell;
+
+use embassy_sync::{
+    blocking_mutex::{self, raw::CriticalSectionRawMutex},
+    channel::Channel,
+    mutex::Mutex,
+    signal::Signal,
+};
+
+/// Maximum length of topics and topic filters.
+pub const MAX_TOPIC_LEN: usize = ariel_os_utils::usize_from_env_or!(
+    "CONFIG_MQTT_MAX_TOPIC_LEN",
+    64,
+    "maximum length of MQTT topics"
+);
+
+/// Maximum length of message payloads.
+pub const MAX_PAYLOAD_LEN: usize = ariel_os_utils::usize_from_env_or!(
+    "CONFIG_MQTT_MAX_PAYLOAD_LEN",
+    256,
+    "maximum length of MQTT message payloads"
+);
+
+/// Maximum number of simultaneous subscriptions.
+pub const MAX_SUBSCRIPTIONS: usize = ariel_os_utils::usize_from_env_or!(
+    "CONFIG_MQTT_MAX_SUBSCRIPTIONS",
+    4,
+    "maximum number of simultaneous MQTT subscriptions"
+);
+
+/// Quality of service of the delivery of a message.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum QoS {
+    /// The message is delivered at most once, without acknowledgment.
+    AtMostOnce = 0,
+    /// The message is delivered at least once, and sent again until acknowledged.
+    AtLeastOnce = 1,
+}
+
+/// Error returned by the MQTT client.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The topic name or topic filter is invalid.
+    InvalidTopic,
+    /// A topic, payload, or configuration item exceeds its maximum length.
+    TooLong,
+    /// [`MAX_SUBSCRIPTIONS`] subscriptions are active already.
+    TooManySubscriptions,
+    /// The configuration could not be accessed in storage.
+    #[cfg(feature = "storage")]
+    Storage,
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::InvalidTopic => write!(f, "invalid topic"),
+            Self::TooLong => write!(f, "too long"),
+            Self::TooManySubscriptions => write!(f, "too many subscriptions"),
+            #[cfg(feature = "storage")]
+            Self::Storage => write!(f, "storage error"),
+        }
+    }
+}
+
+impl core::error::Error for Error {}
+
+/// A message received through a [`Subscription`].
+#[derive(Debug, Clone)]
+pub struct Message {
+    topic: heapless::String<MAX_TOPIC_LEN>,
+    payload: heapless::Vec<u8, MAX_PAYLOAD_LEN>,
+    retain: bool,
+}
+
+impl Message {
+    /// Returns the topic the message was published to.
+    #[must_use]
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    /// Returns the payload of the message.
+    #[must_use]
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    /// Whether the message was retained by the broker, i.e., published before subscribing.
+    #[must_use]
+    pub fn is_retained(&self) -> bool {
+        self.retain
+    }
+}
+
+/// A message to be published by the client task.
+pub(crate) struct Outgoing {
+    topic: heapless::String<MAX_TOPIC_LEN>,
+    payload: heapless::Vec<u8, MAX_PAYLOAD_LEN>,
+    qos: QoS,
+    retain: bool,
+    packet_id: u16,
+}
+
+/// Messages to be published, one at a time.
+static OUTGOING: Channel<CriticalSectionRawMutex, Outgoing, 1> = Channel::new();
+/// Signaled with the packet identifier of a message once it is published.
+static PUBLISHED: Signal<CriticalSectionRawMutex, u16> = Signal::new();
+/// Serializes publishing, so that [`PUBLISHED`] is only awaited by one task.
+static PUBLISH_LOCK: Mutex<CriticalSectionRawMutex, ()> = Mutex::new(());
+
+static LAST_PACKET_ID: blocking_mutex::Mutex<CriticalSectionRawMutex, Cell<u16>> =
+    blocking_mutex::Mutex::new(Cell::new(0));
+
+/// Returns a non-zero packet identifier that is not in use.
+pub(crate) fn next_packet_id() -> u16 {
+    LAST_PACKET_ID.lock(|last| {
+        let id = last.get().checked_add(1).unwrap_or(1);
+        last.set(id);
+        id
+    })
+}
+
+/// Publishes `payload` to `topic`.
+///
+/// With [`QoS::AtMostOnce`], this returns once the message is sent; with [`QoS::AtLeastOnce`],
+/// once its delivery is acknowledged by the broker.
+/// While the client is not connected, this waits until it is.
+/// When `retain` is set, the broker delivers the message to future subscribers as well.
+///
+/// # Errors
+///
+/// - Returns [`Error::InvalidTopic`] if `topic` is empty or contains wildcards.
+/// - Returns [`Error::TooLong`] if `topic` or `payload` exceed [`MAX_TOPIC_LEN`] or
+///   [`MAX_PAYLOAD_LEN`].
+pub async fn publish(topic: &str, payload: &[u8], qos: QoS, retain: bool) -> Result<(), Error> {
+    if topic.is_empty() || topic.contains(['+', '#']) {
+        return Err(Error::InvalidTopic);
+    }
+    let message = Outgoing {
+        topic: heapless::String::try_from(topic).map_err(|()| Error::TooLong)?,
+        payload: heapless::Vec::from_slice(payload).map_err(|()| Error::TooLong)?,
+        qos,
+        retain,
+        packet_id: next_packet_id(),
+    };
+    let packet_id = message.packet_id;
+
+    let _guard = PUBLISH_LOCK.lock().await;
+    OUTGOING.send(message).await;
+    // A previous call may have been cancelled before its message was published.
+    while PUBLISHED.wait().await != packet_id {}
+    Ok(())
+}
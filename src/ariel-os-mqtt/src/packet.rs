@@ -0,0 +1,649 @@
+//! Encoding and decoding of MQTT 3.1.1 control packets.
+//!
+//! Only the packets needed by a client limited to QoS 0 and 1 are supported.
+
+use ariel_os_debug::log::warn;
+use embedded_io_async::Read;
+
+use crate::QoS;
+
+/// Bytes reserved in front of encoded packets for the fixed header.
+const HEADER_RESERVE: usize = 5;
+
+/// Largest value of the remaining length of a packet.
+const MAX_REMAINING_LEN: usize = 268_435_455;
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const PUBACK: u8 = 0x40;
+const SUBSCRIBE: u8 = 0x82;
+const SUBACK: u8 = 0x90;
+const UNSUBSCRIBE: u8 = 0xa2;
+const UNSUBACK: u8 = 0xb0;
+const PINGREQ: u8 = 0xc0;
+const PINGRESP: u8 = 0xd0;
+const DISCONNECT: u8 = 0xe0;
+
+/// Protocol name and level of MQTT 3.1.1.
+const PROTOCOL: &[u8] = b"\x00\x04MQTT\x04";
+
+const FLAG_USERNAME: u8 = 0x80;
+const FLAG_PASSWORD: u8 = 0x40;
+const FLAG_CLEAN_SESSION: u8 = 0x02;
+
+const PUBLISH_DUP: u8 = 0x08;
+const PUBLISH_RETAIN: u8 = 0x01;
+
+/// A packet could not be encoded or decoded.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Error {
+    /// The packet does not fit into the buffer.
+    TooLong,
+    /// The received packet is malformed or unexpected.
+    Malformed,
+}
+
+/// Parameters of a CONNECT packet.
+pub(crate) struct Connect<'a> {
+    pub client_id: &'a str,
+    pub username: Option<&'a str>,
+    pub password: Option<&'a [u8]>,
+    pub keep_alive_secs: u16,
+}
+
+/// Writes a packet behind [`HEADER_RESERVE`] bytes, so that the fixed header can be prepended
+/// once the length is known.
+struct Encoder<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Encoder<'a> {
+    fn new(buffer: &'a mut [u8]) -> Self {
+        Self {
+            buffer,
+            len: HEADER_RESERVE,
+        }
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let end = self.len + bytes.len();
+        self.buffer
+            .get_mut(self.len..end)
+            .ok_or(Error::TooLong)?
+            .copy_from_slice(bytes);
+        self.len = end;
+        Ok(())
+    }
+
+    fn u8(&mut self, value: u8) -> Result<(), Error> {
+        self.bytes(&[value])
+    }
+
+    fn u16(&mut self, value: u16) -> Result<(), Error> {
+        self.bytes(&value.to_be_bytes())
+    }
+
+    /// Writes length-prefixed data, as used for strings and binary data.
+    fn data(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.u16(u16::try_from(data.len()).map_err(|_| Error::TooLong)?)?;
+        self.bytes(data)
+    }
+
+    /// Prepends the fixed header starting with `first`, returning the complete packet.
+    fn finish(self, first: u8) -> Result<&'a [u8], Error> {
+        let Self { buffer, len } = self;
+        let mut remaining = len - HEADER_RESERVE;
+        if remaining > MAX_REMAINING_LEN {
+            return Err(Error::TooLong);
+        }
+
+        let mut encoded = [0u8; 4];
+        let mut encoded_len = 0;
+        for byte in &mut encoded {
+            let [low, ..] = remaining.to_le_bytes();
+            *byte = low & 0x7f;
+            remaining >>= 7;
+            encoded_len += 1;
+            if remaining == 0 {
+                break;
+            }
+            *byte |= 0x80;
+        }
+
+        let start = HEADER_RESERVE - 1 - encoded_len;
+        let (header_first, header_length) = buffer
+            .get_mut(start..HEADER_RESERVE)
+            .and_then(<[u8]>::split_first_mut)
+            .ok_or(Error::TooLong)?;
+        *header_first = first;
+        header_length.copy_from_slice(encoded.get(..encoded_len).ok_or(Error::TooLong)?);
+
+        let buffer: &'a [u8] = buffer;
+        buffer.get(start..len).ok_or(Error::TooLong)
+    }
+}
+
+pub(crate) fn connect<'a>(buffer: &'a mut [u8], connect: &Connect<'_>) -> Result<&'a [u8], Error> {
+    let mut flags = FLAG_CLEAN_SESSION;
+    if connect.username.is_some() {
+        flags |= FLAG_USERNAME;
+    }
+    if connect.password.is_some() {
+        flags |= FLAG_PASSWORD;
+    }
+
+    let mut encoder = Encoder::new(buffer);
+    encoder.bytes(PROTOCOL)?;
+    encoder.u8(flags)?;
+    encoder.u16(connect.keep_alive_secs)?;
+    encoder.data(connect.client_id.as_bytes())?;
+    if let Some(username) = connect.username {
+        encoder.data(username.as_bytes())?;
+    }
+    if let Some(password) = connect.password {
+        encoder.data(password)?;
+    }
+    encoder.finish(CONNECT)
+}
+
+/// Encodes a PUBLISH packet; `packet_id` is only sent for QoS 1.
+pub(crate) fn publish<'a>(
+    buffer: &'a mut [u8],
+    topic: &str,
+    payload: &[u8],
+    qos: QoS,
+    retain: bool,
+    dup: bool,
+    packet_id: u16,
+) -> Result<&'a [u8], Error> {
+    let mut first = PUBLISH | ((qos as u8) << 1);
+    if retain {
+        first |= PUBLISH_RETAIN;
+    }
+    if dup {
+        first |= PUBLISH_DUP;
+    }
+
+    let mut encoder = Encoder::new(buffer);
+    encoder.data(topic.as_bytes())?;
+    if qos == QoS::AtLeastOnce {
+        encoder.u16(packet_id)?;
+    }
+    encoder.bytes(payload)?;
+    encoder.finish(first)
+}
+
+pub(crate) fn puback(buffer: &mut [u8], packet_id: u16) -> Result<&[u8], Error> {
+    let mut encoder = Encoder::new(buffer);
+    encoder.u16(packet_id)?;
+    encoder.finish(PUBACK)
+}
+
+pub(crate) fn subscribe<'a>(
+    buffer: &'a mut [u8],
+    packet_id: u16,
+    filter: &str,
+    qos: QoS,
+) -> Result<&'a [u8], Error> {
+    let mut encoder = Encoder::new(buffer);
+    encoder.u16(packet_id)?;
+    encoder.data(filter.as_bytes())?;
+    encoder.u8(qos as u8)?;
+    encoder.finish(SUBSCRIBE)
+}
+
+pub(crate) fn unsubscribe<'a>(
+    buffer: &'a mut [u8],
+    packet_id: u16,
+    filter: &str,
+) -> Result<&'a [u8], Error> {
+    let mut encoder = Encoder::new(buffer);
+    encoder.u16(packet_id)?;
+    encoder.data(filter.as_bytes())?;
+    encoder.finish(UNSUBSCRIBE)
+}
+
+pub(crate) fn pingreq(buffer: &mut [u8]) -> Result<&[u8], Error> {
+    Encoder::new(buffer).finish(PINGREQ)
+}
+
+pub(crate) fn disconnect(buffer: &mut [u8]) -> Result<&[u8], Error> {
+    Encoder::new(buffer).finish(DISCONNECT)
+}
+
+/// A packet received from the broker.
+pub(crate) enum Packet<'a> {
+    ConnAck {
+        return_code: u8,
+    },
+    Publish {
+        topic: &'a str,
+        /// Present for QoS 1 and 2.
+        packet_id: Option<u16>,
+        payload: &'a [u8],
+        retain: bool,
+    },
+    PubAck {
+        packet_id: u16,
+    },
+    SubAck {
+        packet_id: u16,
+        return_code: u8,
+    },
+    UnsubAck,
+    PingResp,
+}
+
+impl<'a> Packet<'a> {
+    /// Decodes the packet with the fixed header starting with `first`, and the given `body`.
+    fn decode(first: u8, body: &'a [u8]) -> Result<Self, Error> {
+        let u16_at = |offset: usize| {
+            body.get(offset..offset + 2)
+                .and_then(|bytes| bytes.try_into().ok())
+                .map(u16::from_be_bytes)
+                .ok_or(Error::Malformed)
+        };
+
+        match first & 0xf0 {
+            CONNACK => Ok(Self::ConnAck {
+                return_code: *body.get(1).ok_or(Error::Malformed)?,
+            }),
+            PUBLISH => {
+                let topic_len = usize::from(u16_at(0)?);
+                let topic = body.get(2..2 + topic_len).ok_or(Error::Malformed)?;
+                let topic = core::str::from_utf8(topic).map_err(|_| Error::Malformed)?;
+                let mut offset = 2 + topic_len;
+                let packet_id = if (first >> 1) & 0x03 == 0 {
+                    None
+                } else {
+                    let packet_id = u16_at(offset)?;
+                    offset += 2;
+                    Some(packet_id)
+                };
+                Ok(Self::Publish {
+                    topic,
+                    packet_id,
+                    payload: body.get(offset..).ok_or(Error::Malformed)?,
+                    retain: first & PUBLISH_RETAIN != 0,
+                })
+            }
+            PUBACK => Ok(Self::PubAck {
+                packet_id: u16_at(0)?,
+            }),
+            SUBACK => Ok(Self::SubAck {
+                packet_id: u16_at(0)?,
+                return_code: *body.get(2).ok_or(Error::Malformed)?,
+            }),
+            UNSUBACK => Ok(Self::UnsubAck),
+            PINGRESP => Ok(Self::PingResp),
+            _ => Err(Error::Malformed),
+        }
+    }
+}
+
+/// Accumulates received bytes until complete packets are available.
+///
+/// Packets that do not fit into the buffer are skipped.
+pub(crate) struct Receiver<'a> {
+    buffer: &'a mut [u8],
+    filled: usize,
+    /// Bytes still to be skipped from a packet that does not fit into the buffer.
+    discard: usize,
+}
+
+impl<'a> Receiver<'a> {
+    pub(crate) fn new(buffer: &'a mut [u8]) -> Self {
+        Self {
+            buffer,
+            filled: 0,
+            discard: 0,
+        }
+    }
+
+    /// Reads the bytes available from `connection`, returning their number, which is 0 once the
+    /// connection is closed.
+    ///
+    /// This is cancel-safe as long as reading from `connection` is: bytes are only taken into
+    /// account once the read completes.
+    pub(crate) async fn fill<C: Read>(&mut self, connection: &mut C) -> Result<usize, C::Error> {
+        // The buffer is never full here, as a packet filling it is complete and consumed first.
+        let free = self.buffer.get_mut(self.filled..).unwrap_or_default();
+        let read = connection.read(free).await?;
+        self.filled += read;
+
+        let skipped = self.discard.min(self.filled);
+        self.consume(skipped);
+        self.discard -= skipped;
+        Ok(read)
+    }
+
+    /// Returns the length of the next complete packet, if any.
+    pub(crate) fn complete(&mut self) -> Result<Option<usize>, Error> {
+        let mut remaining = 0usize;
+        // The remaining length is encoded in at most four bytes following the first one.
+        for i in 0..4 {
+            let Some(byte) = self
+                .buffer
+                .get(..self.filled)
+                .and_then(|received| received.get(1 + i))
+                .copied()
+            else {
+                return Ok(None);
+            };
+            remaining |= usize::from(byte & 0x7f) << (7 * i);
+            if byte & 0x80 != 0 {
+                continue;
+            }
+
+            let len = 2 + i + remaining;
+            if len > self.buffer.len() {
+                warn!("Skipping MQTT packet of {} bytes", len);
+                let skipped = len.min(self.filled);
+                self.consume(skipped);
+                self.discard = len - skipped;
+                return Ok(None);
+            }
+            return Ok((len <= self.filled).then_some(len));
+        }
+        Err(Error::Malformed)
+    }
+
+    /// Decodes the complete packet of length `len` at the start of the buffer.
+    pub(crate) fn packet(&self, len: usize) -> Result<Packet<'_>, Error> {
+        let packet = self.buffer.get(..len).ok_or(Error::Malformed)?;
+        let (&first, rest) = packet.split_first().ok_or(Error::Malformed)?;
+        // The length of the remaining length field is that of its bytes with a continuation
+        // flag, plus one.
+        let length_len = rest.iter().take_while(|byte| *byte & 0x80 != 0).count() + 1;
+        Packet::decode(first, rest.get(length_len..).ok_or(Error::Malformed)?)
+    }
+
+    /// Removes the first `len` bytes from the buffer.
+    pub(crate) fn consume(&mut self, len: usize) {
+        self.buffer.copy_within(len..self.filled, 0);
+        self.filled -= len;
+    }
+
+    /// Drops all buffered bytes, e.g., when a new connection is established.
+    pub(crate) fn clear(&mut self) {
+        self.filled = 0;
+        self.discard = 0;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #![allow(clippy::indexing_slicing, reason = "panicking is fine in tests")]
+
+    use core::convert::Infallible;
+
+    use super::*;
+
+    /// Provides `data` in reads of at most `chunk` bytes.
+    struct Stream<'a> {
+        data: &'a [u8],
+        chunk: usize,
+    }
+
+    impl embedded_io_async::ErrorType for Stream<'_> {
+        type Error = Infallible;
+    }
+
+    impl Read for Stream<'_> {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Infallible> {
+            let len = self.chunk.min(buf.len()).min(self.data.len());
+            let (read, rest) = self.data.split_at(len);
+            buf[..len].copy_from_slice(read);
+            self.data = rest;
+            Ok(len)
+        }
+    }
+
+    /// Reads from `stream` until a complete packet is available, returning its length.
+    fn next_packet(receiver: &mut Receiver<'_>, stream: &mut Stream<'_>) -> Option<usize> {
+        loop {
+            if let Some(len) = receiver.complete().unwrap() {
+                return Some(len);
+            }
+            let Ok(read) = embassy_futures::block_on(receiver.fill(stream));
+            if read == 0 {
+                return None;
+            }
+        }
+    }
+
+    #[test]
+    fn encode_connect() {
+        let mut buffer = [0; 64];
+        let packet = connect(
+            &mut buffer,
+            &Connect {
+                client_id: "ab",
+                username: Some("u"),
+                password: Some(b"p"),
+                keep_alive_secs: 60,
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            packet,
+            b"\x10\x14\x00\x04MQTT\x04\xc2\x00\x3c\x00\x02ab\x00\x01u\x00\x01p"
+        );
+
+        let packet = connect(
+            &mut buffer,
+            &Connect {
+                client_id: "",
+                username: None,
+                password: None,
+                keep_alive_secs: 0,
+            },
+        )
+        .unwrap();
+        assert_eq!(packet, b"\x10\x0c\x00\x04MQTT\x04\x02\x00\x00\x00\x00");
+    }
+
+    #[test]
+    fn encode_publish() {
+        let mut buffer = [0; 64];
+        let packet = publish(
+            &mut buffer,
+            "t",
+            b"hi",
+            QoS::AtLeastOnce,
+            true,
+            true,
+            0x1234,
+        )
+        .unwrap();
+        assert_eq!(packet, b"\x3b\x07\x00\x01t\x12\x34hi");
+
+        let packet = publish(
+            &mut buffer,
+            "t",
+            b"hi",
+            QoS::AtMostOnce,
+            false,
+            false,
+            0x1234,
+        )
+        .unwrap();
+        assert_eq!(packet, b"\x30\x05\x00\x01thi");
+    }
+
+    #[test]
+    fn encode_others() {
+        let mut buffer = [0; 64];
+        assert_eq!(
+            subscribe(&mut buffer, 7, "a/b", QoS::AtLeastOnce).unwrap(),
+            b"\x82\x08\x00\x07\x00\x03a/b\x01"
+        );
+        assert_eq!(
+            unsubscribe(&mut buffer, 7, "a/b").unwrap(),
+            b"\xa2\x07\x00\x07\x00\x03a/b"
+        );
+        assert_eq!(puback(&mut buffer, 0x0102).unwrap(), b"\x40\x02\x01\x02");
+        assert_eq!(pingreq(&mut buffer).unwrap(), b"\xc0\x00");
+        assert_eq!(disconnect(&mut buffer).unwrap(), b"\xe0\x00");
+    }
+
+    #[test]
+    fn encode_remaining_length() {
+        let mut buffer = [0; 256];
+        // 3 bytes of topic and 200 of payload take two bytes of remaining length.
+        let packet = publish(
+            &mut buffer,
+            "t",
+            &[0xaa; 200],
+            QoS::AtMostOnce,
+            false,
+            false,
+            0,
+        )
+        .unwrap();
+        assert_eq!(packet.len(), 206);
+        assert_eq!(packet[..6], *b"\x30\xcb\x01\x00\x01t");
+
+        let mut buffer = [0; 20];
+        assert_eq!(
+            publish(&mut buffer, "t", &[0; 16], QoS::AtMostOnce, false, false, 0),
+            Err(Error::TooLong)
+        );
+    }
+
+    #[test]
+    fn decode() {
+        let mut buffer = [0; 64];
+        let mut receiver = Receiver::new(&mut buffer);
+        let mut stream = Stream {
+            data: b"\x20\x02\x00\x05\
+                    \x32\x09\x00\x03a/b\x00\x2ahi\
+                    \x31\x05\x00\x01thi\
+                    \x40\x02\x00\x2a\
+                    \x90\x03\x00\x07\x01\
+                    \xb0\x02\x00\x07\
+                    \xd0\x00",
+            chunk: 3,
+        };
+
+        let len = next_packet(&mut receiver, &mut stream).unwrap();
+        assert!(matches!(
+            receiver.packet(len),
+            Ok(Packet::ConnAck { return_code: 5 })
+        ));
+        receiver.consume(len);
+
+        let len = next_packet(&mut receiver, &mut stream).unwrap();
+        assert!(matches!(
+            receiver.packet(len),
+            Ok(Packet::Publish {
+                topic: "a/b",
+                packet_id: Some(0x2a),
+                payload: b"hi",
+                retain: false,
+            })
+        ));
+        receiver.consume(len);
+
+        let len = next_packet(&mut receiver, &mut stream).unwrap();
+        assert!(matches!(
+            receiver.packet(len),
+            Ok(Packet::Publish {
+                topic: "t",
+                packet_id: None,
+                payload: b"hi",
+                retain: true,
+            })
+        ));
+        receiver.consume(len);
+
+        let len = next_packet(&mut receiver, &mut stream).unwrap();
+        assert!(matches!(
+            receiver.packet(len),
+            Ok(Packet::PubAck { packet_id: 0x2a })
+        ));
+        receiver.consume(len);
+
+        let len = next_packet(&mut receiver, &mut stream).unwrap();
+        assert!(matches!(
+            receiver.packet(len),
+            Ok(Packet::SubAck {
+                packet_id: 7,
+                return_code: 1
+            })
+        ));
+        receiver.consume(len);
+
+        let len = next_packet(&mut receiver, &mut stream).unwrap();
+        assert!(matches!(receiver.packet(len), Ok(Packet::UnsubAck)));
+        receiver.consume(len);
+
+        let len = next_packet(&mut receiver, &mut stream).unwrap();
+        assert!(matches!(receiver.packet(len), Ok(Packet::PingResp)));
+        receiver.consume(len);
+
+        assert_eq!(next_packet(&mut receiver, &mut stream), None);
+    }
+
+    #[test]
+    fn decode_malformed() {
+        // The topic is longer than the packet.
+        assert!(Packet::decode(PUBLISH, b"\x00\x05a/b").is_err());
+        // The topic is not UTF-8.
+        assert!(Packet::decode(PUBLISH, b"\x00\x01\xff").is_err());
+        // The packet identifier is missing.
+        assert!(Packet::decode(PUBLISH | 0x02, b"\x00\x01t").is_err());
+        assert!(Packet::decode(SUBACK, b"\x00\x07").is_err());
+        assert!(Packet::decode(CONNACK, b"\x00").is_err());
+        // Packets only sent by clients.
+        assert!(Packet::decode(SUBSCRIBE, b"\x00\x07\x00\x01t\x00").is_err());
+
+        // The remaining length takes at most four bytes.
+        let mut buffer = [0; 16];
+        let mut receiver = Receiver::new(&mut buffer);
+        let mut stream = Stream {
+            data: b"\x30\xff\xff\xff\xff\x01",
+            chunk: 16,
+        };
+        let Ok(_) = embassy_futures::block_on(receiver.fill(&mut stream));
+        assert_eq!(receiver.complete(), Err(Error::Malformed));
+    }
+
+    #[test]
+    fn skip_oversized() {
+        let mut buffer = [0; 8];
+        let mut receiver = Receiver::new(&mut buffer);
+        // A PUBLISH packet of 22 bytes, which does not fit, followed by a PINGRESP.
+        let mut data = [0; 24];
+        data[..4].copy_from_slice(b"\x30\x14\x00\x01");
+        data[22..].copy_from_slice(b"\xd0\x00");
+        let mut stream = Stream {
+            data: &data,
+            chunk: 5,
+        };
+
+        let len = next_packet(&mut receiver, &mut stream).unwrap();
+        assert!(matches!(receiver.packet(len), Ok(Packet::PingResp)));
+        receiver.consume(len);
+        assert_eq!(next_packet(&mut receiver, &mut stream), None);
+    }
+
+    #[test]
+    fn skip_oversized_buffered() {
+        let mut buffer = [0; 8];
+        let mut receiver = Receiver::new(&mut buffer);
+        // The rest of the oversized packet arrives together with the next one.
+        let mut data = [0; 14];
+        data[..2].copy_from_slice(b"\x30\x0a");
+        data[12..].copy_from_slice(b"\xd0\x00");
+        let mut stream = Stream {
+            data: &data,
+            chunk: 8,
+        };
+
+        let len = next_packet(&mut receiver, &mut stream).unwrap();
+        assert!(matches!(receiver.packet(len), Ok(Packet::PingResp)));
+    }
+}
@@ -0,0 +1,166 @@
+//! Broker configuration kept in storage.
+//!
+//! A configuration set through [`set()`] takes precedence over the one provided at build time,
+//! e.g., to provision devices after flashing; the client reconnects when it is set or cleared.
+
+use ariel_os_debug::log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::{Config, Error, client::RECONFIGURED};
+
+/// Maximum length of the host name or IP address of the broker.
+pub const MAX_BROKER_LEN: usize = 64;
+/// Maximum length of the client identifier, which all brokers are required to accept.
+pub const MAX_CLIENT_ID_LEN: usize = 23;
+/// Maximum length of the user name.
+pub const MAX_USERNAME_LEN: usize = 32;
+/// Maximum length of the password.
+pub const MAX_PASSWORD_LEN: usize = 64;
+
+// Items are stored separately to fit the size of storage values; the broker is written last and
+// cleared first, so that it is only found along with the other items.
+const BROKER_KEY: &str = "ariel-os-mqtt.broker";
+const CLIENT_ID_KEY: &str = "ariel-os-mqtt.client-id";
+const USERNAME_KEY: &str = "ariel-os-mqtt.username";
+const PASSWORD_KEY: &str = "ariel-os-mqtt.password";
+const OPTIONS_KEY: &str = "ariel-os-mqtt.options";
+
+type Broker = (heapless::String<MAX_BROKER_LEN>, u16);
+
+#[derive(Serialize, Deserialize)]
+struct Options {
+    keep_alive_secs: u16,
+    tls: bool,
+}
+
+/// A configuration read from storage.
+pub(crate) struct StoredConfig {
+    broker: Broker,
+    client_id: heapless::String<MAX_CLIENT_ID_LEN>,
+    username: Option<heapless::String<MAX_USERNAME_LEN>>,
+    password: Option<heapless::Vec<u8, MAX_PASSWORD_LEN>>,
+    options: Options,
+}
+
+impl StoredConfig {
+    pub(crate) fn config(&self) -> Config<'_> {
+        Config {
+            broker: &self.broker.0,
+            port: self.broker.1,
+            client_id: &self.client_id,
+            username: self.username.as_deref(),
+            password: self.password.as_deref(),
+            keep_alive_secs: self.options.keep_alive_secs,
+            #[cfg(feature = "tls")]
+            tls: self.options.tls.then_some(
+                ariel_os_embassy::net::tls::Authentication::StoredPinnedKeys { client: None },
+            ),
+        }
+    }
+}
+
+/// Stores `config`, which is used from the next connection on.
+///
+/// When `Config::tls` is set, the broker is authenticated through the keys pinned in storage
+/// (see `ariel_os_embassy::net::tls::pinned_keys`), whichever authentication it describes.
+///
+/// # Errors
+///
+/// - Returns [`Error::TooLong`] if an item exceeds its maximum length.
+/// - Returns [`Error::Storage`] if storing fails.
+pub async fn set(config: &Config<'_>) -> Result<(), Error> {
+    let too_long = |()| Error::TooLong;
+    let broker: Broker = (
+        heapless::String::try_from(config.broker).map_err(too_long)?,
+        config.port,
+    );
+    let client_id: heapless::String<MAX_CLIENT_ID_LEN> =
+        heapless::String::try_from(config.client_id).map_err(too_long)?;
+    let username: Option<heapless::String<MAX_USERNAME_LEN>> = config
+        .username
+        .map(heapless::String::try_from)
+        .transpose()
+        .map_err(too_long)?;
+    let password: Option<heapless::Vec<u8, MAX_PASSWORD_LEN>> = config
+        .password
+        .map(heapless::Vec::from_slice)
+        .transpose()
+        .map_err(too_long)?;
+    let options = Options {
+        keep_alive_secs: config.keep_alive_secs,
+        #[cfg(feature = "tls")]
+        tls: config.tls.is_some(),
+        #[cfg(not(feature = "tls"))]
+        tls: false,
+    };
+
+    insert(BROKER_KEY, None::<Broker>).await?;
+    insert(CLIENT_ID_KEY, client_id).await?;
+    insert(USERNAME_KEY, username).await?;
+    insert(PASSWORD_KEY, password).await?;
+    insert(OPTIONS_KEY, options).await?;
+    insert(BROKER_KEY, Some(broker)).await?;
+
+    RECONFIGURED.signal(());
+    Ok(())
+}
+
+/// Clears the stored configuration, so that the one provided at build time is used from the next
+/// connection on.
+///
+/// # Errors
+///
+/// Returns [`Error::Storage`] if storing fails.
+pub async fn clear() -> Result<(), Error> {
+    insert(BROKER_KEY, None::<Broker>).await?;
+    insert(PASSWORD_KEY, None::<heapless::Vec<u8, MAX_PASSWORD_LEN>>).await?;
+
+    RECONFIGURED.signal(());
+    Ok(())
+}
+
+/// Reads the stored configuration, if any.
+pub(crate) async fn load() -> Option<StoredConfig> {
+    match read().await {
+        Ok(config) => config,
+        Err(_) => {
+            warn!("Stored MQTT configuration is unreadable, ignoring it");
+            None
+        }
+    }
+}
+
+async fn read() -> Result<Option<StoredConfig>, Error> {
+    let Some(broker) = get::<Option<Broker>>(BROKER_KEY).await?.flatten() else {
+        return Ok(None);
+    };
+    let config = StoredConfig {
+        broker,
+        client_id: get(CLIENT_ID_KEY).await?.unwrap_or_default(),
+        username: get(USERNAME_KEY).await?.flatten(),
+        password: get(PASSWORD_KEY).await?.flatten(),
+        options: get(OPTIONS_KEY).await?.ok_or(Error::Storage)?,
+    };
+
+    if cfg!(not(feature = "tls")) && config.options.tls {
+        warn!("Stored MQTT configuration requires TLS, which is not enabled; ignoring it");
+        return Ok(None);
+    }
+    Ok(Some(config))
+}
+
+async fn insert<V>(key: &str, value: V) -> Result<(), Error>
+where
+    V: Serialize + for<'d> Deserialize<'d>,
+{
+    ariel_os_storage::insert(key, value)
+        .await
+        .map_err(|_| Error::Storage)
+}
+
+async fn get<V>(key: &str) -> Result<Option<V>, Error>
+where
+    V: Serialize + for<'d> Deserialize<'d>,
+{
+    ariel_os_storage::get(key).await.map_err(|_| Error::Storage)
+}
@@ -0,0 +1,360 @@
+//! Dispatches received messages to the tasks subscribed to their topic.
+//!
+//! Each [`Subscription`] owns one of `CONFIG_MQTT_MAX_SUBSCRIPTIONS` slots, which holds its topic
+//! filter and a queue of received messages.
+//! The client task sends the SUBSCRIBE and UNSUBSCRIBE packets as slots change, and again for all
+//! slots after reconnecting, as sessions are not kept by the broker.
+
+use core::cell::RefCell;
+
+use ariel_os_debug::log::warn;
+use embassy_sync::{
+    blocking_mutex::{Mutex, raw::CriticalSectionRawMutex},
+    channel::Channel,
+    signal::Signal,
+};
+
+use crate::{Error, MAX_PAYLOAD_LEN, MAX_SUBSCRIPTIONS, MAX_TOPIC_LEN, Message, QoS};
+
+/// Number of messages queued for each subscription.
+const QUEUE_LEN: usize = ariel_os_utils::usize_from_env_or!(
+    "CONFIG_MQTT_SUBSCRIPTION_QUEUE_LEN",
+    2,
+    "number of MQTT messages queued for each subscription"
+);
+
+pub(crate) type Filter = heapless::String<MAX_TOPIC_LEN>;
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum State {
+    /// The SUBSCRIBE packet is still to be sent.
+    Requested,
+    Subscribed,
+    /// The subscription was dropped; the UNSUBSCRIBE packet is still to be sent.
+    Dropped,
+}
+
+struct Entry {
+    filter: Filter,
+    qos: QoS,
+    state: State,
+}
+
+struct Slot {
+    entry: Mutex<CriticalSectionRawMutex, RefCell<Option<Entry>>>,
+    queue: Channel<CriticalSectionRawMutex, Message, QUEUE_LEN>,
+}
+
+impl Slot {
+    const fn new() -> Self {
+        Self {
+            entry: Mutex::new(RefCell::new(None)),
+            queue: Channel::new(),
+        }
+    }
+
+    fn with_entry<R>(&self, f: impl FnOnce(&mut Option<Entry>) -> R) -> R {
+        self.entry.lock(|entry| f(&mut entry.borrow_mut()))
+    }
+
+    /// Whether the slot holds a subscription to `filter` that was not dropped.
+    fn is_active_for(&self, filter: &str) -> bool {
+        self.with_entry(|entry| {
+            entry
+                .as_ref()
+                .is_some_and(|entry| entry.state != State::Dropped && entry.filter == filter)
+        })
+    }
+
+    /// Frees the slot, dropping the messages still queued.
+    fn free(&self) {
+        self.with_entry(|entry| *entry = None);
+        while self.queue.try_receive().is_ok() {}
+    }
+}
+
+static SLOTS: [Slot; MAX_SUBSCRIPTIONS] = [const { Slot::new() }; MAX_SUBSCRIPTIONS];
+
+/// Signaled when a subscription is added or dropped.
+pub(crate) static CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Receives the messages published to the topics matching a filter.
+///
+/// The client unsubscribes from the topic filter when this is dropped.
+pub struct Subscription {
+    slot: &'static Slot,
+}
+
+impl Subscription {
+    /// Waits for the next message.
+    ///
+    /// Messages arriving while `CONFIG_MQTT_SUBSCRIPTION_QUEUE_LEN` (default: 2) messages are
+    /// queued already are dropped.
+    pub async fn next(&mut self) -> Message {
+        self.slot.queue.receive().await
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.slot.with_entry(|entry| {
+            if let Some(entry) = entry {
+                entry.state = State::Dropped;
+            }
+        });
+        CHANGED.signal(());
+    }
+}
+
+/// Subscribes to the topics matching `filter`, which may contain the `+` and `#` wildcards.
+///
+/// Messages are delivered with at most the given `qos`.
+/// The subscription is sent to the broker in the background, and again after reconnecting.
+///
+/// # Errors
+///
+/// - Returns [`Error::InvalidTopic`] if `filter` is not a valid topic filter.
+/// - Returns [`Error::TooLong`] if `filter` is longer than `CONFIG_MQTT_MAX_TOPIC_LEN`.
+/// - Returns [`Error::TooManySubscriptions`] if `CONFIG_MQTT_MAX_SUBSCRIPTIONS` subscriptions are
+///   active already.
+pub fn subscribe(filter: &str, qos: QoS) -> Result<Subscription, Error> {
+    if !is_valid_filter(filter) {
+        return Err(Error::InvalidTopic);
+    }
+    let filter = Filter::try_from(filter).map_err(|()| Error::TooLong)?;
+
+    let mut new_entry = Some(Entry {
+        filter,
+        qos,
+        state: State::Requested,
+    });
+    let slot = SLOTS
+        .iter()
+        .find(|slot| {
+            slot.with_entry(|entry| {
+                if entry.is_none() {
+                    *entry = new_entry.take();
+                    true
+                } else {
+                    false
+                }
+            })
+        })
+        .ok_or(Error::TooManySubscriptions)?;
+
+    CHANGED.signal(());
+    Ok(Subscription { slot })
+}
+
+/// A change of subscription to be sent to the broker.
+pub(crate) enum Change {
+    Subscribe(Filter, QoS),
+    Unsubscribe(Filter),
+}
+
+/// Returns the next change to be sent, considering it sent.
+pub(crate) fn next_change() -> Option<Change> {
+    next_change_in(&SLOTS)
+}
+
+fn next_change_in(slots: &[Slot]) -> Option<Change> {
+    for slot in slots {
+        let change = slot.with_entry(|entry| match entry {
+            Some(entry) if entry.state == State::Requested => {
+                entry.state = State::Subscribed;
+                Some(Change::Subscribe(entry.filter.clone(), entry.qos))
+            }
+            Some(entry) if entry.state == State::Dropped => {
+                Some(Change::Unsubscribe(entry.filter.clone()))
+            }
+            _ => None,
+        });
+        match change {
+            Some(Change::Unsubscribe(filter)) => {
+                slot.free();
+                // The broker keeps a single subscription per filter, which other slots may still
+                // use.
+                if !slots.iter().any(|other| other.is_active_for(&filter)) {
+                    return Some(Change::Unsubscribe(filter));
+                }
+            }
+            Some(change) => return Some(change),
+            None => {}
+        }
+    }
+    None
+}
+
+/// Prepares the subscriptions for a new session, in which none are known to the broker.
+pub(crate) fn reset() {
+    for slot in &SLOTS {
+        let dropped = slot.with_entry(|entry| match entry {
+            Some(entry) if entry.state == State::Dropped => true,
+            Some(entry) => {
+                entry.state = State::Requested;
+                false
+            }
+            None => false,
+        });
+        if dropped {
+            slot.free();
+        }
+    }
+}
+
+/// Queues a received message for all subscriptions matching its topic.
+pub(crate) fn dispatch(topic: &str, payload: &[u8], retain: bool) {
+    let (Ok(topic_name), Ok(payload)) = (
+        heapless::String::<MAX_TOPIC_LEN>::try_from(topic),
+        heapless::Vec::<u8, MAX_PAYLOAD_LEN>::from_slice(payload),
+    ) else {
+        warn!("Dropping MQTT message on {}: too long", topic);
+        return;
+    };
+    let message = Message {
+        topic: topic_name,
+        payload,
+        retain,
+    };
+
+    for slot in &SLOTS {
+        let matches = slot.with_entry(|entry| {
+            entry.as_ref().is_some_and(|entry| {
+                entry.state != State::Dropped && filter_matches(&entry.filter, topic)
+            })
+        });
+        if matches && slot.queue.try_send(message.clone()).is_err() {
+            warn!("Dropping MQTT message on {}: queue full", topic);
+        }
+    }
+}
+
+/// Whether `topic` matches `filter`.
+fn filter_matches(filter: &str, topic: &str) -> bool {
+    // Wildcards do not match topics starting with `$`, which are reserved for the broker.
+    if topic.starts_with('$') && (filter.starts_with('+') || filter.starts_with('#')) {
+        return false;
+    }
+
+    let mut topic_levels = topic.split('/');
+    for filter_level in filter.split('/') {
+        match (filter_level, topic_levels.next()) {
+            // `#` also matches the parent level, e.g., `a/#` matches `a`.
+            ("#", _) => return true,
+            ("+", Some(_)) => {}
+            (filter_level, Some(topic_level)) if filter_level == topic_level => {}
+            _ => return false,
+        }
+    }
+    topic_levels.next().is_none()
+}
+
+/// Whether `filter` is a valid topic filter: `+` has to occupy an entire level, and `#` the entire
+/// last level.
+fn is_valid_filter(filter: &str) -> bool {
+    let mut levels = filter.split('/').peekable();
+    while let Some(level) = levels.next() {
+        let is_last = levels.peek().is_none();
+        let valid = match level {
+            "+" => true,
+            "#" => is_last,
+            level => !level.contains(['+', '#']),
+        };
+        if !valid {
+            return false;
+        }
+    }
+    !filter.is_empty()
+}
+
+#[cfg(test)]
+mod test {
+    #![allow(clippy::indexing_slicing, reason = "panicking is fine in tests")]
+
+    use super::*;
+
+    fn slots(entries: &[(&str, State)]) -> [Slot; 4] {
+        let slots = [const { Slot::new() }; 4];
+        for (slot, &(filter, state)) in slots.iter().zip(entries) {
+            slot.with_entry(|entry| {
+                *entry = Some(Entry {
+                    filter: Filter::try_from(filter).unwrap(),
+                    qos: QoS::AtMostOnce,
+                    state,
+                });
+            });
+        }
+        slots
+    }
+
+    fn is_unsubscribe(change: Option<Change>, expected: &str) -> bool {
+        matches!(change, Some(Change::Unsubscribe(filter)) if filter == expected)
+    }
+
+    #[test]
+    fn changes() {
+        let slots = slots(&[("a", State::Requested), ("b", State::Dropped)]);
+        assert!(matches!(
+            next_change_in(&slots),
+            Some(Change::Subscribe(filter, QoS::AtMostOnce)) if filter == "a"
+        ));
+        assert!(is_unsubscribe(next_change_in(&slots), "b"));
+        assert!(next_change_in(&slots).is_none());
+
+        assert!(slots[0].is_active_for("a"));
+        assert!(slots[1].with_entry(|entry| entry.is_none()));
+    }
+
+    #[test]
+    fn shared_filter() {
+        let slots = slots(&[
+            ("a/+", State::Dropped),
+            ("a/+", State::Subscribed),
+            ("b", State::Dropped),
+        ]);
+        // The first slot is freed without unsubscribing, as the second one still uses the filter.
+        assert!(is_unsubscribe(next_change_in(&slots), "b"));
+        assert!(next_change_in(&slots).is_none());
+        assert!(slots[0].with_entry(|entry| entry.is_none()));
+
+        slots[1].with_entry(|entry| entry.as_mut().unwrap().state = State::Dropped);
+        assert!(is_unsubscribe(next_change_in(&slots), "a/+"));
+        assert!(next_change_in(&slots).is_none());
+    }
+
+    #[test]
+    fn shared_filter_requested() {
+        let slots = slots(&[("a", State::Requested), ("a", State::Dropped)]);
+        assert!(matches!(
+            next_change_in(&slots),
+            Some(Change::Subscribe(filter, _)) if filter == "a"
+        ));
+        assert!(next_change_in(&slots).is_none());
+        assert!(slots[0].is_active_for("a"));
+    }
+
+    #[test]
+    fn matching() {
+        assert!(filter_matches("a/b", "a/b"));
+        assert!(!filter_matches("a/b", "a/c"));
+        assert!(!filter_matches("a/b", "a/b/c"));
+        assert!(filter_matches("a/+/c", "a/b/c"));
+        assert!(!filter_matches("a/+", "a"));
+        assert!(filter_matches("a/#", "a"));
+        assert!(filter_matches("a/#", "a/b/c"));
+        assert!(filter_matches("#", "a/b"));
+        assert!(!filter_matches("#", "$SYS/uptime"));
+        assert!(!filter_matches("+/uptime", "$SYS/uptime"));
+        assert!(filter_matches("$SYS/#", "$SYS/uptime"));
+    }
+
+    #[test]
+    fn valid_filters() {
+        for filter in ["a", "a/b", "+", "#", "a/+/c", "a/#", "/", "+/+"] {
+            assert!(is_valid_filter(filter), "{filter}");
+        }
+        for filter in ["", "a+", "a/#/c", "a/b#", "#/a"] {
+            assert!(!is_valid_filter(filter), "{filter}");
+        }
+    }
+}
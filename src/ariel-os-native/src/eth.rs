@@ -0,0 +1,270 @@
+//! Provides a network device backed by a Linux TAP or TUN interface.
+//!
+//! The interface named in `CONFIG_NATIVE_TUNTAP_INTERFACE` needs to exist and be accessible to
+//! the user running the application, e.g. after
+//!
+//! ```sh
+//! sudo ip tuntap add dev tap0 mode tap user $USER
+//! sudo ip link set tap0 up
+//! ```
+//!
+//! By default, it is used as a TAP interface that carries Ethernet frames; with
+//! `CONFIG_NATIVE_TUNTAP_TUN` set to `true`, it is used as a TUN interface that carries IP
+//! packets.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{Read as _, Write as _};
+use std::os::fd::AsRawFd as _;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use ariel_os_debug::log::{error, info};
+use embassy_net_driver::{Capabilities, HardwareAddress, LinkState, Medium};
+use embassy_sync::waitqueue::AtomicWaker;
+
+/// Name of the interface to attach to.
+const INTERFACE: &str = ariel_os_utils::str_from_env_or!(
+    "CONFIG_NATIVE_TUNTAP_INTERFACE",
+    "tap0",
+    "name of the TAP or TUN interface used as network device"
+);
+
+/// Whether the interface is a TUN (rather than a TAP) interface.
+const TUN: bool = ariel_os_utils::bool_from_env_or!(
+    "CONFIG_NATIVE_TUNTAP_TUN",
+    false,
+    "whether the network interface is a TUN interface carrying IP packets"
+);
+
+/// Maximum size of an IP packet on the interface.
+const IP_MTU: usize = 1500;
+/// Length of the Ethernet header preceding the IP packet on a TAP interface.
+const ETHERNET_HEADER_LEN: usize = 14;
+
+/// Number of received frames that are queued until the network stack processes them.
+const RX_QUEUE_LEN: usize = 8;
+
+// Values from `linux/if_tun.h`.
+const TUNSETIFF: libc::c_ulong = 0x4004_54ca;
+const IFF_TUN: libc::c_short = 0x0001;
+const IFF_TAP: libc::c_short = 0x0002;
+const IFF_NO_PI: libc::c_short = 0x1000;
+
+/// The `struct ifreq` of `linux/if.h`, reduced to the members used with `TUNSETIFF`.
+#[repr(C)]
+struct IfReq {
+    name: [u8; libc::IFNAMSIZ],
+    flags: libc::c_short,
+    _padding: [u8; 22],
+}
+
+pub type NetworkDevice = TunTapDevice;
+
+/// Opens the configured interface, using `mac_addr` as its MAC address.
+///
+/// # Panics
+///
+/// Panics if the interface can not be opened.
+pub fn device(_peripherals: &mut crate::OptionalPeripherals, mac_addr: [u8; 6]) -> NetworkDevice {
+    TunTapDevice::new(INTERFACE, TUN, mac_addr).unwrap_or_else(|e| {
+        panic!("network interface {INTERFACE} could not be opened: {e}");
+    })
+}
+
+/// Frames received from the interface, along with the waker of the network stack.
+struct Received {
+    frames: Mutex<VecDeque<Vec<u8>>>,
+    waker: AtomicWaker,
+}
+
+/// A network device backed by a Linux TAP or TUN interface.
+///
+/// Frames are read from the interface in a dedicated thread; sending happens directly from the
+/// network stack.
+pub struct TunTapDevice {
+    file: File,
+    medium: Medium,
+    mac_addr: [u8; 6],
+    received: Arc<Received>,
+}
+
+impl TunTapDevice {
+    /// Attaches to the interface `name`.
+    ///
+    /// The `mac_addr` is only used on TAP interfaces.
+    ///
+    /// # Errors
+    ///
+    /// This produces an error if the interface can not be opened or attached to.
+    pub fn new(name: &str, tun: bool, mac_addr: [u8; 6]) -> std::io::Result<Self> {
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/net/tun")?;
+
+        let mut request = IfReq {
+            name: [0; libc::IFNAMSIZ],
+            flags: (if tun { IFF_TUN } else { IFF_TAP }) | IFF_NO_PI,
+            _padding: [0; 22],
+        };
+        // The name needs to be nul terminated.
+        request
+            .name
+            .get_mut(..name.len())
+            .filter(|_| name.len() < libc::IFNAMSIZ)
+            .ok_or(std::io::ErrorKind::InvalidInput)?
+            .copy_from_slice(name.as_bytes());
+        // SAFETY: `TUNSETIFF` reads and writes an `ifreq`, of which `IfReq` has the layout and
+        // size; the file descriptor is valid for the duration of the call.
+        if unsafe { libc::ioctl(file.as_raw_fd(), TUNSETIFF, &raw mut request) } < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        let received = Arc::new(Received {
+            frames: Mutex::new(VecDeque::with_capacity(RX_QUEUE_LEN)),
+            waker: AtomicWaker::new(),
+        });
+
+        let mut reader = file.try_clone()?;
+        let receiving = received.clone();
+        let mtu = if tun {
+            IP_MTU
+        } else {
+            IP_MTU + ETHERNET_HEADER_LEN
+        };
+        std::thread::Builder::new()
+            .name("tuntap-rx".into())
+            .spawn(move || {
+                let mut buffer = vec![0; mtu];
+                loop {
+                    match reader.read(&mut buffer) {
+                        Ok(len) => {
+                            let mut frames = receiving
+                                .frames
+                                .lock()
+                                .unwrap_or_else(PoisonError::into_inner);
+                            // Like a full hardware queue, this drops frames the stack does not
+                            // keep up with.
+                            if let Some(frame) =
+                                buffer.get(..len).filter(|_| frames.len() < RX_QUEUE_LEN)
+                            {
+                                frames.push_back(frame.to_vec());
+                            }
+                            drop(frames);
+                            receiving.waker.wake();
+                        }
+                        Err(e) => {
+                            error!("Reading from the network interface failed: {}", e);
+                            return;
+                        }
+                    }
+                }
+            })?;
+
+        info!(
+            "Using {} interface {}.",
+            if tun { "TUN" } else { "TAP" },
+            name
+        );
+
+        Ok(Self {
+            file,
+            medium: if tun { Medium::Ip } else { Medium::Ethernet },
+            mac_addr,
+            received,
+        })
+    }
+}
+
+impl embassy_net_driver::Driver for TunTapDevice {
+    type RxToken<'a>
+        = RxToken
+    where
+        Self: 'a;
+
+    type TxToken<'a>
+        = TxToken<'a>
+    where
+        Self: 'a;
+
+    fn receive(
+        &mut self,
+        cx: &mut core::task::Context<'_>,
+    ) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        // Registering before checking, so that a frame arriving in between wakes the stack.
+        self.received.waker.register(cx.waker());
+        let frame = self
+            .received
+            .frames
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .pop_front()?;
+        Some((
+            RxToken { frame },
+            TxToken {
+                file: &mut self.file,
+            },
+        ))
+    }
+
+    fn transmit(&mut self, _cx: &mut core::task::Context<'_>) -> Option<Self::TxToken<'_>> {
+        // Writes to the interface do not block.
+        Some(TxToken {
+            file: &mut self.file,
+        })
+    }
+
+    fn link_state(&mut self, _cx: &mut core::task::Context<'_>) -> LinkState {
+        LinkState::Up
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        let mut capabilities = Capabilities::default();
+        capabilities.medium = self.medium;
+        capabilities.max_transmission_unit = match self.medium {
+            Medium::Ethernet => IP_MTU + ETHERNET_HEADER_LEN,
+            _ => IP_MTU,
+        };
+        capabilities
+    }
+
+    fn hardware_address(&self) -> HardwareAddress {
+        match self.medium {
+            Medium::Ethernet => HardwareAddress::Ethernet(self.mac_addr),
+            _ => HardwareAddress::Ip,
+        }
+    }
+}
+
+#[doc(hidden)]
+pub struct RxToken {
+    frame: Vec<u8>,
+}
+
+impl embassy_net_driver::RxToken for RxToken {
+    fn consume<R, F>(mut self, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        f(&mut self.frame)
+    }
+}
+
+#[doc(hidden)]
+pub struct TxToken<'a> {
+    file: &'a mut File,
+}
+
+impl embassy_net_driver::TxToken for TxToken<'_> {
+    fn consume<R, F>(self, len: usize, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        let mut buffer = vec![0; len];
+        let result = f(&mut buffer);
+        if let Err(e) = self.file.write_all(&buffer) {
+            error!("Writing to the network interface failed: {}", e);
+        }
+        result
+    }
+}
@@ -2,6 +2,9 @@
 
 #![cfg_attr(nightly, feature(doc_auto_cfg))]
 
+#[cfg(feature = "eth")]
+pub mod eth;
+
 #[cfg(feature = "hwrng")]
 pub mod hwrng;
 
@@ -25,24 +25,40 @@ pub type NetworkDevice = cyw43::NetDriver<'static>;
 
 static STATE: StaticCell<cyw43::State> = StaticCell::new();
 
+/// The CYW43 Wi-Fi interface, joining networks as a station.
 #[cfg(feature = "wifi")]
-pub async fn join(mut control: cyw43::Control<'static>) {
-    use ariel_os_debug::log::info;
-    loop {
-        //control.join_open(WIFI_NETWORK).await;
-        match control
-            .join(
-                crate::wifi::WIFI_NETWORK,
-                JoinOptions::new(crate::wifi::WIFI_PASSWORD.as_bytes()),
-            )
-            .await
-        {
-            Ok(_) => {
-                info!("Wifi connected!");
-                break;
+pub struct WifiStation(Control<'static>);
+
+#[cfg(feature = "wifi")]
+impl WifiStation {
+    #[must_use]
+    pub fn new(control: Control<'static>) -> Self {
+        Self(control)
+    }
+}
+
+#[cfg(feature = "wifi")]
+impl ariel_os_embassy_common::wifi::Station for WifiStation {
+    async fn join(&mut self, network: &ariel_os_embassy_common::wifi::Credentials) -> bool {
+        use ariel_os_debug::log::info;
+
+        let options = if network.is_open() {
+            JoinOptions::new_open()
+        } else {
+            JoinOptions::new(network.password().as_bytes())
+        };
+        match self.0.join(network.ssid(), options).await {
+            Ok(()) => {
+                info!("Wifi connected to {}!", network.ssid());
+                true
             }
             Err(err) => {
-                info!(" Wifi join failed with status={}", err.status);
+                info!(
+                    " Wifi join of {} failed with status={}",
+                    network.ssid(),
+                    err.status
+                );
+                false
             }
         }
     }
@@ -10,11 +10,9 @@ bind_interrupts!(struct Irqs {
 
 pub type NetworkDevice = Ethernet<'static, ETH, GenericSMI>;
 
-pub fn device(peripherals: &mut crate::OptionalPeripherals) -> NetworkDevice {
+pub fn device(peripherals: &mut crate::OptionalPeripherals, mac_addr: [u8; 6]) -> NetworkDevice {
     static PKTS: StaticCell<eth::PacketQueue<4, 4>> = StaticCell::new();
 
-    let mac_addr = [0xCA, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC];
-
     Ethernet::new(
         PKTS.init(eth::PacketQueue::<4, 4>::new()),
         peripherals.ETH.take().unwrap(),
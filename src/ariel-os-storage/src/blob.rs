@@ -0,0 +1,1111 @@
+//! Storage of values larger than a single record, accessed as byte streams.
+//!
+//! A blob is split into chunks of at most [`CHUNK_SIZE`] bytes, each of which is stored as a
+//! separate record.
+//! A header record stored under the blob's key itself records the total length and which set of
+//! chunk records is current.
+//! Blobs are written to [`BlobWriter`]s and read from [`BlobReader`]s, which implement the
+//! [`embedded_io_async`] traits.
+//!
+//! Writing a blob only replaces the previous value once [`BlobWriter::finish()`] succeeds: the
+//! header record is written last, and writing a single record is atomic.
+//! If a write is interrupted (by a power failure, or by dropping the writer), the previous value
+//! stays readable, and the chunks left over by the interrupted write are removed when the next
+//! writer is created for the same key.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use embedded_io_async::{Read as _, Write as _};
+//!
+//! let mut writer = storage::blob::writer("certificate").await?;
+//! writer.write_all(CERTIFICATE).await?;
+//! writer.finish().await?;
+//!
+//! let mut reader = storage::blob::reader("certificate").await?.unwrap();
+//! let mut buf = [0; 256];
+//! let len = reader.read(&mut buf).await?;
+//! ```
+//!
+//! # Encryption
+//!
+//! With the `encrypted` feature, blobs can also be stored with authenticated encryption under the
+//! key of the `encrypted` module, through `encrypted_writer()`, `encrypted_reader()` and
+//! `encrypted_remove()`.
+//! Each chunk record is encrypted on its own, with its storage key as associated data, and the
+//! header record is encrypted as well.
+//! Every write of a blob picks a random ID that is part of the nonces of its records, and that is
+//! stored in the header; so neither can chunk records be modified, reordered, dropped or taken
+//! from an earlier write of the blob, nor can the header be modified, without being detected.
+//! Such records fail authentication with [`Error::Tampered`].
+//!
+//! Chunk records of encrypted blobs hold [`CHUNK_SIZE`] bytes including a 16-byte tag, so the
+//! length limit of their keys is the same as for plain blobs.
+//! Encrypted blobs can only be accessed through the `encrypted_*` functions; [`len()`] does not
+//! apply to them.
+//!
+//! <div class="warning">
+//! Blob keys share the key space of regular values.
+//! The header record is not meant to be accessed through [`crate::get()`].
+//! </div>
+//!
+//! <div class="warning">
+//! On STM32, records can not be removed, so chunk records that are not current any more stay in
+//! storage until they are overwritten by a later write of the same key.
+//! As the two chunk key sets alternate, this keeps up to one outdated copy of the largest blob
+//! ever written under each key, and `remove()` is not available.
+//! </div>
+
+use core::fmt::Write as _;
+
+use arrayvec::ArrayString;
+#[cfg(feature = "encrypted")]
+use chacha20poly1305::ChaCha20Poly1305;
+use serde::{Deserialize, Serialize};
+
+use crate::{DATA_BUFFER_SIZE, FlashError, MAX_KEY_LEN, lock, storage::KEY_OVERHEAD};
+
+/// Maximum number of bytes stored in a single chunk record.
+pub const CHUNK_SIZE: usize = 64;
+
+/// Length of the longest suffix appended to a blob key to form a chunk key.
+///
+/// The suffix has the shape `#{generation}{index:x}`.
+const CHUNK_KEY_SUFFIX_LEN: usize = 2 + 8;
+
+/// Maximum length of a blob key.
+///
+/// This is shorter than [`MAX_KEY_LEN`] because chunk records need to fit their key and data
+/// into [`DATA_BUFFER_SIZE`].
+pub const MAX_BLOB_KEY_LEN: usize =
+    DATA_BUFFER_SIZE - CHUNK_SIZE - CHUNK_KEY_SUFFIX_LEN - KEY_OVERHEAD;
+
+const _: () = assert!(MAX_BLOB_KEY_LEN + CHUNK_KEY_SUFFIX_LEN <= MAX_KEY_LEN);
+
+/// Header record stored under the blob's key.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+struct Header {
+    /// Total length of the blob in bytes.
+    len: u32,
+    /// Number of chunk records the blob is stored in.
+    chunks: u32,
+    /// Which of the two chunk key sets is current (0 or 1).
+    generation: u8,
+}
+
+/// Header record of an encrypted blob, which is stored encrypted under the blob's key.
+#[cfg(feature = "encrypted")]
+#[derive(Serialize, Deserialize, Clone, Copy)]
+struct SealedHeader {
+    header: Header,
+    /// The random ID of the write that produced the current chunks.
+    write_id: u64,
+}
+
+/// Record index through which the header's nonce is told apart from those of the chunks.
+#[cfg(feature = "encrypted")]
+const HEADER_INDEX: u32 = u32::MAX;
+
+/// How the records of a blob are protected.
+#[derive(Clone, Copy)]
+enum Sealing {
+    /// Records are stored as they are.
+    Plain,
+    /// Records are encrypted and authenticated, see the [module level documentation][self].
+    #[cfg(feature = "encrypted")]
+    Encrypted {
+        cipher: &'static ChaCha20Poly1305,
+        /// ID of the write that produces (or produced) the chunk records.
+        write_id: u64,
+    },
+}
+
+impl Sealing {
+    /// Returns how many bytes of the blob a chunk record holds.
+    fn chunk_capacity(self) -> usize {
+        match self {
+            Self::Plain => CHUNK_SIZE,
+            #[cfg(feature = "encrypted")]
+            Self::Encrypted { .. } => CHUNK_SIZE - crate::encrypted::CHUNK_OVERHEAD,
+        }
+    }
+
+    /// Protects the first `len` bytes of `chunk` in place, and returns the length of the record.
+    fn seal(self, chunk_key: &str, index: u32, chunk: &mut [u8; CHUNK_SIZE], len: usize) -> usize {
+        #[cfg(not(feature = "encrypted"))]
+        let _ = (chunk_key, index, chunk);
+        match self {
+            Self::Plain => len,
+            #[cfg(feature = "encrypted")]
+            Self::Encrypted { cipher, write_id } => crate::encrypted::seal_chunk(
+                cipher,
+                &crate::encrypted::blob_nonce(write_id, index),
+                chunk_key,
+                chunk,
+                len,
+            )
+            .expect("chunk capacity leaves space for the tag"),
+        }
+    }
+
+    /// Verifies and unprotects a chunk record in place, and returns the length of its data.
+    fn open(self, chunk_key: &str, index: u32, record: &mut [u8]) -> Result<usize, Error> {
+        #[cfg(not(feature = "encrypted"))]
+        let _ = (chunk_key, index);
+        match self {
+            Self::Plain => Ok(record.len()),
+            #[cfg(feature = "encrypted")]
+            Self::Encrypted { cipher, write_id } => crate::encrypted::open_chunk(
+                cipher,
+                &crate::encrypted::blob_nonce(write_id, index),
+                chunk_key,
+                record,
+            )
+            .map_err(|_| Error::Tampered),
+        }
+    }
+}
+
+/// Errors produced by blob operations.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The underlying storage failed.
+    Storage(sequential_storage::Error<FlashError>),
+    /// The key is longer than [`MAX_BLOB_KEY_LEN`].
+    KeyTooLong,
+    /// A chunk record is missing or inconsistent with the header.
+    ///
+    /// This happens when a blob is replaced or removed while being read.
+    Inconsistent,
+    /// The blob would exceed the maximum blob size of 4 GiB.
+    TooLarge,
+    /// A record of an encrypted blob fails authentication.
+    ///
+    /// This happens when the blob was modified in storage or was not stored encrypted, or when
+    /// the encryption key changed (see the `encrypted` module).
+    Tampered,
+}
+
+impl From<sequential_storage::Error<FlashError>> for Error {
+    fn from(error: sequential_storage::Error<FlashError>) -> Self {
+        Self::Storage(error)
+    }
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Storage(_) => f.write_str("storage error"),
+            Self::KeyTooLong => f.write_str("blob key too long"),
+            Self::Inconsistent => f.write_str("blob chunks inconsistent with header"),
+            Self::TooLarge => f.write_str("blob too large"),
+            Self::Tampered => f.write_str("blob fails authentication"),
+        }
+    }
+}
+
+impl core::error::Error for Error {}
+
+impl embedded_io_async::Error for Error {
+    fn kind(&self) -> embedded_io_async::ErrorKind {
+        match self {
+            Self::Storage(sequential_storage::Error::FullStorage) => {
+                embedded_io_async::ErrorKind::OutOfMemory
+            }
+            Self::KeyTooLong => embedded_io_async::ErrorKind::InvalidInput,
+            Self::Inconsistent | Self::Tampered => embedded_io_async::ErrorKind::InvalidData,
+            Self::TooLarge => embedded_io_async::ErrorKind::Unsupported,
+            Self::Storage(_) => embedded_io_async::ErrorKind::Other,
+        }
+    }
+}
+
+/// A validated blob key.
+#[derive(Clone, Copy)]
+struct BlobKey(ArrayString<MAX_KEY_LEN>);
+
+impl BlobKey {
+    fn new(key: &str) -> Result<Self, Error> {
+        if key.len() > MAX_BLOB_KEY_LEN {
+            return Err(Error::KeyTooLong);
+        }
+        Ok(Self(ArrayString::from(key).map_err(|_| Error::KeyTooLong)?))
+    }
+
+    fn chunk(&self, generation: u8, index: u32) -> ArrayString<MAX_KEY_LEN> {
+        let mut key = self.0;
+        write!(key, "#{generation}{index:x}").expect("sized by MAX_BLOB_KEY_LEN");
+        key
+    }
+
+    /// Reads the header record.
+    ///
+    /// For encrypted blobs, this also returns the sealing of the current chunks, whose write ID
+    /// is taken from the header.
+    async fn header(&self, sealing: Sealing) -> Result<Option<(Header, Sealing)>, Error> {
+        match sealing {
+            Sealing::Plain => Ok(lock()
+                .await
+                .get(&self.0)
+                .await?
+                .map(|header| (header, Sealing::Plain))),
+            #[cfg(feature = "encrypted")]
+            Sealing::Encrypted { cipher, .. } => {
+                let mut record = [0; DATA_BUFFER_SIZE];
+                let len = {
+                    let mut storage = lock().await;
+                    let mut data_buffer = [0; DATA_BUFFER_SIZE];
+                    let Some(stored) = storage.fetch_bytes(&self.0, &mut data_buffer).await? else {
+                        return Ok(None);
+                    };
+                    record
+                        .get_mut(..stored.len())
+                        .ok_or(Error::Inconsistent)?
+                        .copy_from_slice(stored);
+                    stored.len()
+                };
+                let (record, _) = record.split_at_mut(len);
+
+                let sealed: SealedHeader = crate::encrypted::open_with(cipher, &self.0, record)
+                    .map_err(|e| match e {
+                        sequential_storage::map::SerializationError::Custom(
+                            crate::encrypted::TAMPERED,
+                        ) => Error::Tampered,
+                        _ => Error::Inconsistent,
+                    })?;
+                Ok(Some((
+                    sealed.header,
+                    Sealing::Encrypted {
+                        cipher,
+                        write_id: sealed.write_id,
+                    },
+                )))
+            }
+        }
+    }
+
+    /// Writes the header record, protected like the chunks written by `sealing`.
+    async fn store_header(&self, header: Header, sealing: Sealing) -> Result<(), Error> {
+        match sealing {
+            Sealing::Plain => lock().await.insert(&self.0, header).await?,
+            #[cfg(feature = "encrypted")]
+            Sealing::Encrypted { cipher, write_id } => {
+                let mut record = [0; DATA_BUFFER_SIZE];
+                let len = crate::encrypted::seal_with(
+                    cipher,
+                    &crate::encrypted::blob_nonce(write_id, HEADER_INDEX),
+                    &self.0,
+                    &SealedHeader { header, write_id },
+                    &mut record,
+                )
+                .map_err(sequential_storage::Error::SerializationError)?;
+                let (record, _) = record.split_at(len);
+                lock().await.store_bytes(&self.0, record).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Removes the chunk records of `generation`, starting at `from`, up to the first chunk that is
+/// not found.
+///
+/// Chunks are written in ascending order and removed in descending order, so the chunks of a
+/// generation always form a contiguous range starting at 0, even after an interrupted write or
+/// removal.
+///
+/// STM32 flash drivers do not implement `MultiwriteNorFlash`, so this does nothing there; see the
+/// module documentation for how much storage the remaining chunks take.
+async fn remove_chunks(key: &BlobKey, generation: u8, from: u32) -> Result<(), Error> {
+    #[cfg(not(context = "stm32"))]
+    {
+        let mut end = from;
+        loop {
+            let mut data_buffer = [0; DATA_BUFFER_SIZE];
+            if lock()
+                .await
+                .fetch_bytes(&key.chunk(generation, end), &mut data_buffer)
+                .await?
+                .is_none()
+            {
+                break;
+            }
+            end += 1;
+        }
+        for index in (from..end).rev() {
+            lock()
+                .await
+                .remove_key(&key.chunk(generation, index))
+                .await?;
+        }
+    }
+    #[cfg(context = "stm32")]
+    let _ = (key, generation, from);
+    Ok(())
+}
+
+/// Returns the length of the blob stored under `key`, or `None` if there is none.
+///
+/// # Errors
+///
+/// Returns an error if the key is too long or the storage fails.
+pub async fn len(key: &str) -> Result<Option<u32>, Error> {
+    Ok(BlobKey::new(key)?
+        .header(Sealing::Plain)
+        .await?
+        .map(|(header, _)| header.len))
+}
+
+/// Opens the blob stored under `key` for reading.
+///
+/// Returns `None` if there is no blob with that key.
+///
+/// # Errors
+///
+/// Returns an error if the key is too long or the storage fails.
+pub async fn reader(key: &str) -> Result<Option<BlobReader>, Error> {
+    open_reader(key, Sealing::Plain).await
+}
+
+/// Opens the encrypted blob stored under `key` for reading, see [`reader()`].
+///
+/// Reading fails with [`Error::Tampered`] if any record of the blob fails authentication.
+///
+/// # Errors
+///
+/// Returns an error if the key is too long, the storage fails, or the header record fails
+/// authentication.
+///
+/// # Panics
+///
+/// Panics if the device does not provide an identity.
+#[cfg(feature = "encrypted")]
+pub async fn encrypted_reader(key: &str) -> Result<Option<BlobReader>, Error> {
+    // The write ID is taken from the header.
+    open_reader(key, encrypted_sealing(0)).await
+}
+
+async fn open_reader(key: &str, sealing: Sealing) -> Result<Option<BlobReader>, Error> {
+    let key = BlobKey::new(key)?;
+    Ok(key
+        .header(sealing)
+        .await?
+        .map(|(header, sealing)| BlobReader {
+            key,
+            header,
+            sealing,
+            next_chunk: 0,
+            remaining: header.len,
+            buffer: [0; CHUNK_SIZE],
+            start: 0,
+            end: 0,
+        }))
+}
+
+/// Starts writing a blob under `key`.
+///
+/// Any previous blob under that key stays readable until [`BlobWriter::finish()`] is called.
+/// Leftovers from a previously interrupted write are cleaned up first.
+///
+/// Only a single writer should exist per key at any time.
+///
+/// # Errors
+///
+/// Returns an error if the key is too long or the storage fails.
+pub async fn writer(key: &str) -> Result<BlobWriter, Error> {
+    create_writer(key, Sealing::Plain).await
+}
+
+/// Starts writing an encrypted blob under `key`, see [`writer()`].
+///
+/// This replaces any previous blob under that key, including one that fails authentication.
+///
+/// # Errors
+///
+/// Returns an error if the key is too long or the storage fails.
+///
+/// # Panics
+///
+/// Panics if the device does not provide an identity.
+#[cfg(feature = "encrypted")]
+pub async fn encrypted_writer(key: &str) -> Result<BlobWriter, Error> {
+    use rand_core::RngCore as _;
+
+    create_writer(
+        key,
+        encrypted_sealing(ariel_os_random::crypto_rng().next_u64()),
+    )
+    .await
+}
+
+async fn create_writer(key: &str, sealing: Sealing) -> Result<BlobWriter, Error> {
+    let key = BlobKey::new(key)?;
+    let previous = match key.header(sealing).await {
+        Ok(previous) => previous.map(|(header, _)| header),
+        // The current chunks are not readable any more.
+        Err(Error::Tampered) => None,
+        Err(error) => return Err(error),
+    };
+    if previous.is_none() {
+        // Without a readable header, chunks of either generation may be left over, e.g., by an
+        // interrupted removal; removing both sets makes room.
+        remove_chunks(&key, 1, 0).await?;
+    }
+    let generation = previous.map_or(0, |header| header.generation ^ 1);
+
+    remove_chunks(&key, generation, 0).await?;
+
+    Ok(BlobWriter {
+        key,
+        sealing,
+        previous,
+        generation,
+        chunks: 0,
+        len: 0,
+        buffer: [0; CHUNK_SIZE],
+        fill: 0,
+    })
+}
+
+/// Removes the blob stored under `key`.
+///
+/// Removing a blob that does not exist is not an error.
+///
+/// # Errors
+///
+/// Returns an error if the key is too long or the storage fails.
+// STM32 flash drivers do not implement `MultiwriteNorFlash`.
+#[cfg(not(context = "stm32"))]
+pub async fn remove(key: &str) -> Result<(), Error> {
+    remove_blob(key, Sealing::Plain).await
+}
+
+/// Removes the encrypted blob stored under `key`, see [`remove()`].
+///
+/// A blob whose header fails authentication is removed as well.
+///
+/// # Errors
+///
+/// Returns an error if the key is too long or the storage fails.
+///
+/// # Panics
+///
+/// Panics if the device does not provide an identity.
+// STM32 flash drivers do not implement `MultiwriteNorFlash`.
+#[cfg(all(feature = "encrypted", not(context = "stm32")))]
+pub async fn encrypted_remove(key: &str) -> Result<(), Error> {
+    remove_blob(key, encrypted_sealing(0)).await
+}
+
+#[cfg(not(context = "stm32"))]
+async fn remove_blob(key: &str, sealing: Sealing) -> Result<(), Error> {
+    let key = BlobKey::new(key)?;
+    let generations = match key.header(sealing).await {
+        Ok(Some((header, _))) => header.generation..=header.generation,
+        // Without a readable header, chunks of either generation may be left over, e.g., by an
+        // interrupted removal.
+        Ok(None) | Err(Error::Tampered) => 0..=1,
+        Err(error) => return Err(error),
+    };
+    // Removing the header first makes the blob disappear atomically; chunks that remain after an
+    // interruption are removed by the next writer or removal, which remove both generations
+    // when there is no header.
+    lock().await.remove_key(&key.0).await?;
+    for generation in generations {
+        remove_chunks(&key, generation, 0).await?;
+    }
+    Ok(())
+}
+
+/// Returns the sealing of encrypted blobs for a write with the given ID.
+#[cfg(feature = "encrypted")]
+fn encrypted_sealing(write_id: u64) -> Sealing {
+    Sealing::Encrypted {
+        cipher: crate::encrypted::cipher(),
+        write_id,
+    }
+}
+
+/// A stream of the bytes of a stored blob, see [`reader()`].
+pub struct BlobReader {
+    key: BlobKey,
+    header: Header,
+    sealing: Sealing,
+    next_chunk: u32,
+    remaining: u32,
+    buffer: [u8; CHUNK_SIZE],
+    start: usize,
+    end: usize,
+}
+
+impl BlobReader {
+    /// Returns the total length of the blob.
+    #[must_use]
+    pub fn len(&self) -> u32 {
+        self.header.len
+    }
+
+    /// Returns whether the blob is empty.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.header.len == 0
+    }
+
+    async fn fill_buffer(&mut self) -> Result<(), Error> {
+        if self.next_chunk >= self.header.chunks {
+            return Err(Error::Inconsistent);
+        }
+
+        let chunk_key = self.key.chunk(self.header.generation, self.next_chunk);
+        let len = {
+            let mut storage = lock().await;
+            let mut data_buffer = [0; DATA_BUFFER_SIZE];
+            let chunk = storage
+                .fetch_bytes(&chunk_key, &mut data_buffer)
+                .await?
+                .ok_or(Error::Inconsistent)?;
+
+            let target = self
+                .buffer
+                .get_mut(..chunk.len())
+                .ok_or(Error::Inconsistent)?;
+            target.copy_from_slice(chunk);
+            chunk.len()
+        };
+        let (record, _) = self.buffer.split_at_mut(len);
+        self.start = 0;
+        self.end = self.sealing.open(&chunk_key, self.next_chunk, record)?;
+        self.next_chunk += 1;
+
+        Ok(())
+    }
+}
+
+impl embedded_io_async::ErrorType for BlobReader {
+    type Error = Error;
+}
+
+impl embedded_io_async::Read for BlobReader {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if self.remaining == 0 || buf.is_empty() {
+            return Ok(0);
+        }
+        if self.start == self.end {
+            self.fill_buffer().await?;
+        }
+
+        let available = self.buffer.get(self.start..self.end).unwrap_or_default();
+        let len = available.len().min(buf.len());
+        let (source, _) = available.split_at(len);
+        let (target, _) = buf.split_at_mut(len);
+        target.copy_from_slice(source);
+
+        self.start += len;
+        #[expect(clippy::cast_possible_truncation, reason = "len <= CHUNK_SIZE")]
+        let len_u32 = len as u32;
+        self.remaining = self
+            .remaining
+            .checked_sub(len_u32)
+            .ok_or(Error::Inconsistent)?;
+
+        Ok(len)
+    }
+}
+
+/// A sink writing a blob into storage, see [`writer()`].
+///
+/// The written data only replaces the previous blob once [`BlobWriter::finish()`] is called.
+/// Dropping the writer without finishing it abandons the written data.
+pub struct BlobWriter {
+    key: BlobKey,
+    sealing: Sealing,
+    previous: Option<Header>,
+    generation: u8,
+    chunks: u32,
+    len: u32,
+    buffer: [u8; CHUNK_SIZE],
+    fill: usize,
+}
+
+impl BlobWriter {
+    /// Writes the buffered data into a chunk record.
+    async fn store_chunk(&mut self) -> Result<(), Error> {
+        if self.fill == 0 {
+            return Ok(());
+        }
+
+        let chunk_key = self.key.chunk(self.generation, self.chunks);
+        // Sealing works on a copy, so that the buffered data is kept if storing fails.
+        let mut record = self.buffer;
+        let len = self
+            .sealing
+            .seal(&chunk_key, self.chunks, &mut record, self.fill);
+        let (record, _) = record.split_at(len);
+        lock().await.store_bytes(&chunk_key, record).await?;
+
+        self.chunks += 1;
+        self.fill = 0;
+
+        Ok(())
+    }
+
+    /// Completes the blob, making it replace any previous blob stored under the same key.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage fails, in which case the previous blob stays in place.
+    pub async fn finish(mut self) -> Result<(), Error> {
+        self.store_chunk().await?;
+
+        let header = Header {
+            len: self.len,
+            chunks: self.chunks,
+            generation: self.generation,
+        };
+        self.key.store_header(header, self.sealing).await?;
+
+        if let Some(previous) = self.previous {
+            remove_chunks(&self.key, previous.generation, 0).await?;
+        }
+
+        Ok(())
+    }
+}
+
+impl embedded_io_async::ErrorType for BlobWriter {
+    type Error = Error;
+}
+
+impl embedded_io_async::Write for BlobWriter {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        let capacity = self.sealing.chunk_capacity();
+        if self.fill == capacity {
+            self.store_chunk().await?;
+        }
+
+        let free = self.buffer.get_mut(self.fill..capacity).unwrap_or_default();
+        let len = free.len().min(buf.len());
+
+        #[expect(clippy::cast_possible_truncation, reason = "len <= CHUNK_SIZE")]
+        let len_u32 = len as u32;
+        self.len = self.len.checked_add(len_u32).ok_or(Error::TooLarge)?;
+
+        let (target, _) = free.split_at_mut(len);
+        let (source, _) = buf.split_at(len);
+        target.copy_from_slice(source);
+        self.fill += len;
+
+        Ok(len)
+    }
+
+    /// Writes any buffered data into a chunk record.
+    ///
+    /// This does not make the data readable; see [`BlobWriter::finish()`].
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        self.store_chunk().await
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::indexing_slicing, reason = "panicking is fine in tests")]
+mod test {
+    use embassy_futures::block_on;
+    use embedded_io_async::{Read as _, Write as _};
+
+    use super::*;
+
+    /// Returns `len` bytes that differ between neighboring chunks.
+    fn data(len: usize, seed: u8) -> Vec<u8> {
+        (0..len)
+            .map(|i| u8::try_from(i % 251).unwrap().wrapping_add(seed))
+            .collect()
+    }
+
+    async fn write(key: &str, data: &[u8]) {
+        let mut writer = writer(key).await.unwrap();
+        writer.write_all(data).await.unwrap();
+        writer.finish().await.unwrap();
+    }
+
+    async fn read(key: &str) -> Option<Vec<u8>> {
+        let mut reader = reader(key).await.unwrap()?;
+        let mut data = vec![0; usize::try_from(reader.len()).unwrap()];
+        reader.read_exact(&mut data).await.unwrap();
+        assert_eq!(reader.read(&mut [0; 1]).await.unwrap(), 0);
+        Some(data)
+    }
+
+    /// Returns the number of chunk records stored for the blob under `key`.
+    async fn chunk_records(key: &str) -> usize {
+        let prefix = format!("{key}#");
+        let mut chunk_keys = std::collections::BTreeSet::new();
+        lock()
+            .await
+            .for_each_key(|key| {
+                if key.starts_with(&prefix) {
+                    chunk_keys.insert(key.to_owned());
+                }
+            })
+            .await
+            .unwrap();
+        chunk_keys.len()
+    }
+
+    #[test]
+    fn chunked_round_trip() {
+        crate::test_storage::init();
+        block_on(async {
+            for (index, size) in [0, 1, CHUNK_SIZE, CHUNK_SIZE + 1, 5 * CHUNK_SIZE + 3]
+                .into_iter()
+                .enumerate()
+            {
+                let key = format!("round-trip-{index}");
+                let written = data(size, 0);
+                write(&key, &written).await;
+
+                assert_eq!(len(&key).await.unwrap(), Some(u32::try_from(size).unwrap()));
+                assert_eq!(read(&key).await.unwrap(), written);
+                assert_eq!(chunk_records(&key).await, size.div_ceil(CHUNK_SIZE));
+            }
+
+            assert!(reader("round-trip-missing").await.unwrap().is_none());
+        });
+    }
+
+    #[test]
+    fn small_reads_cross_chunks() {
+        crate::test_storage::init();
+        block_on(async {
+            let written = data(3 * CHUNK_SIZE, 1);
+            write("small-reads", &written).await;
+
+            let mut reader = reader("small-reads").await.unwrap().unwrap();
+            let mut read = Vec::new();
+            let mut buf = [0; 7];
+            loop {
+                let len = reader.read(&mut buf).await.unwrap();
+                if len == 0 {
+                    break;
+                }
+                read.extend_from_slice(&buf[..len]);
+            }
+            assert_eq!(read, written);
+        });
+    }
+
+    #[test]
+    fn header_written_last() {
+        crate::test_storage::init();
+        block_on(async {
+            let old = data(2 * CHUNK_SIZE, 2);
+            let new = data(3 * CHUNK_SIZE + 5, 3);
+            write("header-last", &old).await;
+
+            let mut replacing = writer("header-last").await.unwrap();
+            replacing.write_all(&new).await.unwrap();
+            replacing.flush().await.unwrap();
+            // All chunks of the new blob are stored, but the old blob is still current.
+            assert_eq!(read("header-last").await.unwrap(), old);
+
+            replacing.finish().await.unwrap();
+            assert_eq!(read("header-last").await.unwrap(), new);
+            // The chunks of the old blob are removed.
+            assert_eq!(chunk_records("header-last").await, 4);
+        });
+    }
+
+    #[test]
+    fn cleanup_after_partial_write() {
+        crate::test_storage::init();
+        block_on(async {
+            let old = data(CHUNK_SIZE, 4);
+            write("partial", &old).await;
+
+            let mut abandoned = writer("partial").await.unwrap();
+            abandoned.write_all(&data(3 * CHUNK_SIZE, 5)).await.unwrap();
+            abandoned.flush().await.unwrap();
+            drop(abandoned);
+
+            assert_eq!(read("partial").await.unwrap(), old);
+            assert_eq!(chunk_records("partial").await, 1 + 3);
+
+            // The next writer removes what the abandoned one left over.
+            let cleaning = writer("partial").await.unwrap();
+            assert_eq!(chunk_records("partial").await, 1);
+            drop(cleaning);
+
+            // Without a previous blob, abandoned chunks are not readable either.
+            let mut abandoned = writer("partial-new").await.unwrap();
+            abandoned.write_all(&data(CHUNK_SIZE + 1, 6)).await.unwrap();
+            abandoned.flush().await.unwrap();
+            drop(abandoned);
+            assert!(read("partial-new").await.is_none());
+            let new = data(5, 7);
+            write("partial-new", &new).await;
+            assert_eq!(read("partial-new").await.unwrap(), new);
+            assert_eq!(chunk_records("partial-new").await, 1);
+        });
+    }
+
+    #[test]
+    fn remove_blob() {
+        crate::test_storage::init();
+        block_on(async {
+            write("remove", &data(2 * CHUNK_SIZE + 1, 8)).await;
+            remove("remove").await.unwrap();
+
+            assert_eq!(len("remove").await.unwrap(), None);
+            assert_eq!(chunk_records("remove").await, 0);
+            // Removing it again is not an error.
+            remove("remove").await.unwrap();
+        });
+    }
+
+    /// Removes the header and the last `chunks` chunk records of the blob under `key`, like a
+    /// removal interrupted after these.
+    async fn interrupt_remove(key: &str, chunks: u32) {
+        let key = BlobKey::new(key).unwrap();
+        let (header, _) = key.header(Sealing::Plain).await.unwrap().unwrap();
+        let mut storage = lock().await;
+        storage.remove_key(&key.0).await.unwrap();
+        for index in (header.chunks - chunks..header.chunks).rev() {
+            storage
+                .remove_key(&key.chunk(header.generation, index))
+                .await
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn interrupted_remove() {
+        crate::test_storage::init();
+        block_on(async {
+            // The second write makes generation 1 current.
+            write("interrupted", &data(4 * CHUNK_SIZE, 9)).await;
+            write("interrupted", &data(3 * CHUNK_SIZE, 10)).await;
+            interrupt_remove("interrupted", 1).await;
+            assert!(read("interrupted").await.is_none());
+            assert_eq!(chunk_records("interrupted").await, 2);
+
+            // The next writer removes the remaining chunks although there is no header.
+            let cleaning = writer("interrupted").await.unwrap();
+            assert_eq!(chunk_records("interrupted").await, 0);
+            drop(cleaning);
+
+            // So does the next removal.
+            write("interrupted-twice", &data(2 * CHUNK_SIZE, 11)).await;
+            interrupt_remove("interrupted-twice", 0).await;
+            assert_eq!(chunk_records("interrupted-twice").await, 2);
+            remove("interrupted-twice").await.unwrap();
+            assert_eq!(chunk_records("interrupted-twice").await, 0);
+        });
+    }
+
+    #[test]
+    fn remove_chunks_from_index() {
+        crate::test_storage::init();
+        block_on(async {
+            write("descending", &data(5 * CHUNK_SIZE, 12)).await;
+            let key = BlobKey::new("descending").unwrap();
+
+            // Removing from an index keeps the chunks below it.
+            remove_chunks(&key, 0, 3).await.unwrap();
+            assert_eq!(chunk_records("descending").await, 3);
+            for index in 0..3 {
+                let mut data_buffer = [0; DATA_BUFFER_SIZE];
+                assert!(
+                    lock()
+                        .await
+                        .fetch_bytes(&key.chunk(0, index), &mut data_buffer)
+                        .await
+                        .unwrap()
+                        .is_some()
+                );
+            }
+
+            remove_chunks(&key, 0, 0).await.unwrap();
+            assert_eq!(chunk_records("descending").await, 0);
+        });
+    }
+
+    /// Returns the sealing of an encrypted write with the given ID, using a fixed test key.
+    #[cfg(feature = "encrypted")]
+    fn test_sealing(device: u8, write_id: u64) -> Sealing {
+        use chacha20poly1305::KeyInit as _;
+
+        Sealing::Encrypted {
+            cipher: Box::leak(Box::new(ChaCha20Poly1305::new(
+                &chacha20poly1305::Key::from([device; 32]),
+            ))),
+            write_id,
+        }
+    }
+
+    #[cfg(feature = "encrypted")]
+    async fn write_sealed(key: &str, data: &[u8], sealing: Sealing) {
+        let mut writer = create_writer(key, sealing).await.unwrap();
+        writer.write_all(data).await.unwrap();
+        writer.finish().await.unwrap();
+    }
+
+    #[cfg(feature = "encrypted")]
+    async fn read_sealed(key: &str, sealing: Sealing) -> Result<Option<Vec<u8>>, Error> {
+        let Some(mut reader) = open_reader(key, sealing).await? else {
+            return Ok(None);
+        };
+        let mut data = vec![0; usize::try_from(reader.len()).unwrap()];
+        reader.read_exact(&mut data).await.map_err(|e| match e {
+            embedded_io_async::ReadExactError::Other(e) => e,
+            embedded_io_async::ReadExactError::UnexpectedEof => panic!("blob ended early"),
+        })?;
+        Ok(Some(data))
+    }
+
+    /// Returns the stored record of chunk `index` of generation `generation`.
+    #[cfg(feature = "encrypted")]
+    async fn chunk_record(key: &str, generation: u8, index: u32) -> Vec<u8> {
+        let chunk_key = BlobKey::new(key).unwrap().chunk(generation, index);
+        let mut data_buffer = [0; DATA_BUFFER_SIZE];
+        lock()
+            .await
+            .fetch_bytes(&chunk_key, &mut data_buffer)
+            .await
+            .unwrap()
+            .unwrap()
+            .to_vec()
+    }
+
+    #[cfg(feature = "encrypted")]
+    async fn store_chunk_record(key: &str, generation: u8, index: u32, record: &[u8]) {
+        let chunk_key = BlobKey::new(key).unwrap().chunk(generation, index);
+        lock().await.store_bytes(&chunk_key, record).await.unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "encrypted")]
+    fn encrypted_round_trip() {
+        crate::test_storage::init();
+        block_on(async {
+            let capacity = test_sealing(1, 0).chunk_capacity();
+            assert_eq!(capacity, CHUNK_SIZE - 16);
+
+            for (index, size) in [0, 1, capacity, capacity + 1, 4 * capacity + 3]
+                .into_iter()
+                .enumerate()
+            {
+                let key = format!("sealed-{index}");
+                let written = data(size, 9);
+                write_sealed(&key, &written, test_sealing(1, 100)).await;
+
+                let read = read_sealed(&key, test_sealing(1, 0)).await.unwrap();
+                assert_eq!(read.unwrap(), written);
+                assert_eq!(chunk_records(&key).await, size.div_ceil(capacity));
+                if size > 0 {
+                    let record = chunk_record(&key, 0, 0).await;
+                    assert_eq!(record.len(), size.min(capacity) + 16);
+                    assert_ne!(record[..size.min(capacity)], written[..size.min(capacity)]);
+                }
+            }
+
+            assert!(
+                read_sealed("sealed-missing", test_sealing(1, 0))
+                    .await
+                    .unwrap()
+                    .is_none()
+            );
+        });
+    }
+
+    #[test]
+    #[cfg(feature = "encrypted")]
+    fn encrypted_tampering() {
+        crate::test_storage::init();
+        block_on(async {
+            let written = data(3 * CHUNK_SIZE, 10);
+            write_sealed("sealed-tampered", &written, test_sealing(1, 1)).await;
+
+            // A different device (or build-time secret) can not read the blob.
+            assert!(matches!(
+                read_sealed("sealed-tampered", test_sealing(2, 0)).await,
+                Err(Error::Tampered)
+            ));
+
+            // Modified chunks are detected.
+            let original = chunk_record("sealed-tampered", 0, 1).await;
+            let mut modified = original.clone();
+            modified[0] ^= 1;
+            store_chunk_record("sealed-tampered", 0, 1, &modified).await;
+            assert!(matches!(
+                read_sealed("sealed-tampered", test_sealing(1, 0)).await,
+                Err(Error::Tampered)
+            ));
+
+            // So are chunks at a different position.
+            let first = chunk_record("sealed-tampered", 0, 0).await;
+            store_chunk_record("sealed-tampered", 0, 1, &first).await;
+            assert!(matches!(
+                read_sealed("sealed-tampered", test_sealing(1, 0)).await,
+                Err(Error::Tampered)
+            ));
+
+            store_chunk_record("sealed-tampered", 0, 1, &original).await;
+            let read = read_sealed("sealed-tampered", test_sealing(1, 0)).await;
+            assert_eq!(read.unwrap().unwrap(), written);
+
+            // Chunks from an earlier write of the same generation are detected.
+            write_sealed("sealed-tampered", &data(10, 11), test_sealing(1, 2)).await;
+            write_sealed("sealed-tampered", &written, test_sealing(1, 3)).await;
+            store_chunk_record("sealed-tampered", 0, 0, &first).await;
+            assert!(matches!(
+                read_sealed("sealed-tampered", test_sealing(1, 0)).await,
+                Err(Error::Tampered)
+            ));
+
+            // A plain blob does not pass as an encrypted one.
+            write("sealed-plain", &data(5, 12)).await;
+            assert!(matches!(
+                read_sealed("sealed-plain", test_sealing(1, 0)).await,
+                Err(Error::Tampered)
+            ));
+        });
+    }
+
+    #[test]
+    #[cfg(feature = "encrypted")]
+    fn encrypted_replaces_unreadable() {
+        crate::test_storage::init();
+        block_on(async {
+            // A blob that fails authentication is replaced by the next write.
+            write("sealed-replaced", &data(3 * CHUNK_SIZE, 13)).await;
+            let written = data(CHUNK_SIZE, 14);
+            write_sealed("sealed-replaced", &written, test_sealing(1, 4)).await;
+            let read = read_sealed("sealed-replaced", test_sealing(1, 0)).await;
+            assert_eq!(read.unwrap().unwrap(), written);
+            assert_eq!(chunk_records("sealed-replaced").await, 2);
+
+            // ... and removed along with all its chunks.
+            write_sealed("sealed-removed", &data(CHUNK_SIZE, 15), test_sealing(2, 5)).await;
+            remove_blob("sealed-removed", test_sealing(1, 0))
+                .await
+                .unwrap();
+            assert!(
+                read_sealed("sealed-removed", test_sealing(2, 0))
+                    .await
+                    .unwrap()
+                    .is_none()
+            );
+            assert_eq!(chunk_records("sealed-removed").await, 0);
+        });
+    }
+
+    #[test]
+    fn key_too_long() {
+        crate::test_storage::init();
+        block_on(async {
+            let key = "k".repeat(MAX_BLOB_KEY_LEN + 1);
+            assert!(matches!(writer(&key).await, Err(Error::KeyTooLong)));
+            assert!(matches!(reader(&key).await, Err(Error::KeyTooLong)));
+        });
+    }
+}
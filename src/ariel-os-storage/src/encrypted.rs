@@ -0,0 +1,383 @@
+//! Key-value pair storage whose values are encrypted and authenticated.
+//!
+//! The functions in this module mirror the ones at the crate root, but store values using
+//! ChaCha20-Poly1305 under a key that is derived from the device identity (see
+//! [`ariel_os_identity::device_id_bytes()`]) and from an optional build-time secret set in the
+//! `CONFIG_STORAGE_ENCRYPTION_SECRET` environment variable.
+//! The storage key of a record is authenticated along with its value, so records can neither be
+//! modified nor moved to a different key without being detected.
+//!
+//! Reading a record that fails authentication returns a
+//! [`SerializationError::Custom`] error carrying [`TAMPERED`].
+//! This is also what happens when a value that was stored through the plain [`crate::insert()`]
+//! is read through [`get()`], or after the device identity or the build-time secret changed.
+//!
+//! <div class="warning">
+//! The device identity is not a secret.
+//! Without a build-time secret, the encryption key can be derived by anyone who knows the device
+//! identity, and only protects against accidental modification and transplanting records between
+//! devices.
+//! Platforms with a hardware key store do not use it yet.
+//! </div>
+//!
+//! Encrypted values take [`OVERHEAD`] more bytes than their serialized form.
+//! As a record's key and value share a buffer of [`DATA_BUFFER_SIZE`] bytes, the serialized value
+//! can be at most [`max_value_len()`] bytes long, which depends on the length of the key.
+//! Larger values can be stored as encrypted blobs, see [`crate::blob::encrypted_writer()`].
+
+use chacha20poly1305::{AeadInPlace, ChaCha20Poly1305, KeyInit, Nonce, Tag};
+use embassy_sync::once_lock::OnceLock;
+use rand_core::RngCore as _;
+use sequential_storage::map::SerializationError;
+
+use crate::{
+    DATA_BUFFER_SIZE, Deserialize, FlashError, PostcardValue, Serialize, storage::KEY_OVERHEAD,
+};
+
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+/// Number of bytes an encrypted record takes in addition to the serialized value.
+pub const OVERHEAD: usize = NONCE_LEN + TAG_LEN;
+
+/// Returns the maximum length of a serialized value stored in an encrypted record under `key`.
+///
+/// Longer values fail to be inserted with a
+/// [`SerializationError::BufferTooSmall`] error.
+#[must_use]
+pub const fn max_value_len(key: &str) -> usize {
+    DATA_BUFFER_SIZE.saturating_sub(KEY_OVERHEAD + key.len() + OVERHEAD)
+}
+
+/// Code of the [`SerializationError::Custom`] error returned for records that fail
+/// authentication.
+pub const TAMPERED: i32 = 1;
+
+/// Salt of the key derivation; changing it invalidates all encrypted records.
+const KDF_SALT: &[u8] = b"ariel-os-storage";
+/// Info of the key derivation, which allows deriving further keys from the same material.
+const KDF_INFO: &[u8] = b"encrypted values v1";
+
+static CIPHER: OnceLock<ChaCha20Poly1305> = OnceLock::new();
+
+/// Derives the device-unique storage key.
+///
+/// # Panics
+///
+/// Panics if the device does not provide an identity.
+pub(crate) fn cipher() -> &'static ChaCha20Poly1305 {
+    CIPHER.get_or_init(|| {
+        const SECRET: &str = ariel_os_utils::str_from_env_or!(
+            "CONFIG_STORAGE_ENCRYPTION_SECRET",
+            "",
+            "secret mixed into the key of encrypted storage values"
+        );
+
+        let device_id = ariel_os_identity::device_id_bytes()
+            .expect("encrypted storage requires a device identity");
+
+        let mut extract = hkdf::HkdfExtract::<sha2::Sha256>::new(Some(KDF_SALT));
+        extract.input_ikm(device_id.as_ref());
+        extract.input_ikm(SECRET.as_bytes());
+        let (_, hkdf) = extract.finalize();
+
+        let mut key = chacha20poly1305::Key::default();
+        hkdf.expand(KDF_INFO, &mut key)
+            .expect("output length is valid for SHA-256");
+
+        ChaCha20Poly1305::new(&key)
+    })
+}
+
+/// Serializes and encrypts `value` into `record`, returning the used length.
+///
+/// The record is laid out as nonce, ciphertext and tag; the storage key is used as associated
+/// data.
+pub(crate) fn seal<T: Serialize>(
+    key: &str,
+    value: &T,
+    record: &mut [u8; DATA_BUFFER_SIZE],
+) -> Result<usize, SerializationError> {
+    // Random nonces are fine here: records are written rarely enough that the birthday bound of
+    // 96-bit nonces is out of reach for the lifetime of a flash device.
+    let mut nonce = [0; NONCE_LEN];
+    ariel_os_random::crypto_rng().fill_bytes(&mut nonce);
+
+    seal_with(cipher(), &nonce, key, value, record)
+}
+
+/// Authenticates and decrypts a record created by [`seal()`] in place, and deserializes it.
+pub(crate) fn open<T: for<'d> Deserialize<'d>>(
+    key: &str,
+    record: &mut [u8],
+) -> Result<T, SerializationError> {
+    open_with(cipher(), key, record)
+}
+
+/// Implements [`seal()`] with a given cipher and nonce.
+pub(crate) fn seal_with<T: Serialize>(
+    cipher: &ChaCha20Poly1305,
+    nonce: &[u8; NONCE_LEN],
+    key: &str,
+    value: &T,
+    record: &mut [u8; DATA_BUFFER_SIZE],
+) -> Result<usize, SerializationError> {
+    let (nonce_area, rest) = record.split_at_mut(NONCE_LEN);
+    let value_area = rest
+        .get_mut(..max_value_len(key))
+        .ok_or(SerializationError::BufferTooSmall)?;
+
+    let plaintext_len = postcard::to_slice(value, value_area)
+        .map_err(|e| match e {
+            postcard::Error::SerializeBufferFull => SerializationError::BufferTooSmall,
+            _ => SerializationError::Custom(0),
+        })?
+        .len();
+
+    nonce_area.copy_from_slice(nonce);
+
+    let (ciphertext, rest) = rest.split_at_mut(plaintext_len);
+    let tag = cipher
+        .encrypt_in_place_detached(Nonce::from_slice(nonce), key.as_bytes(), ciphertext)
+        .map_err(|_| SerializationError::Custom(0))?;
+    let (tag_area, _) = rest.split_at_mut(TAG_LEN);
+    tag_area.copy_from_slice(&tag);
+
+    Ok(NONCE_LEN + plaintext_len + TAG_LEN)
+}
+
+/// Implements [`open()`] with a given cipher.
+pub(crate) fn open_with<T: for<'d> Deserialize<'d>>(
+    cipher: &ChaCha20Poly1305,
+    key: &str,
+    record: &mut [u8],
+) -> Result<T, SerializationError> {
+    if record.len() < OVERHEAD {
+        return Err(SerializationError::Custom(TAMPERED));
+    }
+
+    let (nonce, rest) = record.split_at_mut(NONCE_LEN);
+    let (ciphertext, tag) = rest.split_at_mut(rest.len() - TAG_LEN);
+
+    cipher
+        .decrypt_in_place_detached(
+            Nonce::from_slice(nonce),
+            key.as_bytes(),
+            ciphertext,
+            Tag::from_slice(tag),
+        )
+        .map_err(|_| SerializationError::Custom(TAMPERED))?;
+
+    postcard::from_bytes(ciphertext).map_err(|e| match e {
+        postcard::Error::DeserializeUnexpectedEnd => SerializationError::InvalidData,
+        _ => SerializationError::Custom(0),
+    })
+}
+
+/// Number of bytes a chunk record of an encrypted blob takes in addition to its data.
+pub(crate) const CHUNK_OVERHEAD: usize = TAG_LEN;
+
+/// Builds the nonce of a record of an encrypted blob.
+///
+/// Every write of a blob picks a random `write_id`, and numbers its records by `index`, so nonces
+/// are not stored with the chunks.
+pub(crate) fn blob_nonce(write_id: u64, index: u32) -> [u8; NONCE_LEN] {
+    let mut nonce = [0; NONCE_LEN];
+    let (id_area, index_area) = nonce.split_at_mut(8);
+    id_area.copy_from_slice(&write_id.to_le_bytes());
+    index_area.copy_from_slice(&index.to_le_bytes());
+    nonce
+}
+
+/// Encrypts the first `len` bytes of `chunk` in place and places the tag after them, returning
+/// the length of the sealed chunk.
+///
+/// The chunk's storage key is used as associated data.
+pub(crate) fn seal_chunk(
+    cipher: &ChaCha20Poly1305,
+    nonce: &[u8; NONCE_LEN],
+    chunk_key: &str,
+    chunk: &mut [u8],
+    len: usize,
+) -> Result<usize, SerializationError> {
+    let (data, rest) = chunk
+        .split_at_mut_checked(len)
+        .ok_or(SerializationError::BufferTooSmall)?;
+    let tag_area = rest
+        .get_mut(..TAG_LEN)
+        .ok_or(SerializationError::BufferTooSmall)?;
+
+    let tag = cipher
+        .encrypt_in_place_detached(Nonce::from_slice(nonce), chunk_key.as_bytes(), data)
+        .map_err(|_| SerializationError::Custom(0))?;
+    tag_area.copy_from_slice(&tag);
+
+    Ok(len + TAG_LEN)
+}
+
+/// Authenticates and decrypts a chunk sealed by [`seal_chunk()`] in place, returning the length
+/// of its data.
+pub(crate) fn open_chunk(
+    cipher: &ChaCha20Poly1305,
+    nonce: &[u8; NONCE_LEN],
+    chunk_key: &str,
+    chunk: &mut [u8],
+) -> Result<usize, SerializationError> {
+    let len = chunk
+        .len()
+        .checked_sub(TAG_LEN)
+        .ok_or(SerializationError::Custom(TAMPERED))?;
+    let (data, tag) = chunk.split_at_mut(len);
+
+    cipher
+        .decrypt_in_place_detached(
+            Nonce::from_slice(nonce),
+            chunk_key.as_bytes(),
+            data,
+            Tag::from_slice(tag),
+        )
+        .map_err(|_| SerializationError::Custom(TAMPERED))?;
+
+    Ok(len)
+}
+
+/// Encrypts a key-value pair and stores it into flash memory.
+///
+/// It will overwrite the last value that has the same key.
+pub async fn insert<'d, V>(key: &str, value: V) -> Result<(), sequential_storage::Error<FlashError>>
+where
+    V: Serialize + Deserialize<'d> + Into<PostcardValue<V>>,
+{
+    crate::lock().await.insert_encrypted::<V>(key, value).await
+}
+
+/// Gets and decrypts the last stored value from the flash that is associated with the given key.
+///
+/// Note: Always [`get()`] the same value type that was [`insert()`]!
+///
+/// If no value with the key is found, `None` is returned.
+/// If the value fails authentication, an error carrying [`TAMPERED`] is returned (see
+/// [`is_tampered()`]).
+pub async fn get<V>(key: &str) -> Result<Option<V>, sequential_storage::Error<FlashError>>
+where
+    V: Serialize + for<'d> Deserialize<'d> + Into<PostcardValue<V>>,
+{
+    crate::lock().await.get_encrypted(key).await
+}
+
+/// Returns whether `error` was returned by [`get()`] for a record that failed authentication.
+///
+/// Such a record was not written through [`insert()`] on this device with the current build-time
+/// secret, e.g., because it was stored in plain text by an earlier firmware.
+#[must_use]
+pub fn is_tampered(error: &sequential_storage::Error<FlashError>) -> bool {
+    matches!(
+        error,
+        sequential_storage::Error::SerializationError(SerializationError::Custom(TAMPERED))
+    )
+}
+
+/// Deletes an item from flash.
+///
+/// This is the same as [`crate::remove()`], and is only provided for symmetry.
+// STM32 flash drivers do not implement `MultiwriteNorFlash`.
+#[cfg(not(context = "stm32"))]
+pub async fn remove(key: &str) -> Result<(), sequential_storage::Error<FlashError>> {
+    crate::remove(key).await
+}
+
+#[cfg(test)]
+#[allow(clippy::indexing_slicing, reason = "panicking is fine in tests")]
+mod test {
+    use super::*;
+
+    const NONCE: [u8; NONCE_LEN] = [7; NONCE_LEN];
+
+    type TestValue = (u32, [u8; 4]);
+
+    fn test_cipher(device: u8) -> ChaCha20Poly1305 {
+        ChaCha20Poly1305::new(&chacha20poly1305::Key::from([device; 32]))
+    }
+
+    fn test_seal(key: &str, value: &TestValue) -> ([u8; DATA_BUFFER_SIZE], usize) {
+        let mut record = [0; DATA_BUFFER_SIZE];
+        let len = seal_with(&test_cipher(1), &NONCE, key, value, &mut record).unwrap();
+        (record, len)
+    }
+
+    /// Returns whether opening `record` on `device` under `key` fails authentication.
+    fn fails_authentication(device: u8, key: &str, record: &mut [u8]) -> bool {
+        matches!(
+            open_with::<TestValue>(&test_cipher(device), key, record),
+            Err(SerializationError::Custom(TAMPERED))
+        )
+    }
+
+    #[test]
+    fn round_trip() {
+        let value = (0x1234_5678, *b"abcd");
+        let (mut record, len) = test_seal("key", &value);
+        let serialized_len = postcard::to_slice(&value, &mut [0; 16]).unwrap().len();
+        assert_eq!(len, serialized_len + OVERHEAD);
+        assert_eq!(&record[..NONCE_LEN], &NONCE);
+
+        let opened: TestValue = open_with(&test_cipher(1), "key", &mut record[..len]).unwrap();
+        assert_eq!(opened, value);
+    }
+
+    #[test]
+    fn tampered() {
+        let (record, len) = test_seal("key", &(1, *b"abcd"));
+        for index in 0..len {
+            let mut modified = record;
+            modified[index] ^= 0x01;
+            assert!(fails_authentication(1, "key", &mut modified[..len]));
+        }
+
+        let mut truncated = record;
+        assert!(fails_authentication(1, "key", &mut truncated[..len - 1]));
+        assert!(fails_authentication(
+            1,
+            "key",
+            &mut truncated[..OVERHEAD - 1]
+        ));
+    }
+
+    #[test]
+    fn wrong_key() {
+        let (record, len) = test_seal("key", &(1, *b"abcd"));
+
+        let mut moved = record;
+        assert!(fails_authentication(1, "other", &mut moved[..len]));
+
+        let mut other_device = record;
+        assert!(fails_authentication(2, "key", &mut other_device[..len]));
+    }
+
+    #[test]
+    fn max_value_len_accounts_for_key() {
+        let long_key = "ariel-os-coap.own-edhoc-credential";
+        assert_eq!(
+            max_value_len(long_key),
+            DATA_BUFFER_SIZE - KEY_OVERHEAD - long_key.len() - OVERHEAD
+        );
+        assert_eq!(max_value_len(&"k".repeat(DATA_BUFFER_SIZE)), 0);
+
+        let zeros = [0u8; DATA_BUFFER_SIZE];
+        for key in ["k", long_key] {
+            let max = max_value_len(key);
+            let mut record = [0; DATA_BUFFER_SIZE];
+
+            // Slices are serialized with a 1-byte length prefix up to 127 bytes.
+            let fitting = &zeros[..max - 1];
+            let len = seal_with(&test_cipher(1), &NONCE, key, &fitting, &mut record).unwrap();
+            assert_eq!(len + key.len() + KEY_OVERHEAD, DATA_BUFFER_SIZE);
+
+            let too_long = &zeros[..max];
+            assert!(matches!(
+                seal_with(&test_cipher(1), &NONCE, key, &too_long, &mut record),
+                Err(SerializationError::BufferTooSmall)
+            ));
+        }
+    }
+}
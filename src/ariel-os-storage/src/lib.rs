@@ -4,15 +4,21 @@
 //! While not doing so won't cause unsafety, it might return garbage data, or panic.
 
 #![cfg_attr(not(test), no_std)]
+#![cfg_attr(nightly, feature(doc_auto_cfg))]
 #![deny(missing_docs)]
 // TODO: overhaul errors
 #![expect(clippy::missing_errors_doc)]
 
+pub mod blob;
+#[cfg(feature = "encrypted")]
+pub mod encrypted;
 mod postcard_value;
 mod storage;
 
+#[cfg(not(test))]
 use core::ops::Range;
 
+#[cfg(not(test))]
 use ariel_os_hal::hal::{
     OptionalPeripherals,
     storage::{Flash, FlashError, init as flash_init},
@@ -25,6 +31,9 @@ use embassy_sync::{
 
 pub use storage::*;
 
+#[cfg(test)]
+use test_storage::{Flash, FlashError};
+
 static STORAGE: OnceLock<Mutex<CriticalSectionRawMutex, Storage<Flash>>> = OnceLock::new();
 
 const MARKER_KEY: &str = "ARIEL_INIT_MARK";
@@ -36,6 +45,7 @@ const MARKER_VALUE: u8 = 0;
 /// This function is also the place to configure a platform dependent `OFFSET`,
 /// which configures an offset between the linker flash address map and the
 /// flash driver address map.
+#[cfg(not(test))]
 fn flash_range_from_linker() -> Range<u32> {
     #[cfg(all(context = "nrf", not(context = "nrf5340-net")))]
     const OFFSET: usize = 0x0;
@@ -63,6 +73,7 @@ fn flash_range_from_linker() -> Range<u32> {
     start..end
 }
 
+#[cfg(not(test))]
 fn init_(p: &mut OptionalPeripherals) {
     use ariel_os_debug::log::info;
     let flash_range = flash_range_from_linker();
@@ -80,6 +91,7 @@ fn init_(p: &mut OptionalPeripherals) {
 ///
 /// Panics when initializing the flash fails.
 #[doc(hidden)]
+#[cfg(not(test))]
 pub async fn init(p: &mut OptionalPeripherals) {
     init_(p);
 
@@ -136,6 +148,23 @@ pub async fn remove(key: &str) -> Result<(), sequential_storage::Error<FlashErro
     lock().await.remove(key).await
 }
 
+/// Calls `f` with the key of every item stored in flash.
+///
+/// A key may be passed repeatedly if its value was overwritten; the key used internally to
+/// detect initialized storage is not passed.
+pub async fn for_each_key(
+    mut f: impl FnMut(&str),
+) -> Result<(), sequential_storage::Error<FlashError>> {
+    lock()
+        .await
+        .for_each_key(|key| {
+            if key != MARKER_KEY {
+                f(key);
+            }
+        })
+        .await
+}
+
 /// Resets the flash in the entire flash range.
 pub async fn erase_all() -> Result<(), sequential_storage::Error<FlashError>> {
     let mut s = lock().await;
@@ -165,3 +194,30 @@ pub async fn erase_all() -> Result<(), sequential_storage::Error<FlashError>> {
 pub async fn lock() -> MutexGuard<'static, CriticalSectionRawMutex, storage::Storage<Flash>> {
     STORAGE.get().await.lock().await
 }
+
+/// Backs the global storage with a mock flash in tests.
+#[cfg(test)]
+pub(crate) mod test_storage {
+    use sequential_storage::mock_flash::{MockFlashBase, WriteCountCheck};
+
+    use super::{Mutex, STORAGE, Storage};
+
+    const PAGES: usize = 8;
+    const BYTES_PER_WORD: usize = 4;
+    const PAGE_WORDS: usize = 256;
+
+    pub(crate) type Flash = MockFlashBase<PAGES, BYTES_PER_WORD, PAGE_WORDS>;
+    pub(crate) use sequential_storage::mock_flash::MockFlashError as FlashError;
+
+    /// Initializes the global storage, which is shared by all tests.
+    ///
+    /// Tests use distinct keys, as they run concurrently.
+    pub(crate) fn init() {
+        #[expect(clippy::cast_possible_truncation, reason = "the mock flash is small")]
+        const SIZE: u32 = (PAGES * BYTES_PER_WORD * PAGE_WORDS) as u32;
+        let _ = STORAGE.init(Mutex::new(Storage::new(
+            Flash::new(WriteCountCheck::Twice, None, true),
+            0..SIZE,
+        )));
+    }
+}
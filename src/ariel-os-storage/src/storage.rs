@@ -7,7 +7,7 @@ use embedded_storage_async::nor_flash::{ErrorType, MultiwriteNorFlash, NorFlash}
 use sequential_storage::{
     cache::NoCache,
     erase_all,
-    map::{Value, fetch_item, remove_item, store_item},
+    map::{Value, fetch_all_items, fetch_item, remove_item, store_item},
 };
 
 pub use crate::postcard_value::PostcardValue;
@@ -18,6 +18,11 @@ pub const MAX_KEY_LEN: usize = 64usize;
 /// Data buffer length.
 pub const DATA_BUFFER_SIZE: usize = 128usize;
 
+/// Upper bound on the bytes that `sequential-storage` adds to a record's key.
+///
+/// A record's key and value share the [`DATA_BUFFER_SIZE`] bytes of the data buffer.
+pub(crate) const KEY_OVERHEAD: usize = 4;
+
 /// Object holding an instance of a key-value pair storage.
 ///
 /// You should probably look into using the global instance accessible via
@@ -123,6 +128,125 @@ impl<F: NorFlash> Storage<F> {
         Ok(postcard_value.map(PostcardValue::into_inner))
     }
 
+    /// Encrypts a key-value pair and stores it into flash memory.
+    ///
+    /// It will overwrite the last value that has the same key.
+    /// See [`crate::encrypted`] for details.
+    ///
+    /// # Panics
+    ///
+    /// Currently panics if `key.len() > MAX_KEY_LEN`, or if the device does not provide an
+    /// identity.
+    #[cfg(feature = "encrypted")]
+    pub async fn insert_encrypted<'d, V>(
+        &mut self,
+        key: &str,
+        value: V,
+    ) -> Result<(), sequential_storage::Error<<F as ErrorType>::Error>>
+    where
+        V: Serialize + Deserialize<'d> + Into<PostcardValue<V>>,
+    {
+        let mut record = [0; DATA_BUFFER_SIZE];
+        let len = crate::encrypted::seal(key, &value, &mut record)
+            .map_err(sequential_storage::Error::SerializationError)?;
+        let (record, _) = record.split_at(len);
+        self.insert_raw(key, record).await
+    }
+
+    /// Gets and decrypts the last stored value from the flash that is associated with the given
+    /// key.
+    ///
+    /// If no value with the key is found, `None` is returned.
+    /// See [`crate::encrypted`] for details.
+    ///
+    /// # Panics
+    ///
+    /// Currently panics if `key.len() > MAX_KEY_LEN`, or if the device does not provide an
+    /// identity.
+    #[cfg(feature = "encrypted")]
+    pub async fn get_encrypted<V>(
+        &mut self,
+        key: &str,
+    ) -> Result<Option<V>, sequential_storage::Error<<F as ErrorType>::Error>>
+    where
+        V: Serialize + for<'d> Deserialize<'d> + Into<PostcardValue<V>>,
+    {
+        let array_key = ArrayString::<MAX_KEY_LEN>::from(key).unwrap();
+        let mut data_buffer = [0; DATA_BUFFER_SIZE];
+
+        let Some(stored) = self.fetch_bytes(&array_key, &mut data_buffer).await? else {
+            return Ok(None);
+        };
+
+        // Decryption happens in place, and the fetched slice is borrowed immutably.
+        let mut record = [0; DATA_BUFFER_SIZE];
+        let (record, _) = record.split_at_mut(stored.len());
+        record.copy_from_slice(stored);
+
+        crate::encrypted::open(key, record)
+            .map(Some)
+            .map_err(sequential_storage::Error::SerializationError)
+    }
+
+    /// Gets the raw bytes of the last stored value associated with the given key.
+    ///
+    /// The returned slice borrows from `data_buffer`.
+    pub(crate) async fn fetch_bytes<'b>(
+        &mut self,
+        key: &ArrayString<MAX_KEY_LEN>,
+        data_buffer: &'b mut [u8; DATA_BUFFER_SIZE],
+    ) -> Result<Option<&'b [u8]>, sequential_storage::Error<<F as ErrorType>::Error>> {
+        fetch_item::<_, &[u8], _>(
+            &mut self.flash,
+            self.storage_range.clone(),
+            &mut NoCache::new(),
+            data_buffer,
+            key,
+        )
+        .await
+    }
+
+    /// Stores raw bytes under the given key.
+    pub(crate) async fn store_bytes(
+        &mut self,
+        key: &ArrayString<MAX_KEY_LEN>,
+        value: &[u8],
+    ) -> Result<(), sequential_storage::Error<<F as ErrorType>::Error>> {
+        let mut data_buffer = [0; DATA_BUFFER_SIZE];
+        store_item(
+            &mut self.flash,
+            self.storage_range.clone(),
+            &mut NoCache::new(),
+            &mut data_buffer,
+            key,
+            &value,
+        )
+        .await
+    }
+
+    /// Calls `f` with the key of every item stored in this [`Storage`] instance.
+    ///
+    /// Keys whose value was overwritten are passed once for every value that has not been
+    /// garbage collected yet.
+    pub async fn for_each_key(
+        &mut self,
+        mut f: impl FnMut(&str),
+    ) -> Result<(), sequential_storage::Error<<F as ErrorType>::Error>> {
+        let mut data_buffer = [0; DATA_BUFFER_SIZE];
+        let mut cache = NoCache::new();
+        let mut items = fetch_all_items::<ArrayString<MAX_KEY_LEN>, _, _>(
+            &mut self.flash,
+            self.storage_range.clone(),
+            &mut cache,
+            &mut data_buffer,
+        )
+        .await?;
+        while let Some((key, _)) = items.next::<&[u8]>(&mut data_buffer).await? {
+            f(&key);
+        }
+        Ok(())
+    }
+
     /// Resets the flash in the entire flash range of this [`Storage`] instance.
     pub async fn erase_all(
         &mut self,
@@ -152,13 +276,21 @@ impl<F: MultiwriteNorFlash> Storage<F> {
         key: &str,
     ) -> Result<(), sequential_storage::Error<<F as ErrorType>::Error>> {
         let key = ArrayString::<MAX_KEY_LEN>::from(key).unwrap();
+        self.remove_key(&key).await
+    }
+
+    /// Deletes an item from flash, given its key in its stored form.
+    pub(crate) async fn remove_key(
+        &mut self,
+        key: &ArrayString<MAX_KEY_LEN>,
+    ) -> Result<(), sequential_storage::Error<<F as ErrorType>::Error>> {
         let mut data_buffer = [0; DATA_BUFFER_SIZE];
         remove_item(
             &mut self.flash,
             self.storage_range.clone(),
             &mut NoCache::new(),
             &mut data_buffer,
-            &key,
+            key,
         )
         .await
     }
@@ -816,3 +816,15 @@ pub fn current_stack_limits() -> Option<(usize, usize)> {
             .map(|thread| (thread.stack_lowest, thread.stack_highest))
     })
 }
+
+/// Returns a thread's stack limits (lowest, highest).
+///
+/// Returns `None` if this is not a valid thread.
+pub fn stack_limits(thread_id: ThreadId) -> Option<(usize, usize)> {
+    SCHEDULER.with(|scheduler| {
+        scheduler.is_valid_tid(thread_id).then(|| {
+            let thread = scheduler.get_unchecked(thread_id);
+            (thread.stack_lowest, thread.stack_highest)
+        })
+    })
+}
@@ -33,6 +33,7 @@ macro_rules! define_env_with_default_macro {
 
 define_env_with_default_macro!(usize_from_env_or, usize, "a usize");
 define_env_with_default_macro!(u8_from_env_or, u8, "a u8");
+define_env_with_default_macro!(u32_from_env_or, u32, "a u32");
 
 #[macro_export]
 macro_rules! bool_from_env_or {
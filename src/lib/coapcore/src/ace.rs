@@ -69,6 +69,8 @@ pub struct HeaderMap<'a> {
     #[n(1)]
     // Might be extended as more exotic algorithms are supported
     pub alg: Option<i32>,
+    #[cbor(b(4), with = "minicbor::bytes")]
+    pub kid: Option<&'a [u8]>,
     #[cbor(b(5), with = "minicbor::bytes")]
     pub(crate) iv: Option<&'a [u8]>,
 }
@@ -78,6 +80,7 @@ impl HeaderMap<'_> {
     fn updated_with(&self, other: &Self) -> Self {
         Self {
             alg: self.alg.or(other.alg),
+            kid: self.kid.or(other.kid),
             iv: self.iv.or(other.iv),
         }
     }
@@ -249,6 +252,8 @@ pub struct CwtClaimsSet<'a> {
     pub aud: Option<&'a str>,
     #[n(4)]
     pub(crate) exp: u64,
+    #[n(5)]
+    pub(crate) nbf: Option<u64>,
     #[n(6)]
     pub(crate) iat: u64,
     #[b(8)]
@@ -439,6 +444,7 @@ impl AceCborAuthzInfoResponse {
 pub(crate) fn process_acecbor_authz_info<GC: crate::GeneralClaims>(
     payload: &[u8],
     authorities: &impl crate::seccfg::ServerSecurityConfig<GeneralClaims = GC>,
+    time: &mut impl crate::time::TimeProvider,
     nonce2: [u8; OWN_NONCE_LEN],
     server_recipient_id: impl FnOnce(&[u8]) -> COwn,
 ) -> Result<(AceCborAuthzInfoResponse, liboscore::PrimitiveContext, GC), CredentialError> {
@@ -480,6 +486,7 @@ pub(crate) fn process_acecbor_authz_info<GC: crate::GeneralClaims>(
 
     let (processed, parsed) =
         authorities.decrypt_symmetric_token(&headers, aad_encoded.as_ref(), buffer)?;
+    time.past_trusted(parsed.iat);
 
     // Currently disabled because no formatting is available while there; works with
     // <https://codeberg.org/chrysn/minicbor-adapters/pulls/1>
@@ -524,6 +531,7 @@ pub(crate) fn process_acecbor_authz_info<GC: crate::GeneralClaims>(
 pub(crate) fn process_edhoc_token<GeneralClaims>(
     ead3: &[u8],
     authorities: &impl crate::seccfg::ServerSecurityConfig<GeneralClaims = GeneralClaims>,
+    time: &mut impl crate::time::TimeProvider,
 ) -> Result<(lakers::Credential, GeneralClaims), CredentialError> {
     let mut buffer = heapless::Vec::<u8, MAX_SUPPORTED_ACCESSTOKEN_LEN>::new();
 
@@ -556,6 +564,7 @@ pub(crate) fn process_edhoc_token<GeneralClaims>(
     } else {
         return Err(CredentialErrorDetail::UnsupportedExtension.into());
     };
+    time.past_trusted(parsed.iat);
 
     let Cnf {
         osc: None,
@@ -0,0 +1,626 @@
+//! Client side of OSCORE/EDHOC: protecting outgoing requests.
+//!
+//! An [`OscoreEdhocClient`] holds security contexts with any number of peers. For every exchange,
+//! it is combined with a CoAP client stack that sends requests to a particular peer through
+//! [`.to()`][OscoreEdhocClient::to]; the resulting [`ProtectedStack`] is itself a
+//! [`coap_request::Stack`], and transparently protects requests built into it and verifies the
+//! responses.
+//!
+//! If no security context is established with the peer yet, an EDHOC exchange is run first (in the
+//! role of the initiator), and the resulting EDHOC message 3 is sent along with the first OSCORE
+//! request (as described in [RFC9668](https://www.rfc-editor.org/rfc/rfc9668.html)).
+//!
+//! Block-wise transfer ([RFC7959](https://www.rfc-editor.org/rfc/rfc7959.html)) is performed by
+//! sending requests with Block1 or Block2 options through the [`ProtectedStack`]: those are
+//! protected as inner options, and every block is a request of its own.
+#![expect(
+    clippy::redundant_closure_for_method_calls,
+    reason = "all occurrences of this make the code strictly less obvious to understand"
+)]
+
+use coap_message::{Code as _, MessageOption, MinimalWritableMessage, ReadableMessage};
+use coap_message_implementations::{inmemory, inmemory_write};
+use defmt_or_log::{Debug2Format, debug, error, trace};
+
+use crate::helpers::COwn;
+use crate::seccfg::ServerSecurityConfig;
+
+const MAX_CONTEXTS: usize = 4;
+const _MAX_CONTEXTS_CHECK: () = assert!(MAX_CONTEXTS <= COwn::GENERATABLE_VALUES);
+
+/// Space allocated for each copy of a message that is made while protecting it or verifying its
+/// response.
+///
+/// This matches the size used in the server side; see there for why these copies are needed.
+const MESSAGE_BUFFER_SIZE: usize = 1152;
+
+/// Content-Format `application/cid-edhoc+cbor-seq`, used for EDHOC message 1 with a prefixed
+/// `true`.
+const CONTENT_FORMAT_CID_EDHOC: u8 = 65;
+
+/// Copy of the OSCORE option
+type OscoreOption = heapless::Vec<u8, 16>;
+
+/// Errors that can occur when sending a request through a [`ProtectedStack`].
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum ClientError<T> {
+    /// The underlying stack failed to deliver the request or receive a response.
+    Transport(T),
+    /// The request could not be built, typically because it exceeds the available space.
+    RequestBuild,
+    /// The EDHOC exchange with the peer failed.
+    ///
+    /// This includes the cases of the client having no credential configured, and of the peer's
+    /// credential not being trusted.
+    Edhoc,
+    /// The request could not be protected.
+    Protect,
+    /// The response could not be verified.
+    ///
+    /// This includes unprotected responses, such as a 4.01 Unauthorized sent by a peer that lost
+    /// the security context. As anyone on the path can send those, the security context is kept;
+    /// when the application has other reasons to assume that the peer lost it, it can start over
+    /// through [`OscoreEdhocClient::forget()`].
+    Unprotect,
+}
+
+/// A security context held by an [`OscoreEdhocClient`].
+struct ClientContext<Peer> {
+    peer: Peer,
+    stage: ClientStage,
+}
+
+enum ClientStage {
+    Empty,
+    Oscore {
+        context: liboscore::PrimitiveContext,
+        /// The identifier the peer uses to address us in OSCORE (the EDHOC `C_I`).
+        recipient_id: COwn,
+        /// EDHOC message 3 that is yet to be sent along with the first OSCORE request.
+        ///
+        /// It is cleared once a response has been verified, which proves that the peer has
+        /// processed it.
+        pending_message_3: Option<lakers::BufferMessage3>,
+    },
+}
+
+impl<Peer> ClientContext<Peer> {
+    fn recipient_id(&self) -> Option<COwn> {
+        match &self.stage {
+            ClientStage::Empty => None,
+            ClientStage::Oscore { recipient_id, .. } => Some(*recipient_id),
+        }
+    }
+
+    fn is_for(&self, peer: &Peer) -> bool
+    where
+        Peer: PartialEq,
+    {
+        matches!(self.stage, ClientStage::Oscore { .. }) && self.peer == *peer
+    }
+}
+
+const LEVEL_ESTABLISHED: usize = 0;
+const LEVEL_EMPTY: usize = 1;
+const LEVEL_COUNT: usize = 2;
+
+impl<Peer> crate::oluru::PriorityLevel for ClientContext<Peer> {
+    fn level(&self) -> usize {
+        match self.stage {
+            ClientStage::Empty => LEVEL_EMPTY,
+            ClientStage::Oscore { .. } => LEVEL_ESTABLISHED,
+        }
+    }
+}
+
+/// A pool of security contexts with different peers.
+type ClientContextPool<Peer> =
+    crate::oluru::OrderedPool<ClientContext<Peer>, MAX_CONTEXTS, LEVEL_COUNT>;
+
+/// A CoAP client component that establishes OSCORE security contexts with peers through EDHOC.
+///
+/// This is the client side counterpart of [`OscoreEdhocHandler`][crate::OscoreEdhocHandler]. It
+/// is configured with the same [`ServerSecurityConfig`]: the credential the client uses towards
+/// peers is its [`own_edhoc_credential()`][ServerSecurityConfig::own_edhoc_credential], and peers
+/// are only accepted if their `ID_CRED_R` can be [expanded][ServerSecurityConfig::expand_id_cred_r]
+/// into a trusted credential.
+///
+/// The `Peer` type identifies which peer a security context belongs to; it is typically the
+/// peer's socket address. Up to a fixed number of contexts are kept; when more are needed, the
+/// least recently used context is evicted.
+pub struct OscoreEdhocClient<
+    Peer: PartialEq,
+    Crypto: lakers::Crypto,
+    CryptoFactory: Fn() -> Crypto,
+    SSC: ServerSecurityConfig,
+> {
+    pool: ClientContextPool<Peer>,
+    authorities: SSC,
+    crypto_factory: CryptoFactory,
+}
+
+impl<
+    Peer: PartialEq,
+    Crypto: lakers::Crypto,
+    CryptoFactory: Fn() -> Crypto,
+    SSC: ServerSecurityConfig,
+> OscoreEdhocClient<Peer, Crypto, CryptoFactory, SSC>
+{
+    /// Creates a new client.
+    ///
+    /// The `crypto_factory` is called whenever an EDHOC exchange is started.
+    pub fn new(authorities: SSC, crypto_factory: CryptoFactory) -> Self {
+        Self {
+            pool: ClientContextPool::new(),
+            authorities,
+            crypto_factory,
+        }
+    }
+
+    /// Combines the client with a CoAP stack that sends requests to `peer`.
+    ///
+    /// Requests sent through the returned stack are protected with the security context
+    /// established with `peer`, establishing one first if needed.
+    pub fn to<S: coap_request::Stack>(
+        &mut self,
+        stack: S,
+        peer: Peer,
+    ) -> ProtectedStack<'_, S, Peer, Crypto, CryptoFactory, SSC> {
+        ProtectedStack {
+            client: self,
+            stack,
+            peer,
+        }
+    }
+
+    /// Removes any security context established with `peer`.
+    pub fn forget(&mut self, peer: &Peer) {
+        self.pool.lookup(
+            |c| c.is_for(peer),
+            |c| {
+                c.stage = ClientStage::Empty;
+            },
+        );
+    }
+}
+
+/// A CoAP stack that protects requests with OSCORE.
+///
+/// This is created through [`OscoreEdhocClient::to()`].
+pub struct ProtectedStack<
+    'a,
+    S: coap_request::Stack,
+    Peer: PartialEq,
+    Crypto: lakers::Crypto,
+    CryptoFactory: Fn() -> Crypto,
+    SSC: ServerSecurityConfig,
+> {
+    client: &'a mut OscoreEdhocClient<Peer, Crypto, CryptoFactory, SSC>,
+    stack: S,
+    peer: Peer,
+}
+
+impl<
+    S: coap_request::Stack,
+    Peer: PartialEq + Clone,
+    Crypto: lakers::Crypto,
+    CryptoFactory: Fn() -> Crypto,
+    SSC: ServerSecurityConfig,
+> ProtectedStack<'_, S, Peer, Crypto, CryptoFactory, SSC>
+{
+    /// Runs an EDHOC exchange with the peer, and stores the resulting security context.
+    ///
+    /// EDHOC message 3 is not sent yet, but stored to be sent along with the first OSCORE request.
+    async fn establish(&mut self) -> Result<(), ClientError<S::TransportError>> {
+        let edhoc_error = |e: lakers::EDHOCError| {
+            error!("EDHOC processing failed: {:?}", Debug2Format(&e));
+            ClientError::Edhoc
+        };
+
+        let (own_credential, own_key) =
+            self.client
+                .authorities
+                .own_edhoc_credential()
+                .ok_or_else(|| {
+                    error!("No own credential available for EDHOC.");
+                    ClientError::Edhoc
+                })?;
+
+        let c_i = COwn::not_in_iter(
+            self.client
+                .pool
+                .iter()
+                .filter_map(|entry| entry.recipient_id()),
+        );
+
+        let (initiator, message_1) = lakers::EdhocInitiator::new(
+            (self.client.crypto_factory)(),
+            lakers::EDHOCMethod::StatStat,
+            lakers::EDHOCSuite::CipherSuite2,
+        )
+        .prepare_message_1(Some(c_i.into()), &None)
+        .map_err(edhoc_error)?;
+
+        debug!("Sending EDHOC message 1 with C_I = {:?}", c_i);
+        let message_2 = self
+            .stack
+            .request(EdhocMessage1 {
+                message_1: message_1.as_slice(),
+            })
+            .await
+            .map_err(ClientError::Transport)??;
+
+        let (mut initiator, c_r, id_cred_r, ead_2) =
+            initiator.parse_message_2(&message_2).map_err(edhoc_error)?;
+
+        if let Some(ead_2) = ead_2 {
+            if ead_2.is_critical {
+                error!("Critical EAD2 item received, aborting");
+                return Err(ClientError::Edhoc);
+            }
+        }
+
+        let Some(cred_r) = self.client.authorities.expand_id_cred_r(id_cred_r) else {
+            error!("Peer's credential is not trusted.");
+            return Err(ClientError::Edhoc);
+        };
+
+        initiator
+            .set_identity(own_key, own_credential)
+            .map_err(edhoc_error)?;
+        let initiator = initiator.verify_message_2(cred_r).map_err(edhoc_error)?;
+        let (initiator, message_3, _prk_out) = initiator
+            .prepare_message_3(lakers::CredentialTransfer::ByReference, &None)
+            .map_err(edhoc_error)?;
+        let mut initiator = initiator
+            .completed_without_message_4()
+            .map_err(edhoc_error)?;
+
+        let oscore_secret = initiator.edhoc_exporter(0u8, &[], 16); // label is 0
+        let oscore_salt = initiator.edhoc_exporter(1u8, &[], 8); // label is 1
+        let oscore_secret = &oscore_secret[..16];
+        let oscore_salt = &oscore_salt[..8];
+
+        // The roles are swapped compared to the server side.
+        let sender_id = c_r.as_slice();
+        let recipient_id = c_i.as_slice();
+
+        // FIXME probe cipher suite
+        let hkdf = liboscore::HkdfAlg::from_number(crate::iana::cose_alg::HKDF_HMAC256256).unwrap();
+        let aead =
+            liboscore::AeadAlg::from_number(crate::iana::cose_alg::AES_CCM_16_64_128).unwrap();
+
+        let immutables = liboscore::PrimitiveImmutables::derive(
+            hkdf,
+            oscore_secret,
+            oscore_salt,
+            None,
+            aead,
+            sender_id,
+            recipient_id,
+        )
+        .map_err(|e| {
+            error!("Deriving OSCORE context failed: {:?}", Debug2Format(&e));
+            ClientError::Edhoc
+        })?;
+
+        let context = liboscore::PrimitiveContext::new_from_fresh_material(immutables);
+
+        debug!("EDHOC completed, OSCORE context established.");
+
+        self.client.pool.force_insert(ClientContext {
+            peer: self.peer.clone(),
+            stage: ClientStage::Oscore {
+                context,
+                recipient_id: c_i,
+                pending_message_3: Some(message_3),
+            },
+        });
+
+        Ok(())
+    }
+}
+
+impl<
+    S: coap_request::Stack,
+    Peer: PartialEq + Clone,
+    Crypto: lakers::Crypto,
+    CryptoFactory: Fn() -> Crypto,
+    SSC: ServerSecurityConfig,
+> coap_request::Stack for ProtectedStack<'_, S, Peer, Crypto, CryptoFactory, SSC>
+{
+    type RequestUnionError =
+        <inmemory_write::Message<'static> as MinimalWritableMessage>::UnionError;
+    type RequestMessage<'b>
+        = inmemory_write::Message<'b>
+    where
+        Self: 'b;
+    type ResponseMessage<'b>
+        = inmemory::Message<'b>
+    where
+        Self: 'b;
+    type TransportError = ClientError<S::TransportError>;
+
+    async fn request<Req: coap_request::Request<Self>>(
+        &mut self,
+        mut request: Req,
+    ) -> Result<Req::Output, Self::TransportError> {
+        let peer = self.peer.clone();
+
+        if !self.client.pool.iter().any(|c| c.is_for(&peer)) {
+            self.establish().await?;
+        }
+
+        // Build the plaintext request. The same buffer is later reused for the plaintext
+        // response.
+        let mut plaintext_code = 0;
+        let mut plaintext_buffer = [0u8; MESSAGE_BUFFER_SIZE];
+        let mut plaintext =
+            inmemory_write::Message::new(&mut plaintext_code, &mut plaintext_buffer);
+        let carry = request.build_request(&mut plaintext).await.map_err(|e| {
+            error!("Building request failed: {:?}", Debug2Format(&e));
+            ClientError::RequestBuild
+        })?;
+        let plaintext_len = plaintext.finish();
+        #[allow(
+            clippy::indexing_slicing,
+            reason = "length is populated by the message"
+        )]
+        let plaintext = inmemory::Message::new(plaintext_code, &plaintext_buffer[..plaintext_len]);
+
+        // Protect it into a second message.
+        let mut protected_code = 0;
+        let mut protected_buffer = [0u8; MESSAGE_BUFFER_SIZE];
+        let mut protected =
+            inmemory_write::Message::new(&mut protected_code, &mut protected_buffer);
+        let mut message_3 = None;
+        let protected_result = self.client.pool.lookup(
+            |c| c.is_for(&peer),
+            |c| {
+                let ClientStage::Oscore {
+                    context,
+                    pending_message_3,
+                    ..
+                } = &mut c.stage
+                else {
+                    unreachable!("is_for only matches established contexts");
+                };
+                message_3.clone_from(pending_message_3);
+                liboscore::protect_request(&mut protected, context, |inner| {
+                    copy_message(&plaintext, inner)
+                })
+            },
+        );
+        let Some(Ok((mut correlation, Ok(())))) = protected_result else {
+            error!("Protecting request failed.");
+            return Err(ClientError::Protect);
+        };
+        let protected_len = protected.finish();
+        #[allow(
+            clippy::indexing_slicing,
+            reason = "length is populated by the message"
+        )]
+        let protected = inmemory::Message::new(protected_code, &protected_buffer[..protected_len]);
+        if message_3
+            .as_ref()
+            .is_some_and(|m| m.as_slice().len() + protected.payload().len() > MESSAGE_BUFFER_SIZE)
+        {
+            error!("Request with EDHOC message 3 exceeds buffer");
+            return Err(ClientError::RequestBuild);
+        }
+
+        // Send it, and copy the response into a message libOSCORE can work on.
+        let mut response_code = 0;
+        let mut response_buffer = [0u8; MESSAGE_BUFFER_SIZE];
+        let mut response = inmemory_write::Message::new(&mut response_code, &mut response_buffer);
+        let oscore_option = self
+            .stack
+            .request(Forward {
+                message: &protected,
+                message_3: message_3.as_ref().map(|m| m.as_slice()),
+                response: &mut response,
+            })
+            .await
+            .map_err(ClientError::Transport)??;
+
+        let Some(oscore_option) = oscore_option else {
+            // Most likely a 4.01 Unauthorized because the peer lost its context (or never
+            // processed message 3). As it is not authenticated, it may just as well have been
+            // injected to make us run EDHOC again, so the context is kept.
+            debug!("Unprotected response received, keeping security context.");
+            return Err(ClientError::Unprotect);
+        };
+        let Ok(oscore_option) = liboscore::OscoreOption::parse(&oscore_option) else {
+            error!("Response contains unparsable OSCORE option.");
+            return Err(ClientError::Unprotect);
+        };
+
+        // Unprotect the response into the (now unused) plaintext buffer.
+        let mut plaintext_code = 0;
+        let mut plaintext =
+            inmemory_write::Message::new(&mut plaintext_code, &mut plaintext_buffer);
+        let unprotected = self.client.pool.lookup(
+            |c| c.is_for(&peer),
+            |c| {
+                let ClientStage::Oscore {
+                    context,
+                    pending_message_3,
+                    ..
+                } = &mut c.stage
+                else {
+                    unreachable!("is_for only matches established contexts");
+                };
+                let result = liboscore::unprotect_response(
+                    &mut response,
+                    context,
+                    oscore_option,
+                    &mut correlation,
+                    |inner| copy_message(inner, &mut plaintext),
+                );
+                if matches!(result, Ok(Ok(()))) {
+                    *pending_message_3 = None;
+                }
+                result
+            },
+        );
+        let Some(Ok(Ok(()))) = unprotected else {
+            error!("Verifying response failed.");
+            return Err(ClientError::Unprotect);
+        };
+        trace!("Response verified.");
+        let plaintext_len = plaintext.finish();
+        #[allow(
+            clippy::indexing_slicing,
+            reason = "length is populated by the message"
+        )]
+        let plaintext = inmemory::Message::new(plaintext_code, &plaintext_buffer[..plaintext_len]);
+
+        Ok(request.process_response(&plaintext, carry).await)
+    }
+}
+
+/// Copies code, options and payload from one message into another.
+fn copy_message<M: MinimalWritableMessage>(
+    from: &impl ReadableMessage,
+    to: &mut M,
+) -> Result<(), M::UnionError> {
+    to.set_code(M::Code::new(from.code().into())?);
+    for opt in from.options() {
+        to.add_option(M::OptionNumber::new(opt.number())?, opt.value())?;
+    }
+    to.set_payload(from.payload())?;
+    Ok(())
+}
+
+/// A request that sends EDHOC message 1 and produces message 2.
+struct EdhocMessage1<'m> {
+    message_1: &'m [u8],
+}
+
+impl EdhocMessage1<'_> {
+    fn build<M: MinimalWritableMessage>(&self, request: &mut M) -> Result<(), M::UnionError> {
+        request.set_code(M::Code::new(coap_numbers::code::POST)?);
+        let uri_path = M::OptionNumber::new(coap_numbers::option::URI_PATH)?;
+        request.add_option(uri_path, b".well-known")?;
+        request.add_option(uri_path, b"edhoc")?;
+        request.add_option(
+            M::OptionNumber::new(coap_numbers::option::CONTENT_FORMAT)?,
+            &[CONTENT_FORMAT_CID_EDHOC],
+        )?;
+        // The initiator's connection identifier is `true`, indicating that this is message 1.
+        let mut payload = heapless::Vec::<u8, { lakers::MAX_MESSAGE_SIZE_LEN + 1 }>::new();
+        // Infallible: message 1 is at most MAX_MESSAGE_SIZE_LEN long.
+        let _ = payload.push(0xf5);
+        let _ = payload.extend_from_slice(self.message_1);
+        request.set_payload(&payload)?;
+        Ok(())
+    }
+}
+
+impl<S: coap_request::Stack> coap_request::Request<S> for EdhocMessage1<'_> {
+    type Output = Result<lakers::BufferMessage2, ClientError<S::TransportError>>;
+    type Carry = ();
+
+    async fn build_request(
+        &mut self,
+        request: &mut S::RequestMessage<'_>,
+    ) -> Result<(), S::RequestUnionError> {
+        self.build(request)
+    }
+
+    async fn process_response(
+        &mut self,
+        response: &S::ResponseMessage<'_>,
+        _carry: (),
+    ) -> Self::Output {
+        let code: u8 = response.code().into();
+        if code != coap_numbers::code::CHANGED {
+            error!("EDHOC message 1 was answered with code {:?}", code);
+            return Err(ClientError::Edhoc);
+        }
+        lakers::BufferMessage2::new_from_slice(response.payload()).map_err(|_| {
+            error!("EDHOC message 2 too long");
+            ClientError::Edhoc
+        })
+    }
+}
+
+/// A request that sends an already protected message, and copies the response.
+///
+/// If EDHOC message 3 is present, it is sent along as per RFC9668.
+///
+/// The response's OSCORE option is produced, if present.
+struct Forward<'m, 'r> {
+    message: &'m inmemory::Message<'m>,
+    message_3: Option<&'m [u8]>,
+    response: &'m mut inmemory_write::Message<'r>,
+}
+
+impl Forward<'_, '_> {
+    fn build<M: MinimalWritableMessage>(&self, request: &mut M) -> Result<(), M::UnionError> {
+        request.set_code(M::Code::new(self.message.code().into())?);
+        let mut edhoc_pending = self.message_3.is_some();
+        for opt in self.message.options() {
+            // Options need to be added in sequence; the EDHOC option (21) goes after the OSCORE
+            // option (9) and any Uri-Host or Uri-Port that are still in the outer message.
+            if edhoc_pending && opt.number() > coap_numbers::option::EDHOC {
+                request.add_option(M::OptionNumber::new(coap_numbers::option::EDHOC)?, &[])?;
+                edhoc_pending = false;
+            }
+            request.add_option(M::OptionNumber::new(opt.number())?, opt.value())?;
+        }
+        if edhoc_pending {
+            request.add_option(M::OptionNumber::new(coap_numbers::option::EDHOC)?, &[])?;
+        }
+
+        let ciphertext = self.message.payload();
+        match self.message_3 {
+            None => request.set_payload(ciphertext)?,
+            Some(message_3) => {
+                // FIXME: This copy goes away once we can require a MutableWritableMessage from
+                // the stack.
+                let mut payload = heapless::Vec::<u8, MESSAGE_BUFFER_SIZE>::new();
+                // Infallible: the combined length was checked before sending.
+                let _ = payload.extend_from_slice(message_3);
+                let _ = payload.extend_from_slice(ciphertext);
+                request.set_payload(&payload)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<S: coap_request::Stack> coap_request::Request<S> for Forward<'_, '_> {
+    type Output = Result<Option<OscoreOption>, ClientError<S::TransportError>>;
+    type Carry = ();
+
+    async fn build_request(
+        &mut self,
+        request: &mut S::RequestMessage<'_>,
+    ) -> Result<(), S::RequestUnionError> {
+        self.build(request)
+    }
+
+    async fn process_response(
+        &mut self,
+        response: &S::ResponseMessage<'_>,
+        _carry: (),
+    ) -> Self::Output {
+        let oscore_option = response
+            .options()
+            .find(|o| o.number() == coap_numbers::option::OSCORE)
+            .and_then(|o| OscoreOption::from_slice(o.value()).ok());
+        if oscore_option.is_none() {
+            let code: u8 = response.code().into();
+            debug!("Unprotected response with code {:?}", code);
+        }
+        // The response is read in sequence, so options are added in sequence as well.
+        copy_message(response, self.response).map_err(|e| {
+            error!("Response could not be copied: {:?}", Debug2Format(&e));
+            ClientError::Unprotect
+        })?;
+        Ok(oscore_option)
+    }
+}
@@ -2,20 +2,19 @@
 /// authorization step.
 #[derive(Debug)]
 pub struct CredentialError {
-    #[expect(
-        dead_code,
-        reason = "This is deliberately unused: The kind is merely a debug helper, and when no logging is active, code should not behave any different no matter in which way the credential processing failed"
-    )]
+    // The kind is merely a debug and audit helper: code should not behave any different no matter
+    // in which way the credential processing failed.
     detail: CredentialErrorDetail,
     pub(crate) position: Option<usize>,
 }
 
 /// Classification of a [`CredentialError`].
 ///
-/// The variants in here are mainly used for debug output, and all signify *that*
-/// the processing of the token was not successful. This type can be used to
-/// construct a [`CredentialError`].
-#[derive(Debug, Copy, Clone)]
+/// The variants in here are mainly used for debug output and [security
+/// events](crate::events::SecurityEvent), and all signify *that* the processing of the token was
+/// not successful. This type can be used to construct a [`CredentialError`].
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 #[non_exhaustive]
 pub enum CredentialErrorDetail {
     /// Input data contains items that violate a protocol.
@@ -47,6 +46,13 @@ pub enum CredentialErrorDetail {
     VerifyFailed,
 }
 
+impl CredentialError {
+    /// Returns the classification of the error.
+    pub(crate) fn detail(&self) -> CredentialErrorDetail {
+        self.detail
+    }
+}
+
 impl From<CredentialErrorDetail> for CredentialError {
     fn from(value: CredentialErrorDetail) -> Self {
         Self {
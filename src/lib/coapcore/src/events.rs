@@ -0,0 +1,147 @@
+//! Security relevant events that occur while processing requests.
+//!
+//! An [`OscoreEdhocHandler`](crate::OscoreEdhocHandler) reports [`SecurityEvent`]s to the function
+//! set through [`.with_security_events()`](crate::OscoreEdhocHandler::with_security_events),
+//! typically to produce audit records. Unlike the log output of this crate, events never contain
+//! key material: they only identify peers by the identifiers they sent (connection identifiers,
+//! OSCORE Key IDs or the `ID_CRED_x` presented in EDHOC).
+//!
+//! Events are reported synchronously while a request is processed; the reporting function should
+//! thus merely store or enqueue them.
+
+use crate::CredentialErrorKind;
+
+/// Maximum number of bytes retained of a [`PeerId`].
+pub const MAX_PEER_ID_LEN: usize = 32;
+
+/// An identifier that a peer used for itself or for a security context.
+///
+/// Identifiers longer than [`MAX_PEER_ID_LEN`] are truncated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerId {
+    bytes: heapless::Vec<u8, MAX_PEER_ID_LEN>,
+    truncated: bool,
+}
+
+impl PeerId {
+    pub(crate) fn new(bytes: &[u8]) -> Self {
+        let retained = bytes.get(..MAX_PEER_ID_LEN).unwrap_or(bytes);
+        Self {
+            bytes: heapless::Vec::from_slice(retained).expect("length was limited"),
+            truncated: retained.len() < bytes.len(),
+        }
+    }
+
+    /// Returns the (possibly truncated) identifier.
+    #[must_use]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Returns whether the identifier was longer than what [`.as_bytes()`][Self::as_bytes]
+    /// returns.
+    #[must_use]
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for PeerId {
+    fn format(&self, f: defmt::Formatter<'_>) {
+        if self.truncated {
+            defmt::write!(f, "{=[u8]:02x}...", self.as_bytes());
+        } else {
+            defmt::write!(f, "{=[u8]:02x}", self.as_bytes());
+        }
+    }
+}
+
+/// The step of an EDHOC exchange in which processing failed.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EdhocStage {
+    /// Processing of EDHOC message 1 failed.
+    Message1,
+    /// Processing or verification of EDHOC message 3 failed.
+    Message3,
+}
+
+/// The security context in which a request was sent.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Peer {
+    /// The request was sent without protection.
+    Unprotected,
+    /// The request was protected with OSCORE, using this device's recipient ID `kid`.
+    Oscore {
+        /// The OSCORE Key ID of the request.
+        kid: PeerId,
+    },
+    /// The request was protected with Group OSCORE by the group member `sender_id`.
+    Group {
+        /// The sender ID of the group member.
+        sender_id: PeerId,
+    },
+}
+
+/// An event reported by an [`OscoreEdhocHandler`](crate::OscoreEdhocHandler).
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum SecurityEvent {
+    /// An EDHOC exchange was completed, and an OSCORE context was established.
+    EdhocCompleted {
+        /// The `ID_CRED_I` presented by the peer.
+        id_cred: PeerId,
+        /// This device's recipient ID in the new OSCORE context.
+        recipient_id: PeerId,
+    },
+    /// An EDHOC message could not be processed, or the peer's authentication failed.
+    EdhocFailed {
+        /// The step at which the exchange was aborted.
+        stage: EdhocStage,
+    },
+    /// The credential that a peer presented in EDHOC is not known or not acceptable.
+    UnknownCredential {
+        /// The `ID_CRED_I` presented by the peer.
+        id_cred: PeerId,
+    },
+    /// An ACE token was accepted, and an OSCORE context was established.
+    TokenAccepted {
+        /// This device's recipient ID in the new OSCORE context.
+        recipient_id: PeerId,
+    },
+    /// An ACE token (posted to `/authz-info` or sent in EDHOC) was rejected.
+    TokenRejected {
+        /// The reason for which the token was not accepted.
+        reason: CredentialErrorKind,
+    },
+    /// An OSCORE request referred to a security context that does not exist (any more).
+    UnknownSecurityContext {
+        /// The OSCORE Key ID of the request.
+        kid: PeerId,
+    },
+    /// An OSCORE request could not be decrypted, or was a replay of an earlier request.
+    OscoreRequestRejected {
+        /// The OSCORE Key ID of the request.
+        kid: PeerId,
+    },
+    /// The authorization of a security context expired; the context was discarded.
+    AuthorizationExpired {
+        /// This device's recipient ID in the discarded OSCORE context.
+        kid: PeerId,
+    },
+    /// A Group OSCORE request was rejected.
+    GroupRequestRejected {
+        /// The sender ID of the request, if it could be parsed.
+        sender_id: Option<PeerId>,
+        /// The reason for which the request was rejected.
+        reason: crate::group::GroupError,
+    },
+    /// A request was not allowed by the scope of the security context in which it was sent.
+    ScopeDenied {
+        /// The security context in which the request was sent.
+        peer: Peer,
+    },
+}
@@ -0,0 +1,980 @@
+//! Group OSCORE: protecting requests sent to a group of devices.
+//!
+//! This implements the group mode of [Group
+//! OSCORE](https://datatracker.ietf.org/doc/draft-ietf-core-oscore-groupcomm/) on the server side:
+//! A [`GroupContext`] holds the keying material that all members of an OSCORE group share, along
+//! with the authentication credentials of the members that send requests to the group. Requests
+//! protected in group mode (typically sent over multicast) are verified against the sender's
+//! credential, decrypted, and passed to the application if the group's scope allows them.
+//! Responses are protected in group mode as well, using the own sender ID and sequence numbers.
+//!
+//! The keying material is typically provisioned through a group manager as described in
+//! [RFC9594](https://www.rfc-editor.org/rfc/rfc9594.html) and its profiles; this module only
+//! consumes the result.
+//!
+//! The [`GroupContext`] is added to an [`OscoreEdhocHandler`](crate::OscoreEdhocHandler) through
+//! [`.with_group()`](crate::OscoreEdhocHandler::with_group).
+//!
+//! ## Caveats
+//!
+//! * Only the group mode is supported (not the pairwise mode), and only with the algorithms
+//!   AES-CCM-16-64-128 (Group Encryption Algorithm), HKDF SHA-256 and ES256 (Signature Algorithm).
+//! * The replay window of a group member is not known at startup (or when the member is added).
+//!   The member's first request is answered with a 4.01 Unauthorized response that carries an Echo
+//!   option ([RFC9175](https://www.rfc-editor.org/rfc/rfc9175.html)), as Group OSCORE describes
+//!   for initializing replay windows; only once the member repeats a request with that Echo value
+//!   is the request processed and the replay window initialized from it. Requests sent earlier
+//!   (eg. before a reboot) are thus never accepted. The replay window is not persisted, so this
+//!   happens again after every restart.
+//! * Observations can not be registered through group requests.
+//! * Sending requests to a group is not supported yet.
+
+use coap_message_utils::Error as CoAPError;
+use defmt_or_log::{debug, error};
+use p256::ecdsa::{
+    SigningKey, VerifyingKey,
+    signature::{DigestSigner as _, DigestVerifier as _},
+};
+use sha2::Digest as _;
+
+use crate::iana::cose_alg;
+use crate::scope::UnionScope;
+
+/// Maximum number of members (other than this device) whose requests are accepted.
+pub const MAX_MEMBERS: usize = 8;
+
+/// Maximum length of a sender or recipient ID (the nonce length minus 6).
+pub const MAX_ID_LEN: usize = 7;
+
+/// Maximum length of a Group Identifier (the OSCORE ID Context of the group).
+pub const MAX_GROUP_ID_LEN: usize = 8;
+
+/// Maximum length of an authentication credential.
+pub const MAX_CREDENTIAL_LEN: usize = 128;
+
+/// Maximum length of the Master Secret and of the Master Salt.
+const MAX_MASTER_LEN: usize = 32;
+
+const KEY_LEN: usize = 16;
+const NONCE_LEN: usize = 13;
+const TAG_LEN: usize = 8;
+const SIGNATURE_LEN: usize = 64;
+const MAX_PIV_LEN: usize = 5;
+
+/// Number of bytes by which a group mode message's payload exceeds its plaintext.
+pub(crate) const OVERHEAD: usize = TAG_LEN + SIGNATURE_LEN;
+
+/// Maximum length of an OSCORE option value in group mode.
+pub(crate) const MAX_OPTION_LEN: usize = 2 + MAX_PIV_LEN + MAX_GROUP_ID_LEN + MAX_ID_LEN;
+
+/// Value of an OSCORE option in group mode.
+pub(crate) type GroupOption = heapless::Vec<u8, MAX_OPTION_LEN>;
+
+/// Bits of the OSCORE option's flag byte (RFC8613 Section 6.1 and Group OSCORE Section 5).
+mod flags {
+    pub(super) const PIV_LEN: u8 = 0x07;
+    pub(super) const KID: u8 = 0x08;
+    pub(super) const KID_CONTEXT: u8 = 0x10;
+    pub(super) const GROUP: u8 = 0x20;
+    pub(super) const EXTENSION: u8 = 0x80;
+}
+
+/// Group Encryption Algorithm, AES-CCM-16-64-128.
+type Aes128Ccm = ccm::Ccm<aes::Aes128, ccm::consts::U8, ccm::consts::U13>;
+
+/// Keying material shared by the members of an OSCORE group, along with this device's role in it.
+pub struct GroupMaterial<'a> {
+    /// The group's OSCORE Master Secret.
+    pub master_secret: &'a [u8],
+    /// The group's OSCORE Master Salt (empty if absent).
+    pub master_salt: &'a [u8],
+    /// The Group Identifier, used as OSCORE ID Context.
+    pub group_id: &'a [u8],
+    /// The Sender ID of this device in the group.
+    pub sender_id: &'a [u8],
+    /// This device's authentication credential as a CCS (CWT Claims Set) with an EC2 P-256 key.
+    pub sender_credential: &'a [u8],
+    /// The private key matching the public key in [`.sender_credential`][Self::sender_credential].
+    pub private_key: &'a [u8; 32],
+    /// Requests that members of the group are allowed to perform on this device.
+    pub scope: UnionScope,
+}
+
+/// Error type indicating that a group could not be set up from the given material.
+#[derive(Debug, Copy, Clone)]
+pub struct InvalidGroup;
+
+/// Reasons for which a group mode message is not processed.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum GroupError {
+    /// The OSCORE option or the payload can not be parsed as a request in group mode.
+    Malformed,
+    /// The request's ID Context does not match the group.
+    UnknownGroup,
+    /// The request's sender is not a known member of the group.
+    UnknownSender,
+    /// The request's Partial IV was already received.
+    Replay,
+    /// The request's signature does not match the sender's credential.
+    VerifyFailed,
+    /// The request's ciphertext is not authentic.
+    DecryptFailed,
+    /// All sequence numbers that were assigned to this context are used up.
+    SequenceNumbersExhausted,
+    /// A message does not fit in the available buffers.
+    TooLarge,
+}
+
+impl From<GroupError> for CoAPError {
+    fn from(e: GroupError) -> Self {
+        match e {
+            GroupError::Malformed => CoAPError::bad_option(coap_numbers::option::OSCORE),
+            GroupError::UnknownGroup
+            | GroupError::UnknownSender
+            | GroupError::Replay
+            | GroupError::VerifyFailed
+            | GroupError::DecryptFailed => CoAPError::unauthorized(),
+            GroupError::SequenceNumbersExhausted => CoAPError::service_unavailable(),
+            GroupError::TooLarge => CoAPError::internal_server_error(),
+        }
+    }
+}
+
+/// Data from a group request that is needed to protect the response.
+pub(crate) struct Correlation {
+    kid: heapless::Vec<u8, MAX_ID_LEN>,
+    piv: heapless::Vec<u8, MAX_PIV_LEN>,
+    /// Whether the sender's replay window was unknown, so that the request needs to show its
+    /// freshness through an Echo option (see [`GroupContext::check_freshness()`]).
+    needs_echo: bool,
+}
+
+impl Correlation {
+    /// Returns the sender ID of the request.
+    pub(crate) fn sender_id(&self) -> &[u8] {
+        &self.kid
+    }
+}
+
+/// Security context of a device in an OSCORE group.
+pub struct GroupContext {
+    group_id: heapless::Vec<u8, MAX_GROUP_ID_LEN>,
+    common_iv: [u8; NONCE_LEN],
+    signature_encryption_key: [u8; KEY_LEN],
+
+    sender_id: heapless::Vec<u8, MAX_ID_LEN>,
+    sender_key: [u8; KEY_LEN],
+    sender_credential: heapless::Vec<u8, MAX_CREDENTIAL_LEN>,
+    signing_key: SigningKey,
+    /// Next sequence number to be used.
+    sequence_number: u64,
+    /// First sequence number that may not be used any more.
+    sequence_limit: u64,
+
+    members: heapless::Vec<Member, MAX_MEMBERS>,
+    scope: UnionScope,
+
+    /// HKDF input key material and salt, which are needed again for every member that is added.
+    master_secret: heapless::Vec<u8, MAX_MASTER_LEN>,
+    master_salt: heapless::Vec<u8, MAX_MASTER_LEN>,
+
+    /// Echo value through which members show the freshness of their first request.
+    echo: [u8; crate::persistence::ECHO_LEN],
+}
+
+/// Recipient context of another member of the group.
+struct Member {
+    id: heapless::Vec<u8, MAX_ID_LEN>,
+    credential: heapless::Vec<u8, MAX_CREDENTIAL_LEN>,
+    verifying_key: VerifyingKey,
+    recipient_key: [u8; KEY_LEN],
+    replay: ReplayWindow,
+}
+
+/// Sliding replay window of RFC8613 Section 7.4.
+#[derive(Default)]
+struct ReplayWindow {
+    /// Highest sequence number received so far, or `None` while the window is not initialized.
+    highest: Option<u64>,
+    /// Bit `n` is set if sequence number `highest - 1 - n` was received.
+    seen: u32,
+}
+
+impl ReplayWindow {
+    fn is_known(&self) -> bool {
+        self.highest.is_some()
+    }
+
+    /// Initializes the window from a request whose freshness was verified.
+    ///
+    /// All lower sequence numbers count as received, as they may have been used before.
+    fn initialize(&mut self, sequence_number: u64) {
+        self.highest = Some(sequence_number);
+        self.seen = u32::MAX;
+    }
+
+    fn is_fresh(&self, sequence_number: u64) -> bool {
+        let Some(highest) = self.highest else {
+            return true;
+        };
+        match highest.checked_sub(sequence_number) {
+            None => true,
+            Some(0) => false,
+            Some(age) => age <= 32 && self.seen & (1 << (age - 1)) == 0,
+        }
+    }
+
+    fn mark(&mut self, sequence_number: u64) {
+        let Some(highest) = self.highest else {
+            self.highest = Some(sequence_number);
+            return;
+        };
+        if let Some(age) = highest.checked_sub(sequence_number) {
+            if (1..=32).contains(&age) {
+                self.seen |= 1 << (age - 1);
+            }
+        } else {
+            // The previous highest value is now `shift` positions back.
+            let shift = sequence_number - highest;
+            self.seen = u32::try_from(shift)
+                .ok()
+                .and_then(|shift| self.seen.checked_shl(shift))
+                .unwrap_or(0);
+            if shift <= 32 {
+                self.seen |= 1 << (shift - 1);
+            }
+            self.highest = Some(sequence_number);
+        }
+    }
+}
+
+impl GroupContext {
+    /// Sets up the security context of this device in a group.
+    ///
+    /// Only sequence numbers in the range `sequence_numbers` are used to protect responses; the
+    /// caller is responsible for never passing in a range that overlaps with one passed in
+    /// earlier for the same material (eg. by persisting the end of the range before the context
+    /// is used). Once the range is exhausted, responses can not be sent any more.
+    ///
+    /// # Errors
+    ///
+    /// This produces an error if any item exceeds its supported length, or if the private key
+    /// does not match the sender credential.
+    pub fn new(
+        material: &GroupMaterial<'_>,
+        sequence_numbers: core::ops::Range<u64>,
+    ) -> Result<Self, InvalidGroup> {
+        let signing_key =
+            SigningKey::from_bytes(material.private_key.into()).map_err(|_| InvalidGroup)?;
+        if verifying_key_from_ccs(material.sender_credential).as_ref()
+            != Some(signing_key.verifying_key())
+        {
+            error!("Private key does not match the own credential in the group.");
+            return Err(InvalidGroup);
+        }
+
+        let mut context = Self {
+            group_id: heapless::Vec::from_slice(material.group_id).map_err(|()| InvalidGroup)?,
+            common_iv: [0; NONCE_LEN],
+            signature_encryption_key: [0; KEY_LEN],
+            sender_id: heapless::Vec::from_slice(material.sender_id).map_err(|()| InvalidGroup)?,
+            sender_key: [0; KEY_LEN],
+            sender_credential: heapless::Vec::from_slice(material.sender_credential)
+                .map_err(|()| InvalidGroup)?,
+            signing_key,
+            sequence_number: sequence_numbers.start,
+            sequence_limit: sequence_numbers.end.min(1 << (8 * MAX_PIV_LEN)),
+            members: heapless::Vec::new(),
+            scope: material.scope.clone(),
+            master_secret: heapless::Vec::from_slice(material.master_secret)
+                .map_err(|()| InvalidGroup)?,
+            master_salt: heapless::Vec::from_slice(material.master_salt)
+                .map_err(|()| InvalidGroup)?,
+            echo: [0; crate::persistence::ECHO_LEN],
+        };
+        context.common_iv = context.derive(b"", "IV");
+        context.signature_encryption_key = context.derive(b"", "SEKey");
+        context.sender_key = context.derive(material.sender_id, "Key");
+        Ok(context)
+    }
+
+    /// Adds a member of the group whose requests are accepted.
+    ///
+    /// The `credential` is a CCS (CWT Claims Set) containing the member's EC2 P-256 public key.
+    ///
+    /// # Errors
+    ///
+    /// This produces an error if the credential can not be used, if the ID is the own sender ID
+    /// or exceeds its maximum length, or if [`MAX_MEMBERS`] members were already added.
+    pub fn add_member(&mut self, id: &[u8], credential: &[u8]) -> Result<(), InvalidGroup> {
+        if id == self.sender_id.as_slice() {
+            return Err(InvalidGroup);
+        }
+        let member = Member {
+            id: heapless::Vec::from_slice(id).map_err(|()| InvalidGroup)?,
+            credential: heapless::Vec::from_slice(credential).map_err(|()| InvalidGroup)?,
+            verifying_key: verifying_key_from_ccs(credential).ok_or(InvalidGroup)?,
+            recipient_key: self.derive(id, "Key"),
+            replay: ReplayWindow::default(),
+        };
+        // Replacing a member's credential also resets its replay window, as is appropriate for a
+        // new member that may have been assigned a previously used ID.
+        self.members.retain(|m| m.id != member.id);
+        self.members.push(member).map_err(|_| InvalidGroup)
+    }
+
+    /// Returns the first sequence number that was not used yet.
+    ///
+    /// This is useful for persisting the progress of the context when it is shut down cleanly.
+    #[must_use]
+    pub fn next_sequence_number(&self) -> u64 {
+        self.sequence_number
+    }
+
+    /// Accesses the scope that applies to requests from members of the group.
+    pub(crate) fn scope(&self) -> &UnionScope {
+        &self.scope
+    }
+
+    /// Sets the Echo value through which members show the freshness of their first request.
+    ///
+    /// This is a random value picked by the handler at startup.
+    pub(crate) fn set_echo(&mut self, echo: [u8; crate::persistence::ECHO_LEN]) {
+        self.echo = echo;
+    }
+
+    /// Checks whether a decrypted request may be processed, given a function that tells whether
+    /// the request carries a particular Echo value.
+    ///
+    /// If the sender's replay window was known when the request was received, it may. Otherwise,
+    /// it may only if it carries the Echo value, in which case the replay window is initialized
+    /// from it; if not, the Echo value is returned, and needs to be sent in a 4.01 Unauthorized
+    /// response.
+    pub(crate) fn check_freshness(
+        &mut self,
+        correlation: &Correlation,
+        has_echo: impl FnOnce(&[u8]) -> bool,
+    ) -> Result<(), [u8; crate::persistence::ECHO_LEN]> {
+        if !correlation.needs_echo {
+            return Ok(());
+        }
+        if !has_echo(&self.echo) {
+            return Err(self.echo);
+        }
+        if let Some(member) = self
+            .members
+            .iter_mut()
+            .find(|m| m.id.as_slice() == correlation.kid.as_slice())
+        {
+            member.replay.initialize(piv_to_u64(&correlation.piv));
+        }
+        Ok(())
+    }
+
+    /// Verifies and decrypts a request protected in group mode.
+    ///
+    /// On success, `buffer` starts with the request's code and the encoded options and payload,
+    /// and the length of that data is returned.
+    pub(crate) fn unprotect_request(
+        &mut self,
+        option: &[u8],
+        payload: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(Correlation, usize), GroupError> {
+        let parsed = ParsedOption::parse(option)?;
+        let (Some(kid), Some(kid_context)) = (parsed.kid, parsed.kid_context) else {
+            return Err(GroupError::Malformed);
+        };
+        if parsed.piv.is_empty() {
+            return Err(GroupError::Malformed);
+        }
+        if kid_context != self.group_id.as_slice() {
+            return Err(GroupError::UnknownGroup);
+        }
+        let sequence_number = piv_to_u64(parsed.piv);
+
+        let member = self
+            .members
+            .iter()
+            .find(|m| m.id.as_slice() == kid)
+            .ok_or(GroupError::UnknownSender)?;
+        if !member.replay.is_fresh(sequence_number) {
+            return Err(GroupError::Replay);
+        }
+
+        let ciphertext_len = payload
+            .len()
+            .checked_sub(SIGNATURE_LEN)
+            .filter(|len| *len >= TAG_LEN + 1)
+            .ok_or(GroupError::Malformed)?;
+        let (ciphertext, encrypted_signature) = payload.split_at(ciphertext_len);
+
+        let external_aad = self.external_aad(kid, parsed.piv, option, &member.credential)?;
+
+        let mut signature = [0; SIGNATURE_LEN];
+        signature.copy_from_slice(encrypted_signature);
+        self.apply_keystream(kid, parsed.piv, true, &mut signature);
+        let signature =
+            p256::ecdsa::Signature::from_slice(&signature).map_err(|_| GroupError::VerifyFailed)?;
+        member
+            .verifying_key
+            .verify_digest(countersign_digest(&external_aad, ciphertext), &signature)
+            .map_err(|_| GroupError::VerifyFailed)?;
+
+        let plaintext_len = ciphertext_len - TAG_LEN;
+        let (ciphertext, tag) = ciphertext.split_at(plaintext_len);
+        let plaintext = buffer
+            .get_mut(..plaintext_len)
+            .ok_or(GroupError::TooLarge)?;
+        plaintext.copy_from_slice(ciphertext);
+
+        let nonce = self.nonce(kid, parsed.piv);
+        aead_decrypt(&member.recipient_key, &nonce, &external_aad, plaintext, tag)?;
+
+        let member = self
+            .members
+            .iter_mut()
+            .find(|m| m.id.as_slice() == kid)
+            .expect("member was found before");
+        // Until the replay window is initialized, the request may be a replay; it is only
+        // remembered once its freshness is shown (see `check_freshness()`).
+        let needs_echo = !member.replay.is_known();
+        if !needs_echo {
+            member.replay.mark(sequence_number);
+        }
+        debug!(
+            "Group request from {:?} with sequence number {} verified",
+            kid, sequence_number
+        );
+
+        Ok((
+            Correlation {
+                kid: heapless::Vec::from_slice(kid).map_err(|()| GroupError::Malformed)?,
+                piv: heapless::Vec::from_slice(parsed.piv).map_err(|()| GroupError::Malformed)?,
+                needs_echo,
+            },
+            plaintext_len,
+        ))
+    }
+
+    /// Allocates a sequence number for a response, and produces the OSCORE option carrying it.
+    pub(crate) fn response_option(&mut self) -> Result<GroupOption, GroupError> {
+        if self.sequence_number >= self.sequence_limit {
+            error!("Sequence numbers of the group context are exhausted.");
+            return Err(GroupError::SequenceNumbersExhausted);
+        }
+        let piv = u64_to_piv(self.sequence_number);
+        self.sequence_number += 1;
+
+        #[expect(
+            clippy::cast_possible_truncation,
+            reason = "PIV length is limited to 5"
+        )]
+        let mut option = GroupOption::from_slice(&[flags::GROUP | flags::KID | piv.len() as u8])
+            .expect("option has space for the flags");
+        option
+            .extend_from_slice(&piv)
+            .expect("option has space for the PIV");
+        option
+            .extend_from_slice(&self.sender_id)
+            .expect("option has space for the sender ID");
+        Ok(option)
+    }
+
+    /// Protects a response in place.
+    ///
+    /// `option` is the value produced by [`.response_option()`][Self::response_option], and
+    /// `payload` contains the plaintext (code, encoded options and payload) followed by
+    /// [`OVERHEAD`] bytes of space.
+    pub(crate) fn protect_response(
+        &self,
+        correlation: &Correlation,
+        option: &[u8],
+        payload: &mut [u8],
+    ) -> Result<(), GroupError> {
+        let parsed = ParsedOption::parse(option)?;
+        let external_aad = self.external_aad(
+            &correlation.kid,
+            &correlation.piv,
+            option,
+            &self.sender_credential,
+        )?;
+
+        let plaintext_len = payload
+            .len()
+            .checked_sub(OVERHEAD)
+            .ok_or(GroupError::TooLarge)?;
+        let (ciphertext, signature_space) = payload.split_at_mut(plaintext_len + TAG_LEN);
+        let (plaintext, tag_space) = ciphertext.split_at_mut(plaintext_len);
+
+        let nonce = self.nonce(&self.sender_id, parsed.piv);
+        let tag = aead_encrypt(&self.sender_key, &nonce, &external_aad, plaintext)?;
+        tag_space.copy_from_slice(&tag);
+
+        let signature: p256::ecdsa::Signature = self
+            .signing_key
+            .sign_digest(countersign_digest(&external_aad, ciphertext));
+        signature_space.copy_from_slice(&signature.to_bytes());
+        self.apply_keystream(&self.sender_id, parsed.piv, false, signature_space);
+        Ok(())
+    }
+
+    /// Derives a key or IV from the group's master secret (Group OSCORE Section 2.1).
+    fn derive<const L: usize>(&self, id: &[u8], kind: &str) -> [u8; L] {
+        let mut info = [0; 64];
+        let info = encode(&mut info, |e| {
+            e.array(5)?
+                .bytes(id)?
+                .bytes(&self.group_id)?
+                .i32(cose_alg::AES_CCM_16_64_128)?
+                .str(kind)?
+                .encode(L)?;
+            Ok(())
+        })
+        .expect("info fits by construction");
+
+        let mut output = [0; L];
+        hkdf::Hkdf::<sha2::Sha256>::new(Some(&self.master_salt), &self.master_secret)
+            .expand(info, &mut output)
+            .expect("output length is valid for SHA-256");
+        output
+    }
+
+    /// Encrypts or decrypts a signature with the keystream of Group OSCORE Section 4.1.
+    fn apply_keystream(&self, id: &[u8], piv: &[u8], is_request: bool, signature: &mut [u8]) {
+        let mut info = [0; 32];
+        let info = encode(&mut info, |e| {
+            e.array(4)?
+                .bytes(id)?
+                .bytes(&self.group_id)?
+                .bool(is_request)?
+                .encode(SIGNATURE_LEN)?;
+            Ok(())
+        })
+        .expect("info fits by construction");
+
+        let mut keystream = [0; SIGNATURE_LEN];
+        hkdf::Hkdf::<sha2::Sha256>::new(Some(piv), &self.signature_encryption_key)
+            .expand(info, &mut keystream)
+            .expect("output length is valid for SHA-256");
+        for (byte, key) in signature.iter_mut().zip(keystream) {
+            *byte ^= key;
+        }
+    }
+
+    /// Builds the AEAD nonce for a message from the sender `id` (RFC8613 Section 5.2).
+    fn nonce(&self, id: &[u8], piv: &[u8]) -> [u8; NONCE_LEN] {
+        let mut nonce = [0; NONCE_LEN];
+        #[allow(
+            clippy::indexing_slicing,
+            clippy::cast_possible_truncation,
+            reason = "lengths of IDs and PIVs are checked on parsing"
+        )]
+        {
+            nonce[0] = id.len() as u8;
+            nonce[NONCE_LEN - MAX_PIV_LEN - id.len()..NONCE_LEN - MAX_PIV_LEN].copy_from_slice(id);
+            nonce[NONCE_LEN - piv.len()..].copy_from_slice(piv);
+        }
+        for (byte, iv) in nonce.iter_mut().zip(self.common_iv) {
+            *byte ^= iv;
+        }
+        nonce
+    }
+
+    /// Builds the external AAD of a message in group mode (Group OSCORE Section 3.4), already
+    /// wrapped in the `Enc_structure` that is used as AEAD additional data.
+    ///
+    /// The Enc_structure's last item is the external AAD; [`countersign_digest()`] extracts it
+    /// from there.
+    fn external_aad(
+        &self,
+        request_kid: &[u8],
+        request_piv: &[u8],
+        option: &[u8],
+        sender_credential: &[u8],
+    ) -> Result<heapless::Vec<u8, EXTERNAL_AAD_LEN>, GroupError> {
+        let mut aad_array = [0; EXTERNAL_AAD_LEN];
+        let aad_array = encode(&mut aad_array, |e| {
+            e.array(9)?
+                .u8(1)?
+                .array(4)?
+                .i32(cose_alg::AES_CCM_16_64_128)?
+                .i32(cose_alg::ES256)?
+                .null()?
+                .null()?
+                .bytes(request_kid)?
+                .bytes(request_piv)?
+                .bytes(b"")?
+                .bytes(&self.group_id)?
+                .bytes(option)?
+                .bytes(sender_credential)?
+                .null()?;
+            Ok(())
+        })
+        .ok_or(GroupError::TooLarge)?;
+        heapless::Vec::from_slice(aad_array).map_err(|()| GroupError::TooLarge)
+    }
+}
+
+/// Upper bound of the external AAD's length.
+const EXTERNAL_AAD_LEN: usize =
+    32 + 2 * MAX_ID_LEN + MAX_GROUP_ID_LEN + MAX_OPTION_LEN + MAX_CREDENTIAL_LEN;
+
+/// Parts of an OSCORE option value.
+struct ParsedOption<'a> {
+    piv: &'a [u8],
+    kid_context: Option<&'a [u8]>,
+    kid: Option<&'a [u8]>,
+}
+
+impl<'a> ParsedOption<'a> {
+    fn parse(option: &'a [u8]) -> Result<Self, GroupError> {
+        let (&flag_byte, rest) = option.split_first().ok_or(GroupError::Malformed)?;
+        if flag_byte & flags::GROUP == 0 || flag_byte & flags::EXTENSION != 0 {
+            return Err(GroupError::Malformed);
+        }
+        let piv_len = usize::from(flag_byte & flags::PIV_LEN);
+        if piv_len > MAX_PIV_LEN {
+            return Err(GroupError::Malformed);
+        }
+        let (piv, mut rest) = rest
+            .split_at_checked(piv_len)
+            .ok_or(GroupError::Malformed)?;
+        let kid_context = if flag_byte & flags::KID_CONTEXT == 0 {
+            None
+        } else {
+            let (&len, tail) = rest.split_first().ok_or(GroupError::Malformed)?;
+            let (kid_context, tail) = tail
+                .split_at_checked(usize::from(len))
+                .ok_or(GroupError::Malformed)?;
+            rest = tail;
+            Some(kid_context)
+        };
+        let kid = if flag_byte & flags::KID == 0 {
+            None
+        } else if rest.len() > MAX_ID_LEN {
+            return Err(GroupError::Malformed);
+        } else {
+            Some(rest)
+        };
+        Ok(Self {
+            piv,
+            kid_context,
+            kid,
+        })
+    }
+}
+
+fn piv_to_u64(piv: &[u8]) -> u64 {
+    piv.iter()
+        .fold(0, |acc, byte| (acc << 8) | u64::from(*byte))
+}
+
+/// Encodes a sequence number in the shortest Partial IV.
+fn u64_to_piv(sequence_number: u64) -> heapless::Vec<u8, MAX_PIV_LEN> {
+    let bytes = sequence_number.to_be_bytes();
+    let skip = bytes
+        .iter()
+        .take(bytes.len() - 1)
+        .take_while(|b| **b == 0)
+        .count();
+    #[allow(clippy::indexing_slicing, reason = "skip is less than the length")]
+    heapless::Vec::from_slice(&bytes[skip..]).expect("sequence numbers are limited to 5 bytes")
+}
+
+/// Computes the digest of the `Countersign_structure` that is signed in group mode.
+fn countersign_digest(external_aad: &[u8], ciphertext: &[u8]) -> sha2::Sha256 {
+    let mut head = [0; 32];
+    let head = encode(&mut head, |e| {
+        e.array(4)?.str("CounterSignature0")?.bytes(b"")?;
+        Ok(())
+    })
+    .expect("head fits by construction");
+
+    let mut digest = sha2::Sha256::new();
+    digest.update(head);
+    digest.update(bstr_head(external_aad.len()));
+    digest.update(external_aad);
+    digest.update(bstr_head(ciphertext.len()));
+    digest.update(ciphertext);
+    digest
+}
+
+/// Encodes the head of a CBOR byte string of length `len`.
+///
+/// Lengths are limited to 16 bits, which suffices for any CoAP message processed here.
+fn bstr_head(len: usize) -> heapless::Vec<u8, 3> {
+    #[expect(
+        clippy::cast_possible_truncation,
+        reason = "values are checked against their range"
+    )]
+    let head: &[u8] = match len {
+        0..24 => &[0x40 | len as u8],
+        24..256 => &[0x58, len as u8],
+        _ => &[0x59, (len >> 8) as u8, len as u8],
+    };
+    heapless::Vec::from_slice(head).expect("heads are at most 3 bytes long")
+}
+
+fn aead_encrypt(
+    key: &[u8; KEY_LEN],
+    nonce: &[u8; NONCE_LEN],
+    external_aad: &[u8],
+    buffer: &mut [u8],
+) -> Result<[u8; TAG_LEN], GroupError> {
+    use ccm::KeyInit as _;
+    use ccm::aead::AeadInPlace as _;
+
+    let mut aad = [0; EXTERNAL_AAD_LEN + 16];
+    let aad = enc_structure(&mut aad, external_aad)?;
+    let tag = Aes128Ccm::new(key.into())
+        .encrypt_in_place_detached(nonce.into(), aad, buffer)
+        .map_err(|_| GroupError::TooLarge)?;
+    Ok(tag.into())
+}
+
+fn aead_decrypt(
+    key: &[u8; KEY_LEN],
+    nonce: &[u8; NONCE_LEN],
+    external_aad: &[u8],
+    buffer: &mut [u8],
+    tag: &[u8],
+) -> Result<(), GroupError> {
+    use ccm::KeyInit as _;
+    use ccm::aead::AeadInPlace as _;
+
+    let mut aad = [0; EXTERNAL_AAD_LEN + 16];
+    let aad = enc_structure(&mut aad, external_aad)?;
+    Aes128Ccm::new(key.into())
+        .decrypt_in_place_detached(nonce.into(), aad, buffer, ccm::Tag::from_slice(tag))
+        .map_err(|_| GroupError::DecryptFailed)
+}
+
+/// Builds the COSE `Enc_structure` for an `Encrypt0` object with an empty protected header.
+fn enc_structure<'b>(buffer: &'b mut [u8], external_aad: &[u8]) -> Result<&'b [u8], GroupError> {
+    encode(buffer, |e| {
+        e.array(3)?
+            .str("Encrypt0")?
+            .bytes(b"")?
+            .bytes(external_aad)?;
+        Ok(())
+    })
+    .ok_or(GroupError::TooLarge)
+}
+
+/// Runs `f` on an encoder writing into `buffer`, and returns the written part.
+fn encode<'b>(
+    buffer: &'b mut [u8],
+    f: impl FnOnce(
+        &mut minicbor::Encoder<&mut minicbor::encode::write::Cursor<&mut [u8]>>,
+    ) -> Result<(), minicbor::encode::Error<minicbor::encode::write::EndOfSlice>>,
+) -> Option<&'b [u8]> {
+    let mut cursor = minicbor::encode::write::Cursor::new(&mut *buffer);
+    f(&mut minicbor::Encoder::new(&mut cursor)).ok()?;
+    let len = cursor.position();
+    buffer.get(..len)
+}
+
+/// Extracts the EC2 P-256 public key from a CCS (CWT Claims Set) of the form `{8 /cnf/: {1
+/// /COSE_Key/: {-2 /x/: ..., -3 /y/: ..., ...}}}`.
+fn verifying_key_from_ccs(ccs: &[u8]) -> Option<VerifyingKey> {
+    let mut decoder = minicbor::Decoder::new(ccs);
+    find_in_map(&mut decoder, 8)?;
+    find_in_map(&mut decoder, 1)?;
+
+    let (mut x, mut y) = (None, None);
+    for _ in 0..decoder.map().ok()?? {
+        match decoder.i32().ok()? {
+            -2 => x = Some(decoder.bytes().ok()?),
+            -3 => y = Some(decoder.bytes().ok()?),
+            _ => decoder.skip().ok()?,
+        }
+    }
+    let x: &[u8; 32] = x?.try_into().ok()?;
+    let y: &[u8; 32] = y?.try_into().ok()?;
+    VerifyingKey::from_encoded_point(&p256::EncodedPoint::from_affine_coordinates(
+        x.into(),
+        y.into(),
+        false,
+    ))
+    .ok()
+}
+
+/// Advances a decoder positioned at a map with integer keys to the value of `key`.
+fn find_in_map(decoder: &mut minicbor::Decoder<'_>, key: i32) -> Option<()> {
+    for _ in 0..decoder.map().ok()?? {
+        if decoder.i32().ok()? == key {
+            return Some(());
+        }
+        decoder.skip().ok()?;
+    }
+    None
+}
+
+/// Returns the sender ID in an OSCORE option value in group mode, if it can be parsed.
+pub(crate) fn sender_id(option: &[u8]) -> Option<&[u8]> {
+    ParsedOption::parse(option).ok()?.kid
+}
+
+/// Returns whether an OSCORE option value indicates the group mode.
+pub(crate) fn is_group_option(option: &[u8]) -> bool {
+    option
+        .first()
+        .is_some_and(|flag_byte| flag_byte & flags::GROUP != 0)
+}
+
+#[cfg(test)]
+#[allow(clippy::indexing_slicing, reason = "panicking is fine in tests")]
+mod test {
+    use super::*;
+
+    const OWN_KEY: [u8; 32] = [0x11; 32];
+    const MEMBER_KEY: [u8; 32] = [0x22; 32];
+    const MEMBER_ID: &[u8] = b"\x02";
+    const ECHO: [u8; crate::persistence::ECHO_LEN] = [7; crate::persistence::ECHO_LEN];
+
+    /// Builds a CCS holding the public key of `private_key`.
+    fn ccs(private_key: &[u8; 32]) -> heapless::Vec<u8, MAX_CREDENTIAL_LEN> {
+        use p256::elliptic_curve::sec1::ToEncodedPoint as _;
+
+        let point = SigningKey::from_bytes(private_key.into())
+            .unwrap()
+            .verifying_key()
+            .to_encoded_point(false);
+        let mut buffer = [0; MAX_CREDENTIAL_LEN];
+        let encoded = encode(&mut buffer, |e| {
+            e.map(1)?
+                .u8(8)?
+                .map(1)?
+                .u8(1)?
+                .map(4)?
+                .u8(1)?
+                .u8(2)?
+                .i8(-1)?
+                .u8(1)?
+                .i8(-2)?
+                .bytes(point.x().unwrap())?
+                .i8(-3)?
+                .bytes(point.y().unwrap())?;
+            Ok(())
+        })
+        .unwrap();
+        heapless::Vec::from_slice(encoded).unwrap()
+    }
+
+    fn context() -> GroupContext {
+        let own_credential = ccs(&OWN_KEY);
+        let mut context = GroupContext::new(
+            &GroupMaterial {
+                master_secret: &[1; 16],
+                master_salt: &[2; 8],
+                group_id: b"group",
+                sender_id: b"\x01",
+                sender_credential: &own_credential,
+                private_key: &OWN_KEY,
+                scope: UnionScope::AllowAll,
+            },
+            0..10,
+        )
+        .unwrap();
+        context.add_member(MEMBER_ID, &ccs(&MEMBER_KEY)).unwrap();
+        context.set_echo(ECHO);
+        context
+    }
+
+    fn correlation(sequence_number: u64, needs_echo: bool) -> Correlation {
+        Correlation {
+            kid: heapless::Vec::from_slice(MEMBER_ID).unwrap(),
+            piv: u64_to_piv(sequence_number),
+            needs_echo,
+        }
+    }
+
+    fn member_replay(context: &GroupContext) -> &ReplayWindow {
+        &context.members[0].replay
+    }
+
+    #[test]
+    fn setup() {
+        assert!(verifying_key_from_ccs(&ccs(&MEMBER_KEY)).is_some());
+
+        let own_credential = ccs(&OWN_KEY);
+        let mismatched = GroupMaterial {
+            master_secret: &[1; 16],
+            master_salt: &[2; 8],
+            group_id: b"group",
+            sender_id: b"\x01",
+            sender_credential: &own_credential,
+            private_key: &MEMBER_KEY,
+            scope: UnionScope::AllowAll,
+        };
+        assert!(GroupContext::new(&mismatched, 0..10).is_err());
+
+        let mut context = context();
+        assert!(context.add_member(b"\x01", &ccs(&MEMBER_KEY)).is_err());
+        assert!(!member_replay(&context).is_known());
+    }
+
+    #[test]
+    fn replay_window() {
+        let mut window = ReplayWindow::default();
+        assert!(!window.is_known());
+
+        window.initialize(40);
+        assert!(window.is_known());
+        // Nothing up to the request that initialized the window is accepted.
+        for sequence_number in [0, 7, 39, 40] {
+            assert!(!window.is_fresh(sequence_number));
+        }
+        assert!(window.is_fresh(41));
+
+        window.mark(43);
+        assert!(!window.is_fresh(43));
+        assert!(window.is_fresh(41));
+        assert!(window.is_fresh(42));
+        assert!(!window.is_fresh(40));
+
+        window.mark(41);
+        assert!(!window.is_fresh(41));
+        assert!(window.is_fresh(42));
+
+        // Sequence numbers that fell out of the window are not accepted any more.
+        window.mark(100);
+        assert!(!window.is_fresh(42));
+        assert!(!window.is_fresh(67));
+        assert!(window.is_fresh(68));
+    }
+
+    #[test]
+    fn first_request_needs_echo() {
+        let mut context = context();
+
+        // Without the Echo value, the request is not processed, and the window stays unknown.
+        assert_eq!(
+            context.check_freshness(&correlation(5, true), |_| false),
+            Err(ECHO)
+        );
+        assert!(!member_replay(&context).is_known());
+
+        // With it, the window is initialized from the request.
+        assert_eq!(
+            context.check_freshness(&correlation(6, true), |echo| echo == ECHO),
+            Ok(())
+        );
+        let window = member_replay(&context);
+        assert!(window.is_known());
+        assert!(!window.is_fresh(5));
+        assert!(!window.is_fresh(6));
+        assert!(window.is_fresh(7));
+
+        // Requests received once the window is known are not checked.
+        assert_eq!(
+            context.check_freshness(&correlation(7, false), |_| {
+                panic!("Echo is not looked for")
+            }),
+            Ok(())
+        );
+    }
+}
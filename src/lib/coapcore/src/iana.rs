@@ -22,4 +22,7 @@ pub(crate) mod cose_alg {
 
     /// AES-CCM-16-64-128
     pub(crate) const AES_CCM_16_64_128: i32 = 10;
+
+    /// ECDSA w/ SHA-256
+    pub(crate) const ES256: i32 = -7;
 }
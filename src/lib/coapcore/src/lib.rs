@@ -1,7 +1,8 @@
 //! A CoAP security tool for embedded devices, supporting OSCORE/EDHOC and managing credentials.
 //!
 //! This crate is under active development; breaking changes will be made as necessary. It
-//! currently only handles the server side of CoAP exchanges. At runtime, there is more copying of
+//! mainly handles the server side of CoAP exchanges; client side support is limited to EDHOC
+//! and OSCORE (see the [`client`] module). At runtime, there is more copying of
 //! messages than is generally preferred; those result from limitations of underlying tools and are
 //! being addressed there.
 //!
@@ -27,6 +28,17 @@
 //!
 //! The arguments passed to the [`OscoreEdhocHandler`] at construction guide its behavior.
 //!
+//! Application handlers that use block-wise transfer work unmodified behind it: Block options are
+//! protected as inner options of OSCORE, and thus processed by the application handler. Outer
+//! block-wise transfer (splitting up the OSCORE protected message itself) is not supported.
+//!
+//! Requests that are sent to a group of devices (typically over multicast) are processed if a
+//! [`group::GroupContext`] is added to the handler; see the [`group`] module for details.
+//!
+//! On the client side, an [`OscoreEdhocClient`](client::OscoreEdhocClient) is combined with a
+//! [`coap_request::Stack`] for each peer, resulting in a stack that protects requests sent
+//! through it.
+//!
 //! # Logging
 //!
 //! Extensive logging is available in this crate through [`defmt_or_log`], depending on features
@@ -48,6 +60,10 @@
 //! **Warning**: At the Debug level, this module may show cryptographic key material. This will be
 //! revised once all components have been interop-tested.
 //!
+//! Where records of security relevant occurrences (eg. failed handshakes or rejected tokens) are
+//! needed beyond log output, the handler can report them as [`events::SecurityEvent`]s, which never
+//! contain key material.
+//!
 //! # Caveats
 //!
 //! Currently, this has hidden dependencies on a particular implementation of the [`coap_message`]
@@ -82,5 +98,11 @@ pub mod oluru;
 mod seccontext;
 pub use seccontext::*;
 
+pub mod client;
+pub mod events;
+pub mod group;
+pub mod observe;
+pub mod persistence;
+
 mod error;
 pub use error::{CredentialError, CredentialErrorDetail as CredentialErrorKind};
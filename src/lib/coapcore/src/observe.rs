@@ -0,0 +1,199 @@
+//! Support for observing resources ([RFC7641](https://www.rfc-editor.org/rfc/rfc7641.html))
+//! behind an [`OscoreEdhocHandler`][crate::OscoreEdhocHandler].
+//!
+//! The handler itself does not keep track of observers: whenever it processes a registration
+//! (a GET request with an Observe option of value 0) that passes its authorization checks, it
+//! retains an [`Observation`], which the CoAP stack retrieves through
+//! [`take_observation()`][crate::OscoreEdhocHandler::take_observation] right after the response
+//! was built. The stack stores it along with the peer's address and token, and later passes it to
+//! [`build_notification()`][crate::OscoreEdhocHandler::build_notification] to produce
+//! notifications.
+//!
+//! Whether a notification is due is up to the stack: through
+//! [`observe_value()`][crate::OscoreEdhocHandler::observe_value], it learns the value of the
+//! Observe option a notification would have, without building (and, for OSCORE, protecting) one.
+//! That value indicates both whether the resource accepted the registration at all, and whether it
+//! changed since the latest notification.
+//!
+//! For OSCORE protected observations, each notification is protected with a new sequence number
+//! of the server, as required by [RFC8613 Section
+//! 4.1.3.5.2](https://www.rfc-editor.org/rfc/rfc8613.html#section-4.1.3.5.2).
+
+use coap_message::{MessageOption as _, MinimalWritableMessage as _, ReadableMessage};
+use coap_message_implementations::{inmemory, inmemory_write};
+use defmt_or_log::debug;
+
+use crate::helpers::COwn;
+
+/// Space for the options of a registration request that are retained to build notifications.
+///
+/// Registrations with more options are not retained.
+const MAX_REQUEST_OPTIONS_LEN: usize = 64;
+
+/// An observation registered through an [`OscoreEdhocHandler`][crate::OscoreEdhocHandler].
+///
+/// It is only meaningful to the handler that produced it.
+pub struct Observation {
+    pub(crate) security: ObservationSecurity,
+    pub(crate) request: ObservedRequest,
+}
+
+impl Observation {
+    /// Whether the observation was registered in an OSCORE protected request.
+    #[must_use]
+    pub fn is_protected(&self) -> bool {
+        matches!(self.security, ObservationSecurity::Oscore { .. })
+    }
+}
+
+pub(crate) enum ObservationSecurity {
+    Unprotected,
+    Oscore {
+        kid: COwn,
+        /// Correlation data of the registration, set once the registration's response was
+        /// protected; notifications can only be built from then on.
+        correlation: Option<liboscore::raw::oscore_requestid_t>,
+    },
+}
+
+/// Code and options of a registration request (possibly the plaintext of an OSCORE request), from
+/// which notifications are built.
+pub(crate) struct ObservedRequest {
+    code: u8,
+    options: [u8; MAX_REQUEST_OPTIONS_LEN],
+    len: usize,
+}
+
+impl ObservedRequest {
+    /// Copies code and options of `request` if it is an observation registration that fits.
+    pub(crate) fn registration(request: &impl ReadableMessage) -> Option<Self> {
+        let code: u8 = request.code().into();
+        if code != coap_numbers::code::GET
+            || !request.options().any(|o| {
+                o.number() == coap_numbers::option::OBSERVE && matches!(o.value(), [] | [0])
+            })
+        {
+            return None;
+        }
+
+        let mut copy = Self {
+            code,
+            options: [0; MAX_REQUEST_OPTIONS_LEN],
+            len: 0,
+        };
+        let mut copy_code = code;
+        let mut message = inmemory_write::Message::new(&mut copy_code, &mut copy.options);
+        for o in request.options() {
+            if message.add_option(o.number(), o.value()).is_err() {
+                debug!("Observation registration is too large to retain.");
+                return None;
+            }
+        }
+        copy.len = message.finish();
+        Some(copy)
+    }
+
+    /// Produces the retained request as a message.
+    pub(crate) fn message(&self) -> inmemory::Message<'_> {
+        #[allow(
+            clippy::indexing_slicing,
+            reason = "length is populated by the message"
+        )]
+        inmemory::Message::new(self.code, &self.options[..self.len])
+    }
+}
+
+/// Encodes the lowest 24 bits of `value`, to which the Observe option is limited, as its value.
+pub(crate) fn observe_value(value: u64) -> heapless::Vec<u8, 3> {
+    let bytes = value.to_be_bytes();
+    #[allow(clippy::indexing_slicing, reason = "the slice is within the 8 bytes")]
+    let bytes = &bytes[5..];
+    let leading_zeros = bytes.iter().take_while(|b| **b == 0).count();
+    #[allow(clippy::indexing_slicing, reason = "count is at most the length")]
+    heapless::Vec::from_slice(&bytes[leading_zeros..]).expect("at most 3 bytes")
+}
+
+/// Decodes the value of the Observe option of `message`, if it has one.
+pub(crate) fn observe_option(message: &impl ReadableMessage) -> Option<u32> {
+    message
+        .options()
+        .find(|o| o.number() == coap_numbers::option::OBSERVE)
+        .map(|o| {
+            o.value()
+                .iter()
+                .fold(0, |value, byte| (value << 8) | u32::from(*byte))
+        })
+}
+
+/// Returns the sequence number that `context` uses next, which becomes the Partial IV of the
+/// next notification.
+pub(crate) fn next_sequence_number(context: &mut liboscore::PrimitiveContext) -> u64 {
+    // SAFETY: For a primitive context, `.as_mut()` points the data pointer to its state, which is
+    // valid and exclusively borrowed for as long as `context` is.
+    let state: &liboscore::raw::oscore_context_primitive =
+        unsafe { &*context.as_mut().data.cast() };
+    state.sender_sequence_number
+}
+
+/// Status of an observation after a notification was built.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum NotificationOutcome {
+    /// The notification is a successful response, and the observation continues.
+    Continues,
+    /// The notification is an error response; it is sent, and the observation ends.
+    Final,
+}
+
+/// Errors that prevent a notification from being built; the observation ends without a further
+/// notification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum NotificationError {
+    /// The security context of the observation is not available any more.
+    ContextLost,
+    /// The observer's authorization does not allow access any more (e.g., because it expired).
+    NotAllowed,
+    /// The security context has no reserved sequence numbers left until its state is persisted
+    /// again (see [`crate::persistence`]).
+    SequenceNumbersExhausted,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn observe_values() {
+        assert_eq!(observe_value(0), []);
+        assert_eq!(observe_value(1), [1]);
+        assert_eq!(observe_value(0x100), [1, 0]);
+        assert_eq!(observe_value(0xff_ffff), [0xff, 0xff, 0xff]);
+        // Only the lowest 24 bits are used.
+        assert_eq!(observe_value(0x0100_0002), [2]);
+    }
+
+    #[test]
+    fn observe_options() {
+        for value in [0, 1, 0x100, 0xff_ffff] {
+            let mut code = coap_numbers::code::CONTENT;
+            let mut buffer = [0u8; 16];
+            let mut message = inmemory_write::Message::new(&mut code, &mut buffer);
+            message
+                .add_option(coap_numbers::option::OBSERVE, &observe_value(value))
+                .unwrap();
+            let len = message.finish();
+            let message = inmemory::Message::new(code, buffer.get(..len).unwrap());
+            assert_eq!(
+                observe_option(&message),
+                Some(u32::try_from(value).unwrap())
+            );
+        }
+
+        assert_eq!(
+            observe_option(&inmemory::Message::new(coap_numbers::code::CONTENT, &[])),
+            None
+        );
+    }
+}
@@ -267,6 +267,13 @@ impl<T: PriorityLevel, const N: usize, const L: usize> OrderedPool<T, N, L> {
     pub(crate) fn iter(&self) -> impl Iterator<Item = &T> {
         self.entries.iter()
     }
+
+    /// Returns an iterator visiting all items mutably in arbitrary order.
+    ///
+    /// Items must not be altered in a way that changes their priority level.
+    pub(crate) fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.entries.iter_mut()
+    }
 }
 
 impl<T: PriorityLevel, const N: usize, const L: usize> core::default::Default
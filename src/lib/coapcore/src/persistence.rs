@@ -0,0 +1,501 @@
+//! Persistence of OSCORE security contexts across restarts.
+//!
+//! Security contexts established through EDHOC live in memory, so after a restart, every peer
+//! would need to run EDHOC again. When persistence is enabled through
+//! [`OscoreEdhocHandler::with_persistence()`](crate::OscoreEdhocHandler::with_persistence), the
+//! application can keep those contexts in storage instead:
+//!
+//! * It repeatedly takes [`PersistedContext`]s from the handler through
+//!   [`.pending_persistence()`](crate::OscoreEdhocHandler::pending_persistence) (the handler calls
+//!   the function passed in when there are any), writes them to storage, and confirms that
+//!   through
+//!   [`.confirm_persisted()`](crate::OscoreEdhocHandler::confirm_persisted).
+//! * At startup, it passes the stored contexts to
+//!   [`.restore_context()`](crate::OscoreEdhocHandler::restore_context).
+//!
+//! Two parts of a context's state are handled as described in [RFC8613 Appendix
+//! B.1](https://www.rfc-editor.org/rfc/rfc8613#appendix-B.1):
+//!
+//! * Own sequence numbers are reserved in blocks: a context only uses sequence numbers below the
+//!   limit of its last confirmed [`PersistedContext`], and a restored context continues at that
+//!   limit. (Sequence numbers are only needed for notifications and for responses to requests
+//!   that may be replays, so reservations are rare).
+//! * The replay window is not persisted. Until a restored context has received a request that is
+//!   demonstrably fresh, requests are answered with a 4.01 Unauthorized response that carries an
+//!   Echo option, and only processed once the peer repeats them with that Echo value.
+//!
+//! Only contexts whose peer credential is known to the server's
+//! [`ServerSecurityConfig`](crate::seccfg::ServerSecurityConfig) are persisted; their
+//! authorization is looked up again when they are restored. Contexts established through ACE
+//! tokens are not persisted.
+//!
+//! **Warning**: A [`PersistedContext`] contains key material, and needs to be stored with the same
+//! care as a private key.
+
+use crate::helpers::COwn;
+
+/// Maximum length of the `ID_CRED_I` of a peer whose context is persisted.
+///
+/// Contexts of peers presenting longer credential identifiers (typically credentials by value)
+/// are not persisted.
+pub const MAX_ID_CRED_LEN: usize = 48;
+
+/// Maximum length of a [`PersistedContext`] in its encoded form.
+pub const MAX_ENCODED_LEN: usize = 1
+    + 2 * 5
+    + 2 * (1 + MAX_ID_LEN)
+    + (1 + MASTER_SECRET_LEN)
+    + (1 + MASTER_SALT_LEN)
+    + (2 + MAX_ID_CRED_LEN)
+    + 9;
+
+/// Maximum length of a sender or recipient ID with the supported AEAD algorithm.
+const MAX_ID_LEN: usize = 7;
+
+/// Length of the master secret exported from EDHOC.
+pub(crate) const MASTER_SECRET_LEN: usize = 16;
+
+/// Length of the master salt exported from EDHOC.
+pub(crate) const MASTER_SALT_LEN: usize = 8;
+
+/// Length of the Echo value sent to peers of restored contexts.
+pub(crate) const ECHO_LEN: usize = 8;
+
+/// Persistence settings of a handler.
+pub(crate) struct Settings {
+    /// Function called when there are contexts to persist.
+    pub(crate) notify: fn(),
+    /// Number of sequence numbers reserved at a time.
+    pub(crate) reservation: u64,
+    /// Echo value through which peers of restored contexts show the freshness of their requests.
+    pub(crate) echo: [u8; ECHO_LEN],
+}
+
+/// Input material of a security context.
+#[derive(Clone, PartialEq, Eq)]
+pub(crate) struct Material {
+    hkdf: i32,
+    aead: i32,
+    master_secret: [u8; MASTER_SECRET_LEN],
+    master_salt: [u8; MASTER_SALT_LEN],
+    sender_id: heapless::Vec<u8, MAX_ID_LEN>,
+    recipient_id: heapless::Vec<u8, MAX_ID_LEN>,
+    id_cred: heapless::Vec<u8, MAX_ID_CRED_LEN>,
+}
+
+impl Material {
+    /// Retains the input material of a context established through EDHOC, or returns `None` if
+    /// it exceeds what can be persisted.
+    pub(crate) fn new(
+        hkdf: i32,
+        aead: i32,
+        master_secret: &[u8],
+        master_salt: &[u8],
+        sender_id: &[u8],
+        recipient_id: &[u8],
+        id_cred: &[u8],
+    ) -> Option<Self> {
+        Some(Self {
+            hkdf,
+            aead,
+            master_secret: master_secret.try_into().ok()?,
+            master_salt: master_salt.try_into().ok()?,
+            sender_id: heapless::Vec::from_slice(sender_id).ok()?,
+            recipient_id: heapless::Vec::from_slice(recipient_id).ok()?,
+            id_cred: heapless::Vec::from_slice(id_cred).ok()?,
+        })
+    }
+
+    /// Derives the security context's keys.
+    fn derive(&self) -> Option<liboscore::PrimitiveImmutables> {
+        liboscore::PrimitiveImmutables::derive(
+            liboscore::HkdfAlg::from_number(self.hkdf).ok()?,
+            &self.master_secret,
+            &self.master_salt,
+            None,
+            liboscore::AeadAlg::from_number(self.aead).ok()?,
+            &self.sender_id,
+            &self.recipient_id,
+        )
+        .ok()
+    }
+}
+
+/// Persistence state of a security context.
+pub(crate) struct Persistence {
+    material: Material,
+    /// Own sequence numbers below this limit are reserved in storage.
+    sequence_number_limit: u64,
+    /// Whether the replay window was lost in a restart, and needs to be recovered through Echo.
+    pub(crate) replay_window_unknown: bool,
+}
+
+impl Persistence {
+    /// Starts persistence of a newly established context.
+    pub(crate) fn new(material: Material) -> Self {
+        Self {
+            material,
+            sequence_number_limit: 0,
+            replay_window_unknown: false,
+        }
+    }
+
+    /// Sets up a context from its persisted form.
+    ///
+    /// The context starts out with an unknown replay window, and its next sequence number is the
+    /// persisted limit; no sequence numbers can be used until a new limit is confirmed.
+    pub(crate) fn restore(
+        persisted: &PersistedContext,
+    ) -> Option<(liboscore::PrimitiveContext, Self)> {
+        let mut context =
+            liboscore::PrimitiveContext::new_from_fresh_material(persisted.material.derive()?);
+        state(&mut context).sender_sequence_number = persisted.sequence_number_limit;
+        Some((
+            context,
+            Self {
+                material: persisted.material.clone(),
+                sequence_number_limit: persisted.sequence_number_limit,
+                replay_window_unknown: true,
+            },
+        ))
+    }
+
+    /// Returns the [`PersistedContext`] that needs to be stored, if the reserved sequence numbers
+    /// run low (or none have been reserved yet).
+    pub(crate) fn pending(
+        &self,
+        context: &mut liboscore::PrimitiveContext,
+        reservation: u64,
+    ) -> Option<PersistedContext> {
+        let next = state(context).sender_sequence_number;
+        (next.saturating_add(reservation / 2) >= self.sequence_number_limit).then(|| {
+            PersistedContext {
+                material: self.material.clone(),
+                sequence_number_limit: next
+                    .max(self.sequence_number_limit)
+                    .saturating_add(reservation),
+            }
+        })
+    }
+
+    /// Records that `persisted` was stored, if it belongs to this context.
+    pub(crate) fn confirm(&mut self, persisted: &PersistedContext) -> bool {
+        if persisted.material != self.material {
+            return false;
+        }
+        self.sequence_number_limit = self
+            .sequence_number_limit
+            .max(persisted.sequence_number_limit);
+        true
+    }
+
+    /// Updates the replay window after a request was decrypted in a context whose replay window is
+    /// unknown.
+    ///
+    /// If the request carried the expected Echo value (`fresh`), the replay window is recovered
+    /// from it. Otherwise, the request's sequence number is not remembered: it may be a replay
+    /// from before the restart, and must not keep the peer's actual requests out. For the same
+    /// reason, the response to it must not reuse the request's nonce.
+    pub(crate) fn process_unknown_replay_window(
+        &mut self,
+        context: &mut liboscore::PrimitiveContext,
+        correlation: &mut liboscore::raw::oscore_requestid_t,
+        fresh: bool,
+    ) {
+        let state = state(context);
+        if fresh {
+            let used = usize::from(correlation.used_bytes).min(correlation.partial_iv.len());
+            let mut piv = [0; 8];
+            #[allow(
+                clippy::indexing_slicing,
+                reason = "the Partial IV is shorter than a u64"
+            )]
+            piv[8 - used..].copy_from_slice(&correlation.partial_iv[..used]);
+            state.replay_window_left_edge = u64::from_be_bytes(piv) + 1;
+            self.replay_window_unknown = false;
+        } else {
+            state.replay_window_left_edge = 0;
+            correlation.is_first_use = false;
+        }
+        state.replay_window = 0;
+    }
+
+    /// Returns whether the context may use its next sequence number.
+    pub(crate) fn has_sequence_number(&self, context: &mut liboscore::PrimitiveContext) -> bool {
+        state(context).sender_sequence_number < self.sequence_number_limit
+    }
+}
+
+/// Gives access to the mutable state (sequence number and replay window) of a context.
+fn state(
+    context: &mut liboscore::PrimitiveContext,
+) -> &mut liboscore::raw::oscore_context_primitive {
+    // SAFETY: For a primitive context, `.as_mut()` points the data pointer to its state, which is
+    // valid and exclusively borrowed for as long as `context` is.
+    unsafe { &mut *context.as_mut().data.cast() }
+}
+
+/// A security context in the form in which it is persisted.
+///
+/// See the [module level documentation][self] for how this is used.
+#[derive(Clone)]
+pub struct PersistedContext {
+    material: Material,
+    sequence_number_limit: u64,
+}
+
+/// Error decoding a [`PersistedContext`].
+#[derive(Debug)]
+pub struct InvalidPersistedContext;
+
+impl PersistedContext {
+    /// Returns this device's recipient ID in the context.
+    ///
+    /// Contexts with the same recipient ID replace each other, so this is suitable as a key in
+    /// storage.
+    #[must_use]
+    pub fn recipient_id(&self) -> &[u8] {
+        &self.material.recipient_id
+    }
+
+    /// Encodes the context into `buffer`, and returns the encoded part.
+    ///
+    /// The encoded form is a CBOR array; it fits into [`MAX_ENCODED_LEN`] bytes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the buffer is shorter than [`MAX_ENCODED_LEN`].
+    pub fn encode<'b>(&self, buffer: &'b mut [u8]) -> &'b [u8] {
+        let mut cursor = minicbor::encode::write::Cursor::new(&mut *buffer);
+        minicbor::Encoder::new(&mut cursor)
+            .array(8)
+            .and_then(|e| e.i32(self.material.hkdf))
+            .and_then(|e| e.i32(self.material.aead))
+            .and_then(|e| e.bytes(&self.material.master_secret))
+            .and_then(|e| e.bytes(&self.material.master_salt))
+            .and_then(|e| e.bytes(&self.material.sender_id))
+            .and_then(|e| e.bytes(&self.material.recipient_id))
+            .and_then(|e| e.bytes(&self.material.id_cred))
+            .and_then(|e| e.u64(self.sequence_number_limit))
+            .expect("buffer is large enough for any context");
+        let len = cursor.position();
+        buffer.get(..len).expect("cursor stays within the buffer")
+    }
+
+    /// Decodes a context from its encoded form.
+    ///
+    /// # Errors
+    ///
+    /// This produces an error if the input is not an encoded context.
+    pub fn decode(encoded: &[u8]) -> Result<Self, InvalidPersistedContext> {
+        Self::decode_inner(encoded).ok_or(InvalidPersistedContext)
+    }
+
+    fn decode_inner(encoded: &[u8]) -> Option<Self> {
+        let mut decoder = minicbor::Decoder::new(encoded);
+        if decoder.array().ok()? != Some(8) {
+            return None;
+        }
+        let hkdf = decoder.i32().ok()?;
+        let aead = decoder.i32().ok()?;
+        let master_secret = decoder.bytes().ok()?;
+        let master_salt = decoder.bytes().ok()?;
+        let sender_id = decoder.bytes().ok()?;
+        let recipient_id = decoder.bytes().ok()?;
+        let id_cred = decoder.bytes().ok()?;
+        let sequence_number_limit = decoder.u64().ok()?;
+        if decoder.position() != encoded.len() {
+            return None;
+        }
+        // Only contexts with own recipient IDs are ever persisted.
+        COwn::from_kid(recipient_id)?;
+
+        Some(Self {
+            material: Material::new(
+                hkdf,
+                aead,
+                master_secret,
+                master_salt,
+                sender_id,
+                recipient_id,
+                id_cred,
+            )?,
+            sequence_number_limit,
+        })
+    }
+
+    /// Returns the peer's `ID_CRED_I`, through which the context's authorization is looked up.
+    pub(crate) fn id_cred(&self) -> &[u8] {
+        &self.material.id_cred
+    }
+}
+
+/// Error restoring a [`PersistedContext`].
+#[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum RestoreError {
+    /// Persistence was not enabled on the handler.
+    NotEnabled,
+    /// The context's algorithms or identifiers are not usable.
+    InvalidMaterial,
+    /// The peer's credential is not known (any more) to the server's security configuration.
+    UnknownCredential,
+    /// The handler has no space for more security contexts.
+    NoSpace,
+    /// A context with the same recipient ID is already in use.
+    InUse,
+}
+
+#[cfg(test)]
+#[allow(clippy::indexing_slicing, reason = "panicking is fine in tests")]
+mod test {
+    use super::*;
+
+    use crate::iana::cose_alg;
+
+    fn material(recipient_id: &[u8]) -> Material {
+        Material::new(
+            cose_alg::HKDF_HMAC256256,
+            cose_alg::AES_CCM_16_64_128,
+            &[1; MASTER_SECRET_LEN],
+            &[2; MASTER_SALT_LEN],
+            b"\x05",
+            recipient_id,
+            b"\xa1\x04\x41\x2b",
+        )
+        .unwrap()
+    }
+
+    fn persisted(sequence_number_limit: u64) -> PersistedContext {
+        PersistedContext {
+            material: material(b"\x0a"),
+            sequence_number_limit,
+        }
+    }
+
+    #[test]
+    fn material_limits() {
+        assert!(Material::new(0, 0, &[1; 16], &[2; 8], b"", b"\x0a", b"").is_some());
+        // Master secret and salt have the lengths exported from EDHOC.
+        assert!(Material::new(0, 0, &[1; 15], &[2; 8], b"", b"\x0a", b"").is_none());
+        assert!(Material::new(0, 0, &[1; 16], &[2; 9], b"", b"\x0a", b"").is_none());
+        assert!(Material::new(0, 0, &[1; 16], &[2; 8], &[3; 8], b"\x0a", b"").is_none());
+        let id_cred = [4; MAX_ID_CRED_LEN + 1];
+        assert!(Material::new(0, 0, &[1; 16], &[2; 8], b"", b"\x0a", &id_cred).is_none());
+    }
+
+    #[test]
+    fn encode_decode() {
+        let mut buffer = [0; MAX_ENCODED_LEN];
+        for limit in [0, 23, 1 << 40, u64::MAX] {
+            let encoded = persisted(limit).encode(&mut buffer);
+            let decoded = PersistedContext::decode(encoded).unwrap();
+            assert!(decoded.material == material(b"\x0a"));
+            assert_eq!(decoded.sequence_number_limit, limit);
+            assert_eq!(decoded.recipient_id(), b"\x0a");
+            assert_eq!(decoded.id_cred(), b"\xa1\x04\x41\x2b");
+        }
+
+        // The largest encodable context fits the advertised length.
+        let largest = PersistedContext {
+            material: Material::new(
+                i32::MIN,
+                i32::MIN,
+                &[1; MASTER_SECRET_LEN],
+                &[2; MASTER_SALT_LEN],
+                &[3; MAX_ID_LEN],
+                &[3; MAX_ID_LEN],
+                &[4; MAX_ID_CRED_LEN],
+            )
+            .unwrap(),
+            sequence_number_limit: u64::MAX,
+        };
+        assert_eq!(largest.encode(&mut buffer).len(), MAX_ENCODED_LEN);
+    }
+
+    #[test]
+    fn decode_rejects() {
+        let mut buffer = [0; MAX_ENCODED_LEN + 1];
+        let len = persisted(100).encode(&mut buffer).len();
+
+        // Truncated, or with trailing data.
+        assert!(PersistedContext::decode(&buffer[..len - 1]).is_err());
+        assert!(PersistedContext::decode(&buffer[..len + 1]).is_err());
+
+        // With a different number of items.
+        let mut modified = buffer;
+        modified[0] = 0x87;
+        assert!(PersistedContext::decode(&modified[..len]).is_err());
+
+        // With a recipient ID that this device does not assign.
+        let other = PersistedContext {
+            material: material(b"\x18"),
+            sequence_number_limit: 100,
+        };
+        let encoded = other.encode(&mut buffer);
+        assert!(PersistedContext::decode(encoded).is_err());
+
+        assert!(PersistedContext::decode(&[]).is_err());
+    }
+
+    #[test]
+    fn sequence_number_reservation() {
+        const RESERVATION: u64 = 64;
+
+        let (mut context, mut persistence) = Persistence::restore(&persisted(100)).unwrap();
+        assert!(persistence.replay_window_unknown);
+        assert_eq!(state(&mut context).sender_sequence_number, 100);
+
+        // Nothing is reserved for the restored context until a new limit is confirmed.
+        assert!(!persistence.has_sequence_number(&mut context));
+        let pending = persistence.pending(&mut context, RESERVATION).unwrap();
+        assert_eq!(pending.sequence_number_limit, 100 + RESERVATION);
+        assert!(!persistence.has_sequence_number(&mut context));
+        assert!(persistence.confirm(&pending));
+        assert!(persistence.has_sequence_number(&mut context));
+
+        // While more than half of the reservation is left, nothing needs to be stored.
+        assert!(persistence.pending(&mut context, RESERVATION).is_none());
+        state(&mut context).sender_sequence_number = 100 + RESERVATION / 2 - 1;
+        assert!(persistence.pending(&mut context, RESERVATION).is_none());
+        state(&mut context).sender_sequence_number = 100 + RESERVATION / 2;
+        // The new reservation extends the current one.
+        let pending = persistence.pending(&mut context, RESERVATION).unwrap();
+        assert_eq!(pending.sequence_number_limit, 100 + 2 * RESERVATION);
+
+        // Confirming an older state does not lower the limit.
+        assert!(persistence.confirm(&persisted(50)));
+        state(&mut context).sender_sequence_number = 100 + RESERVATION - 1;
+        assert!(persistence.has_sequence_number(&mut context));
+        state(&mut context).sender_sequence_number = 100 + RESERVATION;
+        assert!(!persistence.has_sequence_number(&mut context));
+
+        // States of other contexts are not taken over.
+        let other = PersistedContext {
+            material: material(b"\x0b"),
+            sequence_number_limit: 1000,
+        };
+        assert!(!persistence.confirm(&other));
+        assert!(!persistence.has_sequence_number(&mut context));
+
+        assert!(persistence.confirm(&pending));
+        assert!(persistence.has_sequence_number(&mut context));
+    }
+
+    #[test]
+    fn new_context_reserves() {
+        let material = material(b"\x0a");
+        let mut context =
+            liboscore::PrimitiveContext::new_from_fresh_material(material.derive().unwrap());
+        let mut persistence = Persistence::new(material);
+        assert!(!persistence.replay_window_unknown);
+
+        // A new context starts at sequence number 0, with nothing reserved yet.
+        assert!(!persistence.has_sequence_number(&mut context));
+        let pending = persistence.pending(&mut context, 16).unwrap();
+        assert_eq!(pending.sequence_number_limit, 16);
+        assert!(persistence.confirm(&pending));
+        assert!(persistence.has_sequence_number(&mut context));
+    }
+}
@@ -94,6 +94,29 @@ impl AifValue {
 
         Ok(Self(buffer))
     }
+
+    /// Returns whether every permission granted by `other` is also granted by `self`.
+    ///
+    /// Permissions are compared path by path, as each AIF entry only applies to requests to
+    /// exactly its path.
+    #[must_use]
+    pub fn covers(&self, other: &AifValue) -> bool {
+        let mut other_decoder = minicbor::Decoder::new(&other.0);
+        other_decoder
+            .array_iter::<(&str, u32)>()
+            .unwrap()
+            .all(|item| {
+                let (path, perms) = item.unwrap();
+                let mut decoder = minicbor::Decoder::new(&self.0);
+                let held = decoder
+                    .array_iter::<(&str, u32)>()
+                    .unwrap()
+                    .map(Result::unwrap)
+                    .filter(|(held_path, _)| *held_path == path)
+                    .fold(0, |held, (_, perms)| held | perms);
+                perms & !held == 0
+            })
+    }
 }
 
 impl Scope for AifValue {
@@ -168,6 +191,20 @@ pub enum UnionScope {
     DenyAll,
 }
 
+impl UnionScope {
+    /// Returns whether every permission granted by `other` is also granted by `self`.
+    ///
+    /// This allows checking that a peer only grants permissions it holds itself.
+    #[must_use]
+    pub fn covers(&self, other: &AifValue) -> bool {
+        match self {
+            UnionScope::AifValue(v) => v.covers(other),
+            UnionScope::AllowAll => true,
+            UnionScope::DenyAll => AifValue::parse(&[0x80]).is_ok_and(|empty| empty.covers(other)),
+        }
+    }
+}
+
 impl Scope for UnionScope {
     fn request_is_allowed<M: ReadableMessage>(&self, request: &M) -> bool {
         match self {
@@ -201,3 +238,46 @@ impl From<core::convert::Infallible> for UnionScope {
         match value {}
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn aif(bytes: &[u8]) -> AifValue {
+        AifValue::parse(bytes).unwrap()
+    }
+
+    #[test]
+    fn covers() {
+        // [["/a", GET | PUT], ["/b", GET]]
+        let held = aif(&[
+            0x82, 0x82, 0x62, b'/', b'a', 0x05, 0x82, 0x62, b'/', b'b', 0x01,
+        ]);
+        // [["/a", GET]]
+        let fewer_methods = aif(&[0x81, 0x82, 0x62, b'/', b'a', 0x01]);
+        // [["/a", POST]]
+        let other_method = aif(&[0x81, 0x82, 0x62, b'/', b'a', 0x02]);
+        // [["/a/c", GET]]
+        let other_path = aif(&[0x81, 0x82, 0x64, b'/', b'a', b'/', b'c', 0x01]);
+        // [["/a", GET], ["/a", PUT], ["/b", GET]]
+        let split = aif(&[
+            0x83, 0x82, 0x62, b'/', b'a', 0x01, 0x82, 0x62, b'/', b'a', 0x04, 0x82, 0x62, b'/',
+            b'b', 0x01,
+        ]);
+        let empty = aif(&[0x80]);
+
+        assert!(held.covers(&held));
+        assert!(held.covers(&fewer_methods));
+        assert!(held.covers(&split));
+        assert!(split.covers(&held));
+        assert!(held.covers(&empty));
+        assert!(!held.covers(&other_method));
+        assert!(!held.covers(&other_path));
+        assert!(!fewer_methods.covers(&held));
+
+        assert!(UnionScope::AllowAll.covers(&held));
+        assert!(UnionScope::DenyAll.covers(&empty));
+        assert!(!UnionScope::DenyAll.covers(&fewer_methods));
+        assert!(UnionScope::AifValue(held).covers(&fewer_methods));
+    }
+}
@@ -135,6 +135,23 @@ pub trait ServerSecurityConfig {
         None
     }
 
+    /// Expands the `ID_CRED_R` of a server that this device runs EDHOC with as the initiator (see
+    /// [`OscoreEdhocClient`][crate::client::OscoreEdhocClient]) into its credential.
+    ///
+    /// Unlike [`expand_id_cred_x()`][Self::expand_id_cred_x], which may accept any credential
+    /// sent by value at the level of [`nosec_authorization()`][Self::nosec_authorization], this
+    /// only produces credentials of peers that are trusted to be the server: accepting just any
+    /// credential would allow anyone on the path to impersonate the server.
+    ///
+    /// The default implementation trusts no server.
+    #[allow(
+        unused_variables,
+        reason = "Names are human visible part of API description"
+    )]
+    fn expand_id_cred_r(&self, id_cred_r: lakers::IdCred) -> Option<lakers::Credential> {
+        None
+    }
+
     /// Generates the scope representing unauthenticated access.
     ///
     /// Their time aspect is typically unbounded.
@@ -367,6 +384,67 @@ impl ServerSecurityConfig for ConfigBuilder {
             defmt_or_log::wrappers::Cbor(id_cred_x.as_full_value())
         );
 
+        // Without a known client, no credential is accepted, not even at the level of
+        // unauthenticated access.
+        self.known_edhoc_clients.as_ref()?;
+
+        if let Some(known) = self.expand_known_credential(&id_cred_x) {
+            return Some(known);
+        }
+
+        if let Some(unauthorized_claims) = self.nosec_authorization() {
+            trace!("Unauthenticated clients are generally accepted, evaluating credential.");
+            if let Some(credential_by_value) = id_cred_x.get_ccs().as_ref() {
+                debug!("The unauthorized client provided a usable credential by value.");
+                #[expect(clippy::clone_on_copy, reason = "Lakers items are overly copy happy")]
+                return Some((credential_by_value.clone(), unauthorized_claims));
+            }
+        }
+
+        None
+    }
+
+    fn expand_id_cred_r(&self, id_cred_r: lakers::IdCred) -> Option<lakers::Credential> {
+        trace!(
+            "Evaluating server's credential {}",
+            defmt_or_log::wrappers::Cbor(id_cred_r.as_full_value())
+        );
+        // Unlike in expand_id_cred_x, a credential sent by value is never accepted on its own.
+        self.expand_known_credential(&id_cred_r)
+            .map(|(credential, _claims)| credential)
+    }
+
+    fn render_not_allowed<M: coap_message::MutableWritableMessage>(
+        &self,
+        message: &mut M,
+    ) -> Result<(), NotAllowedRenderingFailed> {
+        use coap_message::Code;
+        message.set_code(M::Code::new(coap_numbers::code::UNAUTHORIZED).map_err(|_| {
+            error!("CoAP stack can not represent Unauthorized responses.");
+            NotAllowedRenderingFailed
+        })?);
+        message
+            .set_payload(self.request_creation_hints)
+            .map_err(|_| {
+                error!("Request creation hints do not fit in error message.");
+                NotAllowedRenderingFailed
+            })?;
+        Ok(())
+    }
+}
+
+impl Default for ConfigBuilder {
+    fn default() -> Self {
+        ConfigBuilder::new()
+    }
+}
+
+impl ConfigBuilder {
+    /// Expands `id_cred_x` into the configured known EDHOC credential, if it refers to it.
+    fn expand_known_credential(
+        &self,
+        id_cred_x: &lakers::IdCred,
+    ) -> Option<(lakers::Credential, ConfigBuilderClaims)> {
         #[expect(
             clippy::single_element_loop,
             reason = "Expected to be extended to actual loop soon"
@@ -378,7 +456,7 @@ impl ServerSecurityConfig for ConfigBuilder {
             );
             if id_cred_x.reference_only() {
                 // ad Ok: If our credential has no KID, it can't be recognized in this branch
-                if credential.by_kid().as_ref() == Ok(&id_cred_x) {
+                if credential.by_kid().as_ref() == Ok(id_cred_x) {
                     debug!("Peer indicated use of the one preconfigured key by KID.");
                     #[expect(
                         clippy::clone_on_copy,
@@ -395,7 +473,7 @@ impl ServerSecurityConfig for ConfigBuilder {
                 }
             } else {
                 // ad Ok: This is always the case for CCSs, but inapplicable eg. for PSKs.
-                if credential.by_value().as_ref() == Ok(&id_cred_x) {
+                if credential.by_value().as_ref() == Ok(id_cred_x) {
                     debug!("Peer indicated use of the one preconfigured credential by value.");
                     #[expect(
                         clippy::clone_on_copy,
@@ -413,44 +491,9 @@ impl ServerSecurityConfig for ConfigBuilder {
             }
         }
 
-        if let Some(unauthorized_claims) = self.nosec_authorization() {
-            trace!("Unauthenticated clients are generally accepted, evaluating credential.");
-            if let Some(credential_by_value) = id_cred_x.get_ccs().as_ref() {
-                debug!("The unauthorized client provided a usable credential by value.");
-                #[expect(clippy::clone_on_copy, reason = "Lakers items are overly copy happy")]
-                return Some((credential_by_value.clone(), unauthorized_claims));
-            }
-        }
-
         None
     }
 
-    fn render_not_allowed<M: coap_message::MutableWritableMessage>(
-        &self,
-        message: &mut M,
-    ) -> Result<(), NotAllowedRenderingFailed> {
-        use coap_message::Code;
-        message.set_code(M::Code::new(coap_numbers::code::UNAUTHORIZED).map_err(|_| {
-            error!("CoAP stack can not represent Unauthorized responses.");
-            NotAllowedRenderingFailed
-        })?);
-        message
-            .set_payload(self.request_creation_hints)
-            .map_err(|_| {
-                error!("Request creation hints do not fit in error message.");
-                NotAllowedRenderingFailed
-            })?;
-        Ok(())
-    }
-}
-
-impl Default for ConfigBuilder {
-    fn default() -> Self {
-        ConfigBuilder::new()
-    }
-}
-
-impl ConfigBuilder {
     /// Creates an empty server security configuration.
     ///
     /// Without any additional building steps, this is equivalent to [`DenyAll`].
@@ -629,3 +672,78 @@ impl GeneralClaims for ConfigBuilderClaims {
         self.is_important
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use hexlit::hex;
+
+    /// Credential of the peer known to the configuration.
+    const KNOWN: &[u8] = &hex!(
+        "A2026008A101A5010202410A2001215820BBC34960526EA4D32E940CAD2A234148DDC21791A12AFBCBAC93622046DD44F02258204519E257236B2A0CE2023F0931F1F386CA7AFDA64FCDE0108C224C51EABF6072"
+    );
+    /// Credential of some other peer.
+    const UNKNOWN: &[u8] = &hex!(
+        "A2027734322D35302D33312D46462D45462D33372D33322D333908A101A5010202412B2001215820AC75E9ECE3E50BFC8ED60399889522405C47BF16DF96660A41298CB4307F7EB62258206E5DE611388A4B8A8211334AC7D37ECB52A387D257E6DB3C2A93DF21FF3AFFC8"
+    );
+
+    fn credential(ccs: &[u8]) -> lakers::Credential {
+        lakers::Credential::parse_ccs(ccs).unwrap()
+    }
+
+    fn config() -> ConfigBuilder {
+        ConfigBuilder::new()
+            .with_known_edhoc_credential(credential(KNOWN), crate::scope::UnionScope::AllowAll)
+            .allow_unauthenticated(crate::scope::UnionScope::DenyAll)
+    }
+
+    #[test]
+    fn unknown_server_rejected() {
+        let config = config();
+        let unknown = credential(UNKNOWN);
+
+        // A client presenting the credential by value is accepted at the unauthenticated level,
+        assert!(
+            config
+                .expand_id_cred_x(unknown.by_value().unwrap())
+                .is_some_and(|(_, claims)| !claims.is_important)
+        );
+        // but a server presenting it is not trusted.
+        assert!(
+            config
+                .expand_id_cred_r(unknown.by_value().unwrap())
+                .is_none()
+        );
+        assert!(config.expand_id_cred_r(unknown.by_kid().unwrap()).is_none());
+    }
+
+    #[test]
+    fn no_known_client() {
+        let config = ConfigBuilder::new().allow_unauthenticated(crate::scope::UnionScope::DenyAll);
+        let unknown = credential(UNKNOWN);
+
+        assert!(
+            config
+                .expand_id_cred_x(unknown.by_value().unwrap())
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn known_server_accepted() {
+        let config = config();
+        let known = credential(KNOWN);
+
+        for id_cred_r in [known.by_value().unwrap(), known.by_kid().unwrap()] {
+            let expanded = config.expand_id_cred_r(id_cred_r).unwrap();
+            assert_eq!(expanded.bytes.as_slice(), KNOWN);
+        }
+
+        assert!(
+            DenyAll
+                .expand_id_cred_r(known.by_value().unwrap())
+                .is_none()
+        );
+    }
+}
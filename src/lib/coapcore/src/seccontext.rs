@@ -7,14 +7,19 @@
 use core::marker::PhantomData;
 
 use coap_message::{
-    Code, MessageOption, MinimalWritableMessage, MutableWritableMessage, ReadableMessage,
-    error::RenderableOnMinimal,
+    Code, MessageOption, MinimalWritableMessage, MutableWritableMessage, OptionNumber,
+    ReadableMessage, error::RenderableOnMinimal,
 };
 use coap_message_utils::{Error as CoAPError, OptionsExt as _};
 use defmt_or_log::{Debug2Format, debug, error, trace};
 
+use crate::events::{EdhocStage, Peer, PeerId, SecurityEvent};
 use crate::generalclaims::{self, GeneralClaims as _};
 use crate::helpers::COwn;
+use crate::observe::{
+    NotificationError, NotificationOutcome, Observation, ObservationSecurity, ObservedRequest,
+};
+use crate::persistence::{PersistedContext, Persistence, RestoreError};
 use crate::scope::Scope;
 use crate::seccfg::ServerSecurityConfig;
 
@@ -58,6 +63,8 @@ struct SecContextState<Crypto: lakers::Crypto, GeneralClaims: generalclaims::Gen
     // This is Some(...) unless the stage is unusable.
     authorization: Option<GeneralClaims>,
     protocol_stage: SecContextStage<Crypto>,
+    /// Persistence state of an OSCORE context, if it is persisted.
+    persistence: Option<Persistence>,
 }
 
 impl<Crypto: lakers::Crypto, GeneralClaims: generalclaims::GeneralClaims> Default
@@ -67,6 +74,7 @@ impl<Crypto: lakers::Crypto, GeneralClaims: generalclaims::GeneralClaims> Defaul
         Self {
             authorization: None,
             protocol_stage: SecContextStage::Empty,
+            persistence: None,
         }
     }
 }
@@ -189,6 +197,18 @@ pub struct OscoreEdhocHandler<
 
     crypto_factory: CryptoFactory,
     rng: RNG,
+
+    /// Observation registered by the request that is currently being processed.
+    observation: Option<Observation>,
+
+    /// Security context of the OSCORE group this device is a member of, if any.
+    group: Option<crate::group::GroupContext>,
+
+    /// Function to which [`SecurityEvent`]s are reported, if any.
+    security_events: Option<fn(SecurityEvent)>,
+
+    /// Settings for persisting security contexts, if enabled.
+    persistence: Option<crate::persistence::Settings>,
 }
 
 impl<
@@ -229,17 +249,335 @@ impl<
             authorities,
             rng,
             time,
+            observation: None,
+            group: None,
+            security_events: None,
+            persistence: None,
+        }
+    }
+
+    /// Sets a function to which [`SecurityEvent`]s are reported.
+    ///
+    /// See the [`events`][crate::events] module for details.
+    #[must_use]
+    pub fn with_security_events(mut self, report: fn(SecurityEvent)) -> Self {
+        self.security_events = Some(report);
+        self
+    }
+
+    /// Reports the event built by `event` if a function for reporting events is set.
+    fn report(&self, event: impl FnOnce() -> SecurityEvent) {
+        if let Some(report) = self.security_events {
+            report(event());
+        }
+    }
+
+    /// Adds the security context of an OSCORE group, whose members' requests are then processed
+    /// in addition to those of the peers known through `authorities`.
+    ///
+    /// Requests from group members are authorized by the group's scope; see the
+    /// [`group`][crate::group] module for details.
+    #[must_use]
+    pub fn with_group(mut self, mut group: crate::group::GroupContext) -> Self {
+        let mut echo = [0; crate::persistence::ECHO_LEN];
+        self.rng.fill_bytes(&mut echo);
+        group.set_echo(echo);
+        self.group = Some(group);
+        self
+    }
+
+    /// Enables persistence of security contexts established through EDHOC.
+    ///
+    /// `notify` is called whenever [`.pending_persistence()`][Self::pending_persistence] has
+    /// contexts to produce, and `reservation` is the number of sequence numbers reserved in
+    /// storage at a time. See the [`persistence`][crate::persistence] module for details.
+    #[must_use]
+    pub fn with_persistence(mut self, notify: fn(), reservation: u64) -> Self {
+        let mut echo = [0; crate::persistence::ECHO_LEN];
+        self.rng.fill_bytes(&mut echo);
+        self.persistence = Some(crate::persistence::Settings {
+            notify,
+            reservation,
+            echo,
+        });
+        self
+    }
+
+    /// Restores a security context from storage.
+    ///
+    /// This is called at startup, before any requests are processed. The context's authorization
+    /// is looked up from the peer's credential again. No responses that need own sequence numbers
+    /// can be sent in the context until its state was persisted again, so the application should
+    /// process [`.pending_persistence()`][Self::pending_persistence] right after restoring.
+    ///
+    /// # Errors
+    ///
+    /// This produces an error if persistence was not enabled, or if the context can not be used
+    /// any more.
+    pub fn restore_context(&mut self, persisted: &PersistedContext) -> Result<(), RestoreError> {
+        if self.persistence.is_none() {
+            return Err(RestoreError::NotEnabled);
+        }
+        let kid = COwn::from_kid(persisted.recipient_id()).ok_or(RestoreError::InvalidMaterial)?;
+        if self
+            .pool
+            .iter()
+            .any(|c| c.corresponding_cown() == Some(kid))
+        {
+            return Err(RestoreError::InUse);
+        }
+        let id_cred = lakers::IdCred::from_full_value(persisted.id_cred())
+            .map_err(|_| RestoreError::InvalidMaterial)?;
+        let (_, authorization) = self
+            .authorities
+            .expand_id_cred_x(id_cred)
+            .ok_or(RestoreError::UnknownCredential)?;
+        let (context, persistence) =
+            Persistence::restore(persisted).ok_or(RestoreError::InvalidMaterial)?;
+
+        self.pool
+            .insert(SecContextState {
+                authorization: Some(authorization),
+                protocol_stage: SecContextStage::Oscore(context),
+                persistence: Some(persistence),
+            })
+            .map_err(|_| RestoreError::NoSpace)?;
+        self.notify_persistence();
+        Ok(())
+    }
+
+    /// Produces a security context whose state needs to be persisted, if any.
+    ///
+    /// Once it is stored, [`.confirm_persisted()`][Self::confirm_persisted] needs to be called;
+    /// until then, the same context may be produced again.
+    pub fn pending_persistence(&mut self) -> Option<PersistedContext> {
+        let reservation = self.persistence.as_ref()?.reservation;
+        self.pool.iter_mut().find_map(|entry| match entry {
+            SecContextState {
+                protocol_stage: SecContextStage::Oscore(context),
+                persistence: Some(persistence),
+                ..
+            } => persistence.pending(context, reservation),
+            _ => None,
+        })
+    }
+
+    /// Records that a context produced by [`.pending_persistence()`][Self::pending_persistence]
+    /// was stored.
+    ///
+    /// From then on, the context uses the sequence numbers that were reserved with it.
+    pub fn confirm_persisted(&mut self, persisted: &PersistedContext) {
+        for entry in self.pool.iter_mut() {
+            if let Some(persistence) = &mut entry.persistence {
+                if persistence.confirm(persisted) {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Calls the function set in [`.with_persistence()`][Self::with_persistence] if any context
+    /// needs to be persisted.
+    fn notify_persistence(&mut self) {
+        if self.pending_persistence().is_some() {
+            if let Some(settings) = &self.persistence {
+                (settings.notify)();
+            }
         }
     }
 
+    /// Takes the observation registered by the most recently processed request, if any.
+    ///
+    /// The CoAP stack calls this right after a response was built, and if it produces a value,
+    /// stores it along with the peer's address and the request's token. See the
+    /// [`observe`][crate::observe] module for details.
+    pub fn take_observation(&mut self) -> Option<Observation> {
+        self.observation.take()
+    }
+
+    /// Builds a notification for an `observation` registered at this handler.
+    ///
+    /// The registration request is processed again by the inner handler, after checking that the
+    /// authorization under which it was registered is still valid. For OSCORE protected
+    /// observations, the response is protected with a new sequence number.
+    ///
+    /// # Errors
+    ///
+    /// This produces an error if no notification can be built any more; the observation should
+    /// then be removed.
+    ///
+    /// # Panics
+    ///
+    /// Panics for protected observations if the writable message is not a
+    /// [`coap_message_implementations::inmemory_write::Message`]. See module level documentation
+    /// for details.
+    pub fn build_notification<M: MutableWritableMessage>(
+        &mut self,
+        observation: &mut Observation,
+        response: &mut M,
+    ) -> Result<NotificationOutcome, NotificationError> {
+        let request = observation.request.message();
+        match &mut observation.security {
+            ObservationSecurity::Unprotected => {
+                if !self.authorities.nosec_authorization().is_some_and(|s| {
+                    s.scope().request_is_allowed(&request)
+                        && s.time_constraint().is_valid_with(&mut self.time)
+                }) {
+                    return Err(NotificationError::NotAllowed);
+                }
+                Ok(build_notification_inner(
+                    &mut self.inner,
+                    &request,
+                    response,
+                ))
+            }
+            ObservationSecurity::Oscore { kid, correlation } => {
+                let kid = *kid;
+                let correlation = correlation.as_mut().ok_or(NotificationError::ContextLost)?;
+                let inner = &mut self.inner;
+                let time = &mut self.time;
+                let built = self
+                    .pool
+                    .lookup(
+                        |c| c.corresponding_cown() == Some(kid),
+                        |matched| {
+                            let SecContextState {
+                                protocol_stage: SecContextStage::Oscore(oscore_context),
+                                authorization: Some(authorization),
+                                persistence,
+                            } = matched
+                            else {
+                                return Err(NotificationError::ContextLost);
+                            };
+                            if !(authorization.scope().request_is_allowed(&request)
+                                && authorization.time_constraint().is_valid_with(time))
+                            {
+                                return Err(NotificationError::NotAllowed);
+                            }
+                            if persistence
+                                .as_ref()
+                                .is_some_and(|p| !p.has_sequence_number(oscore_context))
+                            {
+                                return Err(NotificationError::SequenceNumbersExhausted);
+                            }
+
+                            let response = coap_message_implementations::inmemory_write::Message::downcast_from(response)
+                                .expect("OSCORE handler currently requires a response message implementation that is of fixed type");
+                            // Notifications are 2.05 Content on the outside (RFC8613 Section 4.2),
+                            // with an outer Observe option for the benefit of proxies. Its value
+                            // is taken from the notification's Partial IV, which increases with
+                            // every notification (RFC8613 Section 4.1.3.5.2).
+                            response.set_code(coap_numbers::code::CONTENT);
+                            let sequence_number =
+                                crate::observe::next_sequence_number(oscore_context);
+                            response
+                                .add_option(
+                                    coap_numbers::option::OBSERVE,
+                                    &crate::observe::observe_value(sequence_number),
+                                )
+                                .map_err(|_| {
+                                    error!("Adding outer Observe option failed");
+                                    NotificationError::ContextLost
+                                })?;
+
+                            liboscore::protect_response(
+                                response,
+                                oscore_context,
+                                // Having been used for the registration's response, this makes
+                                // libOSCORE include a new Partial IV.
+                                correlation,
+                                |response| build_notification_inner(inner, &request, response),
+                            )
+                            .map_err(|_| {
+                                error!("Protecting notification failed");
+                                NotificationError::ContextLost
+                            })
+                        },
+                    )
+                    .ok_or(NotificationError::ContextLost)?;
+                self.notify_persistence();
+                built
+            }
+        }
+    }
+
+    /// Runs the registration request of an `observation` through the inner handler like
+    /// [`build_notification()`][Self::build_notification], and returns the value of the Observe
+    /// option in the result, or `None` if it has none.
+    ///
+    /// The result is built into `buffer` without any protection, and discarded; this does not use
+    /// up any sequence numbers of OSCORE protected observations. Comparing the value to that of the
+    /// latest notification tells whether the observed resource has changed since.
+    ///
+    /// # Errors
+    ///
+    /// This produces the same errors as [`build_notification()`][Self::build_notification] when
+    /// the observation is not valid any more; it should then be removed.
+    pub fn observe_value(
+        &mut self,
+        observation: &Observation,
+        buffer: &mut [u8],
+    ) -> Result<Option<u32>, NotificationError> {
+        let request = observation.request.message();
+        let allowed = match &observation.security {
+            ObservationSecurity::Unprotected => {
+                self.authorities.nosec_authorization().is_some_and(|s| {
+                    s.scope().request_is_allowed(&request)
+                        && s.time_constraint().is_valid_with(&mut self.time)
+                })
+            }
+            ObservationSecurity::Oscore { kid, .. } => {
+                let kid = *kid;
+                let time = &mut self.time;
+                self.pool
+                    .lookup(
+                        |c| c.corresponding_cown() == Some(kid),
+                        |matched| {
+                            let SecContextState {
+                                protocol_stage: SecContextStage::Oscore(_),
+                                authorization: Some(authorization),
+                                ..
+                            } = matched
+                            else {
+                                return None;
+                            };
+                            Some(
+                                authorization.scope().request_is_allowed(&request)
+                                    && authorization.time_constraint().is_valid_with(time),
+                            )
+                        },
+                    )
+                    .flatten()
+                    .ok_or(NotificationError::ContextLost)?
+            }
+        };
+        if !allowed {
+            return Err(NotificationError::NotAllowed);
+        }
+
+        let mut code = 0;
+        let mut response =
+            coap_message_implementations::inmemory_write::Message::new(&mut code, &mut *buffer);
+        build_notification_inner(&mut self.inner, &request, &mut response);
+        let len = response.finish();
+        #[allow(
+            clippy::indexing_slicing,
+            reason = "length is populated by the message"
+        )]
+        let response = coap_message_implementations::inmemory::Message::new(code, &buffer[..len]);
+        Ok(crate::observe::observe_option(&response))
+    }
+
     /// Produces a [`COwn`] (as a recipient identifier) that is both available and not equal to the
     /// peer's recipient identifier.
-    fn cown_but_not(&self, c_peer: &[u8]) -> COwn {
+    ///
+    /// This takes the pool rather than `self` so that it can be used while other fields are
+    /// borrowed.
+    fn cown_but_not(pool: &SecContextPool<Crypto, SSC::GeneralClaims>, c_peer: &[u8]) -> COwn {
         // Let's pick one now already: this allows us to use the identifier in our
         // request data.
         COwn::not_in_iter(
-            self.pool
-                .iter()
+            pool.iter()
                 .filter_map(|entry| entry.corresponding_cown())
                 // C_R does not only need to be unique, it also must not be identical
                 // to C_I. If it is not expressible as a COwn (as_slice gives []),
@@ -290,7 +628,12 @@ impl<
                 own_identity.0,
             )
             .process_message_1(message_1)
-            .map_err(render_error)?;
+            .map_err(|e| {
+                self.report(|| SecurityEvent::EdhocFailed {
+                    stage: EdhocStage::Message1,
+                });
+                render_error(e)
+            })?;
 
             if ead_1.is_some_and(|e| e.is_critical) {
                 error!("Critical EAD1 item received, aborting");
@@ -298,7 +641,7 @@ impl<
                 return Err(CoAPError::bad_request());
             }
 
-            let c_r = self.cown_but_not(c_i.as_slice());
+            let c_r = Self::cown_but_not(&self.pool, c_i.as_slice());
 
             let _evicted = self.pool.force_insert(SecContextState {
                 protocol_stage: SecContextStage::EdhocResponderProcessedM1 {
@@ -307,6 +650,7 @@ impl<
                     responder,
                 },
                 authorization: self.authorities.nosec_authorization(),
+                persistence: None,
             });
 
             Ok(OwnRequestData::EdhocOkSend2(c_r))
@@ -345,6 +689,7 @@ impl<
                             responder: taken,
                         },
                     authorization,
+                    ..
                 } = taken
                 else {
                     todo!();
@@ -370,6 +715,7 @@ impl<
                         c_r,
                     },
                     authorization,
+                    persistence: None,
                 };
                 Ok(message_2)
             },
@@ -426,12 +772,24 @@ impl<
             CoAPError::bad_option(coap_numbers::option::OSCORE)
         })?;
 
-        let kid = COwn::from_kid(oscore_option.kid().ok_or_else(|| {
+        let kid_bytes = oscore_option.kid().ok_or_else(|| {
             error!("OSCORE KID is not in our value space");
             CoAPError::bad_option(coap_numbers::option::OSCORE)
-        })?)
-        // same as if it's not found in the pool
-        .ok_or_else(CoAPError::bad_request)?;
+        })?;
+        let security_events = self.security_events;
+        let unknown_context = || {
+            if let Some(report) = security_events {
+                report(SecurityEvent::UnknownSecurityContext {
+                    kid: PeerId::new(kid_bytes),
+                });
+            }
+        };
+        let kid = COwn::from_kid(kid_bytes)
+            // same as if it's not found in the pool
+            .ok_or_else(|| {
+                unknown_context();
+                CoAPError::bad_request()
+            })?;
         // If we don't make progress, we're dropping it altogether. Unless we use the
         // responder we might legally continue (because we didn't send data to EDHOC), but
         // once we've received something that (as we now know) looks like a message 3 and
@@ -442,6 +800,7 @@ impl<
             // following RFC8613 Section 8.2 item 2.2
             .ok_or_else(|| {
                 error!("No security context with this KID.");
+                unknown_context();
                 // FIXME unauthorized (unreleased in coap-message-utils)
                 CoAPError::bad_request()
             })?;
@@ -460,6 +819,7 @@ impl<
         let SecContextState {
             protocol_stage: SecContextStage::Oscore(mut oscore_context),
             authorization: Some(authorization),
+            mut persistence,
         } = taken
         else {
             // FIXME: How'd we even get there? Should this be unreachable?
@@ -480,6 +840,9 @@ impl<
             // to retain the authorization (if there is some kind of renewal tokens / token
             // series).
             debug!("Discarding expired context");
+            self.report(|| SecurityEvent::AuthorizationExpired {
+                kid: PeerId::new(kid.as_slice()),
+            });
             return Err(CoAPError::bad_request());
         }
 
@@ -518,19 +881,55 @@ impl<
                 CoAPError::internal_server_error()
             })?;
 
-        let decrypted = liboscore::unprotect_request(
+        // A context restored from storage only processes requests once their freshness was shown
+        // through an Echo value.
+        let expected_echo = self
+            .persistence
+            .as_ref()
+            .filter(|_| {
+                persistence
+                    .as_ref()
+                    .is_some_and(|p| p.replay_window_unknown)
+            })
+            .map(|settings| settings.echo);
+
+        let mut decrypted = liboscore::unprotect_request(
             &mut copied_message,
             oscore_option,
             &mut oscore_context,
             |request| {
+                if let Some(echo) = expected_echo {
+                    if !request.options().any(|o| {
+                        o.number() == coap_numbers::option::ECHO && o.value() == echo.as_slice()
+                    }) {
+                        return (AuthorizationChecked::NeedsEcho(echo), None);
+                    }
+                }
                 if authorization.scope().request_is_allowed(request) {
-                    AuthorizationChecked::Allowed(self.inner.extract_request_data(request))
+                    let extracted = self.inner.extract_request_data(request);
+                    let observed = extracted
+                        .is_ok()
+                        .then(|| ObservedRequest::registration(request))
+                        .flatten();
+                    (AuthorizationChecked::Allowed(extracted), observed)
                 } else {
-                    AuthorizationChecked::NotAllowed
+                    (AuthorizationChecked::NotAllowed, None)
                 }
             },
         );
 
+        if let (Ok((correlation, (extracted, _))), Some(persistence)) =
+            (&mut decrypted, &mut persistence)
+        {
+            if persistence.replay_window_unknown {
+                persistence.process_unknown_replay_window(
+                    &mut oscore_context,
+                    correlation,
+                    !matches!(extracted, AuthorizationChecked::NeedsEcho(_)),
+                );
+            }
+        }
+
         // With any luck, this never moves out.
         //
         // Storing it even on decryption failure to avoid DoS from the first message (but
@@ -539,6 +938,7 @@ impl<
         let _evicted = self.pool.force_insert(SecContextState {
             protocol_stage: SecContextStage::Oscore(oscore_context),
             authorization: Some(authorization),
+            persistence,
         });
         debug_assert!(
             matches!(
@@ -550,13 +950,36 @@ impl<
             ),
             "A Default (Empty) was placed when an item was taken, which should have the lowest priority"
         );
+        if with_edhoc {
+            self.notify_persistence();
+        }
 
-        let Ok((correlation, extracted)) = decrypted else {
+        let Ok((correlation, (extracted, observed))) = decrypted else {
             // FIXME is that the right code?
             error!("Decryption failure");
+            // libOSCORE does not tell replays apart from other failures.
+            self.report(|| SecurityEvent::OscoreRequestRejected {
+                kid: PeerId::new(kid.as_slice()),
+            });
             return Err(CoAPError::unauthorized());
         };
 
+        if matches!(extracted, AuthorizationChecked::NotAllowed) {
+            self.report(|| SecurityEvent::ScopeDenied {
+                peer: Peer::Oscore {
+                    kid: PeerId::new(kid.as_slice()),
+                },
+            });
+        }
+
+        self.observation = observed.map(|request| Observation {
+            security: ObservationSecurity::Oscore {
+                kid,
+                correlation: None,
+            },
+            request,
+        });
+
         Ok(OwnRequestData::EdhocOscoreRequest {
             kid,
             correlation,
@@ -564,6 +987,69 @@ impl<
         })
     }
 
+    /// Processes a CoAP request that is protected with Group OSCORE in group mode.
+    ///
+    /// # Errors
+    ///
+    /// This produces errors if no group is configured, or if the request is not a valid group
+    /// mode request from a member of the group.
+    fn extract_group_oscore<M: ReadableMessage>(
+        &mut self,
+        request: &M,
+        oscore_option: &crate::group::GroupOption,
+    ) -> Result<OwnRequestData<Result<H::RequestData, H::ExtractRequestError>>, CoAPError> {
+        let Some(group) = self.group.as_mut() else {
+            error!("Group OSCORE request received, but no group is configured.");
+            return Err(CoAPError::bad_option(coap_numbers::option::OSCORE));
+        };
+
+        // See comment on EDHOC_COPY_BUFFER_SIZE; unlike there, the plaintext is needed in any
+        // case.
+        let mut plaintext = [0u8; EDHOC_COPY_BUFFER_SIZE];
+        let (correlation, len) = group
+            .unprotect_request(oscore_option, request.payload(), &mut plaintext)
+            .map_err(|e| {
+                error!("Group OSCORE request rejected: {:?}", e);
+                if let Some(report) = self.security_events {
+                    report(SecurityEvent::GroupRequestRejected {
+                        sender_id: crate::group::sender_id(oscore_option).map(PeerId::new),
+                        reason: e,
+                    });
+                }
+                CoAPError::from(e)
+            })?;
+        let (&code, tail) = plaintext
+            .get(..len)
+            .and_then(<[u8]>::split_first)
+            .ok_or_else(CoAPError::bad_request)?;
+        let decrypted = coap_message_implementations::inmemory::Message::new(code, tail);
+
+        // Until a member's replay window is initialized, its requests are only processed once
+        // their freshness was shown through an Echo value.
+        let freshness = group.check_freshness(&correlation, |echo| {
+            decrypted
+                .options()
+                .any(|o| o.number() == coap_numbers::option::ECHO && o.value() == echo)
+        });
+        let extracted = if let Err(echo) = freshness {
+            AuthorizationChecked::NeedsEcho(echo)
+        } else if group.scope().request_is_allowed(&decrypted) {
+            AuthorizationChecked::Allowed(self.inner.extract_request_data(&decrypted))
+        } else {
+            self.report(|| SecurityEvent::ScopeDenied {
+                peer: Peer::Group {
+                    sender_id: PeerId::new(correlation.sender_id()),
+                },
+            });
+            AuthorizationChecked::NotAllowed
+        };
+
+        Ok(OwnRequestData::GroupOscoreRequest {
+            correlation,
+            extracted,
+        })
+    }
+
     /// Processes an EDHOC message 3 at the beginning of a payload, and returns the number of bytes
     /// that were in the message.
     ///
@@ -608,20 +1094,39 @@ impl<
             let msg_3 = lakers::EdhocMessageBuffer::new_from_slice(&payload[..cutoff])
                 .map_err(too_small)?;
 
+            let edhoc_failed = |e| {
+                self.report(|| SecurityEvent::EdhocFailed {
+                    stage: EdhocStage::Message3,
+                });
+                render_error(e)
+            };
+
             let (responder, id_cred_i, mut ead_3) =
-                responder.parse_message_3(&msg_3).map_err(render_error)?;
+                responder.parse_message_3(&msg_3).map_err(edhoc_failed)?;
+            let id_cred = PeerId::new(id_cred_i.as_full_value());
+            // Retained for persistence, which looks the credential up again at restore time.
+            let id_cred_full =
+                heapless::Vec::<u8, { crate::persistence::MAX_ID_CRED_LEN }>::from_slice(
+                    id_cred_i.as_full_value(),
+                )
+                .ok();
 
             let mut cred_i_and_authorization = None;
 
             if let Some(lakers::EADItem { label: crate::iana::edhoc_ead::ACETOKEN, value: Some(value), .. }) = ead_3.take() {
-                match crate::ace::process_edhoc_token(value.as_slice(), &self.authorities) {
+                match crate::ace::process_edhoc_token(value.as_slice(), &self.authorities, &mut self.time) {
                     Ok(ci_and_a) => cred_i_and_authorization = Some(ci_and_a),
                     Err(e) => {
                         error!("Received unprocessable token {}, error: {:?}", defmt_or_log::wrappers::Cbor(value.as_slice()), Debug2Format(&e));
+                        self.report(|| SecurityEvent::TokenRejected { reason: e.detail() });
                     }
                 }
             }
 
+            // Contexts established through tokens are not persisted: their authorization can not
+            // be looked up again.
+            let from_token = cred_i_and_authorization.is_some();
+
             if cred_i_and_authorization.is_none() {
                 cred_i_and_authorization = self
                     .authorities
@@ -631,6 +1136,7 @@ impl<
             let Some((cred_i, authorization)) = cred_i_and_authorization else {
                 // FIXME: send better message; how much variability should we allow?
                 error!("Peer's ID_CRED_I could not be resolved into CRED_I.");
+                self.report(|| SecurityEvent::UnknownCredential { id_cred });
                 return Err(CoAPError::bad_request());
             };
 
@@ -643,9 +1149,9 @@ impl<
             }
 
             let (responder, _prk_out) =
-                responder.verify_message_3(cred_i).map_err(render_error)?;
+                responder.verify_message_3(cred_i).map_err(edhoc_failed)?;
 
-            let mut responder = responder.completed_without_message_4().map_err(render_error)?;
+            let mut responder = responder.completed_without_message_4().map_err(edhoc_failed)?;
 
             // Once this gets updated beyond Lakers 0.7.2 (likely to 0.8), this will be needed:
             // let mut responder = responder.completed_without_message_4()
@@ -677,9 +1183,32 @@ impl<
 
             let context = liboscore::PrimitiveContext::new_from_fresh_material(immutables);
 
+            let persistence = self
+                .persistence
+                .as_ref()
+                .filter(|_| !from_token)
+                .and_then(|_| {
+                    crate::persistence::Material::new(
+                        crate::iana::cose_alg::HKDF_HMAC256256,
+                        crate::iana::cose_alg::AES_CCM_16_64_128,
+                        oscore_secret,
+                        oscore_salt,
+                        sender_id,
+                        recipient_id,
+                        id_cred_full.as_deref()?,
+                    )
+                })
+                .map(Persistence::new);
+
+            self.report(|| SecurityEvent::EdhocCompleted {
+                id_cred,
+                recipient_id: PeerId::new(recipient_id),
+            });
+
             SecContextState {
                 protocol_stage: SecContextStage::Oscore(context),
                 authorization: Some(authorization),
+                persistence,
             }
         } else {
             // Return the state. Best bet is that it was already advanced to an OSCORE
@@ -732,11 +1261,23 @@ impl<
         // async and the handler has a method to start writing to the message (which kind'a
         // implies rewinding)
 
+        // Responses to observation registrations are 2.05 Content on the outside (RFC8613
+        // Section 4.2).
+        let outer_code = if self
+            .observation
+            .as_ref()
+            .is_some_and(Observation::is_protected)
+        {
+            coap_numbers::code::CONTENT
+        } else {
+            coap_numbers::code::CHANGED
+        };
+
         self.pool
                     .lookup(|c| c.corresponding_cown() == Some(kid), |matched| {
                         // Not checking authorization any more: we don't even have access to the
                         // request any more, that check was done.
-                        let SecContextState { protocol_stage: SecContextStage::Oscore(oscore_context), .. } = matched else {
+                        let SecContextState { protocol_stage: SecContextStage::Oscore(oscore_context), persistence, .. } = matched else {
                             // State vanished before response was built.
                             //
                             // As it is, depending on the CoAP stack, there may be DoS if a peer
@@ -744,11 +1285,19 @@ impl<
                             error!("State vanished before response was built.");
                             return Err(CoAPError::internal_server_error());
                         };
+                        // Responses asking for an Echo can not reuse the request's nonce, which
+                        // may be one from before the restart.
+                        if matches!(extracted, AuthorizationChecked::NeedsEcho(_))
+                            && persistence.as_ref().is_some_and(|p| !p.has_sequence_number(oscore_context))
+                        {
+                            error!("No sequence numbers reserved to request an Echo.");
+                            return Err(CoAPError::internal_server_error());
+                        }
 
                         let response = coap_message_implementations::inmemory_write::Message::downcast_from(response)
                             .expect("OSCORE handler currently requires a response message implementation that is of fixed type");
 
-                        response.set_code(coap_numbers::code::CHANGED);
+                        response.set_code(outer_code);
 
                         if liboscore::protect_response(
                             response,
@@ -758,59 +1307,7 @@ impl<
                             // should be a tie; carry the OSCORE context in an owned way?).
                             oscore_context,
                             &mut correlation,
-                            |response| match extracted {
-                                AuthorizationChecked::Allowed(Ok(extracted)) => match self.inner.build_response(response, extracted) {
-                                    Ok(()) => {
-                                        // All fine, response was built
-                                    },
-                                    // One attempt to render rendering errors
-                                    // FIXME rewind message
-                                    Err(e) => {
-                                        error!("Rendering successful extraction failed with {:?}", Debug2Format(&e));
-                                        match e.render(response) {
-                                            Ok(()) => {
-                                                error!("Error rendered.");
-                                            },
-                                            Err(e2) => {
-                                                error!("Error could not be rendered: {:?}.", Debug2Format(&e2));
-                                                // FIXME rewind message
-                                                response.set_code(coap_numbers::code::INTERNAL_SERVER_ERROR);
-                                            }
-                                        }
-                                    },
-                                },
-                                AuthorizationChecked::Allowed(Err(inner_request_error)) => {
-                                    error!("Extraction failed with {:?}.", Debug2Format(&inner_request_error));
-                                    match inner_request_error.render(response) {
-                                        Ok(()) => {
-                                            error!("Original error rendered successfully.");
-                                        },
-                                        Err(e) => {
-                                            error!("Original error could not be rendered due to {:?}:", Debug2Format(&e));
-                                            // Two attempts to render extraction errors
-                                            // FIXME rewind message
-                                            match e.render(response) {
-                                                Ok(()) => {
-                                                    error!("Error was rendered fine.");
-                                                },
-                                                Err(e2) => {
-                                                    error!("Rendering error caused {:?}.", Debug2Format(&e2));
-                                                    // FIXME rewind message
-                                                    response.set_code(
-                                                        coap_numbers::code::INTERNAL_SERVER_ERROR,
-                                                    );
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                                AuthorizationChecked::NotAllowed => {
-                                    if self.authorities.render_not_allowed(response).is_err() {
-                                        // FIXME rewind message
-                                        response.set_code(coap_numbers::code::UNAUTHORIZED);
-                                    }
-                                }
-                            },
+                            |response| build_inner_response(&mut self.inner, &self.authorities, response, extracted),
                         )
                         .is_err()
                         {
@@ -820,9 +1317,77 @@ impl<
                         Ok(())
                     })
                 .transpose().map_err(Ok)?;
+        self.notify_persistence();
+
+        // Notifications continue from the correlation data as it is after protecting this
+        // response, so that they do not reuse the request's nonce.
+        if let Some(Observation {
+            security:
+                ObservationSecurity::Oscore {
+                    kid: observed_kid,
+                    correlation: observed_correlation,
+                },
+            ..
+        }) = &mut self.observation
+        {
+            if *observed_kid == kid {
+                *observed_correlation = Some(correlation);
+            }
+        }
         Ok(())
     }
 
+    /// Builds a response to a request that was protected with Group OSCORE, protecting it in
+    /// group mode.
+    ///
+    /// # Errors
+    ///
+    /// This produces errors if the response can not be protected (in particular when the group
+    /// context's sequence numbers are exhausted), or if the message can not hold the result.
+    fn build_group_response<M: MutableWritableMessage>(
+        &mut self,
+        response: &mut M,
+        correlation: crate::group::Correlation,
+        extracted: AuthorizationChecked<Result<H::RequestData, H::ExtractRequestError>>,
+    ) -> Result<(), Result<CoAPError, M::UnionError>> {
+        // The inner response is built first, as its length determines the payload's length.
+        let mut buffer = [0u8; EDHOC_COPY_BUFFER_SIZE];
+        let (code_byte, tail) = buffer.split_first_mut().expect("buffer is not empty");
+        let mut code = 0;
+        let mut plaintext =
+            coap_message_implementations::inmemory_write::Message::new(&mut code, tail);
+        build_inner_response(
+            &mut self.inner,
+            &self.authorities,
+            &mut plaintext,
+            extracted,
+        );
+        let len = 1 + plaintext.finish();
+        *code_byte = code;
+
+        let group = self
+            .group
+            .as_mut()
+            .ok_or_else(|| Ok(CoAPError::internal_server_error()))?;
+        let option = group.response_option().map_err(|e| Ok(e.into()))?;
+
+        response.set_code(M::Code::new(coap_numbers::code::CHANGED).map_err(|x| Err(x.into()))?);
+        response
+            .add_option(
+                M::OptionNumber::new(coap_numbers::option::OSCORE).map_err(|x| Err(x.into()))?,
+                &option,
+            )
+            .map_err(|x| Err(x.into()))?;
+        let payload = response
+            .payload_mut_with_len(len + crate::group::OVERHEAD)
+            .map_err(|x| Err(x.into()))?;
+        #[allow(clippy::indexing_slicing, reason = "lengths are checked or set above")]
+        payload[..len].copy_from_slice(&buffer[..len]);
+        group
+            .protect_response(&correlation, &option, payload)
+            .map_err(|e| Ok(e.into()))
+    }
+
     /// Processes a CoAP request containing an ACE token for /authz-info.
     ///
     /// This assumes that the content format was pre-checked to be application/ace+cbor, both in
@@ -840,30 +1405,40 @@ impl<
         let mut nonce2 = [0; crate::ace::OWN_NONCE_LEN];
         self.rng.fill_bytes(&mut nonce2);
 
-        let (response, oscore, generalclaims) =
-            crate::ace::process_acecbor_authz_info(payload, &self.authorities, nonce2, |nonce1| {
+        let (response, oscore, generalclaims) = crate::ace::process_acecbor_authz_info(
+            payload,
+            &self.authorities,
+            &mut self.time,
+            nonce2,
+            |nonce1| {
                 // This preferably (even exclusively) produces EDHOC-ideal recipient IDs, but as long
                 // as we're having more of those than slots, no point in not reusing the code.
-                self.cown_but_not(nonce1)
-            })
-            .map_err(|e| {
-                error!("Sending out error:");
-                error!("{:?}", Debug2Format(&e));
-                e.position
-                    // FIXME: Could also come from processing inner
-                    .map_or(CoAPError::bad_request(), CoAPError::bad_request_with_rbep)
-            })?;
+                Self::cown_but_not(&self.pool, nonce1)
+            },
+        )
+        .map_err(|e| {
+            error!("Sending out error:");
+            error!("{:?}", Debug2Format(&e));
+            self.report(|| SecurityEvent::TokenRejected { reason: e.detail() });
+            e.position
+                // FIXME: Could also come from processing inner
+                .map_or(CoAPError::bad_request(), CoAPError::bad_request_with_rbep)
+        })?;
 
         debug!(
             "Established OSCORE context with recipient ID {:?} and authorization {:?} through ACE-OSCORE",
             oscore.recipient_id(),
             Debug2Format(&generalclaims)
         );
+        self.report(|| SecurityEvent::TokenAccepted {
+            recipient_id: PeerId::new(oscore.recipient_id()),
+        });
         // FIXME: This should be flagged as "unconfirmed" for rapid eviction, as it could be part
         // of a replay.
         let _evicted = self.pool.force_insert(SecContextState {
             protocol_stage: SecContextStage::Oscore(oscore),
             authorization: Some(generalclaims),
+            persistence: None,
         });
 
         Ok(response)
@@ -881,6 +1456,9 @@ pub enum AuthorizationChecked<I> {
     Allowed(I),
     /// Middleware checks failed, return a 4.01 Unauthorized
     NotAllowed,
+    /// The request may be a replay from before a restart, return a 4.01 Unauthorized with this
+    /// Echo value
+    NeedsEcho([u8; crate::persistence::ECHO_LEN]),
 }
 
 /// Request state created by an [`OscoreEdhocHandler`] for successful non-plaintext cases.
@@ -901,6 +1479,11 @@ pub enum OwnRequestData<I> {
         extracted: AuthorizationChecked<I>,
     },
     ProcessedToken(crate::ace::AceCborAuthzInfoResponse),
+    GroupOscoreRequest {
+        #[expect(private_interfaces, reason = "should be addressed eventually")]
+        correlation: crate::group::Correlation,
+        extracted: AuthorizationChecked<I>,
+    },
 }
 
 // FIXME: It'd be tempting to implement Drop for Response to set the slot back to Empty -- but
@@ -908,6 +1491,132 @@ pub enum OwnRequestData<I> {
 // not supported in match or let destructuring. (But our is_gc_eligible should be good enough
 // anyway).
 
+/// Runs a retained observation registration through the inner handler, rendering any errors into
+/// the notification.
+fn build_notification_inner<H: coap_handler::Handler, M: MutableWritableMessage>(
+    inner: &mut H,
+    request: &impl ReadableMessage,
+    response: &mut M,
+) -> NotificationOutcome {
+    let rendered = match inner.extract_request_data(request) {
+        Ok(extracted) => match inner.build_response(response, extracted) {
+            Ok(()) => return NotificationOutcome::Continues,
+            Err(e) => {
+                error!("Rendering notification failed with {:?}", Debug2Format(&e));
+                e.render(response).is_ok()
+            }
+        },
+        Err(e) => {
+            error!(
+                "Extraction for notification failed with {:?}",
+                Debug2Format(&e)
+            );
+            e.render(response).is_ok()
+        }
+    };
+    if !rendered {
+        // FIXME rewind message
+        if let Ok(code) = M::Code::new(coap_numbers::code::INTERNAL_SERVER_ERROR) {
+            response.set_code(code);
+        }
+    }
+    NotificationOutcome::Final
+}
+
+/// Runs the inner handler's response building for a request that was extracted from an OSCORE or
+/// Group OSCORE protected request, rendering any errors into the (inner) response.
+fn build_inner_response<
+    H: coap_handler::Handler,
+    SSC: ServerSecurityConfig,
+    M: MutableWritableMessage,
+>(
+    inner: &mut H,
+    authorities: &SSC,
+    response: &mut M,
+    extracted: AuthorizationChecked<Result<H::RequestData, H::ExtractRequestError>>,
+) {
+    let set_internal_server_error = |response: &mut M| {
+        // FIXME rewind message
+        if let Ok(code) = M::Code::new(coap_numbers::code::INTERNAL_SERVER_ERROR) {
+            response.set_code(code);
+        }
+    };
+
+    match extracted {
+        AuthorizationChecked::Allowed(Ok(extracted)) => {
+            match inner.build_response(response, extracted) {
+                Ok(()) => {
+                    // All fine, response was built
+                }
+                // One attempt to render rendering errors
+                // FIXME rewind message
+                Err(e) => {
+                    error!(
+                        "Rendering successful extraction failed with {:?}",
+                        Debug2Format(&e)
+                    );
+                    match e.render(response) {
+                        Ok(()) => {
+                            error!("Error rendered.");
+                        }
+                        Err(e2) => {
+                            error!("Error could not be rendered: {:?}.", Debug2Format(&e2));
+                            set_internal_server_error(response);
+                        }
+                    }
+                }
+            }
+        }
+        AuthorizationChecked::Allowed(Err(inner_request_error)) => {
+            error!(
+                "Extraction failed with {:?}.",
+                Debug2Format(&inner_request_error)
+            );
+            match inner_request_error.render(response) {
+                Ok(()) => {
+                    error!("Original error rendered successfully.");
+                }
+                Err(e) => {
+                    error!(
+                        "Original error could not be rendered due to {:?}:",
+                        Debug2Format(&e)
+                    );
+                    // Two attempts to render extraction errors
+                    // FIXME rewind message
+                    match e.render(response) {
+                        Ok(()) => {
+                            error!("Error was rendered fine.");
+                        }
+                        Err(e2) => {
+                            error!("Rendering error caused {:?}.", Debug2Format(&e2));
+                            set_internal_server_error(response);
+                        }
+                    }
+                }
+            }
+        }
+        AuthorizationChecked::NotAllowed => {
+            if authorities.render_not_allowed(response).is_err() {
+                // FIXME rewind message
+                if let Ok(code) = M::Code::new(coap_numbers::code::UNAUTHORIZED) {
+                    response.set_code(code);
+                }
+            }
+        }
+        AuthorizationChecked::NeedsEcho(echo) => {
+            let rendered = M::Code::new(coap_numbers::code::UNAUTHORIZED)
+                .map(|code| response.set_code(code))
+                .is_ok()
+                && M::OptionNumber::new(coap_numbers::option::ECHO)
+                    .ok()
+                    .is_some_and(|number| response.add_option(number, &echo).is_ok());
+            if !rendered {
+                set_internal_server_error(response);
+            }
+        }
+    }
+}
+
 /// Renders a [`lakers::MessageBufferError`] into the common Error type.
 ///
 /// It is yet to be determined whether anything more informative should be returned (likely it
@@ -1036,6 +1745,8 @@ impl<
     ) -> Result<Self::RequestData, Self::ExtractRequestError> {
         use OrInner::{Inner, Own};
 
+        self.observation = None;
+
         #[derive(Default, Debug)]
         // SSC could be boolean AS_PARSES_TOKENS but not until feature(generic_const_exprs)
         enum Recognition<SSC: ServerSecurityConfig> {
@@ -1045,6 +1756,8 @@ impl<
             Oscore { oscore: OscoreOption },
             /// Seen an OSCORE option and an EDHOC option
             Edhoc { oscore: OscoreOption },
+            /// Seen an OSCORE option in group mode
+            GroupOscore { oscore: crate::group::GroupOption },
             /// Seen path ".well-known" (after not having seen an OSCORE option)
             WellKnown,
             /// Seen path ".well-known" and "edhoc"
@@ -1075,6 +1788,10 @@ impl<
                 match (self, o.number(), o.value()) {
                     // FIXME: Store full value (but a single one is sufficient while we do EDHOC
                     // extraction)
+                    (Start, option::OSCORE, optval) if crate::group::is_group_option(optval) => match optval.try_into() {
+                        Ok(oscore) => (GroupOscore { oscore }, false),
+                        _ => (Start, true),
+                    },
                     (Start, option::OSCORE, optval) if has_oscore::<SSC>() => match optval.try_into() {
                         Ok(oscore) => (Oscore { oscore }, false),
                         _ => (Start, true),
@@ -1105,7 +1822,12 @@ impl<
             fn errors_handled_here(&self) -> bool {
                 match self {
                     WellKnownEdhoc | AuthzInfo(_) => true,
-                    Start | Oscore { .. } | Edhoc { .. } | WellKnown | Unencrypted => false,
+                    Start
+                    | Oscore { .. }
+                    | Edhoc { .. }
+                    | GroupOscore { .. }
+                    | WellKnown
+                    | Unencrypted => false,
                 }
             }
         }
@@ -1148,11 +1870,21 @@ impl<
                     s.scope().request_is_allowed(request)
                         && s.time_constraint().is_valid_with(&mut self.time)
                 }) {
-                    self.inner
+                    let extracted = self
+                        .inner
                         .extract_request_data(request)
                         .map(|extracted| Inner(AuthorizationChecked::Allowed(extracted)))
-                        .map_err(Inner)
+                        .map_err(Inner)?;
+                    self.observation =
+                        ObservedRequest::registration(request).map(|request| Observation {
+                            security: ObservationSecurity::Unprotected,
+                            request,
+                        });
+                    Ok(extracted)
                 } else {
+                    self.report(|| SecurityEvent::ScopeDenied {
+                        peer: Peer::Unprotected,
+                    });
                     Ok(Inner(AuthorizationChecked::NotAllowed))
                 }
             }
@@ -1198,6 +1930,10 @@ impl<
                     .map(Own)
                     .map_err(Own)
             }
+            GroupOscore { oscore } => self
+                .extract_group_oscore(&request, &oscore)
+                .map(Own)
+                .map_err(Own),
         }
     }
     fn estimate_length(&mut self, req: &Self::RequestData) -> usize {
@@ -1205,6 +1941,7 @@ impl<
             OrInner::Own(_) => 2 + lakers::MAX_BUFFER_LEN,
             OrInner::Inner(AuthorizationChecked::Allowed(i)) => self.inner.estimate_length(i),
             OrInner::Inner(AuthorizationChecked::NotAllowed) => 1,
+            OrInner::Inner(AuthorizationChecked::NeedsEcho(echo)) => 2 + echo.len(),
         }
     }
     fn build_response<M: MutableWritableMessage>(
@@ -1238,9 +1975,19 @@ impl<
                 self.build_oscore_response(response, kid, correlation, extracted)
                     .map_err(Own)?;
             }
+            Own(OwnRequestData::GroupOscoreRequest {
+                correlation,
+                extracted,
+            }) => {
+                self.build_group_response(response, correlation, extracted)
+                    .map_err(Own)?;
+            }
             Inner(AuthorizationChecked::Allowed(i)) => {
                 self.inner.build_response(response, i).map_err(Inner)?;
             }
+            Inner(AuthorizationChecked::NeedsEcho(_)) => {
+                unreachable!("Only constructed for OSCORE requests");
+            }
             Inner(AuthorizationChecked::NotAllowed) => {
                 self.authorities
                     .render_not_allowed(response)
@@ -54,8 +54,8 @@ impl TimeProvider for TimeUnknown {
 /// A processed set of token claims that limit it in time.
 #[derive(Copy, Clone, Debug)]
 pub struct TimeConstraint {
-    // iat would not go in here (that's only to feed a `TimeProvider::past_trusted`; nbf would go
-    // in here but we don't read that yet)
+    // iat would not go in here (that's only to feed a `TimeProvider::past_trusted`)
+    nbf: Option<u64>,
     exp: Option<u64>,
 }
 
@@ -63,7 +63,10 @@ impl TimeConstraint {
     /// Creates a [`TimeConstraint`] with no bounds; it is valid at any time.
     #[must_use]
     pub fn unbounded() -> Self {
-        Self { exp: None }
+        Self {
+            nbf: None,
+            exp: None,
+        }
     }
 
     /// Extract time constraint from a claim.
@@ -73,19 +76,24 @@ impl TimeConstraint {
     #[must_use]
     pub fn from_claims_set(claims: &crate::ace::CwtClaimsSet<'_>) -> Self {
         TimeConstraint {
+            nbf: claims.nbf,
             exp: Some(claims.exp),
         }
     }
 
     /// Evaluates the constraint against time provided by the time provider.
     ///
-    /// Any uncertainty of the time provider is counted for the benefit of the client.
+    /// Any uncertainty of the time provider is counted for the benefit of the client: The
+    /// constraint is valid if any point in the provider's confidence interval satisfies it.
     pub(crate) fn is_valid_with(&self, time_provider: &mut impl TimeProvider) -> bool {
-        let Some(exp) = self.exp else {
+        if self.exp.is_none() && self.nbf.is_none() {
             return true;
-        };
-        let (now_early, _now_late) = time_provider.now();
+        }
+        let (now_early, now_late) = time_provider.now();
 
-        exp > now_early
+        self.exp.is_none_or(|exp| exp > now_early)
+            && self
+                .nbf
+                .is_none_or(|nbf| now_late.is_none_or(|now_late| nbf <= now_late))
     }
 }